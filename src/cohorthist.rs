@@ -23,110 +23,213 @@
  * ---------- */
 
 use itertools::{Itertools, MinMaxResult};
-use std::collections::HashMap;
-use chrono::{NaiveDate, NaiveDateTime};
-use serde::{Deserialize};
+use std::collections::{ BTreeSet, HashMap };
+use chrono::{ Datelike, NaiveDate };
+use crate::csvformat::format_csv;
+use crate::timebin::TimeBin;
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Deserialize, Debug)]
-pub struct YearMonth
+pub const NO_COHORT: i32 = -1;
+
+// What a cohort's legend total shows: the sum over the plotted range, the
+// peak for values that don't add up across intervals (like author counts),
+// or the last value for running totals.
+
+#[derive(Debug, Copy, Clone)]
+pub enum LegendTotal
 {
-    pub year: i32,
-    pub month: Option<i32>,
+    Sum,
+    Peak,
+    Last
 }
 
-impl YearMonth
+#[derive(Debug)]
+pub struct CohortHist
 {
-    pub fn next(&self) -> YearMonth
+    bins: HashMap<TimeBin, HashMap<i32, f64>>,
+    first_cohort: i32,
+    last_cohort: i32,
+    cohort_names: HashMap<i32, String>,
+    cohort_groups: HashMap<i32, i32>,
+    decimals: Option<usize>
+}
+
+impl CohortHist
+{
+    pub fn new() -> CohortHist
+    {
+        CohortHist
+        {
+            bins: HashMap::new(),
+            first_cohort: i32::MAX,
+            last_cohort: i32::MIN,
+            cohort_names: HashMap::new(),
+            cohort_groups: HashMap::new(),
+            decimals: None
+        }
+    }
+
+    pub fn set_decimals(&mut self, decimals: Option<usize>)
+    {
+        // How many decimals to print values with in to_csv(). With None,
+        // values are printed in full.
+
+        self.decimals = decimals;
+    }
+
+    fn format_value(&self, value: f64) -> String
     {
-        let YearMonth { year, month } = *self;
+        match self.decimals
+        {
+            None => format!("{}", value),
+            Some(decimals) =>
+            {
+                // Trim trailing zeros so whole numbers stay whole.
 
-        match month {
-            None => YearMonth { year: year + 1, month: None },
-            Some(11) => YearMonth { year: year + 1, month: Some(0) },
-            Some(m) => YearMonth { year, month: Some(m + 1) },
+                let s = format!("{:.*}", decimals, value);
+                let s = if s.contains('.') { s.trim_end_matches('0').trim_end_matches('.') } else { &s };
+                if s == "-0" { "0".to_string() } else { s.to_string() }
+            }
         }
     }
 
-    pub fn prev(&self) -> YearMonth
+    pub fn set_value(&mut self, ym: TimeBin, cohort: i32, value: f64)
     {
-        let YearMonth { year, month } = *self;
+        // NOTE: This will not work if we're overwriting existing values.
 
-        match month {
-            None => YearMonth { year: year - 1, month: None },
-            Some(0) => YearMonth { year: year - 1, month: Some(11) },
-            Some(m) => YearMonth { year, month: Some(m - 1) },
+        self.check_granularity(ym);
+
+        if cohort != NO_COHORT
+        {
+            if cohort < self.first_cohort { self.first_cohort = cohort; }
+            if cohort > self.last_cohort { self.last_cohort = cohort; }
         }
+
+        self.bins.entry(ym).or_default().insert(cohort, value);
     }
 
-    pub fn begin_dt(&self) -> NaiveDateTime
+    fn check_granularity(&self, ym: TimeBin)
     {
-        let m = self.month.unwrap_or(0) + 1;
-        NaiveDate::from_ymd(self.year, m as u32, 1).and_hms(0, 0, 0)
+        // Bins of different kinds can't be mixed; they don't interleave in
+        // time order and would throw off the zero-filling in to_vecs().
+
+        if let Some(other) = self.bins.keys().next()
+        {
+            assert!(other.is_same_kind(&ym), "Mixed daily, weekly, monthly and yearly bins in histogram");
+        }
     }
 
-    pub fn end_dt(&self) -> NaiveDateTime
+    pub fn pad_to(&mut self, ym: TimeBin)
     {
-        let YearMonth { year, month } = *self;
+        // Extend the histogram with an empty bin so that zero-filled
+        // intervals run through ym. Has no effect if there is already
+        // data at or beyond it.
 
-        let date = match month {
-            None => NaiveDate::from_ymd(year + 1, 1, 1),
-            Some(11) => NaiveDate::from_ymd(year + 1, 1, 1),
-            Some(m) => NaiveDate::from_ymd(year, m as u32 + 2, 1),
-        };
+        if let Some((_, last_ym, _, _)) = self.get_bounds()
+        {
+            if ym <= last_ym { return; }
+        }
 
-        date.and_hms(0, 0, 0)
+        self.check_granularity(ym);
+        self.bins.entry(ym).or_default();
     }
-}
 
-pub const NO_COHORT: i32 = -1;
+    pub fn truncate_after(&mut self, ym: TimeBin)
+    {
+        // Drop the bins past ym, e.g. an incomplete last interval that
+        // shouldn't be drawn or extrapolated from.
 
-#[derive(Debug)]
-pub struct CohortHist
-{
-    bins: HashMap<YearMonth, HashMap<i32, f64>>,
-    first_cohort: i32,
-    last_cohort: i32,
-    cohort_names: HashMap<i32, String>
-}
+        self.bins.retain(|bin, _| *bin <= ym);
+    }
 
-impl CohortHist
-{
-    pub fn new() -> CohortHist
+    pub fn clear_between(&mut self, begin: NaiveDate, end: NaiveDate)
     {
-        CohortHist
+        // Zero every bin that overlaps the days from begin up to, but not
+        // including, end. The bins are kept so the gap shows on the chart.
+
+        for (ym, values) in self.bins.iter_mut()
         {
-            bins: HashMap::new(),
-            first_cohort: i32::MAX,
-            last_cohort: i32::MIN,
-            cohort_names: HashMap::new()
+            if ym.begin_dt().date() < end && ym.end_dt().date() > begin
+            {
+                for value in values.values_mut()
+                {
+                    *value = 0.0;
+                }
+            }
         }
     }
 
-    pub fn set_value(&mut self, ym: YearMonth, cohort: i32, value: f64)
+    pub fn accumulate(&mut self)
     {
-        // NOTE: This will not work if we're overwriting existing values.
+        // Replace each value with the running total of its cohort up to
+        // and including that interval.
 
-        if cohort != NO_COHORT
+        let (first_ym, last_ym, first_cohort, last_cohort) = match self.get_bounds()
         {
-            if cohort < self.first_cohort { self.first_cohort = cohort; }
-            if cohort > self.last_cohort { self.last_cohort = cohort; }
-        }
+            Some(bounds) => bounds,
+            None => return
+        };
 
-        self.bins.entry(ym).or_insert_with(HashMap::new).insert(cohort, value);
+        let cohorts: Vec<i32> = (first_cohort..=last_cohort).chain(std::iter::once(NO_COHORT)).collect();
+        let mut totals: HashMap<i32, f64> = HashMap::new();
+        let mut ym = first_ym;
+
+        while ym <= last_ym
+        {
+            for cohort in &cohorts
+            {
+                let total = totals.entry(*cohort).or_insert(0.0);
+                *total += self.get_value(ym, *cohort).unwrap_or(0.0);
+
+                if *total != 0.0 || self.bins.contains_key(&ym)
+                {
+                    self.bins.entry(ym).or_default().insert(*cohort, *total);
+                }
+            }
+
+            ym = ym.next();
+        }
     }
 
-    pub fn get_value(&self, ym: YearMonth, cohort: i32) -> Option<f64>
+    pub fn normalize_cohorts(&mut self)
     {
-        let result = self.bins.get(&ym)?;
-        let value = result.get(&cohort);
+        // Scale each cohort independently so that its peak is 1.0.
+
+        let mut peaks: HashMap<i32, f64> = HashMap::new();
+
+        for values in self.bins.values()
+        {
+            for (cohort, value) in values
+            {
+                let peak = peaks.entry(*cohort).or_insert(0.0);
+                if *value > *peak { *peak = *value; }
+            }
+        }
 
-        match value
+        for values in self.bins.values_mut()
         {
-            Some(_) => { Some(*value.unwrap()) },
-            None => None
+            for (cohort, value) in values.iter_mut()
+            {
+                if peaks[cohort] > 0.0 { *value /= peaks[cohort]; }
+            }
         }
     }
 
+    pub fn scale(&mut self, factor: f64)
+    {
+        for values in self.bins.values_mut()
+        {
+            for value in values.values_mut()
+            {
+                *value *= factor;
+            }
+        }
+    }
+
+    pub fn get_value(&self, ym: TimeBin, cohort: i32) -> Option<f64>
+    {
+        self.bins.get(&ym)?.get(&cohort).copied()
+    }
+
     pub fn set_cohort_name(&mut self, cohort: i32, name: &str)
     {
         let mut name_string = name.trim().to_string();
@@ -134,6 +237,173 @@ impl CohortHist
         self.cohort_names.insert(cohort, name_string);
     }
 
+    pub fn set_cohort_group(&mut self, cohort: i32, group: i32)
+    {
+        // Nested cohorts belong to an outer cohort, e.g. the first year of
+        // a generation split by domain. Plots shade them alike.
+
+        self.cohort_groups.insert(cohort, group);
+    }
+
+    pub fn get_cohort_group(&self, cohort: i32) -> Option<i32>
+    {
+        self.cohort_groups.get(&cohort).copied()
+    }
+
+    pub fn relabel_cohorts<F>(&mut self, relabel: F)
+        where F: Fn(&str) -> String
+    {
+        for name in self.cohort_names.values_mut()
+        {
+            *name = relabel(name);
+        }
+    }
+
+    pub fn find_cohort(&self, name: &str) -> Option<i32>
+    {
+        self.cohort_names.iter().find(|(_, n)| n.as_str() == name).map(|(g, _)| *g)
+    }
+
+    pub fn remove_cohort(&mut self, cohort: i32)
+    {
+        // Drop a cohort's values and name. The remaining cohorts keep their
+        // numbers, so this is mostly useful for the first and last ones.
+
+        for values in self.bins.values_mut()
+        {
+            values.remove(&cohort);
+        }
+        self.cohort_names.remove(&cohort);
+        self.cohort_groups.remove(&cohort);
+
+        let cohorts = self.bins.values().flat_map(|values| values.keys())
+            .chain(self.cohort_names.keys())
+            .filter(|g| **g != NO_COHORT)
+            .copied()
+            .collect::<Vec<i32>>();
+        self.first_cohort = cohorts.iter().copied().min().unwrap_or(i32::MAX);
+        self.last_cohort = cohorts.iter().copied().max().unwrap_or(i32::MIN);
+    }
+
+    pub fn drop_empty_cohorts(&mut self, first_year: i32, last_year: i32)
+    {
+        // Drop cohorts with nothing in the given years, so they don't take
+        // up slots in the key. The rest are renumbered to stay contiguous,
+        // since styles are picked by position. The catch-all is kept, and
+        // so is everything if nothing would be left.
+
+        if self.last_cohort < self.first_cohort { return; }
+
+        let visible: BTreeSet<i32> = self.bins.iter()
+            .filter(|(ym, _)| ym.year() >= first_year && ym.year() <= last_year)
+            .flat_map(|(_, values)| values.iter())
+            .filter(|(cohort, value)| **cohort != NO_COHORT && **value != 0.0)
+            .map(|(cohort, _)| *cohort)
+            .collect();
+        if visible.is_empty() || visible.len() as i32 == self.get_n_cohorts() { return; }
+
+        let renumber: HashMap<i32, i32> = visible.iter()
+            .enumerate()
+            .map(|(i, cohort)| (*cohort, self.first_cohort + i as i32))
+            .chain(std::iter::once((NO_COHORT, NO_COHORT)))
+            .collect();
+
+        for values in self.bins.values_mut()
+        {
+            *values = values.drain()
+                .filter_map(|(cohort, value)| renumber.get(&cohort).map(|c| (*c, value)))
+                .collect();
+        }
+        self.cohort_names = self.cohort_names.drain()
+            .filter_map(|(cohort, name)| renumber.get(&cohort).map(|c| (*c, name)))
+            .collect();
+        self.cohort_groups = self.cohort_groups.drain()
+            .filter_map(|(cohort, group)| renumber.get(&cohort).map(|c| (*c, group)))
+            .collect();
+        self.last_cohort = self.first_cohort + visible.len() as i32 - 1;
+    }
+
+    pub fn append_cohort(&mut self, other: &CohortHist, other_cohort: i32, name: &str) -> i32
+    {
+        // Copy one of another histogram's cohorts in as a new cohort after
+        // our last one, and return its number.
+
+        let cohort = if self.last_cohort == i32::MIN { 0 } else { self.last_cohort + 1 };
+
+        for (ym, values) in &other.bins
+        {
+            if let Some(value) = values.get(&other_cohort)
+            {
+                self.set_value(*ym, cohort, *value);
+            }
+        }
+
+        self.set_cohort_name(cohort, name);
+        cohort
+    }
+
+    pub fn align_to(&self, reference: &CohortHist) -> CohortHist
+    {
+        // Renumber cohorts to match a reference histogram by name, folding
+        // those it doesn't have into its "Other" cohort, or dropping them if
+        // it has none. Lets histograms of different subsets share a legend.
+
+        let other = reference.find_cohort("Other");
+        let mut aligned = CohortHist::new();
+        aligned.first_cohort = reference.first_cohort;
+        aligned.last_cohort = reference.last_cohort;
+        aligned.cohort_names = reference.cohort_names.clone();
+        aligned.cohort_groups = reference.cohort_groups.clone();
+        aligned.decimals = self.decimals;
+
+        for (ym, values) in &self.bins
+        {
+            let bin = aligned.bins.entry(*ym).or_default();
+
+            for (cohort, value) in values
+            {
+                let target = match reference.find_cohort(&self.get_cohort_name(*cohort))
+                {
+                    Some(target) => target,
+                    None => match other { Some(other) => other, None => continue }
+                };
+
+                *bin.entry(target).or_insert(0.0) += value;
+            }
+        }
+
+        aligned
+    }
+
+    pub fn append_totals_to_names(&mut self, first_year: i32, last_year: i32, total: LegendTotal)
+    {
+        // Add each cohort's total over the given years to its name, e.g.
+        // "redhat.com (12,431)", so that it shows up in the chart's key.
+        // Do this after relabeling, since it changes the names.
+
+        let last_ym = self.bins.keys().filter(|ym| ym.year() >= first_year && ym.year() <= last_year).max().copied();
+        let mut totals: HashMap<i32, f64> = HashMap::new();
+
+        for (ym, values) in self.bins.iter().filter(|(ym, _)| ym.year() >= first_year && ym.year() <= last_year)
+        {
+            for (cohort, value) in values
+            {
+                let t = totals.entry(*cohort).or_insert(0.0);
+                match total
+                {
+                    LegendTotal::Sum => *t += value,
+                    LegendTotal::Peak => *t = t.max(*value),
+                    LegendTotal::Last => if Some(*ym) == last_ym { *t = *value }
+                }
+            }
+        }
+
+        for (cohort, name) in self.cohort_names.iter_mut()
+        {
+            *name = format!("{} ({})", name, format_thousands(totals.get(cohort).copied().unwrap_or(0.0)));
+        }
+    }
+
     pub fn get_cohort_name(&self, cohort: i32) -> String
     {
         let name = self.cohort_names.get(&cohort);
@@ -144,7 +414,23 @@ impl CohortHist
         }
     }
 
-    pub fn get_bounds(&self) -> Option<(YearMonth, YearMonth, i32, i32)>
+    pub fn get_cohort_names(&self) -> Vec<String>
+    {
+        // Names in the order to_vecs() gives the values, after the sum.
+
+        let mut names: Vec<String> = (self.first_cohort..=self.last_cohort)
+            .map(|cohort| self.get_cohort_name(cohort))
+            .collect();
+
+        if !self.get_cohort_name(NO_COHORT).is_empty()
+        {
+            names.push(self.get_cohort_name(NO_COHORT));
+        }
+
+        names
+    }
+
+    pub fn get_bounds(&self) -> Option<(TimeBin, TimeBin, i32, i32)>
     {
         match self.bins.keys().minmax() {
             MinMaxResult::NoElements => None,
@@ -155,14 +441,14 @@ impl CohortHist
 
     pub fn get_n_cohorts(&self) -> i32
     {
-        return self.last_cohort - self.first_cohort + 1;
+        self.last_cohort - self.first_cohort + 1
     }
 
-    pub fn to_vecs(&self) -> Vec<(YearMonth, Vec<(i32, f64)>)>
+    pub fn to_vecs(&self) -> Vec<(TimeBin, Vec<(i32, f64)>)>
     {
-        let mut vecs: Vec<(YearMonth, Vec<(i32, f64)>)> = Vec::new();
-        let first_ym: YearMonth;
-        let last_ym: YearMonth;
+        let mut vecs: Vec<(TimeBin, Vec<(i32, f64)>)> = Vec::new();
+        let first_ym: TimeBin;
+        let last_ym: TimeBin;
         let first_cohort: i32;
         let last_cohort: i32;
 
@@ -173,17 +459,16 @@ impl CohortHist
         let (f, l, fg, lg) = bounds;
         { first_ym = f; last_ym = l; first_cohort = fg; last_cohort = lg; }
 
-        // Pad out so all months are present in first year. This
-        // makes it easier to align the histogram in plots.
+        // Pad out so all months (or weeks, or days) are present in first
+        // year. This makes it easier to align the histogram in plots.
 
-        let mut ym = first_ym;
-        if ym.month.is_some() { ym.month = Some(0); }
+        let mut ym = first_ym.first_of_year();
 
         while ym <= last_ym
         {
             let mut gens_vec: Vec<(i32, f64)> = Vec::new();
             let sum: f64 =
-                if self.bins.contains_key(&ym) { self.bins[&ym].iter().map(|(_, x)| x).sum() }
+                if self.bins.contains_key(&ym) { self.bins[&ym].iter().fold(0.0, |sum, (_, x)| sum + x) }
                 else { 0.0 };
 
             gens_vec.push((NO_COHORT, sum));
@@ -208,56 +493,61 @@ impl CohortHist
         vecs
     }
 
-    pub fn to_csv(&self) -> String
+    pub fn to_csv(&self, delimiter: u8) -> String
     {
-        let mut keys = String::new();
+        let mut rows = Vec::new();
         let vecs = self.to_vecs();
 
         // Print keys in first row.
 
-        let bounds = self.get_bounds();
-        if let Some((_, _, mut g, gl)) = bounds
+        if !vecs.is_empty()
         {
-            keys += match vecs[0].0.month
+            let mut keys: Vec<String> = match vecs[0].0
             {
-                None => "Year|Sum",
-                Some(_) => "Year|Month|Sum"
-            };
+                TimeBin::Year(_) => vec![ "Year", "Sum" ],
+                TimeBin::Month(..) => vec![ "Year", "Month", "Sum" ],
+                TimeBin::Week(..) => vec![ "Year", "Week", "Sum" ],
+                TimeBin::Day(_) => vec![ "Year", "Month", "Day", "Sum" ]
+            }.into_iter().map(|k| k.to_string()).collect();
 
-            while g <= gl
-            {
-                // Avoid empty column names; they break Gnuplot.
-                let mut cohort_name = self.get_cohort_name(g);
-                if cohort_name.is_empty() { cohort_name = "(blank)".to_string(); }
+            // Avoid empty column names; they break Gnuplot.
+            keys.extend(self.get_cohort_names().into_iter()
+                        .map(|name| if name.is_empty() { "(blank)".to_string() } else { name }));
 
-                keys += &format!("|{}", cohort_name);
-                g += 1;
-            }
+            rows.push(keys);
+        }
 
-            if !self.get_cohort_name(NO_COHORT).is_empty()
+        for (ym, gens) in &vecs
+        {
+            let mut row = match *ym
             {
-                keys += &format!("|{}", self.get_cohort_name(NO_COHORT));
-            }
+                TimeBin::Year(year) => vec![ year.to_string() ],
+                TimeBin::Month(year, n) | TimeBin::Week(year, n) => vec![ year.to_string(), n.to_string() ],
+                TimeBin::Day(date) => vec![ date.year().to_string(), date.month0().to_string(), date.day0().to_string() ]
+            };
 
-            keys += "\n";
+            row.extend(gens.iter().map(|(_, value)| self.format_value(*value)));
+            rows.push(row);
         }
 
-        keys + &vecs.iter()
-            .map(|(ym, gens)| {
-                 let prefix = if let Some(month) = ym.month {
-                     format!("{}|{}|", ym.year, month)
-                 } else {
-                     format!("{}|", ym.year)
-                 };
-
-                 prefix + &gens.iter()
-                     .map(|(_, value)| format!("{}", value))
-                     .collect::<Vec<String>>()
-                    .join("|")
-            })
-            .collect::<Vec<String>>()
-            .join("\n")
+        format_csv(&rows, delimiter)
+    }
+}
+
+fn format_thousands(value: f64) -> String
+{
+    // Whole number with comma-separated groups of thousands.
+
+    let digits = format!("{:.0}", value.abs());
+    let mut grouped = String::new();
+
+    for (i, c) in digits.chars().enumerate()
+    {
+        if i > 0 && (digits.len() - i) % 3 == 0 { grouped.push(','); }
+        grouped.push(c);
     }
+
+    if value.round() < 0.0 { format!("-{}", grouped) } else { grouped }
 }
 
 #[cfg(test)]
@@ -265,96 +555,87 @@ mod tests {
     use super::*;
 
     #[test]
-    fn without_month_next() {
-        assert_eq!(
-            YearMonth {
-                year: 2020,
-                month: None,
-            }.next(),
-            YearMonth {
-                year: 2021,
-                month: None,
-            },
-        );
+    fn empty_cohort_hist_bounds() {
+        let hist = CohortHist::new();
+
+        assert!(hist.get_bounds().is_none());
     }
 
     #[test]
-    fn with_month_next() {
-        assert_eq!(
-            YearMonth {
-                year: 2020,
-                month: Some(0),
-            }.next(),
-            YearMonth {
-                year: 2020,
-                month: Some(1),
-            },
-        );
+    fn cohort_hist_bounds() {
+        let mut hist = CohortHist::new();
+
+        hist.set_value(TimeBin::Month(2020, 0), 0, 0.0);
+        hist.set_value(TimeBin::Month(2020, 1), 1, 1.0);
+        hist.set_value(TimeBin::Month(2020, 2), 2, 2.0);
 
+        let (first_ym, last_ym, first_cohort, last_cohort) = hist.get_bounds().unwrap();
         assert_eq!(
-            YearMonth {
-                year: 2020,
-                month: Some(11),
-            }.next(),
-            YearMonth {
-                year: 2021,
-                month: Some(0),
-            },
+            (first_ym, last_ym, first_cohort, last_cohort),
+            (
+                TimeBin::Month(2020, 0),
+                TimeBin::Month(2020, 2),
+                0,
+                2,
+            ),
         );
     }
 
     #[test]
-    fn ym_begin() {
-        assert_eq!(
-            YearMonth { year: 2020, month: None }.begin_dt(),
-            NaiveDate::from_ymd(2020, 1, 1).and_hms(0, 0, 0),
-        );
+    fn cohort_hist_pad_to() {
+        let mut hist = CohortHist::new();
 
-        assert_eq!(
-            YearMonth { year: 2020, month: Some(11) }.begin_dt(),
-            NaiveDate::from_ymd(2020, 12, 1).and_hms(0, 0, 0),
-        );
+        hist.set_value(TimeBin::Year(2018), 0, 1.0);
+        hist.pad_to(TimeBin::Year(2020));
+        hist.pad_to(TimeBin::Year(2019));
+
+        let (_, last_ym, _, _) = hist.get_bounds().unwrap();
+        assert_eq!(last_ym, TimeBin::Year(2020));
+        assert_eq!(hist.to_csv(b'|'), "Year|Sum|(blank)\n2018|1|1\n2019|0|0\n2020|0|0");
     }
 
     #[test]
-    fn ym_end() {
-        assert_eq!(
-            YearMonth { year: 2020, month: None }.end_dt(),
-            NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 0, 0),
-        );
+    fn cohort_hist_accumulate() {
+        let mut hist = CohortHist::new();
 
-        assert_eq!(
-            YearMonth { year: 2020, month: Some(0) }.end_dt(),
-            NaiveDate::from_ymd(2020, 2, 1).and_hms(0, 0, 0),
-        );
+        hist.set_value(TimeBin::Year(2018), 0, 2.0);
+        hist.set_value(TimeBin::Year(2020), 0, 1.0);
+        hist.set_value(TimeBin::Year(2020), 1, 3.0);
+        hist.accumulate();
 
-        assert_eq!(
-            YearMonth { year: 2020, month: Some(11) }.end_dt(),
-            NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 0, 0),
-        );
+        assert_eq!(hist.get_value(TimeBin::Year(2019), 0), Some(2.0));
+        assert_eq!(hist.get_value(TimeBin::Year(2020), 0), Some(3.0));
+        assert_eq!(hist.get_value(TimeBin::Year(2020), 1), Some(3.0));
     }
 
     #[test]
-    fn empty_cohort_hist_bounds() {
-        let hist = CohortHist::new();
+    fn cohort_hist_normalize() {
+        let mut hist = CohortHist::new();
 
-        assert!(hist.get_bounds().is_none());
+        hist.set_value(TimeBin::Year(2019), 0, 5.0);
+        hist.set_value(TimeBin::Year(2020), 0, 10.0);
+        hist.set_value(TimeBin::Year(2020), 1, 0.0);
+        hist.normalize_cohorts();
+
+        assert_eq!(hist.get_value(TimeBin::Year(2019), 0), Some(0.5));
+        assert_eq!(hist.get_value(TimeBin::Year(2020), 0), Some(1.0));
+        assert_eq!(hist.get_value(TimeBin::Year(2020), 1), Some(0.0));
     }
 
     #[test]
-    fn cohort_hist_bounds() {
+    fn cohort_hist_bounds_empty_months() {
         let mut hist = CohortHist::new();
 
-        hist.set_value(YearMonth { year: 2020, month: Some(0) }, 0, 0.0);
-        hist.set_value(YearMonth { year: 2020, month: Some(1) }, 1, 1.0);
-        hist.set_value(YearMonth { year: 2020, month: Some(2) }, 2, 2.0);
+        hist.set_value(TimeBin::Year(2020), 0, 0.0);
+        hist.set_value(TimeBin::Year(2020), 1, 1.0);
+        hist.set_value(TimeBin::Year(2020), 2, 2.0);
 
         let (first_ym, last_ym, first_cohort, last_cohort) = hist.get_bounds().unwrap();
         assert_eq!(
             (first_ym, last_ym, first_cohort, last_cohort),
             (
-                YearMonth { year: 2020, month: Some(0) },
-                YearMonth { year: 2020, month: Some(2) },
+                TimeBin::Year(2020),
+                TimeBin::Year(2020),
                 0,
                 2,
             ),
@@ -362,22 +643,187 @@ mod tests {
     }
 
     #[test]
-    fn cohort_hist_bounds_empty_months() {
+    fn cohort_hist_zero_fills_months() {
         let mut hist = CohortHist::new();
 
-        hist.set_value(YearMonth { year: 2020, month: None }, 0, 0.0);
-        hist.set_value(YearMonth { year: 2020, month: None }, 1, 1.0);
-        hist.set_value(YearMonth { year: 2020, month: None }, 2, 2.0);
+        hist.set_value(TimeBin::Month(2019, 10), 0, 2.0);
+        hist.set_value(TimeBin::Month(2020, 1), 0, 3.0);
+        hist.set_value(TimeBin::Month(2020, 1), NO_COHORT, 1.0);
+        hist.set_cohort_name(0, "a");
+        hist.set_cohort_name(NO_COHORT, "Brief");
 
-        let (first_ym, last_ym, first_cohort, last_cohort) = hist.get_bounds().unwrap();
-        assert_eq!(
-            (first_ym, last_ym, first_cohort, last_cohort),
-            (
-                YearMonth { year: 2020, month: None },
-                YearMonth { year: 2020, month: None },
-                0,
-                2,
-            ),
-        );
+        let vecs = hist.to_vecs();
+        let months: Vec<TimeBin> = vecs.iter().map(|(ym, _)| *ym).collect();
+
+        // The first year is filled from January, and the gap across the
+        // year boundary has a row per month.
+
+        assert_eq!(months.len(), 14);
+        assert_eq!(months[0], TimeBin::Month(2019, 0));
+        assert_eq!(months[11], TimeBin::Month(2019, 11));
+        assert_eq!(months[12], TimeBin::Month(2020, 0));
+        assert_eq!(vecs[11].1, vec![(NO_COHORT, 0.0), (0, 0.0), (NO_COHORT, 0.0)]);
+        assert_eq!(vecs[13].1, vec![(NO_COHORT, 4.0), (0, 3.0), (NO_COHORT, 1.0)]);
+    }
+
+    #[test]
+    fn cohort_hist_brief_only_month() {
+        let mut hist = CohortHist::new();
+
+        hist.set_value(TimeBin::Month(2019, 11), NO_COHORT, 1.0);
+        hist.set_value(TimeBin::Month(2020, 0), 0, 1.0);
+        hist.set_cohort_name(0, "a");
+        hist.set_cohort_name(NO_COHORT, "Brief");
+
+        assert_eq!(hist.get_bounds().unwrap(),
+                   (TimeBin::Month(2019, 11),
+                    TimeBin::Month(2020, 0), 0, 0));
+        assert!(hist.to_csv(b'|').ends_with("2019|11|1|0|1\n2020|0|1|1|0"));
+    }
+
+    #[test]
+    fn cohort_hist_accumulate_across_years() {
+        let mut hist = CohortHist::new();
+
+        hist.set_value(TimeBin::Month(2019, 11), 0, 1.0);
+        hist.set_value(TimeBin::Month(2020, 2), 0, 1.0);
+        hist.accumulate();
+
+        assert_eq!(hist.get_value(TimeBin::Month(2020, 0), 0), Some(1.0));
+        assert_eq!(hist.get_value(TimeBin::Month(2020, 2), 0), Some(2.0));
+        assert_eq!(hist.get_value(TimeBin::Year(2020), 0), None);
+    }
+
+    #[test]
+    fn cohort_hist_clear_between() {
+        let mut hist = CohortHist::new();
+
+        for month in 0..4
+        {
+            hist.set_value(TimeBin::Month(2003, month), 0, 100.0);
+        }
+        hist.clear_between(NaiveDate::from_ymd_opt(2003, 2, 15).unwrap(), NaiveDate::from_ymd_opt(2003, 3, 1).unwrap());
+
+        assert!(hist.to_csv(b'|').ends_with("2003|0|100|100\n2003|1|0|0\n2003|2|100|100\n2003|3|100|100"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn cohort_hist_rejects_mixed_granularity() {
+        let mut hist = CohortHist::new();
+
+        hist.set_value(TimeBin::Month(2020, 0), 0, 1.0);
+        hist.set_value(TimeBin::Year(2020), NO_COHORT, 1.0);
+    }
+
+    #[test]
+    fn cohort_hist_large_values() {
+        let mut hist = CohortHist::new();
+
+        hist.set_value(TimeBin::Year(2019), 0, 3_000_000_000.0);
+        hist.set_value(TimeBin::Year(2020), 0, 3_000_000_000.0);
+        hist.set_decimals(Some(0));
+        hist.accumulate();
+
+        assert!(hist.to_csv(b'|').ends_with("2019|3000000000|3000000000\n2020|6000000000|6000000000"));
+    }
+
+    #[test]
+    fn cohort_hist_decimals() {
+        let mut hist = CohortHist::new();
+
+        hist.set_value(TimeBin::Year(2020), 0, 8.0 / 3.0);
+        hist.set_value(TimeBin::Year(2020), 1, 0.5);
+        hist.set_value(TimeBin::Year(2020), 2, 2.0);
+        assert!(hist.to_csv(b'|').ends_with("2020|5.166666666666666|2.6666666666666665|0.5|2"));
+
+        hist.set_decimals(Some(2));
+        assert!(hist.to_csv(b'|').ends_with("2020|5.17|2.67|0.5|2"));
+    }
+
+    #[test]
+    fn cohort_hist_remove_cohort() {
+        let mut hist = CohortHist::new();
+        let ym = TimeBin::Year(2020);
+
+        hist.set_value(ym, 0, 1.0);
+        hist.set_value(ym, 1, 2.0);
+        hist.set_value(ym, NO_COHORT, 4.0);
+        hist.set_cohort_name(0, "a");
+        hist.set_cohort_name(1, "Other");
+        hist.set_cohort_name(NO_COHORT, "Brief");
+
+        hist.remove_cohort(hist.find_cohort("Other").unwrap());
+        hist.remove_cohort(hist.find_cohort("Brief").unwrap());
+
+        assert_eq!(hist.get_n_cohorts(), 1);
+        assert_eq!(hist.to_csv(b'|'), "Year|Sum|a\n2020|1|1");
+    }
+
+    #[test]
+    fn cohort_hist_legend_totals() {
+        let mut hist = CohortHist::new();
+
+        for (year, value) in &[ (2019, 5000.0), (2020, 7431.0), (2021, 9.0) ]
+        {
+            hist.set_value(TimeBin::Year(*year), 0, *value);
+        }
+        hist.set_cohort_name(0, "redhat.com");
+        hist.set_cohort_name(NO_COHORT, "Brief");
+
+        hist.append_totals_to_names(2019, 2020, LegendTotal::Sum);
+        assert_eq!(hist.get_cohort_name(0), "redhat.com (12,431)");
+        assert_eq!(hist.get_cohort_name(NO_COHORT), "Brief (0)");
+
+        hist.set_cohort_name(0, "redhat.com");
+        hist.append_totals_to_names(2019, 2021, LegendTotal::Peak);
+        assert_eq!(hist.get_cohort_name(0), "redhat.com (7,431)");
+
+        hist.set_cohort_name(0, "redhat.com");
+        hist.append_totals_to_names(2019, 2021, LegendTotal::Last);
+        assert_eq!(hist.get_cohort_name(0), "redhat.com (9)");
+    }
+
+    #[test]
+    fn cohort_hist_align_to() {
+        let ym = TimeBin::Year(2020);
+        let mut reference = CohortHist::new();
+        let mut hist = CohortHist::new();
+
+        for (cohort, name) in &[ (0, "a"), (1, "b"), (2, "Other") ]
+        {
+            reference.set_value(ym, *cohort, 1.0);
+            reference.set_cohort_name(*cohort, name);
+        }
+        for (cohort, name, value) in &[ (0, "b", 2.0), (1, "c", 3.0), (2, "Other", 4.0) ]
+        {
+            hist.set_value(ym, *cohort, *value);
+            hist.set_cohort_name(*cohort, name);
+        }
+
+        assert_eq!(hist.align_to(&reference).to_csv(b'|'), "Year|Sum|a|b|Other\n2020|9|0|2|7");
+
+        reference.remove_cohort(2);
+        assert_eq!(hist.align_to(&reference).to_csv(b'|'), "Year|Sum|a|b\n2020|2|0|2");
+    }
+
+    #[test]
+    fn cohort_hist_drop_empty_cohorts() {
+        let mut hist = CohortHist::new();
+
+        for (cohort, year) in &[ (2010, 2010), (2011, 2011), (2012, 2013) ]
+        {
+            hist.set_value(TimeBin::Year(*year), *cohort, 1.0);
+            hist.set_cohort_name(*cohort, &cohort.to_string());
+        }
+        hist.set_value(TimeBin::Year(2011), NO_COHORT, 2.0);
+        hist.set_cohort_name(NO_COHORT, "Brief");
+
+        hist.drop_empty_cohorts(2011, 2013);
+        assert_eq!(hist.get_n_cohorts(), 2);
+        assert!(hist.to_csv(b'|').starts_with("Year|Sum|2011|2012|Brief\n2010|0|0|0|0\n2011|3|1|0|2\n"));
+
+        hist.drop_empty_cohorts(2014, 2015);
+        assert_eq!(hist.get_n_cohorts(), 2);
     }
 }
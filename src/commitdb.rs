@@ -23,38 +23,251 @@
  * -------- */
 
 use chrono::prelude::Utc;
-use chrono::{ Datelike, DateTime, NaiveDateTime };
-use rusqlite::{ Connection, NO_PARAMS };
-use crate::cohorthist::{ CohortHist, NO_COHORT, YearMonth };
-use crate::common::{ CohortType, IntervalType, UnitType };
+use chrono::{ Datelike, DateTime, Duration, NaiveDate, TimeZone };
+use regex::Regex;
+use rusqlite::{ Connection, OpenFlags, NO_PARAMS };
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::ValueRef;
+use serde::{Serialize};
+use std::collections::{ BTreeMap, BTreeSet, HashMap };
+use std::sync::Arc;
+use sha2::{ Digest, Sha256 };
+use crate::cohorthist::{ CohortHist, NO_COHORT };
+use crate::commitstore::is_postgres_url;
+use crate::common::{ BotSplit, BucketTz, CohortType, DbProfile, HideType, IntervalType, TenureCurve, UnitType, YearRange };
 use crate::errors::*;
-use crate::gitcommitreader::RawCommit;
-use crate::projectmeta::DomainMeta;
+use crate::gitcommitreader::{ RawCommit, RawMerge };
+use crate::identities::Identities;
+use crate::crosstab::DomainPairCounts;
+use crate::onboarding::{ AuthorEvent, AuthorOnboarding };
+use crate::projectmeta::ProjectMeta;
+use crate::remotedb::resolve_db_path;
+use crate::report::{ Period, PeriodStats, month_index };
+use crate::timebin::TimeBin;
+
+// Bumped whenever the layout of the ingested tables changes.
+const SCHEMA_VERSION: i32 = 14;
+
+// Number of early commits considered when finding an author's first suffix.
+const N_FIRST_COMMITS: i32 = 5;
+
+// Upper bounds of the median gap between an author's commits, in days, for
+// the daily, weekly and monthly cadence cohorts. Anything slower is sporadic.
+const CADENCE_DAILY_DAYS: f64 = 2.0;
+const CADENCE_WEEKLY_DAYS: f64 = 10.0;
+const CADENCE_MONTHLY_DAYS: f64 = 45.0;
+
+// Lowercased author names and e-mail addresses that give away automated
+// accounts, e.g. "dependabot[bot]" or "ci-bot@example.org".
+const BOT_NAME_GLOBS: &[&str] =
+    &[ "*[[]bot]", "*-bot", "*_bot", "* bot", "bot", "*-robot", "* robot",
+       "dependabot*", "renovate*", "greenkeeper*", "github-actions*", "pre-commit-ci*" ];
+const BOT_EMAIL_GLOBS: &[&str] =
+    &[ "*[[]bot]@*", "*-bot@*", "*_bot@*", "*.bot@*", "bot@*", "*-robot@*" ];
+
+const SECONDS_PER_YEAR: f64 = 365.25 * 24.0 * 60.0 * 60.0;
+
+// Commits weighted by the author's tenure at the time. The authors table is
+// looked up under its own alias, since some queries already join it.
+const WEIGHTED_COMMITS_SELECTOR: &str =
+    "sum(tenure_weight(raw_commits.author_time - (select tenure.first_time from authors as tenure
+                                                  where tenure.author_name = raw_commits.author_name)))";
+
+// Changes weighted by suffix, summed over the suffixes each commit touched.
+// Likewise, suffixes get their own alias.
+const WEIGHTED_CHANGES_SELECTOR: &str =
+    "sum((select sum(weighted.n_changes * suffix_weight(weighted.suffix)) from suffixes as weighted
+          where weighted.commit_id = raw_commits.id))";
+
+// Tables copied into a snapshot. Everything else is derived and rebuilt
+// when plotting.
+
+const SNAPSHOT_TABLES: &[&str] =
+    &[ "raw_commits", "db_meta", "repos", "commit_repos", "commit_parents", "merges",
+       "ingest_watermarks", "prefixes", "suffix_names", "suffix_stats" ];
 
 pub struct CommitDb
 {
     conn: Connection,
+    hide: Option<HideType>,
+    suffix_filter: String,
+    min_cohort_commits: u32,
+    top_per_year: Option<usize>,
+    bot_split: Option<BotSplit>,
+    weighted_changes: bool,
+    dedupe_cherry_picks: bool,
+    active_authors_built: Option<String>
 }
 
-impl CommitDb
+// Everything stored and derived for a single commit: its raw row, the
+// repos, prefixes and suffixes recorded for it, its author's summary and the
+// cohort it falls in for each cohort type.
+
+pub struct CommitDetails
 {
-    pub fn open(db_path: std::path::PathBuf) -> Result<CommitDb>
+    pub fields: Vec<(String, String)>,
+    pub repos: Vec<String>,
+    pub prefixes: Vec<(String, i64)>,
+    pub suffixes: Vec<(String, i64, i64, i64)>,
+    pub author: Vec<(String, String)>,
+    pub cohorts: Vec<(String, String)>
+}
+
+pub struct DbInfo
+{
+    pub schema_version: i32,
+    pub last_ingest: Option<String>,
+    pub last_postprocess: Option<String>,
+    pub snapshot_version: Option<String>,
+    pub snapshot_meta_hash: Option<String>,
+    pub table_rows: Vec<(String, i64)>,
+    pub repo_coverage: Vec<(String, i64, String, String)>,
+    pub object_sizes: Vec<(String, String, i64)>
+}
+
+#[derive(Serialize, Debug)]
+pub struct AuthorRecord
+{
+    pub name: String,
+    pub emails: Vec<String>,
+    pub first_commit: String,
+    pub last_commit: String,
+    pub n_commits: i64,
+    pub n_changes: i64,
+    pub dominant_domain: String,
+    pub repos: Vec<String>
+}
+
+// A commit as exported for other tools. The year and month are those of the
+// selected bucket time zone, and the ISO week (e.g. "2020-W53") is only
+// filled in when asked for.
+
+#[derive(Serialize, Debug)]
+pub struct CommitRecord
+{
+    pub id: String,
+    pub repo: String,
+    pub author_name: String,
+    pub author_domain: String,
+    pub author_time: String,
+    pub year: i32,
+    pub month: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iso_week: Option<String>,
+    pub n_insertions: i64,
+    pub n_deletions: i64
+}
+
+// Commit message quality in one interval. Bodies are only known for commits
+// ingested with --message-stats, and subjects for those ingested after
+// subjects were recorded.
+
+#[derive(Default, Debug)]
+pub struct MessageStats
+{
+    pub n_commits: i64,
+    pub n_with_body: i64,
+    pub n_body_known: i64,
+    pub subject_length_sum: i64,
+    pub n_subject_known: i64
+}
+
+impl MessageStats
+{
+    pub fn body_share(&self) -> Option<f64>
     {
-        let conn = Connection::open(db_path).chain_err(|| "Failed to open database")?;
+        if self.n_body_known == 0 { None } else { Some(self.n_with_body as f64 / self.n_body_known as f64) }
+    }
+
+    pub fn mean_subject_length(&self) -> Option<f64>
+    {
+        if self.n_subject_known == 0 { None }
+        else { Some(self.subject_length_sum as f64 / self.n_subject_known as f64) }
+    }
+}
+
+// Shape of the commit graph in one interval. Merges are not included in
+// n_commits. Commits on the mainline are those reachable from a branch head
+// by following first parents only.
+
+#[derive(Default, Debug)]
+pub struct GraphStats
+{
+    pub n_commits: i64,
+    pub n_merges: i64,
+    pub n_mainline: i64,
+    pub mean_branch_days: Option<f64>
+}
+
+// Pragmas for each --db-profile. The fast profile is what every database was
+// opened with before profiles existed, and remains the default. Safe syncs on
+// every commit, so a power loss can't lose acknowledged ingests. Bulk turns
+// off the journal and syncing altogether; an ingest that is interrupted may
+// leave the database corrupt, which is fine for scratch databases that can
+// be rebuilt from the repositories. The page size only takes effect when the
+// database is created.
 
-        // Specify a few pragmas to speed SQLite up by a whole lot.
-        for (a, b) in
+fn db_profile_pragmas(profile: DbProfile) -> &'static [(&'static str, &'static str)]
+{
+    match profile
+    {
+        DbProfile::Safe =>
+            &[ ("temp_store", "memory"),
+               ("cache_size", "16384"),
+               ("locking_mode", "exclusive"),
+               ("synchronous", "full"),
+               ("journal_mode", "WAL"),
+               ("mmap_size", "0") ],
+        DbProfile::Fast =>
             &[ ("temp_store", "memory"),
                ("cache_size", "16384"),
                ("locking_mode", "exclusive"),
                ("synchronous", "normal"),
                ("journal_mode", "WAL"),
                ("wal_autocheckpoint", "10000"),
-               ("journal_size_limit", "10000000") ]
+               ("journal_size_limit", "10000000"),
+               ("mmap_size", "268435456") ],
+        DbProfile::Bulk =>
+            &[ ("page_size", "65536"),
+               ("temp_store", "memory"),
+               ("cache_size", "16384"),
+               ("locking_mode", "exclusive"),
+               ("synchronous", "off"),
+               ("journal_mode", "off"),
+               ("mmap_size", "1073741824") ]
+    }
+}
+
+impl CommitDb
+{
+    pub fn open(db_path: std::path::PathBuf) -> Result<CommitDb>
+    {
+        CommitDb::open_with_profile(db_path, DbProfile::Fast)
+    }
+
+    pub fn open_with_profile(db_path: std::path::PathBuf, profile: DbProfile) -> Result<CommitDb>
+    {
+        if is_postgres_url(&db_path)
+        {
+            bail!("PostgreSQL databases can only be ingested into and queried; use an SQLite database for this command");
+        }
+
+        let db_path = resolve_db_path(db_path)?;
+        let conn = Connection::open(db_path).chain_err(|| "Failed to open database")?;
+
+        for (a, b) in db_profile_pragmas(profile)
         {
             conn.pragma_update(None, a, &b.to_string()).chain_err(|| "Failed to set pragma")?;
         }
 
+        add_regexp_function(&conn).chain_err(|| "Failed to register regexp function")?;
+        add_email_to_domain_function(&conn).chain_err(|| "Failed to register email_to_domain function")?;
+        add_email_to_country_function(&conn).chain_err(|| "Failed to register email_to_country function")?;
+        add_subject_to_commit_type_function(&conn)
+            .chain_err(|| "Failed to register subject_to_commit_type function")?;
+        add_tenure_weight_function(&conn, TenureCurve::Linear)
+            .chain_err(|| "Failed to register tenure_weight function")?;
+
         conn.execute_batch("
             create table if not exists raw_commits (
                 id text primary key on conflict replace,
@@ -65,12 +278,31 @@ impl CommitDb
                 author_time int,
                 author_year int,
                 author_month int,
+                author_day int,
                 committer_name text,
                 committer_email text,
+                committer_domain text,
                 committer_time int,
                 n_insertions int,
                 n_deletions int,
-                show_domain bool);
+                show_domain bool,
+                subject text,
+                has_body bool,
+                is_revert bool,
+                is_reverted bool,
+                is_bot bool,
+                is_cherry_pick bool,
+                in_excluded_span bool not null default false,
+                author_country text,
+                author_epoch text,
+                commit_type text,
+                author_year_local int,
+                author_month_local int,
+                author_year_utc int,
+                author_month_utc int,
+                author_utc_offset int,
+                author_day_local int,
+                author_day_utc int);
             create index if not exists index_repo_name on raw_commits (repo_name);
             create index if not exists index_author_name on raw_commits (author_name);
             create index if not exists index_author_email on raw_commits (author_email);
@@ -82,1021 +314,5243 @@ impl CommitDb
             create index if not exists index_committer_email on raw_commits (committer_email);
             create index if not exists index_committer_time on raw_commits (committer_time);
 
+            create table if not exists db_meta (
+                key text primary key,
+                value text);
+
+            create table if not exists repos (
+                repo_name text primary key,
+                branches text,
+                changes_unreliable bool);
+
+            create table if not exists commit_repos (
+                commit_id text,
+                repo_name text,
+                primary key (commit_id, repo_name));
+
+            create table if not exists commit_parents (
+                commit_id text,
+                parent_id text,
+                parent_index int,
+                primary key (commit_id, parent_index));
+            create index if not exists index_parent_id on commit_parents (parent_id);
+
+            create table if not exists merges (
+                id text primary key on conflict replace,
+                repo_name text not null,
+                author_time int,
+                author_year int,
+                author_month int);
+
+            create table if not exists ingest_watermarks (
+                repo_name text primary key,
+                last_author_time int,
+                completed_at text);
+
             create table if not exists prefixes (
-                commit_oid int,
+                commit_id text,
                 prefix text,
                 n_changes int);
-            create index if not exists index_prefix on prefixes (prefix);
 
-            create table if not exists suffixes (
-                commit_oid int,
-                suffix text,
-                n_changes int);
-            create index if not exists index_suffix on suffixes (suffix);
+            create table if not exists suffix_names (
+                id integer primary key,
+                suffix text unique);
+
+            create table if not exists suffix_stats (
+                commit_id text,
+                suffix_id int,
+                n_changes int,
+                n_insertions int,
+                n_deletions int);
+
+            create table if not exists author_events (
+                author_email text,
+                kind text,
+                event_time int);
+            create index if not exists index_event_email on author_events (author_email);
         ").chain_err(|| "Failed to create tables")?;
 
-        Ok(CommitDb { conn })
+        let mut cdb = CommitDb { conn, hide: None, suffix_filter: String::new(), min_cohort_commits: 1,
+                                 top_per_year: None, bot_split: None, weighted_changes: false,
+                                 dedupe_cherry_picks: false, active_authors_built: None };
+        cdb.migrate()?;
+        cdb.conn.pragma_update(None, "user_version", &SCHEMA_VERSION).chain_err(|| "Failed to set pragma")?;
+
+        Ok(cdb)
     }
 
-    pub fn insert_raw_commit(&mut self, commit: &RawCommit) -> Result<()>
+    pub fn open_read_only(db_path: std::path::PathBuf) -> Result<CommitDb>
     {
-        let author_time: i64;
-        let author_year: i32;
-        let author_month: i32;
-        let committer_time: i64;
+        // For inspecting a database as it is on disk. Nothing is created or
+        // migrated, so it may be missing tables newer versions expect.
+
+        let db_path = resolve_db_path(db_path)?;
+        let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .chain_err(|| "Failed to open database")?;
+
+        Ok(CommitDb { conn, hide: None, suffix_filter: String::new(), min_cohort_commits: 1,
+                      top_per_year: None, bot_split: None, weighted_changes: false,
+                      dedupe_cherry_picks: false, active_authors_built: None })
+    }
 
-        if commit.author_time.is_some()
+    #[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+    pub fn insert_rows(&mut self, table: &str, columns: &[String],
+                       rows: &[Vec<rusqlite::types::Value>]) -> Result<()>
+    {
+        // For filling in a copy of a database kept elsewhere, e.g. in
+        // PostgreSQL. Rows replace any with the same key.
+
+        let placeholders = (1..=columns.len()).map(|i| format!("?{}", i)).collect::<Vec<String>>();
+        let tx = self.conn.transaction().chain_err(|| "Failed to begin transaction")?;
         {
-            author_time = commit.author_time.unwrap().timestamp();
-            author_year = commit.author_time.unwrap().year();
-            author_month = commit.author_time.unwrap().month0() as i32;
+            let mut stmt = tx.prepare(&format!("insert or replace into {} ({}) values ({});",
+                                               table, columns.join(", "), placeholders.join(", ")))
+                .chain_err(|| format!("Failed to copy {}", table))?;
+
+            for row in rows
+            {
+                stmt.execute(row).chain_err(|| format!("Failed to copy {}", table))?;
+            }
         }
-        else
+
+        tx.commit().chain_err(|| "Failed to commit transaction")
+    }
+
+    fn migrate(&mut self) -> Result<()>
+    {
+        // Bring databases created by older versions up to date. Columns added
+        // after the fact will be null for previously ingested commits.
+
+        for (column, decl) in &[ ("subject", "text"),
+                                 ("is_revert", "bool"),
+                                 ("is_reverted", "bool"),
+                                 ("author_year_local", "int"),
+                                 ("author_month_local", "int"),
+                                 ("author_year_utc", "int"),
+                                 ("author_month_utc", "int"),
+                                 ("author_utc_offset", "int"),
+                                 ("committer_domain", "text"),
+                                 ("has_body", "bool"),
+                                 ("author_day", "int"),
+                                 ("author_day_local", "int"),
+                                 ("author_day_utc", "int"),
+                                 ("is_bot", "bool"),
+                                 ("author_country", "text"),
+                                 ("is_cherry_pick", "bool"),
+                                 ("in_excluded_span", "bool not null default false"),
+                                 ("author_epoch", "text"),
+                                 ("commit_type", "text") ]
         {
-            author_time = 0;
-            author_year = 1970;
-            author_month = 0;
+            self.add_column_if_missing("raw_commits", column, decl)?;
         }
 
-        if commit.committer_time.is_some()
+        self.add_column_if_missing("repos", "changes_unreliable", "bool")?;
+
+        // The backfills below only need to run once, for databases from
+        // before the schema version they came in with.
+
+        let user_version: i32 = self.conn.query_row("pragma user_version;", NO_PARAMS, |r| r.get(0))
+            .chain_err(|| "Could not query database")?;
+
+        // Older databases only have the author's local year and month, but
+        // the UTC equivalents can be derived from the timestamp.
+
+        if user_version < 3
         {
-            committer_time = commit.committer_time.unwrap().timestamp();
+            self.conn.execute_batch("
+                update raw_commits
+                    set author_year_local = author_year,
+                        author_month_local = author_month
+                    where author_year_local is null;
+                update raw_commits
+                    set author_year_utc = cast(strftime('%Y', author_time, 'unixepoch') as int),
+                        author_month_utc = cast(strftime('%m', author_time, 'unixepoch') as int) - 1
+                    where author_year_utc is null;
+            ").chain_err(|| "Failed to migrate author time buckets")?;
         }
-        else
+
+        // Days weren't recorded at first. Derive them from the timestamp and
+        // the author's UTC offset, and have the bucket time zone selected
+        // again so author_day is filled in too.
+
+        if user_version < 13
         {
-            committer_time = 0;
+            self.conn.execute_batch("
+                update raw_commits
+                    set author_day_local =
+                            cast(strftime('%d', author_time + coalesce(author_utc_offset, 0), 'unixepoch') as int) - 1,
+                        author_day_utc = cast(strftime('%d', author_time, 'unixepoch') as int) - 1
+                    where author_day_local is null;
+                delete from db_meta where key = 'bucket_tz';
+            ").chain_err(|| "Failed to migrate author days")?;
         }
 
-        let mut insert_raw_commit_stmt = self.conn.prepare_cached("
-            insert into raw_commits (
-                id,
-                repo_name,
-                author_name,
-                author_email,
-                author_domain,
-                author_time,
-                author_year,
-                author_month,
-                committer_name,
-                committer_email,
-                committer_time,
-                n_insertions,
-                n_deletions,
-                show_domain
-             ) values
-             (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, true)
-        ").unwrap();
-        insert_raw_commit_stmt.execute (
-            &[&commit.id,
-              &commit.repo_name,
-              &commit.author_name,
-              &commit.author_email,
-              &email_to_domain(&commit.author_email),
-              &author_time.to_string(),
-              &author_year.to_string(),
-              &author_month.to_string(),
-              &commit.committer_name,
-              &commit.committer_email,
-              &committer_time.to_string(),
-              &commit.n_insertions.to_string(),
-              &commit.n_deletions.to_string()]).chain_err(|| "Failed to insert commit")?;
+        if user_version < 9
+        {
+            self.conn.execute("
+                update raw_commits
+                    set committer_domain = email_to_domain(committer_email)
+                    where committer_domain is null;",
+                NO_PARAMS).chain_err(|| "Failed to migrate committer domains")?;
+        }
 
-        let commit_oid: String = self.conn.last_insert_rowid().to_string();
+        // Commits are stored once, so older databases only know the repo
+        // each commit was last ingested from.
 
-        for (prefix, n_changes) in &commit.n_changes_per_prefix {
-            let mut insert_prefix_stats_stmt = self.conn.prepare_cached("
-                insert into prefixes (
-                    commit_oid,
-                    prefix,
-                    n_changes
-                ) values
-                ( ?1, ?2, ?3 )
-            ").unwrap();
-            insert_prefix_stats_stmt.execute (
-                &[&commit_oid, prefix, &n_changes.to_string()]
-            ).chain_err(|| "Failed to insert prefix stats")?;
+        if user_version < 7
+        {
+            self.conn.execute("
+                insert or ignore into commit_repos (commit_id, repo_name)
+                    select id, repo_name from raw_commits;",
+                NO_PARAMS).chain_err(|| "Failed to migrate commit repos")?;
         }
 
-        for (suffix, n_changes) in &commit.n_changes_per_suffix {
-            let mut insert_suffix_stats_stmt = self.conn.prepare_cached("
-                insert into suffixes (
-                    commit_oid,
-                    suffix,
-                    n_changes
-                ) values
-                ( ?1, ?2, ?3 )
-            ").unwrap();
-            insert_suffix_stats_stmt.execute (
-                &[&commit_oid, suffix, &n_changes.to_string()]
-            ).chain_err(|| "Failed to insert suffix stats")?;
+        // Repos ingested before watermarks were kept resume from their
+        // latest commit, as they always did.
+
+        if user_version < 11
+        {
+            self.conn.execute("
+                insert or ignore into ingest_watermarks (repo_name, last_author_time)
+                    select commit_repos.repo_name, max(author_time)
+                    from raw_commits, commit_repos
+                    where commit_repos.commit_id = raw_commits.id
+                    group by commit_repos.repo_name;",
+                NO_PARAMS).chain_err(|| "Failed to migrate ingest watermarks")?;
+        }
+
+        // Prefix and suffix rows used to reference commits by rowid, which
+        // changes when a commit is replaced on re-ingest. Rekey them on the
+        // commit id, dropping rows that no longer belong to any commit.
+
+        for (table, column) in &[ ("prefixes", "prefix"), ("suffixes", "suffix") ]
+        {
+            let n: i64 = self.conn.query_row(
+                &format!("select count(*) from pragma_table_info('{}') where name = 'commit_oid';", table),
+                NO_PARAMS, |r| r.get(0)).chain_err(|| "Could not query database")?;
+
+            if n > 0
+            {
+                self.conn.execute_batch(&format!("
+                    create table {table}_rekeyed as
+                        select raw_commits.id as commit_id, {column}, n_changes
+                        from {table}, raw_commits
+                        where raw_commits.oid = {table}.commit_oid;
+                    drop table {table};
+                    alter table {table}_rekeyed rename to {table};
+                ", table = table, column = column))
+                .chain_err(|| format!("Failed to migrate {}", table))?;
+            }
+        }
+
+        // Suffixes used to be stored as text in every row, which adds up
+        // for monorepos with millions of changed files. They're now kept
+        // once in suffix_names, and suffix_stats refers to them by id.
+        // Insertions and deletions per suffix will be null for commits
+        // ingested before they were recorded.
+
+        let n: i64 = self.conn.query_row(
+            "select count(*) from sqlite_master where type = 'table' and name = 'suffixes';",
+            NO_PARAMS, |r| r.get(0)).chain_err(|| "Could not query database")?;
+
+        if n > 0
+        {
+            self.add_column_if_missing("suffixes", "n_insertions", "int")?;
+            self.add_column_if_missing("suffixes", "n_deletions", "int")?;
+
+            self.conn.execute_batch("
+                insert or ignore into suffix_names (suffix)
+                    select distinct suffix from suffixes;
+                insert into suffix_stats (commit_id, suffix_id, n_changes, n_insertions, n_deletions)
+                    select commit_id, suffix_names.id, n_changes, n_insertions, n_deletions
+                    from suffixes, suffix_names
+                    where suffix_names.suffix = suffixes.suffix;
+                drop table suffixes;
+            ").chain_err(|| "Failed to migrate suffixes")?;
         }
 
+        // Queries see the suffixes with their names filled in.
+
+        self.conn.execute_batch("
+            create view if not exists suffixes as
+                select commit_id, suffix, n_changes, n_insertions, n_deletions
+                from suffix_stats, suffix_names
+                where suffix_names.id = suffix_stats.suffix_id;
+
+            create index if not exists index_subject on raw_commits (subject);
+            create index if not exists index_committer_domain on raw_commits (committer_domain);
+            create index if not exists index_prefix on prefixes (prefix);
+            create index if not exists index_prefix_commit_id on prefixes (commit_id);
+            create index if not exists index_suffix_id on suffix_stats (suffix_id);
+            create index if not exists index_suffix_commit_id on suffix_stats (commit_id);
+        ").chain_err(|| "Failed to create indexes")?;
+
         Ok(())
     }
 
-    pub fn postprocess(&mut self, domains: &Option<Vec<DomainMeta>>) -> Result<()>
+    fn add_column_if_missing(&mut self, table: &str, column: &str, decl: &str) -> Result<()>
     {
-        // Delete commits with unlikely timestamps. These are brobably broken
-        // and would confuse our range detection.
+        let n: i64 = self.conn.query_row(
+            &format!("select count(*) from pragma_table_info('{}') where name = ?1;", table),
+            &[column], |r| r.get(0)).chain_err(|| "Could not query database")?;
 
-        self.conn.execute (
-            format!("delete from raw_commits
-                     where author_year < 1980 or author_year > {}",
-                    Utc::now().year()).as_str(),
-            NO_PARAMS)
-            .chain_err(|| "Failed to trim wayward commits")?;
+        if n == 0
+        {
+            self.conn.execute(&format!("alter table {} add column {} {};", table, column, decl), NO_PARAMS)
+                .chain_err(|| format!("Failed to add column {} to {}", column, table))?;
+        }
 
-        // Delete duplicate commits. Duplicates are defined as commits with the
-        // same timestamp, author e-mail, number of modifications and repository.
-        // If there are multiple duplicates, only one is kept.
-        //
-        // This cuts down on commit overcounting due to trivial duplicates
-        // between branches, build bot activity, etc.
-        //
-        // Note that it does not resolve situations where one repository has been
-        // grafted onto another. We can't reliably determine which is the genesis
-        // repository, so will have to live with overcounting in those cases.
+        Ok(())
+    }
 
+    fn set_db_meta(&mut self, key: &str, value: &str) -> Result<()>
+    {
         self.conn.execute("
-            delete from raw_commits
-            where id in (
-                with dup as (
-                    select *, ROW_NUMBER() OVER (
-                        PARTITION BY author_time, author_email, n_insertions,
-                            n_deletions, repo_name) as row_number
-                    from raw_commits)
-                select id from dup
-                where row_number <> 1
-                order by author_time)",
-                          NO_PARAMS)
-            .chain_err(|| "Failed to delete duplicate commits")?;
+            insert into db_meta (key, value) values (?1, ?2)
+                on conflict (key) do update set value = excluded.value;",
+            &[key, value])
+            .chain_err(|| "Failed to update database metadata")?;
 
-        // We postulate that an e-mail address can only map to a single individual.
-        // Therefore, canonicalize the author names such that each e-mail address
-        // is associated with a single author name (the one most frequently seen).
-        // This cuts down on author overcounting due to typos or inconsistent
-        // spelling of names, errors in VCS metadata, etc.
-        //
-        // Each author name can still map to multiple e-mail addresses.
-        //
-        // Example (picked at random) -- the following e-mail/name occurrence counts:
-        //
-        // sven@convergence.de|Sven Neumann|7
-        // sven@gimp.org|Sven Neumann|8861
-        // sven@gimp.org|MEST 1999  Sven Neumann|8
-        // sven@gimp.org|MEST 1999 Sven Neumann|2
-        // sven@gimp.org|Sven  Neumann|1
-        // sven@gimp.org|MET 2001  Sven Neumann|1
-        //
-        // ...become:
-        //
-        // sven@convergence.de|Sven Neumann|7
-        // sven@gimp.org|Sven Neumann|8873
+        Ok(())
+    }
 
-        self.conn.execute("
-            with email_name_freqs as (
-                select author_email, author_name, count(*) as name_freq
-                from raw_commits
-                group by author_email, author_name
-                order by author_email, count(*) desc),
-            partitioned_freqs as (
-                select *, row_number() over (
-                    partition by author_email
-                    order by name_freq desc) as row_number
-                from email_name_freqs),
-            canonical_names as (
-                select author_email, author_name
-                from partitioned_freqs
-                where row_number = 1)
-            update raw_commits
-                set author_name = (
-                    select author_name from canonical_names
-                    where raw_commits.author_email = canonical_names.author_email
-                    limit 1)
-                where raw_commits.author_email in (
-                    select author_email from canonical_names
-                    where raw_commits.author_email = canonical_names.author_email)",
-                          NO_PARAMS)
-            .chain_err(|| "Error canonicalizing author names")?;
+    fn get_db_meta(&self, key: &str) -> Result<Option<String>>
+    {
+        let mut stmt = self.conn.prepare("select value from db_meta where key = ?1;")
+            .chain_err(|| "Could not query database")?;
+        let mut rows = stmt.query(&[key]).chain_err(|| "Could not query database")?;
 
-        // Show all domains by default.
+        match rows.next().chain_err(|| "Could not query database")?
+        {
+            Some(r) => Ok(r.get(0).chain_err(|| "Could not query database")?),
+            None => Ok(None)
+        }
+    }
 
-        self.conn.execute("
-            update raw_commits
-            set show_domain=true;",
-            NO_PARAMS).chain_err(|| "Error initializing domain visibility")?;
+    pub fn get_sample_rate(&self) -> Result<u32>
+    {
+        // 1 in this many commits was ingested; see set_sample_rate().
 
-        if domains.is_some()
-        {
-            for domain in domains.as_ref().unwrap()
-            {
-                if domain.aggregate_emails.is_some()
-                {
-                    self.conn.execute(&format!("
-                        update raw_commits
-                        set author_domain='{}'
-                        where {}",
-                        domain.name,
-                        domain.sql_emails_selector()),
-                        NO_PARAMS).chain_err(|| "Error mapping e-mail pattern to domains")?;
-                }
+        Ok(self.get_db_meta("sample_rate")?.and_then(|v| v.parse().ok()).unwrap_or(1))
+    }
 
-                if domain.show.is_some()
-                {
-                    let show_domain = domain.show.unwrap();
+    pub fn set_sample_rate(&mut self, sample_rate: u32) -> Result<()>
+    {
+        // Counts are scaled up by a single factor, so commits sampled at
+        // different rates can't share a database.
 
-                    self.conn.execute(&format!("
-                        update raw_commits
-                        set show_domain={}
-                        where author_domain='{}'",
-                        show_domain,
-                        domain.name),
-                        NO_PARAMS).chain_err(|| "Error applying visibility flag to domains")?;
-                }
-            }
+        let current = self.get_sample_rate()?;
+        if current != sample_rate && self.count_commits_where("true")? > 0
+        {
+            bail!("Database holds commits sampled at 1/{}; can't add commits sampled at 1/{} \
+                   (use a new database)", current, sample_rate);
         }
 
-        // Generate table with per-author stats like time of first and
-        // last commit.
+        self.set_db_meta("sample_rate", &sample_rate.to_string())
+    }
 
-        self.conn.execute ("drop table authors;", NO_PARAMS).ok();
-        self.conn.execute_batch ("
-            create table authors as
-                select author_name,
-                       first_time,
-                       first_year,
-                       last_time,
-                       last_year,
-                       last_time-first_time as active_time,
-                       n_commits,
-                       n_changes
-                from
-                (
-                    select author_name,
-                           min(author_time) as first_time,
-                           min(author_year) as first_year,
-                           max(author_time) as last_time,
-                           max(author_year) as last_year,
-                           count(id) as n_commits,
-                           sum(n_insertions) + sum(n_deletions) as n_changes
-                    from raw_commits
-                    group by author_name
-                );
-            create index if not exists index_author_name on authors (author_name);
-            create index if not exists index_first_time on authors (first_time);
-            create index if not exists index_active_time on authors (active_time);
-        ").chain_err(|| "Could not create author summaries")?;
+    pub fn mark_ingested(&mut self) -> Result<()>
+    {
+        // Newly ingested commits are bucketed by local time, so whatever
+        // time zone was selected before no longer applies to all of them.
 
-        Ok(())
+        self.conn.execute("delete from db_meta where key = 'bucket_tz';", NO_PARAMS)
+            .chain_err(|| "Failed to update database metadata")?;
+        self.set_db_meta("last_ingest", &Utc::now().to_rfc3339())?;
+        self.mark_changed()
     }
 
-    pub fn get_last_author_time(&mut self, repo_name: &str) -> DateTime<Utc>
+    fn mark_changed(&mut self) -> Result<()>
+    {
+        self.set_db_meta("last_change", &Utc::now().to_rfc3339())
+    }
+
+    pub fn get_last_change(&mut self) -> Result<Option<DateTime<Utc>>>
+    {
+        // When the ingested data was last modified, as opposed to the derived
+        // tables which are rebuilt on every plot.
+
+        Ok(self.get_db_meta("last_change")?
+           .and_then(|t| DateTime::parse_from_rfc3339(&t).ok())
+           .map(|t| t.with_timezone(&Utc)))
+    }
+
+    pub fn get_info(&mut self) -> Result<DbInfo>
     {
+        let schema_version: i32 = self.conn.query_row("pragma user_version;", NO_PARAMS, |r| r.get(0))
+            .chain_err(|| "Could not query database")?;
+
+        let mut table_rows = Vec::new();
+        let table_names = self.query_strings("
+            select name from sqlite_master
+                where type = 'table' and name not like 'sqlite_%'
+                order by name;")?;
+
+        for name in table_names
+        {
+            let n_rows: i64 = self.conn.query_row(&format!("select count(*) from \"{}\";", name),
+                                                  NO_PARAMS, |r| r.get(0))
+                .chain_err(|| format!("Could not count rows in {}", name))?;
+            table_rows.push((name, n_rows));
+        }
+
+        let mut object_sizes = Vec::new();
         let mut stmt = self.conn.prepare("
-            select author_time from raw_commits
-                where repo_name = ?1
-                order by author_time desc
-                limit 1;").unwrap();
+            select dbstat.name, coalesce(sqlite_master.type, 'internal'), sum(pgsize)
+                from dbstat left join sqlite_master on dbstat.name = sqlite_master.name
+                group by dbstat.name
+                order by sum(pgsize) desc;").chain_err(|| "Could not query database")?;
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
 
-        let rows = stmt.query(&[repo_name]);
-        if let Ok(mut rows) = rows
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
         {
-            let row = rows.next().unwrap_or(None);
-            if let Some(r) = row
-            {
-                return DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(r.get_unwrap::<usize, i64>(0), 0), Utc);
-            }
+            object_sizes.push((r.get(0).unwrap(), r.get(1).unwrap(), r.get(2).unwrap()));
         }
 
-        DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(0, 0), Utc)
+        // Databases from before db_meta existed simply have none of these.
+
+        let has_meta = table_rows.iter().any(|(name, _)| name == "db_meta");
+        let meta = |key| if has_meta { self.get_db_meta(key) } else { Ok(None) };
+
+        Ok(DbInfo
+        {
+            schema_version,
+            last_ingest: meta("last_ingest")?,
+            last_postprocess: meta("last_postprocess")?,
+            snapshot_version: meta("snapshot_fornalder_version")?,
+            snapshot_meta_hash: meta("snapshot_meta_hash")?,
+            table_rows,
+            repo_coverage: self.get_repo_coverage()?,
+            object_sizes
+        })
     }
 
-    fn get_firstyear_hist(&mut self, interval: IntervalType, count_sel: &str) -> Result<CohortHist>
+    pub fn get_repo_coverage(&self) -> Result<Vec<(String, i64, String, String)>>
     {
-        let interval_str = match interval
-        {
-            IntervalType::Month => "author_year, author_month",
-            _ => "author_year"
-        };
-        let mut stmt = self.conn.prepare(&format!("
-            select {}, first_year, {}
-            from raw_commits, authors
-            where raw_commits.author_name=authors.author_name
-                and active_time > (60*60*24*90)
-            group by {}, first_year
-            union select {}, {}, {}
-            from raw_commits, authors
-            where raw_commits.author_name=authors.author_name
-                and active_time <= (60*60*24*90)
-            group by {};
-        ", interval_str,
-           count_sel,
-           interval_str,
-           interval_str,
-           NO_COHORT,
-           count_sel,
-           interval_str)).unwrap();
- 
+        // Each repo's commit count and the dates of its first and last
+        // commits, to tell when repos with short histories skew a chart.
+
+        let mut coverage = Vec::new();
+        let mut stmt = self.conn.prepare("
+            select repo_name, count(*),
+                   date(min(author_time), 'unixepoch'), date(max(author_time), 'unixepoch')
+                from raw_commits
+                group by repo_name
+                order by repo_name;").chain_err(|| "Could not query database")?;
         let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
-        let mut hist = CohortHist::new();
 
         while let Some(r) = rows.next().chain_err(|| "Could not query database")?
         {
-            match interval
+            coverage.push((r.get(0).unwrap(), r.get(1).unwrap(), r.get(2).unwrap(), r.get(3).unwrap()));
+        }
+
+        Ok(coverage)
+    }
+
+    fn query_fields(&self, sql: &str, param: &str) -> Result<Vec<(String, String)>>
+    {
+        // Column names and values of the first row returned, as text.
+
+        let mut stmt = self.conn.prepare(sql).chain_err(|| "Could not query database")?;
+        let names: Vec<String> = stmt.column_names().iter().map(|n| n.to_string()).collect();
+        let mut rows = stmt.query(&[param]).chain_err(|| "Could not query database")?;
+        let mut fields = Vec::new();
+
+        if let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            for (i, name) in names.into_iter().enumerate()
             {
-                IntervalType::Month =>
-                {
-                    hist.set_value(YearMonth { year:  r.get(0).unwrap(),
-                                               month: r.get(1).unwrap() },
-                                   r.get(2).unwrap(), r.get(3).unwrap());
-                    hist.set_cohort_name(r.get(2).unwrap(), &r.get::<_, i32>(2).unwrap().to_string());
-                },
-                IntervalType::Year =>
-                {
-                    hist.set_value(YearMonth { year:  r.get(0).unwrap(),
-                                               month: None },
-                                   r.get(1).unwrap(), r.get(2).unwrap());
-                    hist.set_cohort_name(r.get(1).unwrap(), &r.get::<_, i32>(1).unwrap().to_string());
-                }
+                fields.push((name, format_sql_value(r.get(i).chain_err(|| "Could not query database")?)));
             }
         }
 
-        hist.set_cohort_name(NO_COHORT, &"Brief".to_string());
-
-        Ok(hist)
+        Ok(fields)
     }
 
-    fn get_column_hist(&mut self, column: &str, interval: IntervalType, count_sel: &str) -> Result<CohortHist>
+    pub fn inspect_commit(&mut self, id_prefix: &str) -> Result<CommitDetails>
     {
-        const N_ITEMS: i32 = 15;
-        let interval_str = match interval
+        // Abbreviated ids are accepted as long as they're unambiguous. This
+        // is meant to be called after postprocessing, so the domain and the
+        // author summaries reflect the metadata.
+
+        let ids = self.query_strings(&format!(
+            "select id from raw_commits where substr(id, 1, {}) = {} order by id limit 2;",
+            id_prefix.len(), sql_quote(id_prefix)))?;
+        let id = match ids.as_slice()
         {
-            IntervalType::Month => "author_year, author_month",
-            _ => "author_year"
+            [] => bail!("No commit matching {}", id_prefix),
+            [id] => id.clone(),
+            _ => bail!("Commit id {} is ambiguous", id_prefix)
         };
-        self.conn.execute (&format!("drop table {column}_top;", column = column), NO_PARAMS).ok();
-        self.conn.execute (&format!("
-            create table {column}_top as
-                select raw_commits.{column} as {column},row_number() over(order by {count_selector} desc) as rowid
-                from raw_commits, authors
-                where raw_commits.author_name = authors.author_name
-                    and raw_commits.show_domain = true
-                    and active_time > (60*60*24*90)
-                group by {column}
-                order by {count_selector} desc
-                limit {n_items};",
-            column = column,
-            count_selector = count_sel,
-            n_items = N_ITEMS),
-            NO_PARAMS).chain_err(|| format!("Could not generate {}_top", column))?;
-        let mut stmt = self.conn.prepare(&(format!("
-            select {interval}, {last_item}-{column}_top.rowid, {count_selector}, {column}_top.{column}
-            from {column}_top, raw_commits, authors
-            where raw_commits.{column} = {column}_top.{column}
-                and raw_commits.author_name = authors.author_name
-                and active_time > (60*60*24*90)
-            group by {interval}, {column}_top.rowid",
-            column = column,
-            interval = interval_str,
-            count_selector = count_sel,
-            last_item = N_ITEMS + 1)
 
-            + &format!("
+        let fields = self.query_fields("select * from raw_commits where id = ?1;", &id)?;
+        let repos = self.query_strings(&format!(
+            "select repo_name from commit_repos where commit_id = {} order by repo_name;", sql_quote(&id)))?;
 
-            union
+        let mut prefixes = Vec::new();
+        let mut stmt = self.conn.prepare("
+            select prefix, n_changes from prefixes where commit_id = ?1 order by prefix;")
+            .chain_err(|| "Could not query database")?;
+        let mut rows = stmt.query(&[&id]).chain_err(|| "Could not query database")?;
 
-            select {interval},{item_num},{count_selector},\"Other\"
-            from raw_commits, authors
-            where raw_commits.author_name = authors.author_name
-                and {column} not in (select {column} from {column}_top)
-                and active_time > (60*60*24*90)
-            group by {interval}",
-            column = column,
-            interval = interval_str,
-            count_selector = count_sel,
-            item_num = N_ITEMS + 1)
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            prefixes.push((r.get(0).unwrap(), r.get::<_, Option<i64>>(1).unwrap().unwrap_or(0)));
+        }
 
-            + &format!("
+        let mut suffixes = Vec::new();
+        let mut stmt = self.conn.prepare("
+            select suffix, n_changes, n_insertions, n_deletions from suffixes
+                where commit_id = ?1 order by suffix;")
+            .chain_err(|| "Could not query database")?;
+        let mut rows = stmt.query(&[&id]).chain_err(|| "Could not query database")?;
 
-            union
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            suffixes.push((r.get(0).unwrap(),
+                           r.get::<_, Option<i64>>(1).unwrap().unwrap_or(0),
+                           r.get::<_, Option<i64>>(2).unwrap().unwrap_or(0),
+                           r.get::<_, Option<i64>>(3).unwrap().unwrap_or(0)));
+        }
 
-            select {interval},{item_num},{count_selector},\"Brief\"
-            from raw_commits, authors
-            where raw_commits.author_name = authors.author_name
-                and active_time <= (60*60*24*90)
-            group by {interval}",
-            interval = interval_str,
-            count_selector = count_sel,
-            item_num = NO_COHORT)
+        let author = self.query_fields("
+            select authors.* from authors, raw_commits
+                where authors.author_name = raw_commits.author_name and raw_commits.id = ?1;", &id)?;
 
-            + ";")).unwrap();
+        // The cohorts as the histograms would name them. Commits from
+        // hidden domains aren't counted at all, and authors active for 90
+        // days or less go in "Brief" when counting authors.
 
-        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
-        let mut hist = CohortHist::new();
+        let mut cohorts = Vec::new();
+        let mut stmt = self.conn.prepare(&format!("
+            select author_domain || case when show_domain then '' else ' (hidden)' end,
+                   raw_commits.repo_name,
+                   cast(authors.first_year as text),
+                   authors.first_suffix,
+                   authors.cadence,
+                   {work_hours},
+                   {cross_domain},
+                   case when authors.active_time <= (60*60*24*90) then 'yes' else 'no' end,
+                   case when is_bot then 'yes' else 'no' end,
+                   author_country,
+                   author_epoch,
+                   commit_type
+            from raw_commits left join authors on authors.author_name = raw_commits.author_name
+            where raw_commits.id = ?1;",
+            work_hours = work_hours_case("'Unknown'", "'Off hours'", "'Business hours'"),
+            cross_domain = cross_domain_case("'Unknown'", "'Internal'", "'External, accepted'")))
+            .chain_err(|| "Could not query database")?;
+        let mut rows = stmt.query(&[&id]).chain_err(|| "Could not query database")?;
 
-        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        if let Some(r) = rows.next().chain_err(|| "Could not query database")?
         {
-            match interval
-            {
-                IntervalType::Month =>
-                {
-                    hist.set_value(YearMonth { year:  r.get(0).unwrap(),
-                                               month: r.get(1).unwrap() },
-                                   r.get(2).unwrap(), r.get(3).unwrap());
-                    hist.set_cohort_name(r.get(2).unwrap(), &r.get::<_, String>(4).unwrap());
-                },
-                IntervalType::Year =>
-                {
-                    hist.set_value(YearMonth { year:  r.get(0).unwrap(),
-                                               month: None },
-                                   r.get(1).unwrap(), r.get(2).unwrap());
-                    hist.set_cohort_name(r.get(1).unwrap(), &r.get::<_, String>(3).unwrap());
-                }
-            }
+            let text = |i: usize| r.get::<_, Option<String>>(i).unwrap().unwrap_or_else(|| "none".to_string());
+            let join = |names: Vec<&String>| if names.is_empty() { "none".to_string() }
+                                              else { names.into_iter().cloned().collect::<Vec<_>>().join(", ") };
+
+            cohorts = vec![ (CohortType::Domain, text(0)),
+                            (CohortType::Repo, text(1)),
+                            (CohortType::FirstYear, text(2)),
+                            (CohortType::FirstSuffix, text(3)),
+                            (CohortType::Cadence, text(4)),
+                            (CohortType::Prefix, join(prefixes.iter().map(|p| &p.0).collect())),
+                            (CohortType::Suffix, join(suffixes.iter().map(|s| &s.0).collect())),
+                            (CohortType::WorkHours, text(5)),
+                            (CohortType::CrossDomain, text(6)),
+                            (CohortType::Country, text(9)),
+                            (CohortType::Epoch, text(10)),
+                            (CohortType::CommitType, text(11)) ]
+                .into_iter()
+                .map(|(cohort, name)| (cohort.to_string().to_lowercase(), name))
+                .collect();
+            cohorts.push(("brief".to_string(), text(7)));
+            cohorts.push(("bot".to_string(), text(8)));
         }
 
-        Ok(hist)
+        Ok(CommitDetails { fields, repos, prefixes, suffixes, author, cohorts })
     }
 
-    fn format_column_aggregates_from_where(&self, extra_table: Option<&str>) -> String
+    pub fn write_snapshot(&mut self, out_path: &std::path::Path, anonymize: bool,
+                          limit_years: Option<i32>, meta_hash: Option<String>) -> Result<()>
     {
-        if extra_table.is_some() {
-            format!("from raw_commits, {table} where show_domain = true
-                     and raw_commits.oid = {table}.commit_oid",
-                    table=extra_table.unwrap()).to_string()
-        } else {
-            "from raw_commits where show_domain = true".to_string()
+        // Copies the ingested data into a fresh database that can be attached
+        // to a bug report. Derived tables are left out since plotting rebuilds
+        // them anyway.
+
+        if out_path.exists()
+        {
+            bail!("{} already exists", out_path.display());
         }
-    }
 
-    fn create_subcommit_year_aggregates(&mut self, column: &str, extra_table: &str,
-                                        subtotal_sel: &str, total_sel: &str) -> Result<()>
-    {
-        self.conn.execute (&format!("drop table {}_year_aggregates;", column), NO_PARAMS).ok();
-        self.conn.execute_batch (&format!("
-            create table {column}_year_aggregates as
-                select b.author_year as year,
-                       b.{column} as {column},
-                       sum(cast({column}_count as float)/sub_count) * commit_count as column_sum
-                from
-                (
-                    select author_year,
-                           {subtotal_sel} as sub_count
-                    from {table}, raw_commits
-                    where raw_commits.oid = {table}.commit_oid
-                    group by author_year
-                ) as a,
-                (
-                    select 
-                           author_year,
-                           {column},
-                           {subtotal_sel} as {column}_count
-                    from raw_commits, authors, {table}
-                    where show_domain = true
-                        and raw_commits.oid = {table}.commit_oid
-                        and raw_commits.author_name = authors.author_name
-                        and authors.active_time > (60*60*24*90)
-                    group by author_year,
-                             {column}
-                ) as b,
-                (
-                    select author_year, {total_sel} as commit_count from raw_commits group by author_year
-                ) as c
-                where a.author_year = b.author_year
-                    and a.author_year = c.author_year
-                group by b.author_year,
-                         b.{column};
+        self.conn.execute(&format!("vacuum into {};", sql_quote(&out_path.to_string_lossy())), NO_PARAMS)
+            .chain_err(|| "Failed to copy database")?;
 
-            create index if not exists index_year on {column}_year_aggregates (year);
-            create index if not exists index_{column} on {column}_year_aggregates ({column});
-        ",
-            column=column,
-            table=extra_table,
-            subtotal_sel=subtotal_sel,
-            total_sel=total_sel))
-        .chain_err(|| format!("Could not create {} per-year aggregates", column))?;
+        let mut snap = CommitDb::open(out_path.to_path_buf())?;
 
-        Ok(())
-    }
+        for name in snap.query_strings("
+            select name from sqlite_master
+                where type = 'table' and name not like 'sqlite_%';")?
+        {
+            if !SNAPSHOT_TABLES.contains(&name.as_str())
+            {
+                snap.conn.execute(&format!("drop table \"{}\";", name), NO_PARAMS)
+                    .chain_err(|| format!("Failed to drop {}", name))?;
+            }
+        }
 
-    fn create_subcommit_month_aggregates(&mut self, column: &str, extra_table: &str,
-                                         subtotal_sel: &str, total_sel: &str) -> Result<()>
-    {
-        self.conn.execute (&format!("drop table {}_month_aggregates;", column), NO_PARAMS).ok();
-        self.conn.execute_batch (&format!("
-            create table {column}_month_aggregates as
-                select b.author_year as year,
-                       b.author_month as month,
-                       b.{column} as {column},
-                       sum(cast({column}_count as float)/sub_count) * commit_count as column_sum
-                from
-                (
-                    select author_year,
-                           author_month,
-                           {subtotal_sel} as sub_count
-                    from {table}, raw_commits
-                    where raw_commits.oid = {table}.commit_oid
-                    group by author_year,
-                             author_month
-                ) as a,
-                (
-                    select 
-                           author_year,
-                           author_month,
-                           {column},
-                           {subtotal_sel} as {column}_count
-                    from raw_commits, authors, {table}
-                    where show_domain = true
-                        and raw_commits.oid = {table}.commit_oid
-                        and raw_commits.author_name = authors.author_name
-                        and authors.active_time > (60*60*24*90)
-                    group by author_year,
-                             author_month,
-                             {column}
-                ) as b,
-                (
-                    select author_year,
-                           author_month,
-                           {total_sel} as commit_count
-                    from raw_commits
-                    group by author_year,
-                             author_month
-                ) as c
-                where a.author_year = b.author_year
-                    and a.author_month = b.author_month
-                    and a.author_year = c.author_year
-                    and a.author_month = c.author_month
-                group by b.author_year,
-                         b.author_month,
-                         b.{column};
+        if let Some(n_years) = limit_years
+        {
+            snap.conn.execute_batch(&format!("
+                create temp table snapshot_cutoff as
+                    select max(author_year) - {n_years} + 1 as year from raw_commits;
+                delete from raw_commits where author_year < (select year from snapshot_cutoff);
+                delete from merges where author_year < (select year from snapshot_cutoff);
+                create temp table snapshot_ids as
+                    select id from raw_commits union select id from merges;
+                delete from commit_repos where commit_id not in (select id from snapshot_ids);
+                delete from commit_parents where commit_id not in (select id from snapshot_ids);
+                delete from prefixes where commit_id not in (select id from snapshot_ids);
+                delete from suffix_stats where commit_id not in (select id from snapshot_ids);
+                drop table snapshot_cutoff;
+                drop table snapshot_ids;",
+                n_years = n_years))
+                .chain_err(|| "Failed to trim snapshot")?;
+        }
 
-            create index if not exists index_year on {column}_month_aggregates (year);
-            create index if not exists index_month on {column}_month_aggregates (month);
-            create index if not exists index_{column} on {column}_month_aggregates ({column});
-        ",
-            column=column,
-            table=extra_table,
-            subtotal_sel=subtotal_sel,
-            total_sel=total_sel))
-        .chain_err(|| format!("Could not create {} per-month aggregates", column))?;
+        if anonymize
+        {
+            // Identities are replaced with salted hashes, so the same person
+            // still maps to the same pseudonym and cohorts come out the same.
+            // Email domains are kept since they drive the domain cohorts.
+            // Subjects are hashed too, padded or cut to their original
+            // length, and reverts keep their "Revert" wrapper so they are
+            // still matched to the commits they revert.
 
+            add_anon_hash_function(&snap.conn).chain_err(|| "Failed to register anon_hash function")?;
+            let host = |col: &str| format!("(case when instr({col}, '@') > 0
+                                                 then substr({col}, instr({col}, '@')) else '' end)",
+                                           col = col);
+            let blank = |expr: &str| format!("substr(anon_hash({expr}) || replace(hex(zeroblob(length({expr}))),
+                                                                       '0', 'x'),
+                                                    1, length({expr}))",
+                                             expr = expr);
+            snap.conn.execute_batch(&format!("
+                update raw_commits set
+                    author_name = 'Author ' || anon_hash(author_name),
+                    author_email = anon_hash(author_email) || {author_host},
+                    committer_name = 'Author ' || anon_hash(committer_name),
+                    committer_email = anon_hash(committer_email) || {committer_host},
+                    subject = case when subject like 'Revert \"%\"'
+                                   then 'Revert \"' || {revert_subject} || '\"'
+                                   else {subject} end;",
+                author_host = host("author_email"),
+                committer_host = host("committer_email"),
+                revert_subject = blank("substr(subject, 9, length(subject) - 9)"),
+                subject = blank("subject")))
+                .chain_err(|| "Failed to anonymize snapshot")?;
+        }
+
+        snap.set_db_meta("snapshot_fornalder_version", env!("CARGO_PKG_VERSION"))?;
+        snap.set_db_meta("snapshot_created_at", &Utc::now().to_rfc3339())?;
+        snap.set_db_meta("snapshot_anonymized", if anonymize { "true" } else { "false" })?;
+        match limit_years
+        {
+            Some(n) => snap.set_db_meta("snapshot_limit_years", &n.to_string())?,
+            None => snap.conn.execute("delete from db_meta where key = 'snapshot_limit_years';", NO_PARAMS)
+                .map(|_| ()).chain_err(|| "Failed to update database metadata")?
+        }
+        match meta_hash
+        {
+            Some(h) => snap.set_db_meta("snapshot_meta_hash", &h)?,
+            None => snap.conn.execute("delete from db_meta where key = 'snapshot_meta_hash';", NO_PARAMS)
+                .map(|_| ()).chain_err(|| "Failed to update database metadata")?
+        }
+
+        snap.conn.execute("vacuum;", NO_PARAMS).chain_err(|| "Failed to compact snapshot")?;
         Ok(())
     }
 
-    fn create_column_year_aggregates(&mut self, column: &str, extra_table: Option<&str>) -> Result<()>
+    fn query_strings(&self, sql: &str) -> Result<Vec<String>>
     {
-        let from_where = self.format_column_aggregates_from_where (extra_table);
+        let mut stmt = self.conn.prepare(sql).chain_err(|| "Could not query database")?;
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut strings = Vec::new();
 
-        self.conn.execute (&format!("drop table {}_year_aggregates;", column), NO_PARAMS).ok();
-        self.conn.execute_batch (&format!("
-            create table {column}_year_aggregates as
-                select b.author_year as year,
-                       b.{column} as {column},
-                       sum(cast(author_{column}_count as float)/author_count) as active_author_sum
-                from authors,
-                (
-                    select author_year,
-                           {column},
-                           author_name,
-                           count(*) as author_count
-                    {from_where}
-                    group by author_year,
-                             author_name
-                ) as a,
-                (
-                    select author_year,
-                           {column},
-                           author_name,
-                           count(*) as author_{column}_count
-                    {from_where}
-                    group by author_year,
-                             author_name,
-                             {column}
-                ) as b
-                where a.author_year = b.author_year
-                    and a.author_name = b.author_name
-                    and authors.author_name = b.author_name
-                    and authors.active_time > (60*60*24*90)
-                group by b.author_year,
-                         b.{column};
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            strings.push(r.get(0).chain_err(|| "Could not query database")?);
+        }
+
+        Ok(strings)
+    }
+
+    pub fn count_commits_where(&self, condition: &str) -> Result<i64>
+    {
+        self.conn.query_row(&format!("select count(*) from raw_commits where {};", condition),
+                            NO_PARAMS, |r| r.get(0))
+            .chain_err(|| "Could not query database")
+    }
+
+    pub fn get_time_bounds(&self) -> Result<Option<(TimeBin, TimeBin)>>
+    {
+        let (first, last): (Option<i32>, Option<i32>) = self.conn.query_row(
+            "select min(author_year * 12 + author_month), max(author_year * 12 + author_month)
+             from raw_commits;",
+            NO_PARAMS, |r| Ok((r.get(0)?, r.get(1)?))).chain_err(|| "Could not query database")?;
+
+        Ok(match (first, last)
+        {
+            (Some(first), Some(last)) => Some((TimeBin::from_month_index(first), TimeBin::from_month_index(last))),
+            _ => None
+        })
+    }
+
+    pub fn insert_raw_commit(&mut self, commit: &RawCommit) -> Result<()>
+    {
+        self.insert_commit_row(commit)?;
+        self.insert_path_stats(commit)
+    }
+
+    pub fn insert_commit_row(&mut self, commit: &RawCommit) -> Result<()>
+    {
+        self.active_authors_built = None;
+
+        let author_time: i64;
+        let author_year: i32;
+        let author_month: i32;
+        let author_day: i32;
+        let author_year_utc: i32;
+        let author_month_utc: i32;
+        let author_day_utc: i32;
+        let author_utc_offset: String;
+
+        if let Some(t) = commit.author_time
+        {
+            let author_time_utc = t.with_timezone(&Utc);
+
+            author_time = t.timestamp();
+            author_year = t.year();
+            author_month = t.month0() as i32;
+            author_day = t.day0() as i32;
+            author_year_utc = author_time_utc.year();
+            author_month_utc = author_time_utc.month0() as i32;
+            author_day_utc = author_time_utc.day0() as i32;
+            author_utc_offset = t.offset().local_minus_utc().to_string();
+        }
+        else
+        {
+            author_time = 0;
+            author_year = 1970;
+            author_month = 0;
+            author_day = 0;
+            author_year_utc = 1970;
+            author_month_utc = 0;
+            author_day_utc = 0;
+            author_utc_offset = "".to_string();
+        }
+
+        let committer_time = commit.committer_time.map_or(0, |t| t.timestamp());
+
+        let mut insert_raw_commit_stmt = self.conn.prepare_cached("
+            insert into raw_commits (
+                id,
+                repo_name,
+                author_name,
+                author_email,
+                author_domain,
+                author_time,
+                author_year,
+                author_month,
+                committer_name,
+                committer_email,
+                committer_time,
+                n_insertions,
+                n_deletions,
+                show_domain,
+                subject,
+                is_revert,
+                author_year_local,
+                author_month_local,
+                author_year_utc,
+                author_month_utc,
+                author_utc_offset,
+                committer_domain,
+                has_body,
+                author_day,
+                author_day_local,
+                author_day_utc,
+                commit_type
+             ) values
+             (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, true, ?14, ?15, ?7, ?8, ?16, ?17,
+              nullif(?18, ''), ?19, nullif(?20, ''), ?21, ?21, ?22, ?23)
+        ").unwrap();
+        insert_raw_commit_stmt.execute (
+            &[&commit.id,
+              &commit.repo_name,
+              &commit.author_name,
+              &commit.author_email,
+              &email_to_domain(&commit.author_email),
+              &author_time.to_string(),
+              &author_year.to_string(),
+              &author_month.to_string(),
+              &commit.committer_name,
+              &commit.committer_email,
+              &committer_time.to_string(),
+              &commit.n_insertions.to_string(),
+              &commit.n_deletions.to_string(),
+              &commit.subject,
+              &(commit.is_revert as i32).to_string(),
+              &author_year_utc.to_string(),
+              &author_month_utc.to_string(),
+              &author_utc_offset,
+              &email_to_domain(&commit.committer_email),
+              &commit.has_body.map_or("".to_string(), |b| (b as i32).to_string()),
+              &author_day.to_string(),
+              &author_day_utc.to_string(),
+              &commit_type(&commit.subject).to_string()])
+            .chain_err(|| "Failed to insert commit")?;
+
+        // A commit replaces any earlier copy of itself, even from another
+        // repo, so keep track of every repo it was seen in.
 
-            create index if not exists index_year on {column}_year_aggregates (year);
-            create index if not exists index_{column} on {column}_year_aggregates ({column});
-        ", column=column, from_where=from_where))
-        .chain_err(|| format!("Could not create {} per-year aggregates", column))?;
+        let mut insert_commit_repo_stmt = self.conn.prepare_cached("
+            insert or ignore into commit_repos (commit_id, repo_name) values (?1, ?2)
+        ").unwrap();
+        insert_commit_repo_stmt.execute(&[&commit.id, &commit.repo_name])
+            .chain_err(|| "Failed to insert commit repo")?;
+
+        self.insert_parents(&commit.id, &commit.parents)
+    }
+
+    fn insert_parents(&self, commit_id: &str, parents: &[String]) -> Result<()>
+    {
+        for (i, parent) in parents.iter().enumerate()
+        {
+            let mut insert_parent_stmt = self.conn.prepare_cached("
+                insert or replace into commit_parents (commit_id, parent_id, parent_index)
+                    values (?1, ?2, ?3)
+            ").unwrap();
+            insert_parent_stmt.execute(&[commit_id, parent, &i.to_string()])
+                .chain_err(|| "Failed to insert commit parent")?;
+        }
 
         Ok(())
     }
 
-    fn create_column_month_aggregates(&mut self, column: &str, extra_table: Option<&str>) -> Result<()>
+    pub fn insert_merge(&mut self, merge: &RawMerge) -> Result<()>
     {
-        let from_where = self.format_column_aggregates_from_where (extra_table);
+        let mut insert_merge_stmt = self.conn.prepare_cached("
+            insert into merges (id, repo_name, author_time, author_year, author_month)
+                values (?1, ?2, ?3, ?4, ?5)
+        ").unwrap();
+        insert_merge_stmt.execute(
+            &[&merge.id,
+              &merge.repo_name,
+              &merge.author_time.timestamp().to_string(),
+              &merge.author_time.year().to_string(),
+              &merge.author_time.month0().to_string()]).chain_err(|| "Failed to insert merge")?;
 
-        self.conn.execute (&format!("drop table {}_month_aggregates;", column), NO_PARAMS).ok();
-        self.conn.execute_batch (&format!("
-            create table {column}_month_aggregates as
-                select b.author_year as year,
-                       b.author_month as month,
-                       b.{column} as {column},
-                       sum(cast(author_{column}_count as float)/author_count) as active_author_sum
-                from authors,
-                (
-                    select author_year,
-                           author_month,
-                           {column},
-                           author_name,
-                           count(*) as author_count
-                    {from_where}
-                    group by author_year,
-                             author_month,
-                             author_name
-                ) as a,
-                (
-                    select author_year,
-                           author_month,
-                           {column},
-                           author_name,
-                           count(*) as author_{column}_count
+        self.insert_parents(&merge.id, &merge.parents)
+    }
+
+    pub fn insert_path_stats(&mut self, commit: &RawCommit) -> Result<()>
+    {
+        // The commit may have replaced an earlier copy of itself; make sure
+        // we don't keep its old path stats around.
+
+        for table in &[ "prefixes", "suffix_stats" ]
+        {
+            let mut delete_stats_stmt = self.conn.prepare_cached(
+                &format!("delete from {} where commit_id = ?1", table)).unwrap();
+            delete_stats_stmt.execute(&[&commit.id]).chain_err(|| "Failed to delete old path stats")?;
+        }
+
+        for (prefix, n_changes) in &commit.n_changes_per_prefix {
+            let mut insert_prefix_stats_stmt = self.conn.prepare_cached("
+                insert into prefixes (
+                    commit_id,
+                    prefix,
+                    n_changes
+                ) values
+                ( ?1, ?2, ?3 )
+            ").unwrap();
+            insert_prefix_stats_stmt.execute (
+                &[&commit.id, prefix, &n_changes.to_string()]
+            ).chain_err(|| "Failed to insert prefix stats")?;
+        }
+
+        for (suffix, n_changes) in &commit.n_changes_per_suffix {
+            let n_insertions = commit.n_insertions_per_suffix.get(suffix).cloned().unwrap_or(0);
+            let n_deletions = commit.n_deletions_per_suffix.get(suffix).cloned().unwrap_or(0);
+            let mut insert_suffix_name_stmt = self.conn.prepare_cached("
+                insert or ignore into suffix_names (suffix) values (?1)
+            ").unwrap();
+            insert_suffix_name_stmt.execute (&[suffix]).chain_err(|| "Failed to insert suffix name")?;
+
+            let mut insert_suffix_stats_stmt = self.conn.prepare_cached("
+                insert into suffix_stats (
+                    commit_id,
+                    suffix_id,
+                    n_changes,
+                    n_insertions,
+                    n_deletions
+                ) values
+                ( ?1, (select id from suffix_names where suffix = ?2), ?3, ?4, ?5 )
+            ").unwrap();
+            insert_suffix_stats_stmt.execute (
+                &[&commit.id, suffix, &n_changes.to_string(),
+                  &n_insertions.to_string(), &n_deletions.to_string()]
+            ).chain_err(|| "Failed to insert suffix stats")?;
+        }
+
+        Ok(())
+    }
+
+    pub fn select_bucket_tz(&mut self, bucket_tz: BucketTz) -> Result<()>
+    {
+        self.select_buckets(bucket_tz, None)
+    }
+
+    pub fn select_buckets(&mut self, bucket_tz: BucketTz, fiscal_year_start: Option<u32>) -> Result<()>
+    {
+        // All queries bucket on author_year, author_month and author_day.
+        // Point them at either the author's local time or UTC. This is done
+        // before postprocessing, since the author summaries depend on it.
+        //
+        // With a fiscal year starting in a month other than January, the
+        // year is that of the fiscal year each commit falls in, named after
+        // the calendar year it ends in. Months and days are left alone, so
+        // this is only meaningful for yearly buckets.
+
+        self.active_authors_built = None;
+        let bucket_tz = bucket_tz.to_string().to_lowercase();
+        let start_month0 = fiscal_year_start.unwrap_or(1).max(1) - 1;
+
+        if self.get_db_meta("bucket_tz")?.as_deref() == Some(bucket_tz.as_str())
+            && self.get_db_meta("fiscal_year_start")?.unwrap_or_else(|| "1".to_string())
+                == (start_month0 + 1).to_string()
+        {
+            return Ok(());
+        }
+
+        self.conn.execute(&format!("
+            update raw_commits
+                set author_year = author_year_{tz}
+                        + case when {start} > 0 and author_month_{tz} >= {start} then 1 else 0 end,
+                    author_month = author_month_{tz},
+                    author_day = author_day_{tz};",
+            tz = bucket_tz,
+            start = start_month0),
+            NO_PARAMS).chain_err(|| "Failed to select time zone for buckets")?;
+
+        self.set_db_meta("bucket_tz", &bucket_tz)?;
+        self.set_db_meta("fiscal_year_start", &(start_month0 + 1).to_string())
+    }
+
+    pub fn set_hidden_cohorts(&mut self, hide: Option<HideType>)
+    {
+        // Leave the catch-all "Other" and/or "Brief" cohorts out of the
+        // histograms generated from here on.
+
+        self.hide = hide;
+    }
+
+    pub fn set_tenure_curve(&mut self, curve: TenureCurve) -> Result<()>
+    {
+        // How weighted commit counts grow with the author's tenure.
+
+        add_tenure_weight_function(&self.conn, curve)
+            .chain_err(|| "Failed to register tenure_weight function")
+    }
+
+    pub fn set_suffix_filter(&mut self, include: &[String], exclude: &[String])
+    {
+        // Limit the suffix cohorts generated from here on to those matching
+        // any of the include globs, and none of the exclude globs.
+
+        self.suffix_filter = format_suffix_filter(include, exclude);
+    }
+
+    pub fn set_min_cohort_commits(&mut self, min_commits: u32)
+    {
+        // Authors are split between the cohorts they committed to in each
+        // interval by their share of commits. Cohorts an author made fewer
+        // than min_commits commits to in the interval don't get a share.
+
+        self.min_cohort_commits = min_commits;
+    }
+
+    pub fn set_top_per_year(&mut self, top_per_year: Option<usize>)
+    {
+        // The top cohorts are normally the biggest over all time, which
+        // hides those that dominated early on and then went away. With
+        // this set, they're made up of the top n from each year instead.
+
+        self.top_per_year = top_per_year;
+    }
+
+    pub fn set_bot_split(&mut self, bot_split: Option<BotSplit>)
+    {
+        // Take commits by bots out of the cohorts generated from here on,
+        // and either drop them or gather them in a cohort of their own.
+
+        self.bot_split = bot_split;
+    }
+
+    pub fn set_dedupe_cherry_picks(&mut self, dedupe: bool)
+    {
+        // Count commits that were cherry-picked to other branches once in
+        // commit and change units from here on, rather than once per copy.
+
+        self.dedupe_cherry_picks = dedupe;
+    }
+
+    pub fn set_suffix_weights(&mut self, weights: &HashMap<String, f64>) -> Result<()>
+    {
+        // Scale the changes made to each suffix from here on, so verbose or
+        // generated files don't drown out the rest. Suffixes without a
+        // weight count in full.
+
+        if let Some((suffix, weight)) = weights.iter().find(|(_, w)| !w.is_finite() || **w < 0.0)
+        {
+            bail!("Suffix weight for '{}' must be a non-negative number, not {}", suffix, weight);
+        }
+
+        add_suffix_weight_function(&self.conn, weights)
+            .chain_err(|| "Failed to register suffix_weight function")?;
+        self.weighted_changes = !weights.is_empty();
+        Ok(())
+    }
+
+    fn changes_selector(&self) -> &'static str
+    {
+        // Changes per commit, weighted by suffix if weights were given.
+
+        if self.weighted_changes { WEIGHTED_CHANGES_SELECTOR }
+        else { "sum(n_insertions + n_deletions)" }
+    }
+
+    fn suffix_changes_selector(&self) -> &'static str
+    {
+        if self.weighted_changes { "sum(suffixes.n_changes * suffix_weight(suffixes.suffix))" }
+        else { "sum(suffixes.n_changes)" }
+    }
+
+    fn check_prefix_changes(&self) -> Result<()>
+    {
+        // Prefix rows don't know which suffixes their changes were made to.
+
+        if self.weighted_changes
+        {
+            bail!("Suffix weights can't be applied to changes in prefix cohorts");
+        }
+
+        Ok(())
+    }
+
+    fn format_create_top(&self, column: &str, item_sel: &str, year_sel: &str, value_sel: &str,
+                         from_where: &str, n_items: i32) -> String
+    {
+        // Creates {column}_top, ranking the chosen cohorts by overall size.
+        // When picking per year, the union is capped at n_items by keeping
+        // the cohorts that ranked highest in their best year.
+
+        match self.top_per_year
+        {
+            None => format!("
+                create table {column}_top as
+                    select {item_sel} as {column}, row_number() over(order by {value_sel} desc) as rowid
                     {from_where}
-                    group by author_year,
-                             author_month,
-                             author_name,
-                             {column}
-                ) as b
-                where a.author_year = b.author_year
-                    and a.author_month = b.author_month
-                    and a.author_name = b.author_name
-                    and authors.author_name = b.author_name
-                    and authors.active_time > (60*60*24*90)
-                group by b.author_year,
-                         b.author_month,
-                         b.{column};
+                    group by {column}
+                    order by {value_sel} desc
+                    limit {n_items};",
+                column = column, item_sel = item_sel, value_sel = value_sel,
+                from_where = from_where, n_items = n_items),
+            Some(k) => format!("
+                create table {column}_top as
+                    select {column}, row_number() over(order by total desc) as rowid
+                    from
+                    (
+                        select {column}, sum(value) as total
+                        from
+                        (
+                            select {item_sel} as {column}, {value_sel} as value,
+                                row_number() over(partition by {year_sel} order by {value_sel} desc) as year_rank
+                            {from_where}
+                            group by {year_sel}, {column}
+                        )
+                        where year_rank <= {k}
+                        group by {column}
+                        order by min(year_rank), total desc
+                        limit {n_items}
+                    );",
+                column = column, item_sel = item_sel, year_sel = year_sel, value_sel = value_sel,
+                from_where = from_where, k = k, n_items = n_items)
+        }
+    }
+
+    fn subcommit_filter(&self, table: &str) -> String
+    {
+        if table == "suffixes" { self.suffix_filter.clone() } else { String::new() }
+    }
+
+    fn hides_other(&self) -> bool
+    {
+        self.hide.is_some_and(|h| h.hides_other())
+    }
+
+    fn hides_brief(&self) -> bool
+    {
+        self.hide.is_some_and(|h| h.hides_brief())
+    }
+
+    pub fn postprocess(&mut self, meta: &ProjectMeta,
+                       unknown_domain: &str, drop_unknown_domains: bool) -> Result<()>
+    {
+        let domains = &meta.domains;
+        self.active_authors_built = None;
+
+        // Merge repos that were ingested under names that have since been
+        // aliased to a new one.
+
+        if let Some(aliases) = &meta.repo_aliases
+        {
+            for old_name in aliases.keys()
+            {
+                let new_name = meta.canonical_repo_name(old_name);
+
+                if new_name != *old_name
+                {
+                    self.rename_repo(old_name, &new_name)?;
+                }
+            }
+        }
+
+        self.apply_repo_precedence()?;
+
+        // Delete commits with unlikely timestamps. These are brobably broken
+        // and would confuse our range detection. Check the calendar year,
+        // since author_year may have been moved on to a fiscal year.
+
+        self.conn.execute (
+            format!("delete from raw_commits
+                     where author_year_utc < 1980 or author_year_utc > {}",
+                    Utc::now().year()).as_str(),
+            NO_PARAMS)
+            .chain_err(|| "Failed to trim wayward commits")?;
+
+        // Delete duplicate commits. Duplicates are defined as commits with the
+        // same timestamp, author e-mail, number of modifications and repository.
+        // If there are multiple duplicates, only one is kept.
+        //
+        // This cuts down on commit overcounting due to trivial duplicates
+        // between branches, build bot activity, etc.
+        //
+        // Note that it does not resolve situations where one repository has been
+        // grafted onto another. We can't reliably determine which is the genesis
+        // repository, so will have to live with overcounting in those cases.
+
+        self.conn.execute("
+            delete from raw_commits
+            where id in (
+                with dup as (
+                    select *, ROW_NUMBER() OVER (
+                        PARTITION BY author_time, author_email, n_insertions,
+                            n_deletions, repo_name) as row_number
+                    from raw_commits)
+                select id from dup
+                where row_number <> 1
+                order by author_time)",
+                          NO_PARAMS)
+            .chain_err(|| "Failed to delete duplicate commits")?;
+
+        // Drop path stats for commits that were deleted above.
+
+        self.conn.execute_batch("
+            delete from prefixes where commit_id not in (select id from raw_commits);
+            delete from suffix_stats where commit_id not in (select id from raw_commits);
+        ").chain_err(|| "Failed to delete orphaned path stats")?;
+
+        // We postulate that an e-mail address can only map to a single individual.
+        // Therefore, canonicalize the author names such that each e-mail address
+        // is associated with a single author name (the one most frequently seen).
+        // This cuts down on author overcounting due to typos or inconsistent
+        // spelling of names, errors in VCS metadata, etc.
+        //
+        // Each author name can still map to multiple e-mail addresses.
+        //
+        // Example (picked at random) -- the following e-mail/name occurrence counts:
+        //
+        // sven@convergence.de|Sven Neumann|7
+        // sven@gimp.org|Sven Neumann|8861
+        // sven@gimp.org|MEST 1999  Sven Neumann|8
+        // sven@gimp.org|MEST 1999 Sven Neumann|2
+        // sven@gimp.org|Sven  Neumann|1
+        // sven@gimp.org|MET 2001  Sven Neumann|1
+        //
+        // ...become:
+        //
+        // sven@convergence.de|Sven Neumann|7
+        // sven@gimp.org|Sven Neumann|8873
+
+        self.conn.execute("
+            with email_name_freqs as (
+                select author_email, author_name, count(*) as name_freq
+                from raw_commits
+                group by author_email, author_name
+                order by author_email, count(*) desc),
+            partitioned_freqs as (
+                select *, row_number() over (
+                    partition by author_email
+                    order by name_freq desc) as row_number
+                from email_name_freqs),
+            canonical_names as (
+                select author_email, author_name
+                from partitioned_freqs
+                where row_number = 1)
+            update raw_commits
+                set author_name = (
+                    select author_name from canonical_names
+                    where raw_commits.author_email = canonical_names.author_email
+                    limit 1)
+                where raw_commits.author_email in (
+                    select author_email from canonical_names
+                    where raw_commits.author_email = canonical_names.author_email)",
+                          NO_PARAMS)
+            .chain_err(|| "Error canonicalizing author names")?;
+
+        if let Some(identities) = &meta.identities
+        {
+            self.apply_identities(identities)?;
+        }
+
+        // Mark commits that were later reverted. Git's default revert subject
+        // is 'Revert "<original subject>"', so we match on that within each
+        // repository.
+
+        self.conn.execute_batch("
+            update raw_commits set is_reverted = false;
+            update raw_commits set is_reverted = true
+                where subject is not null
+                    and exists (
+                        select 1 from raw_commits as reverts
+                        where reverts.subject = 'Revert \"' || raw_commits.subject || '\"'
+                            and reverts.repo_name = raw_commits.repo_name
+                            and reverts.is_revert);
+        ").chain_err(|| "Error marking reverted commits")?;
+
+        // Mark probable cherry-picks: commits with the same author, author
+        // time and subject as an earlier-committed one, e.g. backports to a
+        // stable branch. The first copy to be committed is left unmarked.
+
+        self.conn.execute_batch("
+            update raw_commits set is_cherry_pick = false;
+            update raw_commits set is_cherry_pick = true
+                where id in (
+                    with copies as (
+                        select id, row_number() over (
+                            partition by author_time, author_email, subject
+                            order by committer_time, id) as row_number
+                        from raw_commits
+                        where subject is not null)
+                    select id from copies
+                    where row_number <> 1);
+        ").chain_err(|| "Error marking cherry-picked commits")?;
+
+        // Mark the commits in excluded intervals that only apply to some
+        // repos. They're left out of every query that takes a repo filter.
+
+        self.conn.execute("update raw_commits set in_excluded_span = false;", NO_PARAMS)
+            .chain_err(|| "Error marking excluded commits")?;
+
+        if let Some(selector) = meta.sql_repo_exclusion_selector()
+        {
+            self.conn.execute(&format!("update raw_commits set in_excluded_span = true where {};", selector),
+                              NO_PARAMS)
+                .chain_err(|| "Error marking excluded commits")?;
+        }
+
+        // Tag commits by likely bots, going by telltale names and addresses.
+        // The metadata has the last word.
+
+        let bot_selector = BOT_NAME_GLOBS.iter().map(|g| format!("lower(author_name) glob {}", sql_quote(g)))
+            .chain(BOT_EMAIL_GLOBS.iter().map(|g| format!("lower(author_email) glob {}", sql_quote(g))))
+            .collect::<Vec<String>>().join(" or ");
+
+        self.conn.execute(&format!("update raw_commits set is_bot = coalesce({}, false);", bot_selector),
+                          NO_PARAMS).chain_err(|| "Error tagging bots")?;
+
+        if let Some(bots) = &meta.bots
+        {
+            for (selector, is_bot) in &[ (bots.sql_include_selector(), true), (bots.sql_exclude_selector(), false) ]
+            {
+                if let Some(selector) = selector
+                {
+                    self.conn.execute(&format!("update raw_commits set is_bot = {} where {};", is_bot, selector),
+                                      NO_PARAMS).chain_err(|| "Error applying bot patterns")?;
+                }
+            }
+        }
+
+        // Show all domains by default.
+
+        self.conn.execute("
+            update raw_commits
+            set show_domain=true;",
+            NO_PARAMS).chain_err(|| "Error initializing domain visibility")?;
+
+        // Fold e-mails we couldn't get a domain from into a single cohort.
+        // Older versions stored whatever was left of the address, so we
+        // re-derive the domain for those. They can still be claimed by
+        // aggregate patterns below.
+
+        self.conn.execute("
+            update raw_commits
+            set author_domain = ?1
+            where email_to_domain(author_email) = '';",
+            &[unknown_domain]).chain_err(|| "Error folding unknown domains")?;
+        self.conn.execute("
+            update raw_commits
+            set committer_domain = ?1
+            where email_to_domain(committer_email) = '';",
+            &[unknown_domain]).chain_err(|| "Error folding unknown domains")?;
+
+        if domains.is_some()
+        {
+            for domain in domains.as_ref().unwrap()
+            {
+                // Commits mapped to a time-limited domain by an earlier run
+                // may now fall outside it; give them back their own domain.
+
+                for (domain_column, email_column, time_column) in &[ ("author_domain", "author_email", "author_time"),
+                                                                     ("committer_domain", "committer_email", "committer_time") ]
+                {
+                    if let Some(active) = domain.sql_active_selector(time_column)
+                    {
+                        self.conn.execute(&format!("
+                            update raw_commits
+                            set {domain_column} = coalesce(nullif(email_to_domain({email_column}), ''), ?1)
+                            where {domain_column} = ?2 and not {active}",
+                            domain_column = domain_column,
+                            email_column = email_column,
+                            active = active),
+                            &[unknown_domain, &domain.name])
+                            .chain_err(|| "Error limiting domains to their active range")?;
+                    }
+                }
+
+                // Names from the metadata are bound rather than pasted into
+                // the statements, so quotes in them can't change the SQL.
+                // The patterns are quoted where the selectors are built.
+
+                if domain.aggregate_emails.is_some()
+                {
+                    self.conn.execute(&format!("
+                        update raw_commits
+                        set author_domain = ?1
+                        where {}",
+                        domain.sql_emails_selector()),
+                        &[&domain.name]).chain_err(|| "Error mapping e-mail pattern to domains")?;
+                    self.conn.execute(&format!("
+                        update raw_commits
+                        set committer_domain = ?1
+                        where {}",
+                        domain.sql_emails_selector_for("committer_email", "committer_time")),
+                        &[&domain.name]).chain_err(|| "Error mapping e-mail pattern to domains")?;
+                }
+
+                if let Some(show_domain) = domain.show
+                {
+
+                    self.conn.execute(&format!("
+                        update raw_commits
+                        set show_domain = {}
+                        where author_domain = ?1",
+                        show_domain),
+                        &[&domain.name]).chain_err(|| "Error applying visibility flag to domains")?;
+                }
+            }
+        }
+
+        if drop_unknown_domains
+        {
+            self.conn.execute("
+                update raw_commits
+                set show_domain = false
+                where author_domain = ?1;",
+                &[unknown_domain]).chain_err(|| "Error hiding unknown domains")?;
+        }
+
+        // Guess each author's country from the top-level domain of their
+        // e-mail address. Generic TLDs tell us nothing, so those go in the
+        // unknown cohort unless the metadata names a country for the domain.
+
+        self.conn.execute("
+            update raw_commits
+            set author_country = coalesce(email_to_country(author_email), 'Unknown');",
+            NO_PARAMS).chain_err(|| "Error inferring author countries")?;
+
+        if let Some(countries) = &meta.countries
+        {
+            for (domain, country) in countries
+            {
+                self.conn.execute("
+                    update raw_commits
+                    set author_country = ?1
+                    where author_domain = ?2 or email_to_domain(author_email) = ?2;",
+                    &[&country.to_uppercase(), domain]).chain_err(|| "Error applying country overrides")?;
+            }
+        }
+
+        // Name the epoch each commit was made in, for the epoch cohort.
+
+        self.conn.execute(&format!("update raw_commits set author_epoch = {};",
+                                   meta.sql_epoch_selector().unwrap_or_else(|| "'Unknown'".to_string())),
+                          NO_PARAMS).chain_err(|| "Error assigning commit epochs")?;
+
+        // Commits ingested before commit types were recorded get theirs
+        // from the subject now.
+
+        self.conn.execute("
+            update raw_commits set commit_type = subject_to_commit_type(subject)
+            where commit_type is null;",
+            NO_PARAMS).chain_err(|| "Error assigning commit types")?;
+
+        // Generate table with per-author stats like time of first and
+        // last commit.
+
+        self.conn.execute ("drop table authors;", NO_PARAMS).ok();
+        self.conn.execute_batch ("
+            create table authors as
+                select author_name,
+                       first_time,
+                       first_year,
+                       last_time,
+                       last_year,
+                       last_time-first_time as active_time,
+                       n_commits,
+                       n_changes
+                from
+                (
+                    select author_name,
+                           min(author_time) as first_time,
+                           min(author_year) as first_year,
+                           max(author_time) as last_time,
+                           max(author_year) as last_year,
+                           count(id) as n_commits,
+                           sum(n_insertions) + sum(n_deletions) as n_changes
+                    from raw_commits
+                    group by author_name
+                );
+            create index if not exists index_author_name on authors (author_name);
+            create index if not exists index_first_time on authors (first_time);
+            create index if not exists index_active_time on authors (active_time);
+        ").chain_err(|| "Could not create author summaries")?;
+
+        // Note what kind of file each author touched first, i.e. the suffix
+        // with the most changes across their first few commits. Authors whose
+        // commits have no path stats are put in an unknown cohort.
+
+        self.conn.execute_batch(&format!("
+            alter table authors add column first_suffix text;
+            with first_commits as (
+                select id, author_name,
+                       row_number() over (partition by author_name
+                                          order by author_time, id) as row_number
+                from raw_commits),
+            suffix_changes as (
+                select author_name, suffix, sum(suffixes.n_changes) as n_changes
+                from first_commits, suffixes
+                where suffixes.commit_id = first_commits.id
+                    and first_commits.row_number <= {n_first}
+                group by author_name, suffix),
+            ranked as (
+                select author_name, suffix,
+                       row_number() over (partition by author_name
+                                          order by n_changes desc, suffix) as row_number
+                from suffix_changes)
+            update authors
+            set first_suffix = coalesce(
+                (select suffix from ranked
+                 where ranked.author_name = authors.author_name
+                     and ranked.row_number = 1),
+                'Unknown');",
+            n_first = N_FIRST_COMMITS))
+            .chain_err(|| "Could not find authors' first suffixes")?;
+
+        // Classify authors by how often they usually commit, i.e. the median
+        // gap between consecutive commits. Authors with a single commit have
+        // no gap and count as sporadic.
+
+        self.conn.execute_batch(&format!("
+            alter table authors add column cadence text;
+            with gaps as (
+                select author_name,
+                       author_time - lag(author_time) over (partition by author_name
+                                                            order by author_time, id) as gap
+                from raw_commits),
+            ranked as (
+                select author_name, gap,
+                       row_number() over (partition by author_name order by gap) as row_number,
+                       count(*) over (partition by author_name) as n_gaps
+                from gaps
+                where gap is not null),
+            medians as (
+                select author_name, avg(gap) / 86400.0 as median_days
+                from ranked
+                where row_number in ((n_gaps + 1) / 2, (n_gaps + 2) / 2)
+                group by author_name)
+            update authors
+            set cadence = coalesce(
+                (select case when median_days <= {daily} then 'Daily'
+                             when median_days <= {weekly} then 'Weekly'
+                             when median_days <= {monthly} then 'Monthly'
+                             else 'Sporadic' end
+                 from medians
+                 where medians.author_name = authors.author_name),
+                'Sporadic');",
+            daily = CADENCE_DAILY_DAYS,
+            weekly = CADENCE_WEEKLY_DAYS,
+            monthly = CADENCE_MONTHLY_DAYS))
+            .chain_err(|| "Could not find authors' commit cadence")?;
+
+        self.set_db_meta("last_postprocess", &Utc::now().to_rfc3339())
+    }
+
+    pub fn rename_repo(&mut self, old_name: &str, new_name: &str) -> Result<usize>
+    {
+        // Everything is renamed in one transaction, so a failure midway
+        // doesn't leave commits and repo settings under different names.
+
+        self.active_authors_built = None;
+        let tx = self.conn.transaction().chain_err(|| "Failed to begin transaction")?;
+
+        let n_commits = tx.execute(
+            "update raw_commits set repo_name = ?2 where repo_name = ?1;",
+            &[old_name, new_name])
+            .chain_err(|| "Failed to rename repository")?;
+
+        // If the new name is already known, its settings take precedence.
+
+        for table in &[ "repos", "ingest_watermarks", "commit_repos" ]
+        {
+            tx.execute(&format!("update or ignore {} set repo_name = ?2 where repo_name = ?1;", table),
+                       &[old_name, new_name])
+                .chain_err(|| "Failed to rename repository")?;
+            tx.execute(&format!("delete from {} where repo_name = ?1;", table), &[old_name])
+                .chain_err(|| "Failed to rename repository")?;
+        }
+
+        tx.execute("update merges set repo_name = ?2 where repo_name = ?1;",
+                   &[old_name, new_name])
+            .chain_err(|| "Failed to rename repository")?;
+
+        // Derived tables are regenerated on every plot, but may be inspected
+        // directly in the meantime. They don't necessarily exist yet.
+
+        for table in &[ "repo_name_top", "repo_name_year_aggregates", "repo_name_month_aggregates",
+                        "repo_name_day_aggregates" ]
+        {
+            tx.execute(
+                &format!("update {} set repo_name = ?2 where repo_name = ?1;", table),
+                &[old_name, new_name]).ok();
+        }
+
+        if n_commits > 0
+        {
+            tx.execute("
+                insert into db_meta (key, value) values ('last_change', ?1)
+                    on conflict (key) do update set value = excluded.value;",
+                &[&Utc::now().to_rfc3339()])
+                .chain_err(|| "Failed to update database metadata")?;
+        }
+
+        tx.commit().chain_err(|| "Failed to commit transaction")?;
+
+        Ok(n_commits)
+    }
+
+    pub fn get_shared_commits(&self) -> Result<Vec<(String, String, i64)>>
+    {
+        let mut stmt = self.conn.prepare("
+            select a.repo_name, b.repo_name, count(*)
+            from commit_repos as a, commit_repos as b
+            where a.commit_id = b.commit_id
+                and a.repo_name < b.repo_name
+            group by a.repo_name, b.repo_name
+            order by count(*) desc;").chain_err(|| "Could not query database")?;
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut shared = Vec::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            shared.push((r.get(0).unwrap(), r.get(1).unwrap(), r.get(2).unwrap()));
+        }
+
+        Ok(shared)
+    }
+
+    pub fn set_repo_precedence(&mut self, precedence: &[String]) -> Result<()>
+    {
+        self.set_db_meta("repo_precedence", &precedence.join("\n"))?;
+        self.active_authors_built = None;
+        self.apply_repo_precedence()?;
+        self.mark_changed()
+    }
+
+    fn apply_repo_precedence(&mut self) -> Result<()>
+    {
+        // Commits found in several repos are attributed to the first repo in
+        // the precedence list (a list of globs) that contains them. Otherwise
+        // they stay where the last ingest put them.
+
+        let precedence = match self.get_db_meta("repo_precedence")?
+        {
+            Some(p) => p,
+            None => return Ok(())
+        };
+        let globs: Vec<&str> = precedence.lines().collect();
+        let rank = globs.iter().enumerate()
+            .map(|(i, glob)| format!("when commit_repos.repo_name glob {} then {}", sql_quote(glob), i))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        self.conn.execute(&format!("
+            with ranked as (
+                select commit_repos.commit_id, commit_repos.repo_name,
+                       row_number() over (
+                           partition by commit_repos.commit_id
+                           order by case {rank} else {n} end,
+                                    commit_repos.repo_name <> raw_commits.repo_name) as row_number
+                from commit_repos, raw_commits
+                where commit_repos.commit_id = raw_commits.id)
+            update raw_commits
+            set repo_name = (
+                select repo_name from ranked
+                where ranked.commit_id = raw_commits.id
+                    and ranked.row_number = 1)
+            where id in (
+                select commit_id from ranked
+                where row_number = 2);",
+            rank = rank,
+            n = globs.len()),
+            NO_PARAMS).chain_err(|| "Failed to apply repo precedence")?;
+
+        Ok(())
+    }
+
+    pub fn get_content_hash(&mut self) -> Result<String>
+    {
+        // Hash the ingested commit data rather than the database file, since
+        // derived tables are rebuilt (and pages shuffled) on every run.
+
+        let mut stmt = self.conn.prepare("
+            select id, repo_name, author_name, author_email, author_time,
+                   committer_time, n_insertions, n_deletions
+            from raw_commits
+            order by id;").chain_err(|| "Could not query database")?;
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut hasher = Sha256::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            for i in 0..8
+            {
+                let value: rusqlite::types::Value = r.get(i).chain_err(|| "Could not query database")?;
+                hasher.update(format!("{:?}\x1f", value).as_bytes());
+            }
+            hasher.update(b"\x1e");
+        }
+
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    pub fn get_dominant_items(&mut self, column: &str, boundaries: &[i32]) -> Result<Vec<(String, usize, String)>>
+    {
+        // For each author and period, pick the item (e.g. domain) they
+        // committed most under. Periods start at each boundary year and run
+        // until the next one; the last period is open-ended.
+
+        let period_sel = boundaries.iter().enumerate().rev()
+            .map(|(i, b)| format!("when author_year >= {} then {}", b, i))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        let mut stmt = self.conn.prepare(&format!("
+            with period_counts as (
+                select author_name,
+                       case {period_sel} end as period,
+                       {column} as item,
+                       count(*) as n_commits
+                from raw_commits
+                where show_domain = true
+                    and author_year >= {first_year}
+                group by author_name, period, item),
+            ranked as (
+                select *, row_number() over (
+                    partition by author_name, period
+                    order by n_commits desc, item) as row_number
+                from period_counts)
+            select author_name, period, item
+            from ranked
+            where row_number = 1;",
+            period_sel = period_sel,
+            column = column,
+            first_year = boundaries[0])).chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut dominant = Vec::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            dominant.push((r.get(0).unwrap(),
+                           r.get::<_, i64>(1).unwrap() as usize,
+                           r.get::<_, Option<String>>(2).unwrap().unwrap_or_default()));
+        }
+
+        Ok(dominant)
+    }
+
+    fn period_filter(period: &Period) -> String
+    {
+        format!("show_domain = true and author_year * 12 + author_month between {} and {}",
+                month_index(period.first), month_index(period.last))
+    }
+
+    pub fn get_period_stats(&mut self, period: &Period) -> Result<PeriodStats>
+    {
+        let filter = CommitDb::period_filter(period);
+        let (n_commits, n_authors): (i64, i64) = self.conn.query_row(&format!(
+            "select count(*), count(distinct author_name) from raw_commits where {};", filter),
+            NO_PARAMS, |r| Ok((r.get(0)?, r.get(1)?))).chain_err(|| "Could not query database")?;
+
+        // Authors whose first shown commit falls within the period, most
+        // active first.
+
+        let mut stmt = self.conn.prepare(&format!("
+            with firsts as (
+                select author_name, min(author_year * 12 + author_month) as first_month
+                from raw_commits
+                where show_domain = true
+                group by author_name)
+            select raw_commits.author_name, count(*) as n
+            from raw_commits, firsts
+            where firsts.author_name = raw_commits.author_name
+                and firsts.first_month between {first} and {last}
+                and {filter}
+            group by raw_commits.author_name
+            order by n desc, raw_commits.author_name;",
+            first = month_index(period.first),
+            last = month_index(period.last),
+            filter = filter)).chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut new_authors = Vec::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            new_authors.push((r.get(0).unwrap(), r.get(1).unwrap()));
+        }
+
+        let mut stmt = self.conn.prepare(&format!("
+            select author_name, count(*) as n, sum(n_insertions + n_deletions)
+            from raw_commits
+            where {}
+            group by author_name
+            order by n desc, author_name;", filter)).chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut top_authors = Vec::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            top_authors.push((r.get(0).unwrap(), r.get(1).unwrap(), r.get(2).unwrap()));
+        }
+
+        let busiest_month = self.conn.query_row(&format!(
+            "select author_year, author_month, count(*) as n from raw_commits
+             where {}
+             group by author_year, author_month
+             order by n desc, author_year, author_month
+             limit 1;", filter),
+            NO_PARAMS, |r| Ok((TimeBin::Month(r.get(0)?, r.get(1)?), r.get(2)?)));
+
+        let busiest_month = match busiest_month
+        {
+            Ok(b) => Some(b),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(e) => return Err(e).chain_err(|| "Could not query database")
+        };
+
+        Ok(PeriodStats { n_commits, n_authors, new_authors, top_authors, busiest_month })
+    }
+
+    pub fn get_period_domain_commits(&mut self, period: &Period) -> Result<HashMap<String, i64>>
+    {
+        let mut stmt = self.conn.prepare(&format!("
+            select author_domain, count(*) from raw_commits
+            where {}
+            group by author_domain;", CommitDb::period_filter(period)))
+            .chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut counts = HashMap::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            counts.insert(r.get::<_, Option<String>>(0).unwrap().unwrap_or_default(),
+                          r.get(1).unwrap());
+        }
+
+        Ok(counts)
+    }
+
+    pub fn get_author_overlap(&mut self, by: CohortType, exclude_repos: &[String]) -> Result<DomainPairCounts>
+    {
+        // Distinct authors shared by each pair of repos or domains, including
+        // each with itself.
+
+        let column = match by
+        {
+            CohortType::Repo => "repo_name",
+            CohortType::Domain => "author_domain",
+            _ => bail!("Author overlap is only supported by repo or domain")
+        };
+
+        let mut stmt = self.conn.prepare(&format!("
+            with items as (
+                select distinct {column} as item, author_name from raw_commits
+                where show_domain = true {repo_filter})
+            select a.item, b.item, count(*)
+            from items as a, items as b
+            where a.author_name = b.author_name
+            group by a.item, b.item;",
+            column = column,
+            repo_filter = format_repo_filter(exclude_repos)))
+            .chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut pairs = Vec::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            pairs.push((r.get::<_, Option<String>>(0).unwrap().unwrap_or_default(),
+                        r.get::<_, Option<String>>(1).unwrap().unwrap_or_default(),
+                        r.get(2).unwrap()));
+        }
+
+        Ok(pairs)
+    }
+
+    pub fn get_prefix_coupling(&mut self, max_prefixes: usize,
+                               exclude_repos: &[String]) -> Result<Vec<(i32, String, String, i64)>>
+    {
+        // Commits per year touching each pair of top-level directories, with
+        // each directory paired with itself too. Commits touching more than
+        // max_prefixes directories (tree-wide cleanups, license updates) would
+        // couple everything with everything, so they're left out.
+
+        let mut stmt = self.conn.prepare(&format!("
+            with items as (
+                select author_year as year, raw_commits.id as id, prefix
+                from raw_commits, prefixes
+                where raw_commits.id = prefixes.commit_id
+                    and show_domain = true
+                    and prefix != '(blank)'
+                    {repo_filter}),
+            narrow as (
+                select id from items
+                group by id
+                having count(*) <= {max_prefixes})
+            select a.year, a.prefix, b.prefix, count(*)
+            from items as a, items as b
+            where a.id = b.id
+                and a.prefix <= b.prefix
+                and a.id in (select id from narrow)
+            group by a.year, a.prefix, b.prefix;",
+            max_prefixes = max_prefixes,
+            repo_filter = format_repo_filter(exclude_repos)))
+            .chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut pairs = Vec::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            pairs.push((r.get(0).unwrap(), r.get(1).unwrap(), r.get(2).unwrap(), r.get(3).unwrap()));
+        }
+
+        Ok(pairs)
+    }
+
+    pub fn get_domain_crosstab(&mut self, era: Option<YearRange>,
+                               exclude_repos: &[String]) -> Result<Vec<(String, String, i64)>>
+    {
+        // Commit counts per pair of committer and author domain. Commits
+        // where the two differ were applied by someone else, e.g. a
+        // maintainer merging a patch sent by mail.
+
+        let era_filter = match era
+        {
+            Some(era) => format!("and author_year between {} and {}", era.first, era.last),
+            None => "".to_string()
+        };
+
+        let mut stmt = self.conn.prepare(&format!("
+            select committer_domain, author_domain, count(*) from raw_commits
+            where show_domain = true {era_filter} {repo_filter}
+            group by committer_domain, author_domain;",
+            era_filter = era_filter,
+            repo_filter = format_repo_filter(exclude_repos)))
+            .chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut counts = Vec::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            counts.push((r.get::<_, Option<String>>(0).unwrap().unwrap_or_default(),
+                         r.get::<_, Option<String>>(1).unwrap().unwrap_or_default(),
+                         r.get(2).unwrap()));
+        }
+
+        Ok(counts)
+    }
+
+    pub fn get_graph_stats(&mut self, interval: IntervalType) -> Result<Vec<(TimeBin, GraphStats)>>
+    {
+        if let IntervalType::Day = interval
+        {
+            bail!("Graph stats by day are not supported");
+        }
+
+        let n_parents: i64 = self.conn.query_row("select count(*) from commit_parents;", NO_PARAMS, |r| r.get(0))
+            .chain_err(|| "Could not query database")?;
+        if n_parents == 0
+        {
+            bail!("No commit parents recorded; re-ingest with this version of Fornalder");
+        }
+
+        // Find the mainline by walking first parents from every commit that
+        // has no children.
+
+        self.conn.execute_batch("
+            drop table if exists temp.mainline;
+            create temp table mainline (id text primary key);
+            with recursive
+            heads as (
+                select id from (select id from raw_commits union select id from merges)
+                where id not in (select parent_id from commit_parents)),
+            walk(id) as (
+                select id from heads
+                union
+                select parent_id from commit_parents, walk
+                where commit_parents.commit_id = walk.id
+                    and parent_index = 0)
+            insert into mainline select id from walk;
+        ").chain_err(|| "Could not find mainline")?;
+
+        let month_sel = |column: &str| match interval
+        {
+            IntervalType::Day => unreachable!(),
+            IntervalType::Month => column.to_string(),
+            IntervalType::Year => "null".to_string()
+        };
+        let mut stats: BTreeMap<TimeBin, GraphStats> = BTreeMap::new();
+
+        for (table, year, month) in &[ ("raw_commits", "author_year_local", "author_month_local"),
+                                       ("merges", "author_year", "author_month") ]
+        {
+            let mut stmt = self.conn.prepare(&format!("
+                select {year}, {month}, count(*),
+                       count(case when id in (select id from mainline) then 1 end)
+                from {table}
+                group by {year}, {month};",
+                table = table, year = year, month = month_sel(month)))
+                .chain_err(|| "Could not query database")?;
+            let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+
+            while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+            {
+                let entry = stats.entry(row_bin(r, interval).0)
+                    .or_default();
+                let n: i64 = r.get(2).unwrap();
+
+                if *table == "merges" { entry.n_merges += n; } else { entry.n_commits += n; }
+                entry.n_mainline += r.get::<_, i64>(3).unwrap();
+            }
+        }
+
+        // A merged branch is the run of commits reached from the merge's
+        // second parent before hitting the mainline. Its lifetime is the time
+        // from its first commit to the merge.
+
+        let mut stmt = self.conn.prepare(&format!("
+            with recursive
+            branch(merge_id, id) as (
+                select commit_id, parent_id from commit_parents
+                where parent_index = 1
+                    and commit_id in (select id from merges)
+                    and parent_id not in (select id from mainline)
+                union
+                select branch.merge_id, commit_parents.parent_id
+                from branch, commit_parents
+                where commit_parents.commit_id = branch.id
+                    and parent_index = 0
+                    and parent_id not in (select id from mainline)),
+            times as (
+                select id, author_time from raw_commits
+                union all
+                select id, author_time from merges),
+            lifetimes as (
+                select merge_id, min(times.author_time) as first_time
+                from branch, times
+                where times.id = branch.id
+                group by merge_id)
+            select author_year, {month}, avg(author_time - first_time) / 86400.0
+            from merges, lifetimes
+            where lifetimes.merge_id = merges.id
+            group by author_year, {month};",
+            month = month_sel("author_month")))
+            .chain_err(|| "Could not query database")?;
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            stats.entry(row_bin(r, interval).0)
+                .or_default().mean_branch_days = r.get(2).unwrap();
+        }
+
+        Ok(stats.into_iter().collect())
+    }
+
+    pub fn get_message_stats(&mut self, cohort: CohortType, interval: IntervalType, n_cohorts: usize,
+                             exclude_repos: &[String]) -> Result<Vec<(TimeBin, i32, String, MessageStats)>>
+    {
+        // Message stats per interval for the cohorts with the most commits,
+        // ranked from 0, with the rest folded into "Other" last.
+
+        let column = commit_cohort_column(cohort)
+            .chain_err(|| format!("Message stats by the {} cohort are not supported",
+                                  cohort.to_string().to_lowercase()))?;
+        let interval_str = match interval
+        {
+            IntervalType::Day => "author_year, author_month, author_day",
+            IntervalType::Month => "author_year, author_month",
+            IntervalType::Year => "author_year"
+        };
+        let commit_filter = format_repo_filter(exclude_repos);
+
+        let mut stmt = self.conn.prepare(&format!("
+            with cohorts as (
+                select {column} as cohort, count(*) as n
+                from raw_commits
+                where show_domain = true {commit_filter}
+                group by cohort),
+            top as (
+                select cohort, row_number() over (order by n desc, cohort) - 1 as rank
+                from cohorts
+                order by n desc, cohort
+                limit {n_cohorts})
+            select {interval}, coalesce(top.rank, {n_cohorts}) as rank, coalesce(top.cohort, 'Other'),
+                   count(*),
+                   count(case when has_body then 1 end),
+                   count(has_body),
+                   coalesce(sum(length(subject)), 0),
+                   count(subject)
+            from raw_commits left join top on {column} = top.cohort
+            where show_domain = true {commit_filter}
+            group by {interval}, rank
+            order by {interval}, rank;",
+            column = column,
+            interval = interval_str,
+            n_cohorts = n_cohorts,
+            commit_filter = commit_filter)).chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut stats = Vec::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            let (ym, col) = row_bin(r, interval);
+
+            stats.push((ym, r.get(col).unwrap(), r.get::<_, String>(col + 1).unwrap(), MessageStats
+            {
+                n_commits: r.get(col + 2).unwrap(),
+                n_with_body: r.get(col + 3).unwrap(),
+                n_body_known: r.get(col + 4).unwrap(),
+                subject_length_sum: r.get(col + 5).unwrap(),
+                n_subject_known: r.get(col + 6).unwrap()
+            }));
+        }
+
+        Ok(stats)
+    }
+
+    pub fn get_commit_sizes(&mut self, cohort: CohortType, interval: IntervalType, n_cohorts: usize,
+                            exclude_repos: &[String]) -> Result<Vec<(TimeBin, i32, String, i64)>>
+    {
+        // The size (lines inserted plus deleted) of every commit, with its
+        // interval and cohort, ranked as for message stats. Repos whose
+        // change counts were left out at ingest are skipped.
+
+        let column = commit_cohort_column(cohort)
+            .chain_err(|| format!("Commit sizes by the {} cohort are not supported",
+                                  cohort.to_string().to_lowercase()))?;
+        let interval_str = match interval
+        {
+            IntervalType::Day => "author_year, author_month, author_day",
+            IntervalType::Month => "author_year, author_month",
+            IntervalType::Year => "author_year"
+        };
+        let commit_filter = format!("and raw_commits.repo_name not in
+                                         (select repo_name from repos where changes_unreliable) {}",
+                                    format_repo_filter(exclude_repos));
+
+        let mut stmt = self.conn.prepare(&format!("
+            with cohorts as (
+                select {column} as cohort, count(*) as n
+                from raw_commits
+                where show_domain = true {commit_filter}
+                group by cohort),
+            top as (
+                select cohort, row_number() over (order by n desc, cohort) - 1 as rank
+                from cohorts
+                order by n desc, cohort
+                limit {n_cohorts})
+            select {interval}, coalesce(top.rank, {n_cohorts}) as rank, coalesce(top.cohort, 'Other'),
+                   n_insertions + n_deletions as size
+            from raw_commits left join top on {column} = top.cohort
+            where show_domain = true {commit_filter}
+            order by {interval}, rank, size;",
+            column = column,
+            interval = interval_str,
+            n_cohorts = n_cohorts,
+            commit_filter = commit_filter)).chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut sizes = Vec::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            let (ym, col) = row_bin(r, interval);
+
+            sizes.push((ym, r.get(col).unwrap(), r.get(col + 1).unwrap(), r.get(col + 2).unwrap()));
+        }
+
+        Ok(sizes)
+    }
+
+    pub fn get_authors(&mut self) -> Result<Vec<AuthorRecord>>
+    {
+        // Per-author summary over commits in shown domains. Lists are joined
+        // with the unit separator, which won't appear in names or addresses.
+
+        let mut stmt = self.conn.prepare("
+            with shown as (
+                select * from raw_commits where show_domain = true),
+            totals as (
+                select author_name,
+                       min(author_time) as first_time,
+                       max(author_time) as last_time,
+                       count(*) as n_commits,
+                       coalesce(sum(n_insertions + n_deletions), 0) as n_changes
+                from shown
+                group by author_name),
+            domains as (
+                select author_name, author_domain,
+                       row_number() over (partition by author_name
+                                          order by count(*) desc, author_domain) as row_number
+                from shown
+                group by author_name, author_domain),
+            emails as (
+                select author_name, group_concat(author_email, char(31)) as emails
+                from (select distinct author_name, author_email from shown order by author_email)
+                group by author_name),
+            repos as (
+                select author_name, group_concat(repo_name, char(31)) as repos
+                from (select distinct author_name, repo_name from shown order by repo_name)
+                group by author_name)
+            select totals.author_name, emails, first_time, last_time, n_commits, n_changes,
+                   author_domain, repos
+            from totals, domains, emails, repos
+            where domains.author_name = totals.author_name
+                and domains.row_number = 1
+                and emails.author_name = totals.author_name
+                and repos.author_name = totals.author_name
+            order by first_time, totals.author_name;").chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut authors = Vec::new();
+        let split = |s: String| s.split('\x1f').map(|x| x.to_string()).collect::<Vec<String>>();
+        let to_rfc3339 = |t: i64| Utc.timestamp_opt(t, 0).unwrap().to_rfc3339();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            authors.push(AuthorRecord
+            {
+                name: r.get(0).unwrap(),
+                emails: split(r.get(1).unwrap()),
+                first_commit: to_rfc3339(r.get(2).unwrap()),
+                last_commit: to_rfc3339(r.get(3).unwrap()),
+                n_commits: r.get(4).unwrap(),
+                n_changes: r.get(5).unwrap(),
+                dominant_domain: r.get(6).unwrap(),
+                repos: split(r.get(7).unwrap())
+            });
+        }
+
+        Ok(authors)
+    }
+
+    pub fn get_commit_records(&mut self, iso_week: bool, exclude_repos: &[String]) -> Result<Vec<CommitRecord>>
+    {
+        // ISO weeks are derived from the author time here rather than stored.
+        // The Thursday of a date's week is found by going back three days
+        // and then forward to the next Thursday; its year is the ISO year,
+        // and its day of the year gives the week number.
+
+        let local_time = match self.get_db_meta("bucket_tz")?.as_deref()
+        {
+            Some("utc") => "author_time",
+            _ => "author_time + coalesce(author_utc_offset, 0)"
+        };
+        let thursday = format!("date({}, 'unixepoch', '-3 days', 'weekday 4')", local_time);
+        let iso_week_selector =
+            if iso_week { format!("strftime('%Y', {thursday}) || '-W' || printf('%02d', (strftime('%j', {thursday}) - 1) / 7 + 1)",
+                                  thursday = thursday) }
+            else { "null".to_string() };
+
+        let mut stmt = self.conn.prepare(&format!("
+            select id, repo_name, author_name, author_domain, author_time, author_year, author_month,
+                   {iso_week}, coalesce(n_insertions, 0), coalesce(n_deletions, 0)
+            from raw_commits
+            where show_domain = true and author_time is not null {repo_filter}
+            order by author_time, id;",
+            iso_week = iso_week_selector,
+            repo_filter = format_repo_filter(exclude_repos))).chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut commits = Vec::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            commits.push(CommitRecord
+            {
+                id: r.get(0).unwrap(),
+                repo: r.get(1).unwrap(),
+                author_name: r.get::<_, Option<String>>(2).unwrap().unwrap_or_default(),
+                author_domain: r.get::<_, Option<String>>(3).unwrap().unwrap_or_default(),
+                author_time: Utc.timestamp_opt(r.get(4).unwrap(), 0).unwrap()
+                    .to_rfc3339(),
+                year: r.get(5).unwrap(),
+                month: r.get::<_, i32>(6).unwrap() + 1,
+                iso_week: r.get(7).unwrap(),
+                n_insertions: r.get(8).unwrap(),
+                n_deletions: r.get(9).unwrap()
+            });
+        }
+
+        Ok(commits)
+    }
+
+    pub fn get_repo_branches(&mut self, repo_name: &str) -> Result<Option<Vec<String>>>
+    {
+        let mut stmt = self.conn.prepare("
+            select branches from repos
+                where repo_name = ?1 and branches is not null;").chain_err(|| "Could not query database")?;
+        let mut rows = stmt.query(&[repo_name]).chain_err(|| "Could not query database")?;
+
+        match rows.next().chain_err(|| "Could not query database")?
+        {
+            Some(r) =>
+            {
+                let branches: String = r.get(0).chain_err(|| "Could not query database")?;
+                Ok(Some(branches.lines().map(|b| b.to_string()).collect()))
+            },
+            None => Ok(None)
+        }
+    }
+
+    pub fn set_repo_branches(&mut self, repo_name: &str, branches: &[String]) -> Result<()>
+    {
+        self.conn.execute("
+            insert into repos (repo_name, branches) values (?1, ?2)
+                on conflict (repo_name) do update set branches = excluded.branches;",
+            &[repo_name, &branches.join("\n")])
+            .chain_err(|| "Failed to record repository branches")?;
+
+        Ok(())
+    }
+
+    pub fn set_changes_unreliable(&mut self, repo_name: &str) -> Result<()>
+    {
+        // Once set, this stays set; commits ingested without change counts
+        // keep lacking them.
+
+        self.conn.execute("
+            insert into repos (repo_name, changes_unreliable) values (?1, true)
+                on conflict (repo_name) do update set changes_unreliable = true;",
+            &[repo_name])
+            .chain_err(|| "Failed to flag repository")?;
+
+        Ok(())
+    }
+
+    pub fn get_changes_unreliable_repos(&self) -> Result<Vec<String>>
+    {
+        self.query_strings("select repo_name from repos where changes_unreliable order by repo_name;")
+    }
+
+    pub fn get_last_author_time(&mut self, repo_name: &str) -> DateTime<Utc>
+    {
+        // Only repos whose last ingest ran to completion have a watermark.
+        // Anything else is read from the start.
+
+        let t: i64 = self.conn.query_row(
+            "select last_author_time from ingest_watermarks where repo_name = ?1;",
+            &[repo_name], |r| r.get::<_, Option<i64>>(0))
+            .ok().flatten().unwrap_or(0);
+
+        Utc.timestamp_opt(t, 0).unwrap()
+    }
+
+    pub fn begin_repo_ingest(&mut self, _repo_name: &str) -> Result<()>
+    {
+        // A repo's commits are ingested in a single transaction, so an
+        // interrupted ingest leaves nothing behind.
+
+        self.conn.execute_batch("begin immediate;").chain_err(|| "Failed to begin transaction")
+    }
+
+    pub fn finish_repo_ingest(&mut self, repo_name: &str) -> Result<()>
+    {
+        self.conn.execute("
+            insert or replace into ingest_watermarks (repo_name, last_author_time, completed_at)
+                select ?1, max(author_time), ?2
+                from raw_commits, commit_repos
+                where commit_repos.commit_id = raw_commits.id
+                    and commit_repos.repo_name = ?1;",
+            &[repo_name, &Utc::now().to_rfc3339()])
+            .chain_err(|| "Failed to update ingest watermark")?;
+
+        self.conn.execute_batch("commit;").chain_err(|| "Failed to commit transaction")
+    }
+
+    fn get_firstyear_hist(&mut self, interval: IntervalType, count_sel: &str, share: bool,
+                          commit_filter: &str) -> Result<CohortHist>
+    {
+        let interval_str = match interval
+        {
+            IntervalType::Day => "author_year, author_month, author_day",
+            IntervalType::Month => "author_year, author_month",
+            _ => "author_year"
+        };
+        let counts = format!("
+            select {}, first_year, {} as n
+            from raw_commits, authors
+            where raw_commits.author_name=authors.author_name
+                and active_time > (60*60*24*90)
+                {}
+            group by {}, first_year",
+           interval_str,
+           count_sel,
+           commit_filter,
+           interval_str)
+
+            + &omit_if(self.hides_brief() && !share, format!("
+            union select {}, {}, {}
+            from raw_commits, authors
+            where raw_commits.author_name=authors.author_name
+                and active_time <= (60*60*24*90)
+                {}
+            group by {}",
+           interval_str,
+           NO_COHORT,
+           count_sel,
+           commit_filter,
+           interval_str));
+
+        // When showing shares, each generation's count is divided by the
+        // interval's total. Brief authors are part of that total even when
+        // they're hidden, so the shares of the rest don't get inflated.
+
+        let query = if share
+        {
+            format!("
+                select * from (
+                    select {interval}, first_year, 100.0 * n / sum(n) over (partition by {interval})
+                    from ({counts}))
+                {brief_filter};",
+                interval = interval_str,
+                counts = counts,
+                brief_filter = if self.hides_brief() { format!("where first_year <> {}", NO_COHORT) }
+                               else { String::new() })
+        }
+        else
+        {
+            counts + ";"
+        };
+        let mut stmt = self.conn.prepare(&query).unwrap();
+ 
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut hist = CohortHist::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            let (ym, col) = row_bin(r, interval);
+            hist.set_value(ym, r.get(col).unwrap(), r.get(col + 1).unwrap());
+            hist.set_cohort_name(r.get(col).unwrap(), &r.get::<_, i32>(col).unwrap().to_string());
+        }
+
+        if !self.hides_brief()
+        {
+            hist.set_cohort_name(NO_COHORT, "Brief");
+        }
+
+        Ok(hist)
+    }
+
+    fn get_firstyear_domain_hist(&mut self, interval: IntervalType, count_sel: &str,
+                                 commit_filter: &str) -> Result<CohortHist>
+    {
+        // Each first-year generation split into its own top domains and
+        // "Other". Cohorts are numbered by generation, then by the domain's
+        // rank within it, and grouped by generation so plots can shade them
+        // alike. Hidden domains count as "Other".
+
+        const N_ITEMS: i32 = 3;
+        let (interval_str, n_interval_cols) = match interval
+        {
+            IntervalType::Day => ("author_year, author_month, author_day", 3),
+            IntervalType::Month => ("author_year, author_month", 2),
+            _ => ("author_year", 1)
+        };
+        let mut stmt = self.conn.prepare(&(format!("
+            with counts as (
+                select {interval}, first_year,
+                       case when raw_commits.show_domain then raw_commits.author_domain end as cohort_domain,
+                       {count_sel} as n
+                from raw_commits, authors
+                where raw_commits.author_name = authors.author_name
+                    and active_time > (60*60*24*90)
+                    {commit_filter}
+                group by {interval}, first_year, cohort_domain),
+            ranked as (
+                select first_year, cohort_domain,
+                       row_number() over (partition by first_year
+                                          order by sum(n) desc, cohort_domain) as rank
+                from counts
+                where cohort_domain is not null
+                group by first_year, cohort_domain)
+            select {interval}, counts.first_year,
+                   coalesce(case when ranked.rank <= {n_items} then ranked.rank end, {n_items} + 1) as sub,
+                   coalesce(case when ranked.rank <= {n_items} then counts.cohort_domain end, 'Other') as name,
+                   sum(n)
+            from counts left join ranked
+                on ranked.first_year = counts.first_year and ranked.cohort_domain = counts.cohort_domain
+            group by {interval}, counts.first_year, sub, name",
+            interval = interval_str,
+            count_sel = count_sel,
+            commit_filter = commit_filter,
+            n_items = N_ITEMS)
+
+            + &omit_if(self.hides_brief(), format!("
+            union select {interval}, {no_cohort}, {no_cohort}, 'Brief', {count_sel}
+            from raw_commits, authors
+            where raw_commits.author_name = authors.author_name
+                and active_time <= (60*60*24*90)
+                {commit_filter}
+            group by {interval}",
+            interval = interval_str,
+            no_cohort = NO_COHORT,
+            count_sel = count_sel,
+            commit_filter = commit_filter))
+
+            + ";")).chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut values: Vec<(TimeBin, i32, i32, String, f64)> = Vec::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            let (ym, _) = row_bin(r, interval);
+            let sub: i32 = r.get(n_interval_cols + 1).unwrap();
+            if sub > N_ITEMS && self.hides_other() { continue; }
+
+            values.push((ym, r.get(n_interval_cols).unwrap(), sub,
+                         r.get(n_interval_cols + 2).unwrap(), r.get(n_interval_cols + 3).unwrap()));
+        }
+
+        let cohorts: Vec<(i32, i32)> = values.iter()
+            .filter(|(_, first_year, _, _, _)| *first_year != NO_COHORT)
+            .map(|(_, first_year, sub, _, _)| (*first_year, *sub))
+            .collect::<BTreeSet<(i32, i32)>>()
+            .into_iter()
+            .collect();
+        let mut hist = CohortHist::new();
+
+        for (ym, first_year, sub, name, value) in values
+        {
+            if first_year == NO_COHORT
+            {
+                hist.set_value(ym, NO_COHORT, value);
+                hist.set_cohort_name(NO_COHORT, &name);
+                continue;
+            }
+
+            let cohort = cohorts.binary_search(&(first_year, sub)).unwrap() as i32;
+            hist.set_value(ym, cohort, value);
+            hist.set_cohort_name(cohort, &format!("{} {}", first_year, name));
+            hist.set_cohort_group(cohort, first_year);
+        }
+
+        Ok(hist)
+    }
+
+    fn get_column_hist(&mut self, table: &str, column: &str, interval: IntervalType, count_sel: &str,
+                       commit_filter: &str) -> Result<CohortHist>
+    {
+        const N_ITEMS: i32 = 15;
+        let interval_str = match interval
+        {
+            IntervalType::Day => "author_year, author_month, author_day",
+            IntervalType::Month => "author_year, author_month",
+            _ => "author_year"
+        };
+        self.conn.execute (&format!("drop table {column}_top;", column = column), NO_PARAMS).ok();
+        self.conn.execute (&self.format_create_top(column, &format!("{}.{}", table, column), "author_year", count_sel,
+                                                   &format!("
+                from raw_commits, authors
+                where raw_commits.author_name = authors.author_name
+                    and raw_commits.show_domain = true
+                    and active_time > (60*60*24*90)
+                    {}", commit_filter), N_ITEMS),
+            NO_PARAMS).chain_err(|| format!("Could not generate {}_top", column))?;
+        let mut stmt = self.conn.prepare(&(format!("
+            select {interval}, {last_item}-{column}_top.rowid, {count_selector}, {column}_top.{column}
+            from {column}_top, raw_commits, authors
+            where {table}.{column} = {column}_top.{column}
+                and raw_commits.author_name = authors.author_name
+                and raw_commits.show_domain = true
+                and active_time > (60*60*24*90)
+                {commit_filter}
+            group by {interval}, {column}_top.rowid",
+            table = table,
+            column = column,
+            interval = interval_str,
+            count_selector = count_sel,
+            commit_filter = commit_filter,
+            last_item = N_ITEMS + 1)
+
+            + &omit_if(self.hides_other(), format!("
+
+            union
+
+            select {interval},{item_num},{count_selector},\"Other\"
+            from raw_commits, authors
+            where raw_commits.author_name = authors.author_name
+                and raw_commits.show_domain = true
+                and {column} not in (select {column} from {column}_top)
+                and active_time > (60*60*24*90)
+                {commit_filter}
+            group by {interval}",
+            column = column,
+            interval = interval_str,
+            count_selector = count_sel,
+            commit_filter = commit_filter,
+            item_num = N_ITEMS + 1))
+
+            + &omit_if(self.hides_brief(), format!("
+
+            union
+
+            select {interval},{item_num},{count_selector},\"Brief\"
+            from raw_commits, authors
+            where raw_commits.author_name = authors.author_name
+                and raw_commits.show_domain = true
+                and active_time <= (60*60*24*90)
+                {commit_filter}
+            group by {interval}",
+            interval = interval_str,
+            count_selector = count_sel,
+            commit_filter = commit_filter,
+            item_num = NO_COHORT))
+
+            + ";")).unwrap();
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut hist = CohortHist::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            let (ym, col) = row_bin(r, interval);
+            hist.set_value(ym, r.get(col).unwrap(), r.get(col + 1).unwrap());
+            hist.set_cohort_name(r.get(col).unwrap(), &r.get::<_, String>(col + 2).unwrap());
+        }
+
+        Ok(hist)
+    }
+
+    fn format_column_aggregates_from_where(&self, extra_table: Option<&str>, commit_filter: &str) -> String
+    {
+        if let Some(table) = extra_table {
+            format!("from raw_commits, {table} where show_domain = true
+                     and raw_commits.id = {table}.commit_id {filter} {subcommit_filter}",
+                    table=table, filter=commit_filter,
+                    subcommit_filter=self.subcommit_filter(table))
+        } else {
+            format!("from raw_commits where show_domain = true {}", commit_filter)
+        }
+    }
+
+    fn create_active_authors(&mut self, interval: IntervalType, min_activity: u32,
+                             commit_filter: &str) -> Result<()>
+    {
+        // Authors who made at least min_activity commits in a given interval.
+        // Each row is keyed on author and interval, so it can be matched
+        // against raw_commits with active_authors_key(). The table is kept
+        // until the buckets or the data change, so a batch of histograms
+        // with the same parameters only builds it once. Anything that
+        // changes raw_commits must reset active_authors_built.
+
+        let built = format!("{}|{}|{}", interval, min_activity, commit_filter);
+        if self.active_authors_built.as_ref() == Some(&built)
+        {
+            return Ok(());
+        }
+
+        self.conn.execute ("drop table active_authors;", NO_PARAMS).ok();
+        self.conn.execute_batch (&format!("
+            create table active_authors as
+                select {key} as key
+                from raw_commits
+                where show_domain = true
+                    {commit_filter}
+                group by {key}
+                having count(*) >= {min_activity};
+            create index if not exists index_active_authors_key on active_authors (key);
+        ",
+            key = active_authors_key(interval),
+            commit_filter = commit_filter,
+            min_activity = min_activity))
+        .chain_err(|| "Could not create active author table")?;
+
+        self.active_authors_built = Some(built);
+        Ok(())
+    }
+
+    fn create_subcommit_aggregates(&mut self, column: &str, interval: IntervalType, extra_table: &str,
+                                   commit_filter: &str, subtotal_sel: &str, total_sel: &str) -> Result<()>
+    {
+        let (interval_name, bins) = interval_bins(interval);
+        let author_bins = bins.iter().map(|b| format!("author_{}", b)).collect::<Vec<String>>().join(", ");
+        let join_on = |other: &str| bins.iter()
+            .map(|b| format!("{o}.author_{b} = b.author_{b}", o = other, b = b))
+            .collect::<Vec<String>>().join(" and ");
+
+        self.conn.execute (&format!("drop table {}_{}_aggregates;", column, interval_name), NO_PARAMS).ok();
+        self.conn.execute_batch (&format!("
+            create table {column}_{interval}_aggregates as
+                select {bin_sel},
+                       b.{column} as {column},
+                       sum(cast({column}_count as float)/sub_count) * commit_count as column_sum
+                from
+                (
+                    select {author_bins},
+                           {subtotal_sel} as sub_count
+                    from {table}, raw_commits
+                    where raw_commits.id = {table}.commit_id
+                        {commit_filter}
+                    group by {author_bins}
+                ) as a,
+                (
+                    select {author_bins},
+                           {column},
+                           {subtotal_sel} as {column}_count
+                    from raw_commits, authors, {table}
+                    where show_domain = true
+                        and raw_commits.id = {table}.commit_id
+                        and raw_commits.author_name = authors.author_name
+                        and authors.active_time > (60*60*24*90)
+                        {commit_filter}
+                        {subcommit_filter}
+                    group by {author_bins},
+                             {column}
+                ) as b,
+                (
+                    select {author_bins},
+                           {total_sel} as commit_count
+                    from raw_commits
+                    where true {commit_filter}
+                    group by {author_bins}
+                ) as c
+                where {join_a}
+                    and {join_c}
+                group by {b_bins},
+                         b.{column};
+
+            {indexes}
+        ",
+            column=column,
+            interval=interval_name,
+            bin_sel=bins.iter().map(|b| format!("b.author_{b} as {b}", b = b)).collect::<Vec<String>>().join(", "),
+            author_bins=author_bins,
+            table=extra_table,
+            commit_filter=commit_filter,
+            subcommit_filter=self.subcommit_filter(extra_table),
+            subtotal_sel=subtotal_sel,
+            total_sel=total_sel,
+            join_a=join_on("a"),
+            join_c=join_on("c"),
+            b_bins=bins.iter().map(|b| format!("b.author_{}", b)).collect::<Vec<String>>().join(", "),
+            indexes=format_aggregate_indexes(column, interval)))
+        .chain_err(|| format!("Could not create {} per-{} aggregates", column, interval_name))?;
+
+        Ok(())
+    }
+
+    fn create_column_aggregates(&mut self, column: &str, interval: IntervalType, extra_table: Option<&str>,
+                                commit_filter: &str) -> Result<()>
+    {
+        let from_where = self.format_column_aggregates_from_where (extra_table, commit_filter);
+        let (interval_name, bins) = interval_bins(interval);
+        let author_bins = bins.iter().map(|b| format!("author_{}", b)).collect::<Vec<String>>().join(", ");
+
+        self.conn.execute (&format!("drop table {}_{}_aggregates;", column, interval_name), NO_PARAMS).ok();
+        self.conn.execute_batch (&format!("
+            create table {column}_{interval}_aggregates as
+                select {bin_sel},
+                       b.{column} as {column},
+                       sum(cast(author_{column}_count as float)/author_count) as active_author_sum
+                from authors,
+                (
+                    select {author_bins},
+                           author_name,
+                           sum(n) as author_count
+                    from
+                    (
+                        select {author_bins}, author_name, count(*) as n
+                        {from_where}
+                        group by {author_bins}, author_name, {column}
+                        having count(*) >= {min_commits}
+                    )
+                    group by {author_bins},
+                             author_name
+                ) as a,
+                (
+                    select {author_bins},
+                           {column},
+                           author_name,
+                           count(*) as author_{column}_count
+                    {from_where}
+                    group by {author_bins},
+                             author_name,
+                             {column}
+                    having count(*) >= {min_commits}
+                ) as b
+                where {join_a}
+                    and a.author_name = b.author_name
+                    and authors.author_name = b.author_name
+                    and authors.active_time > (60*60*24*90)
+                group by {b_bins},
+                         b.{column};
+
+            {indexes}
+        ",
+            column=column,
+            interval=interval_name,
+            bin_sel=bins.iter().map(|b| format!("b.author_{b} as {b}", b = b)).collect::<Vec<String>>().join(", "),
+            author_bins=author_bins,
+            from_where=from_where,
+            min_commits=self.min_cohort_commits,
+            join_a=bins.iter().map(|b| format!("a.author_{b} = b.author_{b}", b = b))
+                .collect::<Vec<String>>().join(" and "),
+            b_bins=bins.iter().map(|b| format!("b.author_{}", b)).collect::<Vec<String>>().join(", "),
+            indexes=format_aggregate_indexes(column, interval)))
+        .chain_err(|| format!("Could not create {} per-{} aggregates", column, interval_name))?;
+
+        Ok(())
+    }
+
+    fn get_column_authors_hist(&mut self, column: &str, interval: IntervalType,
+                               commit_filter: &str) -> Result<CohortHist>
+    {
+        const N_ITEMS: i32 = 15;
+        let (interval_name, bins) = interval_bins(interval);
+        let interval_str = bins.join(", ");
+        let author_interval_str = bins.iter().map(|b| format!("author_{}", b)).collect::<Vec<String>>().join(", ");
+        let aggregate_table = format!("{}_{}_aggregates", column, interval_name);
+        let extra_table = match column
+        {
+            "prefix" => Some("prefixes"),
+            "suffix" => Some("suffixes"),
+            _ => None
+        };
+
+        self.create_column_aggregates(column, interval, extra_table, commit_filter)?;
+
+        self.conn.execute (&format!("drop table {column}_top;", column = column), NO_PARAMS).ok();
+        self.conn.execute (&self.format_create_top(column, column, "year", "sum(active_author_sum)",
+                                                   &format!("from {}", aggregate_table), N_ITEMS),
+            NO_PARAMS).chain_err(|| "Could not generate top domains")?;
+        let mut stmt = self.conn.prepare(&(format!("
+            select {interval}, {n_items}-{column}_top.rowid as ab, sum(active_author_sum) as ac, {column}_top.{column} as ad
+            from {column}_top, {aggregate_table}
+
+            where {aggregate_table}.{column} = {column}_top.{column}
+            group by {interval}, {column}_top.rowid",
+            interval = interval_str,
+            n_items = N_ITEMS + 1,
+            aggregate_table = aggregate_table,
+            column = column)
+
+            + &omit_if(self.hides_other(), format!("
+
+            union
+
+            select {interval},{n_items},sum(active_author_sum),\"Other\"
+            from {aggregate_table}
+            where {column} not in (select {column} from {column}_top)
+            group by {interval}",
+            interval = interval_str,
+            n_items = N_ITEMS + 1,
+            aggregate_table = aggregate_table,
+            column = column))
+
+            + &omit_if(self.hides_brief(), format!("
+
+            union
+
+            select {interval},{cohort_num},count(distinct raw_commits.author_name),\"Brief\"
+            from raw_commits, authors
+            where raw_commits.author_name=authors.author_name
+                and show_domain = true
+                and active_time <= (60*60*24*90)
+                {commit_filter}
+            group by {interval}",
+            interval = author_interval_str,
+            commit_filter = commit_filter,
+            cohort_num = NO_COHORT))
+
+            + ";")).unwrap();
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut hist = CohortHist::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            let (ym, col) = row_bin(r, interval);
+            hist.set_value(ym, r.get(col).unwrap(), r.get::<_,f64>(col + 1).unwrap());
+            hist.set_cohort_name(r.get(col).unwrap(), &r.get::<_, String>(col + 2).unwrap());
+        }
+
+        if !self.hides_brief()
+        {
+            hist.set_cohort_name(NO_COHORT, "Brief");
+        }
+
+        Ok(hist)
+    }
+
+    fn get_subcommit_hist(&mut self, column: &str, interval: IntervalType,
+                          subtotal_sel: &str, total_sel: &str, commit_filter: &str) -> Result<CohortHist>
+    {
+        const N_ITEMS: i32 = 15;
+        let (interval_name, bins) = interval_bins(interval);
+        let interval_str = bins.join(", ");
+        let author_interval_str = bins.iter().map(|b| format!("author_{}", b)).collect::<Vec<String>>().join(", ");
+        let aggregate_table = format!("{}_{}_aggregates", column, interval_name);
+
+        match column
+        {
+            "prefix" => self.create_subcommit_aggregates(column, interval, "prefixes", commit_filter,
+                                                         subtotal_sel, total_sel)?,
+            "suffix" => self.create_subcommit_aggregates(column, interval, "suffixes", commit_filter,
+                                                         subtotal_sel, total_sel)?,
+            _ => ()
+        }
+
+        self.conn.execute (&format!("drop table {column}_top;", column = column), NO_PARAMS).ok();
+        self.conn.execute (&self.format_create_top(column, column, "year", "sum(column_sum)",
+                                                   &format!("from {}", aggregate_table), N_ITEMS),
+            NO_PARAMS).chain_err(|| "Could not generate top domains")?;
+        let mut stmt = self.conn.prepare(&(format!("
+            select {interval}, {n_items}-{column}_top.rowid as ab, sum(column_sum) as ac, {column}_top.{column} as ad
+            from {column}_top, {aggregate_table}
+
+            where {aggregate_table}.{column} = {column}_top.{column}
+            group by {interval}, {column}_top.rowid",
+            interval = interval_str,
+            n_items = N_ITEMS + 1,
+            aggregate_table = aggregate_table,
+            column = column)
+
+            + &omit_if(self.hides_other(), format!("
+
+            union
+
+            select {interval},{n_items},sum(column_sum),\"Other\"
+            from {aggregate_table}
+            where {column} not in (select {column} from {column}_top)
+            group by {interval}",
+            interval = interval_str,
+            n_items = N_ITEMS + 1,
+            aggregate_table = aggregate_table,
+            column = column))
+
+            + &omit_if(self.hides_brief(), format!("
+
+            union
+
+            select {interval},{cohort_num},{count_selector},\"Brief\"
+            from raw_commits, authors
+            where raw_commits.author_name=authors.author_name
+                and show_domain = true
+                and active_time <= (60*60*24*90)
+                {commit_filter}
+            group by {interval}",
+            interval = author_interval_str,
+            count_selector = total_sel,
+            commit_filter = commit_filter,
+            cohort_num = NO_COHORT))
+
+            + ";")).unwrap();
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut hist = CohortHist::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            let (ym, col) = row_bin(r, interval);
+            hist.set_value(ym, r.get(col).unwrap(), r.get::<_,f64>(col + 1).unwrap());
+            hist.set_cohort_name(r.get(col).unwrap(), &r.get::<_, String>(col + 2).unwrap());
+        }
+
+        if !self.hides_brief()
+        {
+            hist.set_cohort_name(NO_COHORT, "Brief");
+        }
+
+        Ok(hist)
+    }
+
+    pub fn get_cumulative_hist(&mut self, cohort: CohortType, interval: IntervalType,
+                               exclude_repos: &[String]) -> Result<CohortHist>
+    {
+        // Counts the distinct authors seen in each cohort up to each interval.
+        // We first find the interval in which each author first appeared in
+        // each cohort, count those, then take running totals.
+
+        const N_ITEMS: i32 = 15;
+        let interval_sel = match interval
+        {
+            IntervalType::Day => "cast(julianday(printf('%04d-%02d-%02d', author_year, author_month + 1, author_day + 1))
+                                       - julianday('1970-01-01') as int)",
+            IntervalType::Month => "author_year * 12 + author_month",
+            IntervalType::Year => "author_year"
+        };
+        let commit_filter = format_repo_filter(exclude_repos);
+
+        let sql = match cohort
+        {
+            CohortType::FirstYear =>
+            {
+                format!("
+                    select t, first_year, count(*), first_year
+                    from
+                    (
+                        select raw_commits.author_name, min({interval_sel}) as t, first_year
+                        from raw_commits, authors
+                        where raw_commits.author_name = authors.author_name
+                            and show_domain = true
+                            {commit_filter}
+                        group by raw_commits.author_name
+                    )
+                    group by t, first_year;",
+                    interval_sel = interval_sel,
+                    commit_filter = commit_filter)
+            },
+            _ =>
+            {
+                let (column, from_where) = match cohort
+                {
+                    CohortType::FirstSuffix => ("(select first_suffix from authors
+                                                   where authors.author_name = raw_commits.author_name)",
+                                                  "from raw_commits where show_domain = true"),
+                    CohortType::Cadence => ("(select cadence from authors
+                                               where authors.author_name = raw_commits.author_name)",
+                                              "from raw_commits where show_domain = true"),
+                    CohortType::Domain => ("author_domain", "from raw_commits where show_domain = true"),
+                    CohortType::Country => ("author_country", "from raw_commits where show_domain = true"),
+                    CohortType::Epoch => ("author_epoch", "from raw_commits where show_domain = true"),
+                    CohortType::CommitType => ("commit_type", "from raw_commits where show_domain = true"),
+                    CohortType::Repo => ("repo_name", "from raw_commits where show_domain = true"),
+                    CohortType::Prefix => ("prefix", "from raw_commits, prefixes where show_domain = true
+                                                      and raw_commits.id = prefixes.commit_id"),
+                    CohortType::Suffix => ("suffix", "from raw_commits, suffixes where show_domain = true
+                                                      and raw_commits.id = suffixes.commit_id"),
+                    _ => bail!("Cumulative charts don't support the {} cohort", cohort.to_string().to_lowercase())
+                };
+                let commit_filter = match cohort
+                {
+                    CohortType::Suffix => commit_filter.clone() + &self.suffix_filter,
+                    _ => commit_filter.clone()
+                };
+
+                format!("
+                    with firsts as (
+                        select author_name, {column} as item, min({interval_sel}) as t
+                        {from_where}
+                            {commit_filter}
+                        group by author_name, item),
+                    top as (
+                        select item, row_number() over (order by count(*) desc) as rowid
+                        from firsts
+                        group by item
+                        order by count(*) desc
+                        limit {n_items}),
+                    others as (
+                        select author_name, min(t) as t
+                        from firsts
+                        where item not in (select item from top)
+                        group by author_name)
+                    select t, {last_item} - top.rowid, count(*), top.item
+                    from firsts, top
+                    where firsts.item = top.item
+                    group by t, top.rowid
+                    {other_union};",
+                    column = column,
+                    interval_sel = interval_sel,
+                    from_where = from_where,
+                    commit_filter = commit_filter,
+                    n_items = N_ITEMS,
+                    last_item = N_ITEMS + 1,
+                    other_union = omit_if(self.hides_other(), format!("
+                    union all
+                    select t, {last_item}, count(*), 'Other'
+                    from others
+                    group by t", last_item = N_ITEMS + 1)))
+            }
+        };
+
+        let mut stmt = self.conn.prepare(&sql).chain_err(|| "Could not query database")?;
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut hist = CohortHist::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            let t: i32 = r.get(0).unwrap();
+            let ym = match interval
+            {
+                IntervalType::Day => TimeBin::Day(NaiveDate::from_ymd_opt(1970, 1, 1).unwrap() + Duration::days(t as i64)),
+                IntervalType::Month => TimeBin::from_month_index(t),
+                IntervalType::Year => TimeBin::Year(t)
+            };
+            let name: rusqlite::types::Value = r.get(3).unwrap();
+            let name = match name
+            {
+                rusqlite::types::Value::Text(text) => text,
+                rusqlite::types::Value::Integer(i) => i.to_string(),
+                _ => "".to_string()
+            };
+
+            hist.set_value(ym, r.get(1).unwrap(), r.get::<_, i64>(2).unwrap() as f64);
+            hist.set_cohort_name(r.get(1).unwrap(), &name);
+        }
+
+        // Totals carry on past the last time a new author was seen.
+
+        let last_t: Option<i32> = self.conn.query_row(
+            &format!("select max({}) from raw_commits where show_domain = true {};", interval_sel, commit_filter),
+            NO_PARAMS, |r| r.get(0)).chain_err(|| "Could not query database")?;
+
+        if let Some(t) = last_t
+        {
+            hist.pad_to(match interval
+            {
+                IntervalType::Day => TimeBin::Day(NaiveDate::from_ymd_opt(1970, 1, 1).unwrap() + Duration::days(t as i64)),
+                IntervalType::Month => TimeBin::from_month_index(t),
+                IntervalType::Year => TimeBin::Year(t)
+            });
+        }
+
+        hist.accumulate();
+
+        Ok(hist)
+    }
+
+    pub fn get_suffix_growth_hists(&mut self, interval: IntervalType, n_suffixes: i32,
+                                   exclude_repos: &[String]) -> Result<(CohortHist, CohortHist)>
+    {
+        // Net growth (insertions minus deletions) and churn (insertions plus
+        // deletions) for the suffixes with the most changes. Commits ingested
+        // before the split was recorded are left out.
+
+        let interval_str = match interval
+        {
+            IntervalType::Day => "author_year, author_month, author_day",
+            IntervalType::Month => "author_year, author_month",
+            IntervalType::Year => "author_year"
+        };
+        let commit_filter = format!("and show_domain = true
+                                     and raw_commits.id = suffixes.commit_id
+                                     and suffixes.n_insertions is not null {}",
+                                    format_repo_filter(exclude_repos));
+        let mut stmt = self.conn.prepare(&format!("
+            with top_suffixes as (
+                select suffix, row_number() over (order by sum(n_changes) desc, suffix) - 1 as rank
+                from raw_commits, suffixes
+                where true {commit_filter}
+                group by suffix
+                order by sum(n_changes) desc, suffix
+                limit {n_suffixes})
+            select {interval}, top_suffixes.rank, top_suffixes.suffix,
+                   sum(suffixes.n_insertions - suffixes.n_deletions),
+                   sum(suffixes.n_insertions + suffixes.n_deletions)
+            from raw_commits, suffixes, top_suffixes
+            where suffixes.suffix = top_suffixes.suffix {commit_filter}
+            group by {interval}, top_suffixes.rank;",
+            interval = interval_str,
+            n_suffixes = n_suffixes,
+            commit_filter = commit_filter)).chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut net_hist = CohortHist::new();
+        let mut churn_hist = CohortHist::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            let (ym, col) = row_bin(r, interval);
+            let cohort: i32 = r.get(col).unwrap();
+            let suffix: String = r.get(col + 1).unwrap();
+
+            for (hist, value_col) in [(&mut net_hist, col + 2), (&mut churn_hist, col + 3)]
+            {
+                hist.set_value(ym, cohort, r.get::<_, f64>(value_col).unwrap());
+                hist.set_cohort_name(cohort, &suffix);
+            }
+        }
+
+        if net_hist.get_bounds().is_none()
+        {
+            bail!("No per-suffix insertions and deletions; re-ingest into a new database to use this chart");
+        }
+
+        let sample_rate = self.get_sample_rate()? as f64;
+        net_hist.scale(sample_rate);
+        churn_hist.scale(sample_rate);
+        net_hist.set_decimals(Some(0));
+        churn_hist.set_decimals(Some(0));
+        Ok((net_hist, churn_hist))
+    }
+
+    pub fn get_author_commit_counts(&mut self, first_year: Option<i32>, last_year: Option<i32>,
+                                    exclude_repos: &[String]) -> Result<Vec<i64>>
+    {
+        let mut stmt = self.conn.prepare(&format!("
+            select count(*) from raw_commits
+            where show_domain = true
+                and author_year >= {}
+                and author_year <= {}
+                {}
+            group by author_name;",
+            first_year.unwrap_or(i32::MIN),
+            last_year.unwrap_or(i32::MAX),
+            format_repo_filter(exclude_repos))).chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut counts = Vec::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            counts.push(r.get(0).unwrap());
+        }
+
+        Ok(counts)
+    }
+
+    pub fn import_author_events(&mut self, events: &[AuthorEvent]) -> Result<usize>
+    {
+        // Events replace any previously imported ones of the same kinds, so
+        // an updated file can be imported again.
+
+        let mut kinds: Vec<&str> = events.iter().map(|e| e.kind.as_str()).collect();
+        kinds.sort_unstable();
+        kinds.dedup();
+
+        self.conn.execute_batch("begin immediate;").chain_err(|| "Failed to begin transaction")?;
+
+        for kind in kinds
+        {
+            self.conn.execute("delete from author_events where kind = ?1;", &[kind])
+                .chain_err(|| "Failed to replace author events")?;
+        }
+
+        for event in events
+        {
+            self.conn.execute("insert into author_events (author_email, kind, event_time) values (?1, ?2, ?3);",
+                              &[&event.email, &event.kind, &event.time.to_string()])
+                .chain_err(|| "Failed to insert author event")?;
+        }
+
+        self.conn.execute_batch("commit;").chain_err(|| "Failed to commit transaction")?;
+        Ok(events.len())
+    }
+
+    pub fn get_onboarding(&mut self, kind: &str) -> Result<Vec<AuthorOnboarding>>
+    {
+        // Each e-mail address's earliest event of the given kind, with the
+        // author's name and the time of their first commit, if any.
+
+        let mut stmt = self.conn.prepare("
+            with events as (
+                select author_email, min(event_time) as event_time
+                from author_events
+                where kind = ?1
+                group by author_email),
+            firsts as (
+                select lower(trim(author_email)) as email, author_name, min(author_time) as first_time
+                from raw_commits
+                group by lower(trim(author_email)))
+            select events.author_email, firsts.author_name, events.event_time, firsts.first_time
+            from events left join firsts on firsts.email = events.author_email
+            order by events.event_time, events.author_email;")
+            .chain_err(|| "Could not query database")?;
+        let mut rows = stmt.query(&[kind]).chain_err(|| "Could not query database")?;
+        let mut authors = Vec::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            authors.push((r.get(0).unwrap(), r.get(1).unwrap(), r.get(2).unwrap(), r.get(3).unwrap()));
+        }
+
+        if authors.is_empty()
+        {
+            bail!("No author events of kind '{}' have been imported", kind);
+        }
+
+        Ok(authors)
+    }
+
+    fn apply_identities(&mut self, identities: &Identities) -> Result<()>
+    {
+        // Addresses decided to be the same person take the most common name
+        // among them. Those decided to be different people each get their
+        // address appended to the name, unless it was on a previous run.
+
+        for group in &identities.merge
+        {
+            let emails = group.iter().map(|email| sql_quote(email)).collect::<Vec<_>>().join(", ");
+            let name = self.query_strings(&format!("
+                select author_name from raw_commits
+                where author_email in ({emails})
+                group by author_name
+                order by count(*) desc, author_name
+                limit 1;",
+                emails = emails))?;
+
+            if let Some(name) = name.first()
+            {
+                self.conn.execute(&format!("update raw_commits set author_name = ?1 where author_email in ({});",
+                                           emails),
+                                  &[name]).chain_err(|| "Error merging identities")?;
+            }
+        }
+
+        for email in identities.split.iter().flatten()
+        {
+            self.conn.execute("
+                update raw_commits
+                set author_name = author_name || ?2
+                where author_email = ?1 and substr(author_name, -length(?2)) <> ?2;",
+                &[email, &format!(" ({})", email)]).chain_err(|| "Error splitting identities")?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_author_identities(&mut self) -> Result<Vec<(String, String, i64)>>
+    {
+        // Every name and address pair with its number of commits.
+
+        let mut stmt = self.conn.prepare("
+            select author_name, author_email, count(*) from raw_commits
+            where author_name is not null and author_email is not null
+            group by author_name, author_email;")
+            .chain_err(|| "Could not query database")?;
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut identities = Vec::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            identities.push((r.get(0).unwrap(), r.get(1).unwrap(), r.get(2).unwrap()));
+        }
+
+        Ok(identities)
+    }
+
+    pub fn get_author_tenures(&mut self) -> Result<Vec<(i32, i64, i64)>>
+    {
+        // First year, commit count and active time (seconds between first
+        // and last commit) of every author.
+
+        let mut stmt = self.conn.prepare("select first_year, n_commits, active_time from authors;")
+            .chain_err(|| "Could not query database")?;
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut tenures = Vec::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            tenures.push((r.get(0).unwrap(), r.get(1).unwrap(), r.get(2).unwrap()));
+        }
+
+        Ok(tenures)
+    }
+
+    pub fn get_second_commit_gaps(&mut self) -> Result<Vec<(i32, Option<i64>)>>
+    {
+        // First year of every author, and the seconds from their first commit
+        // to their second, if they made one.
+
+        let mut stmt = self.conn.prepare("
+            select authors.first_year, second_commits.author_time - authors.first_time
+            from authors left join
+            (
+                select author_name, author_time
+                from
+                (
+                    select author_name, author_time,
+                           row_number() over (partition by author_name order by author_time, id) as n
+                    from raw_commits
+                )
+                where n = 2
+            ) as second_commits
+            on second_commits.author_name = authors.author_name;")
+            .chain_err(|| "Could not query database")?;
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut gaps = Vec::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            gaps.push((r.get(0).unwrap(), r.get(1).unwrap()));
+        }
+
+        Ok(gaps)
+    }
+
+    pub fn get_top_author_hist(&mut self, unit: UnitType, interval: IntervalType, n_authors: u32,
+                               first_year: Option<i32>, last_year: Option<i32>,
+                               exclude_repos: &[String]) -> Result<CohortHist>
+    {
+        // Activity of the most active authors in the given years, one cohort
+        // per author, ordered by when they first appeared.
+
+        let interval_str = match interval
+        {
+            IntervalType::Day => "author_year, author_month, author_day",
+            IntervalType::Month => "author_year, author_month",
+            IntervalType::Year => "author_year"
+        };
+        let count_sel = match unit
+        {
+            UnitType::Changes => self.changes_selector(),
+            _ => "count(*)"
+        };
+        let commit_filter = format!("and author_year >= {} and author_year <= {} {}",
+                                    first_year.unwrap_or(i32::MIN),
+                                    last_year.unwrap_or(i32::MAX),
+                                    format_repo_filter(exclude_repos));
+
+        let mut stmt = self.conn.prepare(&format!("
+            with top as (
+                select author_name, min(author_time) as first_time
+                from raw_commits
+                where show_domain = true
+                    {commit_filter}
+                group by author_name
+                order by {count_sel} desc, author_name
+                limit {n_authors}),
+            ranked as (
+                select author_name, row_number() over (order by first_time, author_name) - 1 as rank
+                from top)
+            select {interval}, rank, {count_sel}, ranked.author_name
+            from raw_commits, ranked
+            where raw_commits.author_name = ranked.author_name
+                and show_domain = true
+                {commit_filter}
+            group by {interval}, rank;",
+            interval = interval_str,
+            count_sel = count_sel,
+            commit_filter = commit_filter,
+            n_authors = n_authors)).chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut hist = CohortHist::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            let (ym, col) = row_bin(r, interval);
+
+            hist.set_value(ym, r.get(col).unwrap(), r.get::<_, f64>(col + 1).unwrap());
+            hist.set_cohort_name(r.get(col).unwrap(), &r.get::<_, String>(col + 2).unwrap());
+        }
+
+        Ok(hist)
+    }
+
+    pub fn get_cohort_detail_hist(&mut self, cohort: CohortType, cohort_name: &str,
+                                  interval: IntervalType, exclude_repos: &[String]) -> Result<CohortHist>
+    {
+        // Authors, commits and changes over time for a single cohort, as
+        // cohorts 0, 1 and 2 respectively. Each is normalized to its peak
+        // so they can share an axis.
+
+        let interval_str = match interval
+        {
+            IntervalType::Day => "author_year, author_month, author_day",
+            IntervalType::Month => "author_year, author_month",
+            IntervalType::Year => "author_year"
+        };
+
+        if let CohortType::Prefix = cohort { self.check_prefix_changes()?; }
+
+        let (from_where, changes_sel) = match cohort
+        {
+            CohortType::FirstYear => ("from raw_commits, authors
+                                       where raw_commits.author_name = authors.author_name
+                                           and first_year = cast(?1 as int)",
+                                      self.changes_selector()),
+            CohortType::FirstSuffix => ("from raw_commits, authors
+                                         where raw_commits.author_name = authors.author_name
+                                             and first_suffix = ?1",
+                                        self.changes_selector()),
+            CohortType::Cadence => ("from raw_commits, authors
+                                     where raw_commits.author_name = authors.author_name
+                                         and cadence = ?1",
+                                    self.changes_selector()),
+            CohortType::Domain => ("from raw_commits where author_domain = ?1",
+                                   self.changes_selector()),
+            CohortType::Country => ("from raw_commits where author_country = ?1",
+                                    self.changes_selector()),
+            CohortType::Epoch => ("from raw_commits where author_epoch = ?1",
+                                  self.changes_selector()),
+            CohortType::CommitType => ("from raw_commits where commit_type = ?1",
+                                       self.changes_selector()),
+            CohortType::Repo => ("from raw_commits where repo_name = ?1",
+                                 self.changes_selector()),
+            CohortType::Prefix => ("from raw_commits, prefixes
+                                    where raw_commits.id = prefixes.commit_id and prefix = ?1",
+                                   "sum(prefixes.n_changes)"),
+            CohortType::Suffix => ("from raw_commits, suffixes
+                                    where raw_commits.id = suffixes.commit_id and suffix = ?1",
+                                   self.suffix_changes_selector()),
+            CohortType::WorkHours | CohortType::CrossDomain | CohortType::FirstYearDomain =>
+                bail!("Cohort detail charts don't support the {} cohort", cohort.to_string().to_lowercase())
+        };
+
+        let mut stmt = self.conn.prepare(&format!("
+            select {interval},
+                   count(distinct raw_commits.author_name),
+                   count(distinct raw_commits.id),
+                   coalesce({changes_sel}, 0)
+            {from_where}
+                and show_domain = true
+                {commit_filter}
+            group by {interval};",
+            interval = interval_str,
+            changes_sel = changes_sel,
+            from_where = from_where,
+            commit_filter = format_repo_filter(exclude_repos))).chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(&[cohort_name]).chain_err(|| "Could not query database")?;
+        let mut hist = CohortHist::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            let (ym, first_col) = row_bin(r, interval);
+
+            for i in 0..3
+            {
+                hist.set_value(ym, i, r.get::<_, f64>(first_col + i as usize).unwrap());
+            }
+        }
+
+        if hist.get_bounds().is_none()
+        {
+            bail!("No commits found for cohort '{}'", cohort_name);
+        }
+
+        hist.set_cohort_name(0, "Authors");
+        hist.set_cohort_name(1, "Commits");
+        hist.set_cohort_name(2, "Changes");
+        hist.normalize_cohorts();
+        hist.set_decimals(Some(4));
+
+        Ok(hist)
+    }
+
+    fn get_case_hist(&mut self, interval: IntervalType, case_sel: &str, count_sel: &str,
+                     commit_filter: &str) -> Result<CohortHist>
+    {
+        self.get_case_hist_from(interval, "from raw_commits where show_domain = true",
+                                case_sel, count_sel, commit_filter)
+    }
+
+    fn get_case_hist_from(&mut self, interval: IntervalType, from_where: &str, case_sel: &str,
+                          count_sel: &str, commit_filter: &str) -> Result<CohortHist>
+    {
+        // Splits commits into a fixed set of cohorts numbered by case_sel.
+        // The caller names them.
+
+        let interval_str = match interval
+        {
+            IntervalType::Day => "author_year, author_month, author_day",
+            IntervalType::Month => "author_year, author_month",
+            IntervalType::Year => "author_year"
+        };
+        let mut stmt = self.conn.prepare(&format!("
+            select {interval},
+                   {case_sel} as cohort,
+                   {count_selector}
+            {from_where}
+                {commit_filter}
+            group by {interval}, cohort;",
+            interval = interval_str,
+            from_where = from_where,
+            case_sel = case_sel,
+            count_selector = count_sel,
+            commit_filter = commit_filter)).chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut hist = CohortHist::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            let (ym, col) = row_bin(r, interval);
+            hist.set_value(ym, r.get(col).unwrap(), r.get::<_, Option<f64>>(col + 1).unwrap().unwrap_or(0.0));
+        }
+
+        Ok(hist)
+    }
+
+    pub fn get_diversity_hist(&mut self, cohort: CohortType, interval: IntervalType,
+                              exclude_repos: &[String]) -> Result<CohortHist>
+    {
+        // The number of distinct domains, repos, prefixes or suffixes with
+        // any commits in each interval, as a single cohort.
+
+        let (column, from_where, name) = match cohort
+        {
+            CohortType::Domain => ("author_domain", "from raw_commits where show_domain = true", "Domains"),
+            CohortType::Country => ("author_country", "from raw_commits where show_domain = true", "Countries"),
+            CohortType::Epoch => ("author_epoch", "from raw_commits where show_domain = true", "Epochs"),
+            CohortType::CommitType => ("commit_type", "from raw_commits where show_domain = true", "Commit types"),
+            CohortType::Repo => ("raw_commits.repo_name", "from raw_commits where show_domain = true", "Repos"),
+            CohortType::Prefix => ("prefix", "from raw_commits, prefixes where show_domain = true
+                                              and raw_commits.id = prefixes.commit_id", "Prefixes"),
+            CohortType::Suffix => ("suffix", "from raw_commits, suffixes where show_domain = true
+                                              and raw_commits.id = suffixes.commit_id", "Suffixes"),
+            _ => bail!("Diversity charts don't support the {} cohort", cohort.to_string().to_lowercase())
+        };
+        let commit_filter = match cohort
+        {
+            CohortType::Suffix => format_repo_filter(exclude_repos) + &self.suffix_filter,
+            _ => format_repo_filter(exclude_repos)
+        };
+
+        let mut hist = self.get_case_hist_from(interval, from_where, "0",
+                                               &format!("count(distinct {})", column), &commit_filter)?;
+        hist.set_cohort_name(0, name);
+        Ok(hist)
+    }
+
+    pub fn get_identity_skew_hist(&mut self, interval: IntervalType, exclude_repos: &[String]) -> Result<CohortHist>
+    {
+        // Distinct author names and distinct e-mail addresses with commits
+        // in each interval. When the two drift apart, the same people are
+        // being counted under several identities, or vice versa.
+
+        let from_where = "from raw_commits where show_domain = true";
+        let commit_filter = format_repo_filter(exclude_repos);
+
+        let mut hist = self.get_case_hist_from(interval, from_where, "0", "count(distinct author_name)",
+                                               &commit_filter)?;
+        hist.set_cohort_name(0, "Names");
+
+        let emails = self.get_case_hist_from(interval, from_where, "0", "count(distinct lower(author_email))",
+                                             &commit_filter)?;
+        hist.append_cohort(&emails, 0, "E-mail addresses");
+        Ok(hist)
+    }
+
+    fn get_work_hours_hist(&mut self, interval: IntervalType, count_sel: &str,
+                           commit_filter: &str) -> Result<CohortHist>
+    {
+        // Splits commits by whether they were made on a weekday between
+        // 09:00 and 17:00 in the author's own time zone. Commits ingested
+        // before the UTC offset was recorded go in the NO_COHORT bin.
+
+        let mut hist = self.get_case_hist(interval, &work_hours_case(&NO_COHORT.to_string(), "0", "1"),
+                                          count_sel, commit_filter)?;
+
+        if let Some((_, _, first_cohort, last_cohort)) = hist.get_bounds()
+        {
+            if first_cohort > last_cohort
+            {
+                bail!("No commits with a recorded time zone; re-ingest into a new database to use this cohort");
+            }
+        }
+
+        hist.set_cohort_name(0, "Off hours");
+        hist.set_cohort_name(1, "Business hours");
+        hist.set_cohort_name(NO_COHORT, "Unknown");
+
+        Ok(hist)
+    }
+
+    fn get_cross_domain_hist(&mut self, interval: IntervalType, count_sel: &str,
+                             commit_filter: &str) -> Result<CohortHist>
+    {
+        // Splits commits by whether they were committed from the author's
+        // own domain, or accepted from outside by a committer elsewhere, e.g.
+        // a company's gatekeepers applying community patches. Commits without
+        // a committer go in the NO_COHORT bin.
+
+        let mut hist = self.get_case_hist(interval, &cross_domain_case(&NO_COHORT.to_string(), "0", "1"),
+                                          count_sel, commit_filter)?;
+
+        hist.set_cohort_name(0, "Internal");
+        hist.set_cohort_name(1, "External, accepted");
+        hist.set_cohort_name(NO_COHORT, "Unknown");
+
+        Ok(hist)
+    }
+
+    pub fn get_hist(&mut self, cohort: CohortType, unit: UnitType,
+                    interval: IntervalType, min_activity: u32,
+                    exclude_repos: &[String]) -> Result<CohortHist>
+    {
+        self.get_filtered_hist(cohort, unit, interval, min_activity, &format_repo_filter(exclude_repos))
+    }
+
+    pub fn get_hists(&mut self, requests: &[(CohortType, UnitType)], interval: IntervalType,
+                     min_activity: u32, exclude_repos: &[String]) -> Result<Vec<CohortHist>>
+    {
+        // Histograms for many cohort and unit combinations at once, e.g. for
+        // a whole book of charts. They share the postprocessed tables and
+        // the active author table, which are only built once.
+
+        let commit_filter = format_repo_filter(exclude_repos);
+
+        requests.iter()
+            .map(|(cohort, unit)| self.get_filtered_hist(*cohort, *unit, interval, min_activity, &commit_filter)
+                 .chain_err(|| format!("Could not compute {} by {}", unit.to_string().to_lowercase(),
+                                       cohort.to_string().to_lowercase())))
+            .collect()
+    }
+
+    pub fn get_window_hist(&mut self, cohort: CohortType, unit: UnitType, first_year: i32, last_year: i32,
+                           min_activity: u32, exclude_repos: &[String]) -> Result<CohortHist>
+    {
+        // Yearly histogram with the top cohorts picked from the given span of
+        // years only, rather than from all time.
+
+        self.get_filtered_hist(cohort, unit, IntervalType::Year, min_activity,
+                               &format!("and raw_commits.author_year between {} and {} {}",
+                                        first_year, last_year, format_repo_filter(exclude_repos)))
+    }
+
+    pub fn get_repo_hist(&mut self, repo_name: &str, cohort: CohortType, unit: UnitType,
+                         interval: IntervalType, min_activity: u32,
+                         exclude_repos: &[String]) -> Result<CohortHist>
+    {
+        // Histogram of a single repo's commits. The top cohorts are picked
+        // from that repo alone.
+
+        self.get_filtered_hist(cohort, unit, interval, min_activity,
+                               &format!("and raw_commits.repo_name = {} {}",
+                                        sql_quote(repo_name), format_repo_filter(exclude_repos)))
+    }
+
+    pub fn get_top_repos(&mut self, n_repos: usize, exclude_repos: &[String]) -> Result<Vec<String>>
+    {
+        self.query_strings(&format!("
+            select repo_name from raw_commits
+            where show_domain = true {repo_filter}
+            group by repo_name
+            order by count(*) desc, repo_name
+            limit {n_repos};",
+            repo_filter = format_repo_filter(exclude_repos),
+            n_repos = n_repos))
+    }
+
+    pub fn get_breakdown_hist(&mut self, cohort: CohortType, cohort_name: &str, by: CohortType,
+                              unit: UnitType, interval: IntervalType,
+                              exclude_repos: &[String]) -> Result<CohortHist>
+    {
+        // Splits the commits of a single cohort by another cohort type, e.g.
+        // the repos a domain's commits went to.
+
+        let cohort_filter = match cohort
+        {
+            CohortType::FirstYear => format!("and raw_commits.author_name in
+                                                  (select author_name from authors where first_year = {})",
+                                             cohort_name.parse::<i32>()
+                                                 .chain_err(|| format!("Invalid first year '{}'", cohort_name))?),
+            CohortType::FirstSuffix => format!("and raw_commits.author_name in
+                                                    (select author_name from authors where first_suffix = {})",
+                                               sql_quote(cohort_name)),
+            CohortType::Cadence => format!("and raw_commits.author_name in
+                                                (select author_name from authors where cadence = {})",
+                                           sql_quote(cohort_name)),
+            CohortType::Domain => format!("and raw_commits.author_domain = {}", sql_quote(cohort_name)),
+            CohortType::Country => format!("and raw_commits.author_country = {}", sql_quote(cohort_name)),
+            CohortType::Epoch => format!("and raw_commits.author_epoch = {}", sql_quote(cohort_name)),
+            CohortType::CommitType => format!("and raw_commits.commit_type = {}", sql_quote(cohort_name)),
+            CohortType::Repo => format!("and raw_commits.repo_name = {}", sql_quote(cohort_name)),
+            CohortType::Prefix => format!("and raw_commits.id in (select commit_id from prefixes where prefix = {})",
+                                          sql_quote(cohort_name)),
+            CohortType::Suffix => format!("and raw_commits.id in (select commit_id from suffixes where suffix = {})",
+                                          sql_quote(cohort_name)),
+            CohortType::WorkHours | CohortType::CrossDomain | CohortType::FirstYearDomain =>
+                bail!("Breakdowns of the {} cohort are not supported", cohort.to_string().to_lowercase())
+        };
+
+        if self.count_commits_where(&format!("show_domain = true {}", cohort_filter))? == 0
+        {
+            bail!("No commits found for cohort '{}'", cohort_name);
+        }
+
+        self.get_filtered_hist(by, unit, interval, 0,
+                               &format!("{} {}", cohort_filter, format_repo_filter(exclude_repos)))
+    }
+
+    fn get_filtered_hist(&mut self, cohort: CohortType, unit: UnitType,
+                         interval: IntervalType, min_activity: u32,
+                         commit_filter: &str) -> Result<CohortHist>
+    {
+        let dedupe = self.dedupe_cherry_picks && !matches!(unit, UnitType::Authors | UnitType::ActiveAuthors);
+        let all_filter = &format!("{} {}", omit_if(!dedupe, "and not raw_commits.is_cherry_pick".to_string()),
+                                  commit_filter);
+        let commit_filter = &match self.bot_split
+        {
+            Some(_) => format!("and not raw_commits.is_bot {}", all_filter),
+            None => all_filter.to_string()
+        };
+        let active_filter = format!("and {} in (select key from active_authors) {}",
+                                    active_authors_key(interval), commit_filter);
+        let active_selector = format!("count(distinct case when {} in (select key from active_authors)
+                                       then raw_commits.author_name end)",
+                                      active_authors_key(interval));
+
+        if let UnitType::ActiveAuthors = unit
+        {
+            self.create_active_authors(interval, min_activity, all_filter)?;
+        }
+
+        if let UnitType::CommitShare = unit
+        {
+            if !matches!(cohort, CohortType::FirstYear)
+            {
+                bail!("Commit shares are only supported for the firstyear cohort");
+            }
+
+            if let Some(BotSplit::Cohort) = self.bot_split
+            {
+                bail!("Commit shares can't be combined with a bot cohort");
+            }
+        }
+
+        let total_selector = match unit
+        {
+            UnitType::Authors => "count(distinct raw_commits.author_name)",
+            UnitType::ActiveAuthors => &active_selector,
+            UnitType::Commits => "count(*)",
+            UnitType::Reverts => "count(case when is_revert then 1 end)",
+            UnitType::Changes => self.changes_selector(),
+            UnitType::WeightedCommits => WEIGHTED_COMMITS_SELECTOR,
+            UnitType::CommitShare => "count(*)"
+        };
+
+        let subtotal_selector = match unit
+        {
+            UnitType::Authors => "count(distinct raw_commits.author_name)",
+            UnitType::ActiveAuthors => &active_selector,
+            UnitType::Commits => "count(*)",
+            UnitType::Reverts => "count(case when is_revert then 1 end)",
+            UnitType::Changes => self.suffix_changes_selector(), // FIXME: Redundant
+            UnitType::WeightedCommits => WEIGHTED_COMMITS_SELECTOR,
+            UnitType::CommitShare => "count(*)"
+        };
+
+        let mut hist = match cohort
+        {
+            CohortType::FirstYear =>
+            {
+                self.get_firstyear_hist(interval, total_selector, matches!(unit, UnitType::CommitShare),
+                                        commit_filter)
+            },
+            CohortType::FirstYearDomain =>
+            {
+                self.get_firstyear_domain_hist(interval, total_selector, commit_filter)
+            },
+            CohortType::FirstSuffix =>
+            {
+                self.get_column_hist("authors", "first_suffix", interval, total_selector, commit_filter)
+            },
+            CohortType::Cadence =>
+            {
+                self.get_column_hist("authors", "cadence", interval, total_selector, commit_filter)
+            },
+            CohortType::WorkHours =>
+            {
+                self.get_work_hours_hist(interval, total_selector, commit_filter)
+            },
+            CohortType::CrossDomain =>
+            {
+                self.get_cross_domain_hist(interval, total_selector, commit_filter)
+            },
+            CohortType::Domain =>
+            {
+                match unit
+                {
+                    UnitType::Authors => { self.get_column_authors_hist("author_domain", interval, commit_filter) },
+                    UnitType::ActiveAuthors => { self.get_column_authors_hist("author_domain", interval, &active_filter) },
+                    _ => { self.get_column_hist("raw_commits", "author_domain", interval, total_selector, commit_filter) }
+                }
+            },
+            CohortType::Country =>
+            {
+                match unit
+                {
+                    UnitType::Authors => { self.get_column_authors_hist("author_country", interval, commit_filter) },
+                    UnitType::ActiveAuthors => { self.get_column_authors_hist("author_country", interval, &active_filter) },
+                    _ => { self.get_column_hist("raw_commits", "author_country", interval, total_selector, commit_filter) }
+                }
+            },
+            CohortType::Epoch =>
+            {
+                match unit
+                {
+                    UnitType::Authors => { self.get_column_authors_hist("author_epoch", interval, commit_filter) },
+                    UnitType::ActiveAuthors => { self.get_column_authors_hist("author_epoch", interval, &active_filter) },
+                    _ => { self.get_column_hist("raw_commits", "author_epoch", interval, total_selector, commit_filter) }
+                }
+            },
+            CohortType::CommitType =>
+            {
+                match unit
+                {
+                    UnitType::Authors => { self.get_column_authors_hist("commit_type", interval, commit_filter) },
+                    UnitType::ActiveAuthors => { self.get_column_authors_hist("commit_type", interval, &active_filter) },
+                    _ => { self.get_column_hist("raw_commits", "commit_type", interval, total_selector, commit_filter) }
+                }
+            },
+            CohortType::Repo =>
+            {
+                match unit
+                {
+                    UnitType::Authors => { self.get_column_authors_hist("repo_name", interval, commit_filter) },
+                    UnitType::ActiveAuthors => { self.get_column_authors_hist("repo_name", interval, &active_filter) },
+                    _ => { self.get_column_hist("raw_commits", "repo_name", interval, total_selector, commit_filter) }
+                }
+            }
+            CohortType::Prefix =>
+            {
+                match unit
+                {
+                    UnitType::Authors => { self.get_column_authors_hist("prefix", interval, commit_filter) },
+                    UnitType::ActiveAuthors => { self.get_column_authors_hist("prefix", interval, &active_filter) },
+                    UnitType::Changes =>
+                    {
+                        self.check_prefix_changes()?;
+                        self.get_subcommit_hist("prefix", interval, "sum(prefixes.n_changes)", total_selector, commit_filter)
+                    },
+                    _ => { self.get_subcommit_hist("prefix", interval, subtotal_selector, total_selector, commit_filter) }
+                }
+            }
+            CohortType::Suffix =>
+            {
+                match unit
+                {
+                    UnitType::Authors => { self.get_column_authors_hist("suffix", interval, commit_filter) },
+                    UnitType::ActiveAuthors => { self.get_column_authors_hist("suffix", interval, &active_filter) },
+                    UnitType::Changes => { self.get_subcommit_hist("suffix", interval, self.suffix_changes_selector(), total_selector, commit_filter) },
+                    _ => { self.get_subcommit_hist("suffix", interval, subtotal_selector, total_selector, commit_filter) }
+                }
+            }
+        }?;
+
+        if let Some(BotSplit::Cohort) = self.bot_split
+        {
+            let bots = self.get_case_hist(interval, "0", total_selector,
+                                          &format!("and raw_commits.is_bot {}", all_filter))?;
+            hist.append_cohort(&bots, 0, "Bots");
+        }
+
+        if unit.counts_commits()
+        {
+            hist.scale(self.get_sample_rate()? as f64);
+        }
+
+        hist.set_decimals(Some(unit.decimals()));
+        Ok(hist)
+    }
+}
+
+fn omit_if(omit: bool, sql: String) -> String
+{
+    if omit { String::new() } else { sql }
+}
+
+fn work_hours_case(unknown: &str, off_hours: &str, business_hours: &str) -> String
+{
+    format!("
+        case when author_utc_offset is null then {unknown}
+             when cast(strftime('%w', author_time + author_utc_offset, 'unixepoch') as int)
+                      between 1 and 5
+                 and cast(strftime('%H', author_time + author_utc_offset, 'unixepoch') as int)
+                      between 9 and 16 then {business_hours}
+             else {off_hours} end",
+        unknown = unknown,
+        off_hours = off_hours,
+        business_hours = business_hours)
+}
+
+fn cross_domain_case(unknown: &str, internal: &str, external: &str) -> String
+{
+    format!("
+        case when committer_domain is null then {unknown}
+             when committer_domain = author_domain then {internal}
+             else {external} end",
+        unknown = unknown,
+        internal = internal,
+        external = external)
+}
+
+fn format_sql_value(value: rusqlite::types::Value) -> String
+{
+    match value
+    {
+        rusqlite::types::Value::Null => "null".to_string(),
+        rusqlite::types::Value::Integer(i) => i.to_string(),
+        rusqlite::types::Value::Real(f) => f.to_string(),
+        rusqlite::types::Value::Text(text) => text,
+        rusqlite::types::Value::Blob(blob) => format!("<{} bytes>", blob.len())
+    }
+}
+
+// SQLite parses "x regexp y" but leaves the implementation to the
+// application. The compiled pattern is cached for the duration of a statement.
+
+pub fn add_regexp_function(conn: &Connection) -> rusqlite::Result<()>
+{
+    type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+    conn.create_scalar_function(
+        "regexp",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let re: Arc<Regex> = ctx.get_or_create_aux(0, |vr| -> std::result::Result<_, BoxError> {
+                Ok(Regex::new(vr.as_str()?)?)
+            })?;
+            // A missing email or name matches nothing rather than failing
+            // the whole statement.
+
+            let text = match ctx.get_raw(1)
+            {
+                ValueRef::Null => return Ok(false),
+                v => v.as_str().map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?
+            };
+
+            Ok(re.is_match(text))
+        })
+}
+
+fn add_email_to_domain_function(conn: &Connection) -> rusqlite::Result<()>
+{
+    conn.create_scalar_function(
+        "email_to_domain",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let email = ctx.get_raw(0).as_str()
+                .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+
+            Ok(email_to_domain(email))
+        })
+}
+
+fn add_email_to_country_function(conn: &Connection) -> rusqlite::Result<()>
+{
+    conn.create_scalar_function(
+        "email_to_country",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let email = ctx.get_raw(0).as_str()
+                .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+
+            Ok(email_to_country(email))
+        })
+}
+
+fn add_subject_to_commit_type_function(conn: &Connection) -> rusqlite::Result<()>
+{
+    conn.create_scalar_function(
+        "subject_to_commit_type",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let subject = ctx.get::<Option<String>>(0)?;
+
+            Ok(commit_type(subject.as_deref().unwrap_or("")))
+        })
+}
+
+// Short salted hash used to pseudonymize names and email addresses in
+// snapshots. The salt is not stored, so the hashes can't be checked against
+// guessed identities.
+
+fn add_anon_hash_function(conn: &Connection) -> rusqlite::Result<()>
+{
+    let salt = format!("{:?}", Utc::now());
+
+    conn.create_scalar_function(
+        "anon_hash",
+        1,
+        FunctionFlags::SQLITE_UTF8,
+        move |ctx| {
+            let text = ctx.get::<Option<String>>(0)?;
+
+            Ok(text.map(|t| format!("{:x}", Sha256::digest(format!("{}{}", salt, t).as_bytes()))[..10]
+                        .to_string()))
+        })
+}
+
+// Weight of a commit made the given number of seconds after the author's
+// first commit.
+
+// SQL expression for the cohort of a commit in raw_commits, for the cohorts
+// that have one per commit.
+
+fn commit_cohort_column(cohort: CohortType) -> Option<&'static str>
+{
+    match cohort
+    {
+        CohortType::FirstYear => Some("(select cast(first_year as text) from authors
+                                        where authors.author_name = raw_commits.author_name)"),
+        CohortType::FirstSuffix => Some("(select first_suffix from authors
+                                          where authors.author_name = raw_commits.author_name)"),
+        CohortType::Cadence => Some("(select cadence from authors
+                                      where authors.author_name = raw_commits.author_name)"),
+        CohortType::Domain => Some("author_domain"),
+        CohortType::Country => Some("author_country"),
+        CohortType::Epoch => Some("author_epoch"),
+        CohortType::CommitType => Some("commit_type"),
+        CohortType::Repo => Some("raw_commits.repo_name"),
+        _ => None
+    }
+}
+
+fn add_suffix_weight_function(conn: &Connection, weights: &HashMap<String, f64>) -> rusqlite::Result<()>
+{
+    // Suffixes are stored without the leading dot, but people will type it.
+
+    let weights: HashMap<String, f64> = weights.iter()
+        .map(|(suffix, weight)| (suffix.trim_start_matches('.').to_string(), *weight))
+        .collect();
+
+    conn.create_scalar_function(
+        "suffix_weight",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| {
+            let suffix = ctx.get::<Option<String>>(0)?.unwrap_or_default();
+
+            Ok(weights.get(&suffix).cloned().unwrap_or(1.0))
+        })
+}
+
+fn add_tenure_weight_function(conn: &Connection, curve: TenureCurve) -> rusqlite::Result<()>
+{
+    conn.create_scalar_function(
+        "tenure_weight",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| {
+            let seconds = ctx.get::<Option<f64>>(0)?.unwrap_or(0.0);
+
+            Ok(curve.weight(seconds / SECONDS_PER_YEAR))
+        })
+}
+
+// Excludes commits from repos matching any of the given globs. The result is
+// appended to a where clause.
+
+fn sql_quote(s: &str) -> String
+{
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn format_repo_filter(exclude_repos: &[String]) -> String
+{
+    // Commits in repo-specific excluded intervals are always left out; see
+    // ProjectMeta::sql_repo_exclusion_selector().
+
+    " and not raw_commits.in_excluded_span".to_string()
+        + &exclude_repos.iter()
+            .map(|glob| format!(" and raw_commits.repo_name not glob {}", sql_quote(glob)))
+            .collect::<String>()
+}
+
+fn format_suffix_filter(include: &[String], exclude: &[String]) -> String
+{
+    // Suffixes are stored without the leading dot, but people will type it.
+
+    let glob = |g: &String| sql_quote(g.strip_prefix('.').unwrap_or(g));
+    let mut filter = String::new();
+
+    if !include.is_empty()
+    {
+        filter += &format!(" and ({})", include.iter()
+                           .map(|g| format!("suffixes.suffix glob {}", glob(g)))
+                           .collect::<Vec<String>>()
+                           .join(" or "));
+    }
+
+    for g in exclude
+    {
+        filter += &format!(" and suffixes.suffix not glob {}", glob(g));
+    }
+
+    filter
+}
+
+// Rows grouped by interval lead with its year, month and day columns, as many
+// as the interval needs. Returns the bin and the index of the next column.
+
+fn row_bin(r: &rusqlite::Row, interval: IntervalType) -> (TimeBin, usize)
+{
+    match interval
+    {
+        IntervalType::Day => (TimeBin::day(r.get(0).unwrap(), r.get(1).unwrap(), r.get(2).unwrap()), 3),
+        IntervalType::Month => (TimeBin::Month(r.get(0).unwrap(), r.get(1).unwrap()), 2),
+        IntervalType::Year => (TimeBin::Year(r.get(0).unwrap()), 1)
+    }
+}
+
+fn interval_bins(interval: IntervalType) -> (&'static str, &'static [&'static str])
+{
+    // The name of an interval as used in aggregate table names, and the
+    // columns those tables bin by.
+
+    match interval
+    {
+        IntervalType::Day => ("day", &[ "year", "month", "day" ]),
+        IntervalType::Month => ("month", &[ "year", "month" ]),
+        IntervalType::Year => ("year", &[ "year" ])
+    }
+}
+
+fn format_aggregate_indexes(column: &str, interval: IntervalType) -> String
+{
+    let (interval_name, bins) = interval_bins(interval);
+
+    bins.iter().chain(&[ column ])
+        .map(|c| format!("create index if not exists index_{c} on {column}_{interval}_aggregates ({c});",
+                         c = c, column = column, interval = interval_name))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn active_authors_key(interval: IntervalType) -> &'static str
+{
+    match interval
+    {
+        IntervalType::Day => "raw_commits.author_name || '|' || author_year || '|' || author_month || '|' || author_day",
+        IntervalType::Month => "raw_commits.author_name || '|' || author_year || '|' || author_month",
+        IntervalType::Year => "raw_commits.author_name || '|' || author_year"
+    }
+}
+
+pub fn email_to_domain(email: &str) -> String
+{
+    let mut email: String = email.trim().to_lowercase();
+
+    // Strip local part. Addresses without one, or without anything after
+    // it, have no usable domain; we return an empty string for those so
+    // they can be folded together.
+
+    if let Some(p) = email.rfind('@') {
+        email.replace_range(0..=p, "");
+    }
+    else {
+        return "".to_string();
+    }
+
+    if email.is_empty() || email.contains(char::is_whitespace) || !email.contains(char::is_alphanumeric) {
+        return "".to_string();
+    }
+
+    // Trim the domain as much as possible. If the last element looks
+    // like a country code and the next-to-last one is 2-3 letters, it's
+    // likely of the form 'domain.ac.uk' or 'domain.com.au'. We keep
+    // three elements in those cases. Otherwise we keep two as in
+    // 'domain.org'.
+    //
+    // If we wanted to get fancy we could've used this list:
+    //
+    // https://publicsuffix.org/list/public_suffix_list.dat
+    //
+    // ...but the relative gain is likely not worth it.
+
+    let split: Vec<&str> = email.split('.').collect();
+    let n = split.len();
+
+    if n > 2
+    {
+        if split[n - 1].len() < 3
+        {
+            if split[n - 2].len() < 4
+            {
+                // domain.com.au
+                split[n - 3..n].join(".")
+            }
+            else
+            {
+                // domain.au
+                split[n - 2..n].join(".")
+            }
+        }
+        else
+        {
+            // domain.org
+            split[n - 2..n].join(".")
+        }
+    }
+    else
+    {
+        // Already optimal, or malformed
+        email
+    }
+}
+
+// Two-letter TLDs that are mostly sold as generic ones, plus the EU, which
+// isn't a country.
+
+const GENERIC_CCTLDS: &[&str] = &[ "ai", "cc", "co", "eu", "fm", "io", "me", "tv", "ws" ];
+
+pub fn email_to_country(email: &str) -> Option<String>
+{
+    // ISO 3166 code for the country-code TLD of an address, if it has one.
+    // The UK's TLD is the odd one out.
+
+    let domain = email_to_domain(email);
+    let (_, tld) = domain.rsplit_once('.')?;
+
+    if tld.len() != 2 || !tld.chars().all(|c| c.is_ascii_alphabetic()) || GENERIC_CCTLDS.contains(&tld)
+    {
+        return None;
+    }
+
+    match tld
+    {
+        "uk" => Some("GB".to_string()),
+        _ => Some(tld.to_uppercase())
+    }
+}
+
+// Conventional Commits types, and the gitmoji (as emoji or shortcode) that
+// stand in for them.
+
+const COMMIT_TYPES: &[&str] = &[ "feat", "fix", "docs", "style", "refactor", "perf",
+                                 "test", "build", "ci", "chore", "revert" ];
+
+const GITMOJI_TYPES: &[(&str, &str, &str)] = &[
+    ("\u{2728}", ":sparkles:", "feat"),
+    ("\u{1f41b}", ":bug:", "fix"),
+    ("\u{1f691}", ":ambulance:", "fix"),
+    ("\u{1f4dd}", ":memo:", "docs"),
+    ("\u{1f3a8}", ":art:", "style"),
+    ("\u{267b}", ":recycle:", "refactor"),
+    ("\u{26a1}", ":zap:", "perf"),
+    ("\u{2705}", ":white_check_mark:", "test"),
+    ("\u{1f4e6}", ":package:", "build"),
+    ("\u{1f477}", ":construction_worker:", "ci"),
+    ("\u{1f527}", ":wrench:", "chore"),
+    ("\u{23ea}", ":rewind:", "revert") ];
+
+pub fn commit_type(subject: &str) -> &'static str
+{
+    // The type a commit declares in its subject, e.g. "fix(ui)!: ..." or
+    // ":bug: ...". Git's own revert subjects count as reverts. Anything
+    // else is "other".
+
+    let subject = subject.trim_start();
+
+    if let Some((_, _, kind)) = GITMOJI_TYPES.iter()
+        .find(|(emoji, code, _)| subject.starts_with(emoji) || subject.starts_with(code))
+    {
+        return kind;
+    }
+
+    if subject.starts_with("Revert \"")
+    {
+        return "revert";
+    }
+
+    if let Some((head, _)) = subject.split_once(':')
+    {
+        let kind = head.trim_end_matches('!');
+        let kind = match kind.split_once('(')
+        {
+            Some((kind, scope)) if scope.ends_with(')') => kind,
+            _ => kind
+        }.to_lowercase();
+
+        if let Some(kind) = COMMIT_TYPES.iter().find(|t| **t == kind)
+        {
+            return kind;
+        }
+    }
+
+    "other"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A commit by the Dude at noon UTC on the given date.
+
+    fn test_commit(i: usize, date: &str) -> RawCommit {
+        let time = DateTime::parse_from_rfc3339(&format!("{}T12:00:00+00:00", date)).unwrap();
+
+        RawCommit
+        {
+            id: format!("{:040}", i),
+            repo_name: "r".to_string(),
+            author_name: "Dude".to_string(),
+            author_email: "dude@lebowski.com".to_string(),
+            author_time: Some(time),
+            committer_time: Some(time),
+            ..Default::default()
+        }
+    }
+
+    fn insert_commits(db: &mut CommitDb, commits: &[(&str, &str, &str)]) {
+        for (i, (name, email, date)) in commits.iter().enumerate() {
+            db.insert_raw_commit(&RawCommit { author_name: name.to_string(), author_email: email.to_string(),
+                                              ..test_commit(i, date) }).unwrap();
+        }
+    }
+
+    #[test]
+    fn strips_email_username() {
+        assert_eq!(email_to_domain("dude@lebowski.com"), "lebowski.com");
+    }
+
+    #[test]
+    fn unparseable_email_has_no_domain() {
+        assert_eq!(email_to_domain("dude"), "");
+        assert_eq!(email_to_domain("dude@"), "");
+        assert_eq!(email_to_domain("dude@ ..."), "");
+        assert_eq!(email_to_domain("dude@localhost"), "localhost");
+    }
+
+    #[test]
+    fn formats_repo_filter() {
+        assert_eq!(format_repo_filter(&[]), " and not raw_commits.in_excluded_span");
+        assert_eq!(format_repo_filter(&["gtk*".to_string(), "it's".to_string()]),
+                   " and not raw_commits.in_excluded_span \
+                    and raw_commits.repo_name not glob 'gtk*' and raw_commits.repo_name not glob 'it''s'");
+    }
+
+    #[test]
+    fn formats_suffix_filter() {
+        assert_eq!(format_suffix_filter(&[], &[]), "");
+        assert_eq!(format_suffix_filter(&["c".to_string(), ".rs".to_string()], &["po".to_string()]),
+                   " and (suffixes.suffix glob 'c' or suffixes.suffix glob 'rs') and suffixes.suffix not glob 'po'");
+    }
+
+    #[test]
+    fn regexp_does_not_match_null() {
+        let db = CommitDb::open(":memory:".into()).unwrap();
+
+        assert_eq!(db.query_strings("select 'x' where regexp('^a', 'abc');").unwrap(), [ "x" ]);
+        assert!(db.query_strings("select 'x' where regexp('^a', null);").unwrap().is_empty());
+    }
+
+    #[test]
+    fn changes_do_not_overflow() {
+        // Per-commit counts fit in an i32, but sums over giant repos don't.
+
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+
+        for i in 0..3
+        {
+            let time = DateTime::parse_from_rfc3339(&format!("2020-06-0{}T12:00:00+00:00", i + 1)).unwrap();
+            let mut commit = RawCommit
+            {
+                id: format!("{:040}", i),
+                repo_name: "giant".to_string(),
+                author_name: "Dude".to_string(),
+                author_email: "dude@lebowski.com".to_string(),
+                author_time: Some(time),
+                committer_name: "Dude".to_string(),
+                committer_email: "dude@lebowski.com".to_string(),
+                committer_time: Some(time),
+                n_insertions: i32::MAX,
+                n_deletions: i32::MAX,
+                n_changes_per_prefix: HashMap::new(),
+                n_changes_per_suffix: HashMap::new(),
+                n_insertions_per_suffix: HashMap::new(),
+                n_deletions_per_suffix: HashMap::new(),
+                subject: format!("Vendor everything, take {}", i),
+                has_body: None,
+                is_revert: false,
+                parents: Vec::new()
+            };
+            commit.n_changes_per_prefix.insert("src".to_string(), i32::MAX);
+            commit.n_changes_per_suffix.insert("c".to_string(), i32::MAX);
+            db.insert_raw_commit(&commit).unwrap();
+        }
+
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
+
+        let hist = db.get_hist(CohortType::Repo, UnitType::Changes, IntervalType::Year, 5, &[]).unwrap();
+        assert!(hist.to_csv(b'|').ends_with("\n2020|12884901882|12884901882"));
+    }
+
+    #[test]
+    fn weights_commits_by_tenure() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+
+        for (i, date) in [ "2010-01-01", "2014-01-01", "2014-01-02" ].iter().enumerate()
+        {
+            let mut commit = RawCommit
+            {
+                repo_name: "old".to_string(),
+                subject: format!("Abide, take {}", i),
+                ..test_commit(i, date)
+            };
+            commit.n_changes_per_prefix.insert("src".to_string(), 1);
+            commit.n_changes_per_suffix.insert("c".to_string(), 1);
+            db.insert_raw_commit(&commit).unwrap();
+        }
+
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
+
+        let hist = db.get_hist(CohortType::Repo, UnitType::WeightedCommits, IntervalType::Year, 5, &[]).unwrap();
+        assert!(hist.to_csv(b'|').ends_with("\n2014|10|10"));
+
+        db.set_tenure_curve(TenureCurve::Sqrt).unwrap();
+        let hist = db.get_hist(CohortType::Repo, UnitType::WeightedCommits, IntervalType::Year, 5, &[]).unwrap();
+        assert!(hist.to_csv(b'|').ends_with("\n2014|6|6"));
+    }
+
+    #[test]
+    fn weights_changes_by_suffix() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+
+        for (i, (suffix, n_changes, date)) in [ ("c", 10, "2014-01-01"), ("json", 100, "2014-06-01") ].iter().enumerate()
+        {
+            let mut commit = RawCommit
+            {
+                repo_name: "rug".to_string(),
+                n_insertions: *n_changes,
+                subject: format!("Tie the room together, take {}", i),
+                ..test_commit(i, date)
+            };
+            commit.n_changes_per_prefix.insert("src".to_string(), *n_changes);
+            commit.n_changes_per_suffix.insert(suffix.to_string(), *n_changes);
+            db.insert_raw_commit(&commit).unwrap();
+        }
+
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
+
+        let hist = db.get_hist(CohortType::Repo, UnitType::Changes, IntervalType::Year, 5, &[]).unwrap();
+        assert!(hist.to_csv(b'|').ends_with("\n2014|110|110"));
+
+        let weights: HashMap<String, f64> = [ (".json".to_string(), 0.1) ].iter().cloned().collect();
+        db.set_suffix_weights(&weights).unwrap();
+        let hist = db.get_hist(CohortType::Repo, UnitType::Changes, IntervalType::Year, 5, &[]).unwrap();
+        assert!(hist.to_csv(b'|').ends_with("\n2014|20|20"));
+        let hist = db.get_hist(CohortType::Suffix, UnitType::Changes, IntervalType::Year, 5, &[]).unwrap();
+        assert!(hist.to_csv(b'|').ends_with("\n2014|20|10|10|0"));
+        assert!(db.get_hist(CohortType::Prefix, UnitType::Changes, IntervalType::Year, 5, &[]).is_err());
+    }
+
+    #[test]
+    fn splits_generations_by_domain() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+
+        let commits = [ ("Walter", "walter@sobchak.com", "2014-01-01"), ("Walter", "walter@sobchak.com", "2014-06-01"),
+                        ("Walter", "walter@sobchak.com", "2014-07-01"), ("Donny", "donny@bowling.org", "2014-02-01"),
+                        ("Donny", "donny@bowling.org", "2014-08-01"), ("Maude", "maude@art.org", "2015-01-01"),
+                        ("Maude", "maude@art.org", "2015-06-01") ];
+
+        for (i, (name, email, date)) in commits.iter().enumerate()
+        {
+            let mut commit = RawCommit
+            {
+                repo_name: "alley".to_string(),
+                author_name: name.to_string(),
+                author_email: email.to_string(),
+                subject: format!("Roll, take {}", i),
+                ..test_commit(i, date)
+            };
+            commit.n_changes_per_prefix.insert("src".to_string(), 1);
+            commit.n_changes_per_suffix.insert("c".to_string(), 1);
+            db.insert_raw_commit(&commit).unwrap();
+        }
+
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
+        db.set_hidden_cohorts(Some(HideType::Brief));
+
+        let hist = db.get_hist(CohortType::FirstYearDomain, UnitType::Commits, IntervalType::Year, 5, &[]).unwrap();
+        assert_eq!(hist.to_csv(b'|'), "Year|Sum|2014 sobchak.com|2014 bowling.org|2015 art.org\n\
+                                       2014|5|3|2|0\n\
+                                       2015|2|0|0|2");
+        assert_eq!((hist.get_cohort_group(1), hist.get_cohort_group(2)), (Some(2014), Some(2015)));
+    }
+
+    #[test]
+    fn classifies_commit_cadence() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+
+        // The median gap shrugs off Walter's one long break.
+        insert_commits(&mut db, &[ ("Walter", "walter@lebowski.com", "2020-01-01"),
+                                   ("Walter", "walter@lebowski.com", "2020-01-02"),
+                                   ("Walter", "walter@lebowski.com", "2020-01-03"),
+                                   ("Walter", "walter@lebowski.com", "2020-06-01"),
+                                   ("Maude", "maude@lebowski.com", "2020-01-01"),
+                                   ("Maude", "maude@lebowski.com", "2020-01-08"),
+                                   ("Maude", "maude@lebowski.com", "2020-01-15"),
+                                   ("Donny", "donny@lebowski.com", "2020-01-01") ]);
+
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
+
+        let cadence: Vec<(String, String)> = db.conn
+            .prepare("select author_name, cadence from authors order by author_name;").unwrap()
+            .query_map(NO_PARAMS, |r| Ok((r.get(0)?, r.get(1)?))).unwrap()
+            .map(|r| r.unwrap()).collect();
+        assert_eq!(cadence, vec![ ("Donny".to_string(), "Sporadic".to_string()),
+                                  ("Maude".to_string(), "Weekly".to_string()),
+                                  ("Walter".to_string(), "Daily".to_string()) ]);
+    }
+
+    #[test]
+    fn drops_drive_by_cohort_shares() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+
+        for (i, (repo, date)) in [ ("big", "2020-01-01"), ("big", "2020-03-01"), ("big", "2020-06-01"),
+                                   ("tiny", "2020-09-01") ].iter().enumerate()
+        {
+            db.insert_raw_commit(&RawCommit { repo_name: repo.to_string(), ..test_commit(i, date) }).unwrap();
+        }
+
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
+        db.set_hidden_cohorts(Some(HideType::Both));
+
+        let hist = db.get_hist(CohortType::Repo, UnitType::Authors, IntervalType::Year, 5, &[]).unwrap();
+        assert!(hist.to_csv(b'|').ends_with("\n2020|1|0.25|0.75"));
+
+        db.set_min_cohort_commits(2);
+        let hist = db.get_hist(CohortType::Repo, UnitType::Authors, IntervalType::Year, 5, &[]).unwrap();
+        assert!(hist.to_csv(b'|').ends_with("\n2020|1|1"));
+    }
+
+    #[test]
+    fn migrates_suffixes_to_ids() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        {
+            let conn = Connection::open(file.path()).unwrap();
+            conn.execute_batch("
+                create table suffixes (commit_id text, suffix text, n_changes int);
+                insert into suffixes values ('a', 'c', 3), ('a', 'py', 1), ('b', 'c', 2);
+                pragma user_version = 13;").unwrap();
+        }
+
+        let db = CommitDb::open(file.path().to_path_buf()).unwrap();
+
+        assert_eq!(db.query_strings("select suffix from suffix_names order by id;").unwrap(), [ "c", "py" ]);
+        assert_eq!(db.query_strings("select commit_id || suffix || n_changes from suffixes
+                                         order by commit_id, suffix;").unwrap(),
+                   [ "ac3", "apy1", "bc2" ]);
+    }
+
+    #[test]
+    fn backfills_only_older_schemas() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let backfilled = "select coalesce(author_day_local, 'null') || ' ' || coalesce(committer_domain, 'null')
+                          from raw_commits;";
+        let clear = |user_version: i32| Connection::open(file.path()).unwrap().execute_batch(&format!("
+            update raw_commits set author_day_local = null, committer_domain = null;
+            pragma user_version = {};", user_version)).unwrap();
+        {
+            let mut db = CommitDb::open(file.path().to_path_buf()).unwrap();
+            db.insert_raw_commit(&RawCommit { committer_email: "dude@lebowski.com".to_string(),
+                                              ..test_commit(0, "2020-06-09") }).unwrap();
+        }
+
+        clear(SCHEMA_VERSION);
+        let db = CommitDb::open(file.path().to_path_buf()).unwrap();
+        assert_eq!(db.query_strings(backfilled).unwrap(), [ "null null" ]);
+        drop(db);
+
+        clear(8);
+        let db = CommitDb::open(file.path().to_path_buf()).unwrap();
+        assert_eq!(db.query_strings(backfilled).unwrap(), [ "8 lebowski.com" ]);
+    }
+
+    #[test]
+    fn reports_schema_version_on_disk() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        {
+            let conn = Connection::open(file.path()).unwrap();
+            conn.execute_batch("
+                create table raw_commits (id text, repo_name text, author_time int);
+                insert into raw_commits values ('a', 'gtk', 1577880000);
+                pragma user_version = 3;").unwrap();
+        }
+
+        let mut db = CommitDb::open_read_only(file.path().to_path_buf()).unwrap();
+        let info = db.get_info().unwrap();
+
+        assert_eq!(info.schema_version, 3);
+        assert_eq!(info.last_ingest, None);
+        assert_eq!(info.repo_coverage, [ ("gtk".to_string(), 1, "2020-01-01".to_string(), "2020-01-01".to_string()) ]);
+        assert_eq!(db.query_strings("select name from sqlite_master where type = 'table';").unwrap(),
+                   [ "raw_commits" ]);
+    }
+
+    #[test]
+    fn applies_db_profiles() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let settings = "select journal_mode || ' ' || (select synchronous from pragma_synchronous())
+                            || ' ' || (select page_size from pragma_page_size())
+                        from pragma_journal_mode();";
+        {
+            let db = CommitDb::open_with_profile(file.path().to_path_buf(), DbProfile::Bulk).unwrap();
+            assert_eq!(db.query_strings(settings).unwrap(), [ "off 0 65536" ]);
+        }
+
+        // The page size stays with the database; the rest is per connection.
+        let db = CommitDb::open_with_profile(file.path().to_path_buf(), DbProfile::Safe).unwrap();
+        assert_eq!(db.query_strings(settings).unwrap(), [ "wal 2 65536" ]);
+    }
+
+    #[test]
+    fn splits_bots_from_humans() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+        let commits = [ ("Dude", "dude@lebowski.com", "2020-01-01"), ("Dude", "dude@lebowski.com", "2020-06-01"),
+                        ("dependabot[bot]", "49699333+dependabot[bot]@users.noreply.github.com", "2020-02-01"),
+                        ("Build Server", "ci@lebowski.com", "2020-03-01"),
+                        ("Walter Robot", "walter@lebowski.com", "2020-04-01"),
+                        ("Walter Robot", "walter@lebowski.com", "2020-09-01") ];
+
+        insert_commits(&mut db, &commits);
+
+        let meta: ProjectMeta = serde_json::from_str(r#"{ "bots": { "include": [ { "pattern": "ci@*" } ],
+                                                                   "exclude": [ { "pattern": "walter*" } ] } }"#)
+            .unwrap();
+        db.postprocess(&meta, "Unknown", false).unwrap();
+        db.set_hidden_cohorts(Some(HideType::Both));
+
+        db.set_bot_split(Some(BotSplit::Remove));
+        let hist = db.get_hist(CohortType::Domain, UnitType::Commits, IntervalType::Year, 5, &[]).unwrap();
+        assert_eq!(hist.to_csv(b'|'), "Year|Sum|lebowski.com\n2020|4|4");
+
+        db.set_bot_split(Some(BotSplit::Cohort));
+        let hist = db.get_hist(CohortType::Domain, UnitType::Commits, IntervalType::Year, 5, &[]).unwrap();
+        assert_eq!(hist.to_csv(b'|'), "Year|Sum|lebowski.com|Bots\n2020|6|4|2");
+    }
+
+    #[test]
+    fn dedupes_cherry_picks() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+        let commits = [ ("2020-01-01", "2020-01-01", "Fix crash", 10),
+                        ("2020-01-01", "2020-03-01", "Fix crash", 12),
+                        ("2020-06-01", "2020-06-01", "Add feature", 5) ];
+
+        for (i, (author_date, commit_date, subject, n_insertions)) in commits.iter().enumerate()
+        {
+            let commit = RawCommit
+            {
+                committer_time: test_commit(i, commit_date).committer_time,
+                n_insertions: *n_insertions,
+                subject: subject.to_string(),
+                ..test_commit(i, author_date)
+            };
+            db.insert_raw_commit(&commit).unwrap();
+        }
+
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
+        assert_eq!(db.query_strings("select id from raw_commits where is_cherry_pick").unwrap(),
+                   vec![ format!("{:040}", 1) ]);
+
+        let hist = db.get_hist(CohortType::Domain, UnitType::Commits, IntervalType::Year, 5, &[]).unwrap();
+        assert_eq!(hist.to_csv(b'|'), "Year|Sum|lebowski.com\n2020|3|3");
+
+        db.set_dedupe_cherry_picks(true);
+        let hist = db.get_hist(CohortType::Domain, UnitType::Commits, IntervalType::Year, 5, &[]).unwrap();
+        assert_eq!(hist.to_csv(b'|'), "Year|Sum|lebowski.com\n2020|2|2");
+        let hist = db.get_hist(CohortType::Domain, UnitType::Changes, IntervalType::Year, 5, &[]).unwrap();
+        assert_eq!(hist.to_csv(b'|'), "Year|Sum|lebowski.com\n2020|15|15");
+    }
+
+    #[test]
+    fn excludes_repo_spans() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+        let commits = [ ("vendored", "2019-06-01"), ("vendored", "2020-03-01"), ("vendored", "2020-09-01"),
+                        ("app", "2020-03-01") ];
+
+        for (i, (repo_name, date)) in commits.iter().enumerate()
+        {
+            db.insert_raw_commit(&RawCommit { repo_name: repo_name.to_string(), n_insertions: 10,
+                                              ..test_commit(i, date) }).unwrap();
+        }
+
+        let meta: ProjectMeta = serde_json::from_str(
+            r#"{ "excluded": [ { "begin": { "year": 2020, "month": 1 }, "end": { "year": 2020, "month": 6 },
+                                 "repo": "vend*", "text": "Import" } ] }"#).unwrap();
+        db.postprocess(&meta, "Unknown", false).unwrap();
+
+        let hist = db.get_hist(CohortType::Repo, UnitType::Commits, IntervalType::Year, 5, &[]).unwrap();
+        assert_eq!(hist.to_csv(b'|'), "Year|Sum|app|vendored\n2019|1|0|1\n2020|2|1|1");
+        let hist = db.get_hist(CohortType::Domain, UnitType::Changes, IntervalType::Year, 5, &[]).unwrap();
+        assert_eq!(hist.to_csv(b'|'), "Year|Sum|lebowski.com\n2019|10|10\n2020|20|20");
+
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
+        let hist = db.get_hist(CohortType::Domain, UnitType::Commits, IntervalType::Year, 5, &[]).unwrap();
+        assert_eq!(hist.to_csv(b'|'), "Year|Sum|lebowski.com\n2019|1|1\n2020|3|3");
+    }
+
+    #[test]
+    fn splits_commits_by_epoch() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+
+        for (i, date) in [ "2007-06-01", "2009-02-01", "2009-05-01", "2010-01-01" ].iter().enumerate()
+        {
+            db.insert_raw_commit(&test_commit(i, date)).unwrap();
+        }
+
+        let meta: ProjectMeta = serde_json::from_str(
+            r#"{ "epochs": [ { "name": "CVS", "begin": { "year": 2008 }, "end": { "year": 2009, "month": 3 } },
+                             { "name": "Git", "begin": { "year": 2009, "month": 4 } } ] }"#).unwrap();
+        db.postprocess(&meta, "Unknown", false).unwrap();
+
+        let hist = db.get_hist(CohortType::Epoch, UnitType::Commits, IntervalType::Year, 5, &[]).unwrap();
+        assert_eq!(hist.to_csv(b'|'), "Year|Sum|CVS|Unknown|Git\n2007|1|0|1|0\n2008|0|0|0|0\n2009|2|1|0|1\n2010|1|0|0|1");
+    }
+
+    #[test]
+    fn batches_hists() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+
+        for i in 0..12
+        {
+            let commit = RawCommit
+            {
+                repo_name: [ "a", "b" ][i / 6].to_string(),
+                author_name: [ "Dude", "Walter", "Donny" ][i % 3].to_string(),
+                author_email: [ "dude@lebowski.com", "walter@sobchak.com", "donny@kerabatsos.com" ][i % 3].to_string(),
+                n_insertions: i as i32,
+                ..test_commit(i, &format!("20{}-03-01", 19 + i % 2))
+            };
+            db.insert_raw_commit(&commit).unwrap();
+        }
+
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
+
+        let requests = [ (CohortType::Repo, UnitType::ActiveAuthors), (CohortType::Domain, UnitType::Changes),
+                         (CohortType::Domain, UnitType::ActiveAuthors), (CohortType::FirstYear, UnitType::Commits) ];
+        let hists = db.get_hists(&requests, IntervalType::Year, 2, &[]).unwrap();
+
+        for ((cohort, unit), hist) in requests.iter().zip(hists)
+        {
+            let single = db.get_hist(*cohort, *unit, IntervalType::Year, 2, &[]).unwrap();
+            assert_eq!(hist.to_csv(b'|'), single.to_csv(b'|'));
+        }
+
+        // Nobody makes three commits a year, so the active authors must be
+        // worked out anew.
+
+        let hists = db.get_hists(&requests[..1], IntervalType::Year, 3, &[]).unwrap();
+        assert!(hists[0].get_bounds().is_none());
+
+        // Until the Dude makes a third one.
+
+        db.insert_raw_commit(&test_commit(12, "2019-06-01")).unwrap();
+        let hists = db.get_hists(&requests[..1], IntervalType::Year, 3, &[]).unwrap();
+        assert!(hists[0].get_bounds().is_some());
+    }
+
+    #[test]
+    fn applies_identities() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+        let commits = [ ("John Smith", "john@smith.org"), ("John Smith", "jsmith@example.com"),
+                        ("Sven Neumann", "sven@gimp.org"), ("Sven Neumann", "sven@gimp.org"),
+                        ("sven neumann", "neo@wintermute.org") ];
+
+        for (i, (name, email)) in commits.iter().enumerate()
+        {
+            db.insert_raw_commit(&RawCommit { author_name: name.to_string(), author_email: email.to_string(),
+                                              ..test_commit(i, "2020-01-01") }).unwrap();
+        }
+
+        let mut meta = ProjectMeta::new();
+        meta.identities = Some(Identities { merge: vec![ vec![ "neo@wintermute.org".to_string(),
+                                                               "sven@gimp.org".to_string() ] ],
+                                            split: vec![ vec![ "john@smith.org".to_string(),
+                                                               "jsmith@example.com".to_string() ] ] });
+
+        for _ in 0..2
+        {
+            db.postprocess(&meta, "Unknown", false).unwrap();
+            assert_eq!(db.query_strings("select distinct author_name from raw_commits order by author_name;").unwrap(),
+                       [ "John Smith (john@smith.org)", "John Smith (jsmith@example.com)", "Sven Neumann" ]);
+        }
+    }
 
-            create index if not exists index_year on {column}_month_aggregates (year);
-            create index if not exists index_month on {column}_month_aggregates (month);
-            create index if not exists index_{column} on {column}_month_aggregates ({column});
-        ", column=column, from_where=from_where))
-        .chain_err(|| format!("Could not create {} per-month aggregates", column))?;
+    #[test]
+    fn binds_hostile_metadata() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+        let commits = [ ("Dude", "dude@o'brien.com", "2020-01-01"), ("Walter", "walter@lebowski.com", "2020-02-01") ];
 
-        Ok(())
+        insert_commits(&mut db, &commits);
+
+        let meta: ProjectMeta = serde_json::from_str(r#"{
+            "domains": [ { "name": "x'; drop table raw_commits; --", "show": false, "active_from": { "year": 2019 },
+                           "aggregate_emails": [ { "pattern": "*@o'brien.com" } ] } ],
+            "bots": { "include": [ { "pattern": "' or 1=1 or '" } ] },
+            "countries": { "o'brien.com": "I'E" } }"#).unwrap();
+        db.postprocess(&meta, "Unknown", false).unwrap();
+
+        assert_eq!(db.query_strings("select author_domain || '|' || show_domain || '|' || is_bot || '|' || author_country
+                                     from raw_commits order by id;").unwrap(),
+                   [ "x'; drop table raw_commits; --|0|0|I'E", "lebowski.com|1|0|Unknown" ]);
     }
 
-    fn get_column_authors_hist(&mut self, column: &str, interval: IntervalType) -> Result<CohortHist>
-    {
-        const N_ITEMS: i32 = 15;
-        let interval_str: &str;
-        let author_interval_str: &str;
-        let aggregate_table;
+    #[test]
+    fn picks_top_cohorts_per_year() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+        let commits = [ ("old", "2015-01-01"), ("old", "2015-02-01"), ("old", "2015-03-01"),
+                        ("mid", "2015-04-01"), ("mid", "2015-05-01"),
+                        ("new", "2016-01-01"), ("new", "2016-02-01"), ("new", "2016-03-01"),
+                        ("mid", "2016-04-01"), ("mid", "2016-05-01") ];
 
-        match interval
+        for (i, (repo, date)) in commits.iter().enumerate()
         {
-            IntervalType::Year =>
-            {
-                interval_str = "year";
-                author_interval_str = "author_year";
-                aggregate_table = format!("{}_year_aggregates", column);
-                if column == "prefix" {
-                    self.create_column_year_aggregates(column, Some("prefixes"))?;
-                } else if column == "suffix" {
-                    self.create_column_year_aggregates(column, Some("suffixes"))?;
-                } else {
-                    self.create_column_year_aggregates(column, None)?;
-                }
-            },
-            IntervalType::Month =>
-            {
-                interval_str = "year, month";
-                author_interval_str = "author_year, author_month";
-                aggregate_table = format!("{}_month_aggregates", column);
-                if column == "prefix" {
-                    self.create_column_month_aggregates(column, Some("prefixes"))?;
-                } else if column == "suffix" {
-                    self.create_column_month_aggregates(column, Some("suffixes"))?;
-                } else {
-                    self.create_column_month_aggregates(column, None)?;
-                }
-            }
+            db.insert_raw_commit(&RawCommit { repo_name: repo.to_string(), ..test_commit(i, date) }).unwrap();
         }
 
-        self.conn.execute (&format!("drop table {column}_top;", column = column), NO_PARAMS).ok();
-        self.conn.execute (&format!("
-            create table {column}_top as
-                select {column} as {column},row_number() over(order by sum(active_author_sum) desc) as rowid
-                from {aggregate_table}
-                group by {column}
-                order by sum(active_author_sum) desc
-                limit {n_items};",
-            column = column, aggregate_table = aggregate_table, n_items = N_ITEMS),
-            NO_PARAMS).chain_err(|| "Could not generate top domains")?;
-        let mut stmt = self.conn.prepare(&(format!("
-            select {interval}, {n_items}-{column}_top.rowid as ab, sum(active_author_sum) as ac, {column}_top.{column} as ad
-            from {column}_top, {aggregate_table}
-
-            where {aggregate_table}.{column} = {column}_top.{column}
-            group by {interval}, {column}_top.rowid",
-            interval = interval_str,
-            n_items = N_ITEMS + 1,
-            aggregate_table = aggregate_table,
-            column = column)
-
-            // TODO: Optionally hide small cohorts
-            + &format!("
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
+        db.set_hidden_cohorts(Some(HideType::Both));
 
-            union
+        let hist = db.get_hist(CohortType::Repo, UnitType::Commits, IntervalType::Year, 5, &[]).unwrap();
+        assert!(hist.to_csv(b'|').starts_with("Year|Sum|old|new|mid\n"));
 
-            select {interval},{n_items},sum(active_author_sum),\"Other\"
-            from {aggregate_table}
-            where {column} not in (select {column} from {column}_top)
-            group by {interval}",
-            interval = interval_str,
-            n_items = N_ITEMS + 1,
-            aggregate_table = aggregate_table,
-            column = column)
+        db.set_top_per_year(Some(1));
+        let hist = db.get_hist(CohortType::Repo, UnitType::Commits, IntervalType::Year, 5, &[]).unwrap();
+        assert!(hist.to_csv(b'|').starts_with("Year|Sum|old|new\n2015|3|3|0\n"));
+    }
 
-            // TODO: Optionally hide brief contributors
-            + &format!("
+    #[test]
+    fn splits_cross_domain_commits() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
 
-            union
+        for (i, committer) in [ "dude@lebowski.com", "dude@lebowski.com", "maude@art.org" ].iter().enumerate()
+        {
+            db.insert_raw_commit(&RawCommit { committer_email: committer.to_string(),
+                                              ..test_commit(i, &format!("2020-06-0{}", i + 1)) }).unwrap();
+        }
 
-            select {interval},{cohort_num},count(distinct raw_commits.author_name),\"Brief\"
-            from raw_commits, authors
-            where raw_commits.author_name=authors.author_name
-                and show_domain = true
-                and active_time <= (60*60*24*90)
-            group by {interval}",
-            interval = author_interval_str,
-            cohort_num = NO_COHORT)
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
 
-            + ";")).unwrap();
+        let hist = db.get_hist(CohortType::CrossDomain, UnitType::Commits, IntervalType::Year, 5, &[]).unwrap();
+        assert!(hist.to_csv(b'|').ends_with("\n2020|3|2|1|0"));
+    }
 
-        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
-        let mut hist = CohortHist::new();
+    #[test]
+    fn watermark_moves_on_completed_ingest() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
 
-        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        for (i, date) in [ "2015-01-01", "2016-01-01" ].iter().enumerate()
         {
-            match interval
+            let time = DateTime::parse_from_rfc3339(&format!("{}T12:00:00+00:00", date)).unwrap();
+            let commit = RawCommit
             {
-                IntervalType::Month =>
-                {
-                    hist.set_value(YearMonth { year:  r.get(0).unwrap(),
-                                               month: r.get(1).unwrap() },
-                                   r.get(2).unwrap(), r.get::<_,f64>(3).unwrap());
-                    hist.set_cohort_name(r.get(2).unwrap(), &r.get::<_, String>(4).unwrap());
-                },
-                IntervalType::Year =>
-                {
-                    hist.set_value(YearMonth { year:  r.get(0).unwrap(),
-                                               month: None },
-                                   r.get(1).unwrap(), r.get::<_,f64>(2).unwrap());
-                    hist.set_cohort_name(r.get(1).unwrap(), &r.get::<_, String>(3).unwrap());
-                }
-            }
-        }
+                id: format!("{:040}", i),
+                repo_name: "r".to_string(),
+                author_time: Some(time),
+                committer_time: Some(time),
+                ..Default::default()
+            };
 
-        // TODO: Optionally hide brief contributors
-        hist.set_cohort_name(NO_COHORT, &"Brief".to_string());
+            db.begin_repo_ingest("r").unwrap();
+            db.insert_raw_commit(&commit).unwrap();
 
-        Ok(hist)
+            // The second ingest never finishes.
+            if i == 0 { db.finish_repo_ingest("r").unwrap(); }
+        }
+
+        assert_eq!(db.get_last_author_time("r").to_rfc3339(), "2015-01-01T12:00:00+00:00");
+        assert_eq!(db.get_last_author_time("s").timestamp(), 0);
     }
 
-    fn get_subcommit_hist(&mut self, column: &str, interval: IntervalType,
-                          subtotal_sel: &str, total_sel: &str) -> Result<CohortHist>
-    {
-        const N_ITEMS: i32 = 15;
-        let interval_str: &str;
-        let author_interval_str: &str;
-        let aggregate_table;
+    #[test]
+    fn reports_repo_coverage() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
 
-        match interval
+        for (i, (repo, date)) in [ ("old", "2005-03-01"), ("old", "2020-06-01"), ("new", "2015-01-01") ].iter().enumerate()
         {
-            IntervalType::Year =>
-            {
-                interval_str = "year";
-                author_interval_str = "author_year";
-                aggregate_table = format!("{}_year_aggregates", column);
-                if column == "prefix" {
-                    self.create_subcommit_year_aggregates(column, "prefixes", subtotal_sel, total_sel)?;
-                }
-                if column == "suffix" {
-                    self.create_subcommit_year_aggregates(column, "suffixes", subtotal_sel, total_sel)?;
-                }
-            },
-            IntervalType::Month =>
+            let time = DateTime::parse_from_rfc3339(&format!("{}T12:00:00+00:00", date)).unwrap();
+            let commit = RawCommit
             {
-                interval_str = "year, month";
-                author_interval_str = "author_year, author_month";
-                aggregate_table = format!("{}_month_aggregates", column);
-                if column == "prefix" {
-                    self.create_subcommit_month_aggregates(column, "prefixes", subtotal_sel, total_sel)?;
-                }
-                if column == "suffix" {
-                    self.create_subcommit_month_aggregates(column, "suffixes", subtotal_sel, total_sel)?;
-                }
-            }
+                id: format!("{:040}", i),
+                repo_name: repo.to_string(),
+                author_time: Some(time),
+                committer_time: Some(time),
+                ..Default::default()
+            };
+            db.insert_raw_commit(&commit).unwrap();
         }
 
-        self.conn.execute (&format!("drop table {column}_top;", column = column), NO_PARAMS).ok();
-        self.conn.execute (&format!("
-            create table {column}_top as
-                select {column} as {column},row_number() over(order by sum(column_sum) desc) as rowid
-                from {aggregate_table}
-                group by {column}
-                order by sum(column_sum) desc
-                limit {n_items};",
-            column = column, aggregate_table = aggregate_table, n_items = N_ITEMS),
-            NO_PARAMS).chain_err(|| "Could not generate top domains")?;
-        let mut stmt = self.conn.prepare(&(format!("
-            select {interval}, {n_items}-{column}_top.rowid as ab, sum(column_sum) as ac, {column}_top.{column} as ad
-            from {column}_top, {aggregate_table}
+        assert_eq!(db.get_repo_coverage().unwrap(),
+                   vec![ ("new".to_string(), 1, "2015-01-01".to_string(), "2015-01-01".to_string()),
+                         ("old".to_string(), 2, "2005-03-01".to_string(), "2020-06-01".to_string()) ]);
+    }
 
-            where {aggregate_table}.{column} = {column}_top.{column}
-            group by {interval}, {column}_top.rowid",
-            interval = interval_str,
-            n_items = N_ITEMS + 1,
-            aggregate_table = aggregate_table,
-            column = column)
+    #[test]
+    fn scales_sampled_counts() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+        db.set_sample_rate(10).unwrap();
 
-            // TODO: Optionally hide small cohorts
-            + &format!("
+        let time = DateTime::parse_from_rfc3339("2020-06-01T12:00:00+00:00").unwrap();
+        let mut commit = RawCommit
+        {
+            id: format!("{:040}", 0),
+            repo_name: "r".to_string(),
+            author_name: "Dude".to_string(),
+            author_email: "dude@lebowski.com".to_string(),
+            author_time: Some(time),
+            committer_time: Some(time),
+            ..Default::default()
+        };
+        commit.n_changes_per_prefix.insert("src".to_string(), 1);
+        commit.n_changes_per_suffix.insert("c".to_string(), 1);
+        db.insert_raw_commit(&commit).unwrap();
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
 
-            union
+        let hist = db.get_hist(CohortType::Repo, UnitType::Commits, IntervalType::Year, 5, &[]).unwrap();
+        assert!(hist.to_csv(b'|').ends_with("\n2020|10|10"));
+        let hist = db.get_hist(CohortType::Repo, UnitType::Authors, IntervalType::Year, 5, &[]).unwrap();
+        assert!(hist.to_csv(b'|').ends_with("\n2020|1|1"));
 
-            select {interval},{n_items},sum(column_sum),\"Other\"
-            from {aggregate_table}
-            where {column} not in (select {column} from {column}_top)
-            group by {interval}",
-            interval = interval_str,
-            n_items = N_ITEMS + 1,
-            aggregate_table = aggregate_table,
-            column = column)
+        // Can't mix in commits sampled at another rate.
+        assert!(db.set_sample_rate(1).is_err());
+        assert!(db.set_sample_rate(10).is_ok());
+    }
 
-            // TODO: Optionally hide brief contributors
-            + &format!("
+    #[test]
+    fn exports_iso_weeks() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
 
-            union
+        // Around New Year, ISO weeks can belong to the neighboring year. The
+        // last commit is on a Monday in its author's time zone, but still on
+        // Sunday in UTC.
 
-            select {interval},{cohort_num},{count_selector},\"Brief\"
-            from raw_commits, authors
-            where raw_commits.author_name=authors.author_name
-                and show_domain = true
-                and active_time <= (60*60*24*90)
-            group by {interval}",
-            interval = author_interval_str,
-            count_selector = total_sel,
-            cohort_num = NO_COHORT)
+        for (i, time) in [ "2021-01-01T12:00:00+00:00", "2019-12-30T12:00:00+00:00",
+                           "2020-06-15T01:00:00+02:00" ].iter().enumerate()
+        {
+            let time = DateTime::parse_from_rfc3339(time).unwrap();
+            let mut commit = RawCommit
+            {
+                id: format!("{:040}", i),
+                repo_name: "r".to_string(),
+                author_name: "Dude".to_string(),
+                author_email: "dude@lebowski.com".to_string(),
+                author_time: Some(time),
+                committer_time: Some(time),
+                ..Default::default()
+            };
+            commit.n_changes_per_prefix.insert("src".to_string(), 1);
+            commit.n_changes_per_suffix.insert("c".to_string(), 1);
+            db.insert_raw_commit(&commit).unwrap();
+        }
 
-            + ";")).unwrap();
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
 
-        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
-        let mut hist = CohortHist::new();
+        let weeks = |db: &mut CommitDb| db.get_commit_records(true, &[]).unwrap().into_iter()
+            .map(|c| (c.year, c.month, c.iso_week.unwrap()))
+            .collect::<Vec<_>>();
 
-        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        assert_eq!(weeks(&mut db), vec![ (2019, 12, "2020-W01".to_string()),
+                                         (2020, 6, "2020-W25".to_string()),
+                                         (2021, 1, "2020-W53".to_string()) ]);
+
+        db.select_bucket_tz(BucketTz::Utc).unwrap();
+        assert_eq!(weeks(&mut db)[1].2, "2020-W24");
+        assert!(db.get_commit_records(false, &[]).unwrap()[0].iso_week.is_none());
+    }
+
+    #[test]
+    fn fiscal_year_shifts_years() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+
+        for (i, time) in [ "2020-03-31T12:00:00+00:00", "2020-04-01T12:00:00+00:00",
+                           "2020-12-31T12:00:00+00:00" ].iter().enumerate()
         {
-            match interval
+            let time = DateTime::parse_from_rfc3339(time).unwrap();
+            let mut commit = RawCommit
             {
-                IntervalType::Month =>
-                {
-                    hist.set_value(YearMonth { year:  r.get(0).unwrap(),
-                                               month: r.get(1).unwrap() },
-                                   r.get(2).unwrap(), r.get::<_,f64>(3).unwrap());
-                    hist.set_cohort_name(r.get(2).unwrap(), &r.get::<_, String>(4).unwrap());
-                },
-                IntervalType::Year =>
-                {
-                    hist.set_value(YearMonth { year:  r.get(0).unwrap(),
-                                               month: None },
-                                   r.get(1).unwrap(), r.get::<_,f64>(2).unwrap());
-                    hist.set_cohort_name(r.get(1).unwrap(), &r.get::<_, String>(3).unwrap());
-                }
-            }
+                id: format!("{:040}", i),
+                repo_name: "r".to_string(),
+                author_name: "Dude".to_string(),
+                author_email: "dude@lebowski.com".to_string(),
+                author_time: Some(time),
+                committer_time: Some(time),
+                ..Default::default()
+            };
+            commit.n_changes_per_prefix.insert("src".to_string(), 1);
+            commit.n_changes_per_suffix.insert("c".to_string(), 1);
+            db.insert_raw_commit(&commit).unwrap();
         }
 
-        // TODO: Optionally hide brief contributors
-        hist.set_cohort_name(NO_COHORT, &"Brief".to_string());
+        let years = |db: &CommitDb| db.query_strings(
+            "select cast(author_year as text) || '-' || author_month from raw_commits order by author_time;").unwrap();
 
-        Ok(hist)
+        db.select_buckets(BucketTz::Utc, Some(4)).unwrap();
+        assert_eq!(years(&db), vec![ "2020-2", "2021-3", "2021-11" ]);
+
+        db.select_bucket_tz(BucketTz::Utc).unwrap();
+        assert_eq!(years(&db), vec![ "2020-2", "2020-3", "2020-11" ]);
     }
 
-    pub fn get_hist(&mut self, cohort: CohortType, unit: UnitType,
-                    interval: IntervalType) -> Result<CohortHist>
-    {
-        let total_selector = match unit
-        {
-            UnitType::Authors => "count(distinct raw_commits.author_name)",
-            UnitType::Commits => "count(*)",
-            UnitType::Changes => "sum(n_insertions + n_deletions)"
-        };
+    #[test]
+    fn fiscal_year_keeps_current_commits() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+        let today = Utc::now().format("%Y-%m-%d").to_string();
 
-        let subtotal_selector = match unit
-        {
-            UnitType::Authors => "count(distinct raw_commits.author_name)",
-            UnitType::Commits => "count(*)",
-            UnitType::Changes => "sum(suffixes.n_changes)" // FIXME: Redundant
-        };
+        db.insert_raw_commit(&test_commit(0, &today)).unwrap();
+
+        // Start the fiscal year no later than this month, so today's commit
+        // lands in next year's.
+
+        db.select_buckets(BucketTz::Utc, Some(Utc::now().month().max(2))).unwrap();
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
+
+        assert_eq!(db.query_strings("select cast(author_year as text) from raw_commits;").unwrap(),
+                   vec![ (Utc::now().year() + 1).to_string() ]);
+    }
+
+    #[test]
+    fn diversity_counts_distinct_domains() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
 
-        match cohort
+        for (i, (email, year)) in [ ("a@x.org", 2019), ("b@x.org", 2019), ("c@y.org", 2019),
+                                    ("a@x.org", 2020) ].iter().enumerate()
         {
-            CohortType::FirstYear =>
-            {
-                self.get_firstyear_hist(interval, total_selector)
-            },
-            CohortType::Domain =>
-            {
-                match unit
-                {
-                    UnitType::Authors => { self.get_column_authors_hist("author_domain", interval) },
-                    _ => { self.get_column_hist("author_domain", interval, total_selector) }
-                }
-            },
-            CohortType::Repo =>
-            {
-                match unit
-                {
-                    UnitType::Authors => { self.get_column_authors_hist("repo_name", interval) },
-                    _ => { self.get_column_hist("repo_name", interval, total_selector) }
-                }
-            }
-            CohortType::Prefix =>
+            let mut commit = RawCommit
             {
-                match unit
-                {
-                    UnitType::Authors => { self.get_column_authors_hist("prefix", interval) },
-                    UnitType::Changes => { self.get_subcommit_hist("prefix", interval, "sum(prefixes.n_changes)", total_selector) },
-                    _ => { self.get_subcommit_hist("prefix", interval, subtotal_selector, total_selector) }
-                }
-            }
-            CohortType::Suffix =>
-            {
-                match unit
-                {
-                    UnitType::Authors => { self.get_column_authors_hist("suffix", interval) },
-                    UnitType::Changes => { self.get_subcommit_hist("suffix", interval, "sum(suffixes.n_changes)", total_selector) },
-                    _ => { self.get_subcommit_hist("suffix", interval, subtotal_selector, total_selector) }
-                }
-            }
+                author_name: email.to_string(),
+                author_email: email.to_string(),
+                ..test_commit(i, &format!("{}-06-0{}", year, i + 1))
+            };
+            commit.n_changes_per_prefix.insert("src".to_string(), 1);
+            commit.n_changes_per_suffix.insert("c".to_string(), 1);
+            db.insert_raw_commit(&commit).unwrap();
         }
+
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
+
+        let hist = db.get_diversity_hist(CohortType::Domain, IntervalType::Year, &[]).unwrap();
+        assert_eq!(hist.to_csv(b'|'), "Year|Sum|Domains\n2019|2|2\n2020|1|1");
+        assert!(db.get_diversity_hist(CohortType::FirstYear, IntervalType::Year, &[]).is_err());
     }
-}
 
-fn email_to_domain(email: &str) -> String
-{
-    let mut email: String = email.to_lowercase();
+    #[test]
+    fn identity_skew_counts_names_and_emails() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
 
-    // Strip local part.
+        insert_commits(&mut db, &[ ("Ann", "ann@x.org", "2019-06-01"), ("Ann", "ann@home.org", "2019-06-02"),
+                                   ("Bob", "bob@x.org", "2019-06-03"), ("Ann", "ANN@x.org", "2020-06-04") ]);
 
-    if let Some(p) = email.rfind('@') {
-        email.replace_range(0..=p, "");
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
+
+        let hist = db.get_identity_skew_hist(IntervalType::Year, &[]).unwrap();
+        assert_eq!(hist.to_csv(b'|'), "Year|Sum|Names|E-mail addresses\n2019|5|2|3\n2020|2|1|1");
     }
 
-    // Trim the domain as much as possible. If the last element looks
-    // like a country code and the next-to-last one is 2-3 letters, it's
-    // likely of the form 'domain.ac.uk' or 'domain.com.au'. We keep
-    // three elements in those cases. Otherwise we keep two as in
-    // 'domain.org'.
-    //
-    // If we wanted to get fancy we could've used this list:
-    //
-    // https://publicsuffix.org/list/public_suffix_list.dat
-    //
-    // ...but the relative gain is likely not worth it.
+    #[test]
+    fn firstyear_share_includes_brief_authors() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+        insert_commits(&mut db, &[ ("old", "old@x.org", "2019-01-01"), ("old", "old@x.org", "2020-01-01"),
+                                   ("old", "old@x.org", "2020-02-01"), ("old", "old@x.org", "2020-03-01"),
+                                   ("new", "new@x.org", "2020-04-01"), ("new", "new@x.org", "2020-10-01"),
+                                   ("brief", "brief@x.org", "2020-05-01"), ("brief", "brief@x.org", "2020-05-02") ]);
 
-    let split: Vec<&str> = email.split('.').collect();
-    let n = split.len();
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
 
-    if n > 2
-    {
-        if split[n - 1].len() < 3
+        let hist = db.get_hist(CohortType::FirstYear, UnitType::CommitShare, IntervalType::Year, 5, &[]).unwrap();
+        assert_eq!(hist.to_csv(b'|'), "Year|Sum|2019|2020|Brief\n2019|100|100|0|0\n2020|100|42.9|28.6|28.6");
+
+        db.set_hidden_cohorts(Some(HideType::Both));
+        let hist = db.get_hist(CohortType::FirstYear, UnitType::CommitShare, IntervalType::Year, 5, &[]).unwrap();
+        assert_eq!(hist.to_csv(b'|'), "Year|Sum|2019|2020\n2019|100|100|0\n2020|71.4|42.9|28.6");
+
+        assert!(db.get_hist(CohortType::Domain, UnitType::CommitShare, IntervalType::Year, 5, &[]).is_err());
+    }
+
+    #[test]
+    fn infers_country_from_tld() {
+        assert_eq!(email_to_country("dude@lebowski.de"), Some("DE".to_string()));
+        assert_eq!(email_to_country("dude@cam.ac.uk"), Some("GB".to_string()));
+        assert_eq!(email_to_country("dude@lebowski.com"), None);
+        assert_eq!(email_to_country("dude@lebowski.io"), None);
+        assert_eq!(email_to_country("dude"), None);
+    }
+
+    #[test]
+    fn parses_commit_types() {
+        assert_eq!(commit_type("feat: Add a thing"), "feat");
+        assert_eq!(commit_type("fix(parser)!: Don't crash"), "fix");
+        assert_eq!(commit_type("Chore: Bump version"), "chore");
+        assert_eq!(commit_type("\u{1f41b} Fix crash"), "fix");
+        assert_eq!(commit_type(":sparkles: Add a thing"), "feat");
+        assert_eq!(commit_type("Revert \"feat: Add a thing\""), "revert");
+        assert_eq!(commit_type("gtk: Fix a leak"), "other");
+        assert_eq!(commit_type("fix the thing: again"), "other");
+        assert_eq!(commit_type(""), "other");
+    }
+
+    #[test]
+    fn country_overrides_apply_to_domains() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+
+        for (i, email) in [ "a@suse.de", "b@gmail.com", "c@example.fr" ].iter().cycle().take(6).enumerate()
         {
-            if split[n - 2].len() < 4
-            {
-                // domain.com.au
-                split[n - 3..n].join(".")
-            }
-            else
-            {
-                // domain.au
-                split[n - 2..n].join(".")
-            }
+            db.insert_raw_commit(&RawCommit { author_name: email.to_string(), author_email: email.to_string(),
+                                              ..test_commit(i, &format!("2020-{:02}-01", i * 2 + 1)) }).unwrap();
         }
-        else
+
+        let mut meta = ProjectMeta::new();
+        meta.countries = Some([ ("example.fr".to_string(), "be".to_string()) ].iter().cloned().collect());
+        db.postprocess(&meta, "Unknown", false).unwrap();
+        db.set_hidden_cohorts(Some(HideType::Both));
+
+        let hist = db.get_hist(CohortType::Country, UnitType::Commits, IntervalType::Year, 5, &[]).unwrap();
+        assert_eq!(hist.to_csv(b'|'), "Year|Sum|BE|DE|Unknown\n2020|6|2|2|2");
+    }
+
+    #[test]
+    fn joins_author_events_to_first_commits() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+
+        for (i, date) in [ "2020-03-01", "2020-01-01" ].iter().enumerate()
         {
-            // domain.org
-            split[n - 2..n].join(".")
+            let time = DateTime::parse_from_rfc3339(&format!("{}T00:00:00+00:00", date)).unwrap();
+            let commit = RawCommit
+            {
+                id: format!("{:040}", i),
+                repo_name: "r".to_string(),
+                author_name: "Dude".to_string(),
+                author_email: "Dude@Lebowski.com".to_string(),
+                author_time: Some(time),
+                committer_time: Some(time),
+                ..Default::default()
+            };
+            db.insert_raw_commit(&commit).unwrap();
         }
+
+        let event = |email: &str, kind: &str, time| AuthorEvent { email: email.to_string(), kind: kind.to_string(), time };
+        db.import_author_events(&[ event("dude@lebowski.com", "post", 0), event("walter@sobchak.com", "post", 1) ])
+            .unwrap();
+        db.import_author_events(&[ event("dude@lebowski.com", "post", 1577836800 - 86400),
+                                   event("dude@lebowski.com", "post", 1577836800 - 2 * 86400) ]).unwrap();
+
+        assert_eq!(db.get_onboarding("post").unwrap(),
+                   vec![ ("dude@lebowski.com".to_string(), Some("Dude".to_string()), 1577836800 - 2 * 86400,
+                          Some(1577836800)) ]);
+        assert!(db.get_onboarding("cla").is_err());
     }
-    else
-    {
-        // Already optimal, or malformed
-        email
-    }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn inspect_commit_resolves_prefixes() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+
+        for (id, committer, time) in &[ ("abc0", "dude@lebowski.com", "2020-06-01T12:00:00+00:00"),
+                                        ("abd0", "walter@sobchak.com", "2020-06-02T12:00:00+00:00") ]
+        {
+            let time = DateTime::parse_from_rfc3339(time).unwrap();
+            let mut commit = RawCommit
+            {
+                id: id.to_string(),
+                repo_name: "r".to_string(),
+                author_name: "Dude".to_string(),
+                author_email: "dude@lebowski.com".to_string(),
+                author_time: Some(time),
+                committer_email: committer.to_string(),
+                committer_time: Some(time),
+                ..Default::default()
+            };
+            commit.n_changes_per_prefix.insert("src".to_string(), 1);
+            commit.n_changes_per_suffix.insert("c".to_string(), 1);
+            db.insert_raw_commit(&commit).unwrap();
+        }
+
+        db.postprocess(&ProjectMeta::new(), "Unknown", false).unwrap();
+
+        assert!(db.inspect_commit("ab").is_err());
+        assert!(db.inspect_commit("abe").is_err());
+
+        let details = db.inspect_commit("abd").unwrap();
+        let cohort = |name: &str| details.cohorts.iter().find(|c| c.0 == name).unwrap().1.clone();
+
+        assert!(details.fields.contains(&("id".to_string(), "abd0".to_string())));
+        assert_eq!(details.suffixes, vec![ ("c".to_string(), 1, 0, 0) ]);
+        assert_eq!(cohort("domain"), "lebowski.com");
+        assert_eq!(cohort("crossdomain"), "External, accepted");
+        assert_eq!(cohort("brief"), "yes");
+    }
 
     #[test]
-    fn strips_email_username() {
-        assert_eq!(email_to_domain("dude@lebowski.com"), "lebowski.com");
+    fn snapshot_trims_and_anonymizes() {
+        let mut db = CommitDb::open(":memory:".into()).unwrap();
+
+        for (i, year) in [ 2015, 2019, 2020 ].iter().enumerate()
+        {
+            let mut commit = RawCommit { subject: "Abide".to_string(), ..test_commit(i, &format!("{}-06-01", year)) };
+            commit.n_changes_per_prefix.insert("src".to_string(), 1);
+            commit.n_changes_per_suffix.insert("c".to_string(), 1);
+            db.insert_raw_commit(&commit).unwrap();
+        }
+
+        let out_path = std::env::temp_dir().join(format!("fornalder-snapshot-{}.sqlite", std::process::id()));
+        db.write_snapshot(&out_path, true, Some(2), Some("abc".to_string())).unwrap();
+        assert!(db.write_snapshot(&out_path, false, None, None).is_err());
+
+        let snap = CommitDb::open(out_path.clone()).unwrap();
+        let rows = snap.query_strings("
+            select author_name || '|' || author_email || '|' || subject from raw_commits;").unwrap();
+        let n_prefixes: i64 = snap.conn.query_row("select count(*) from prefixes;", NO_PARAMS, |r| r.get(0))
+            .unwrap();
+        let meta_hash = snap.get_db_meta("snapshot_meta_hash").unwrap();
+        drop(snap);
+        std::fs::remove_file(&out_path).unwrap();
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0], rows[1]);
+        assert!(rows[0].starts_with("Author "));
+        assert!(rows[0].contains("@lebowski.com|"));
+        assert!(!rows[0].contains("Dude") && !rows[0].contains("dude") && !rows[0].contains("Abide"));
+        assert_eq!(rows[0].rsplit('|').next().unwrap().len(), 5);
+        assert_eq!(n_prefixes, 2);
+        assert_eq!(meta_hash.as_deref(), Some("abc"));
     }
 }
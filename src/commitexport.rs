@@ -0,0 +1,249 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+
+/* ------------- *
+ * Commit export *
+ * ------------- */
+
+use chrono::Datelike;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::*;
+use crate::cohorthist::CohortHist;
+use crate::commitdb::CommitRecord;
+use crate::common::ExportFormat;
+use crate::csvformat::{ csv_header, format_csv };
+use crate::errors::*;
+use crate::parquetexport::{ ParquetColumn, write_parquet };
+use crate::timebin::TimeBin;
+
+// Per-commit rows for BI tools and spreadsheets, which do their own
+// bucketing. The ISO week column is only present if it was asked for.
+
+pub struct CommitExport
+{
+    commits: Vec<CommitRecord>,
+    iso_week: bool
+}
+
+impl CommitExport
+{
+    pub fn new(commits: Vec<CommitRecord>, iso_week: bool) -> CommitExport
+    {
+        CommitExport { commits, iso_week }
+    }
+
+    pub fn n_commits(&self) -> usize
+    {
+        self.commits.len()
+    }
+
+    pub fn to_csv(&self, delimiter: u8) -> String
+    {
+        let mut rows = vec![ csv_header(&[ "id", "repo", "author_name", "author_domain", "author_time",
+                                           "year", "month" ]) ];
+
+        if self.iso_week { rows[0].push("iso_week".to_string()); }
+        rows[0].extend(csv_header(&[ "n_insertions", "n_deletions" ]));
+
+        for c in &self.commits
+        {
+            let mut row = vec![ c.id.clone(), c.repo.clone(), c.author_name.clone(), c.author_domain.clone(),
+                                c.author_time.clone(), c.year.to_string(), c.month.to_string() ];
+
+            if self.iso_week { row.push(c.iso_week.clone().unwrap_or_default()); }
+            row.extend(vec![ c.n_insertions.to_string(), c.n_deletions.to_string() ]);
+            rows.push(row);
+        }
+
+        format_csv(&rows, delimiter)
+    }
+
+    fn to_parquet_columns(&self) -> Vec<(&'static str, ParquetColumn)>
+    {
+        let c = &self.commits;
+        let mut columns = vec![
+            ("id", ParquetColumn::Str(c.iter().map(|c| c.id.clone()).collect())),
+            ("repo", ParquetColumn::Str(c.iter().map(|c| c.repo.clone()).collect())),
+            ("author_name", ParquetColumn::Str(c.iter().map(|c| c.author_name.clone()).collect())),
+            ("author_domain", ParquetColumn::Str(c.iter().map(|c| c.author_domain.clone()).collect())),
+            ("author_time", ParquetColumn::Str(c.iter().map(|c| c.author_time.clone()).collect())),
+            ("year", ParquetColumn::Int(c.iter().map(|c| c.year).collect())),
+            ("month", ParquetColumn::Int(c.iter().map(|c| c.month).collect())) ];
+
+        if self.iso_week
+        {
+            columns.push(("iso_week", ParquetColumn::OptStr(c.iter().map(|c| c.iso_week.clone()).collect())));
+        }
+
+        columns.push(("n_insertions", ParquetColumn::Long(c.iter().map(|c| c.n_insertions).collect())));
+        columns.push(("n_deletions", ParquetColumn::Long(c.iter().map(|c| c.n_deletions).collect())));
+        columns
+    }
+
+    pub fn write(&self, out_path: &Path, format: ExportFormat, delimiter: u8) -> Result<()>
+    {
+        let content = match format
+        {
+            ExportFormat::Csv => self.to_csv(delimiter),
+            ExportFormat::Json => serde_json::to_string_pretty(&self.commits)
+                .chain_err(|| "Could not serialize commits")?,
+            ExportFormat::Parquet => return write_parquet(out_path, "commits", &self.to_parquet_columns())
+        };
+
+        fs::write(out_path, content + "\n").chain_err(|| "Could not write commits")
+    }
+}
+
+// Commits, authors and changes per interval and cohort, as plotted, for
+// tools that would otherwise have to redo the cohort assignment. Months and
+// days are one-based.
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct AggregateRecord
+{
+    pub year: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub month: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub day: Option<i32>,
+    pub cohort: String,
+    pub n_commits: f64,
+    pub n_authors: f64,
+    pub n_changes: f64
+}
+
+pub struct AggregateExport
+{
+    rows: Vec<AggregateRecord>
+}
+
+impl AggregateExport
+{
+    pub fn new(commits: &CohortHist, authors: &CohortHist, changes: &CohortHist) -> AggregateExport
+    {
+        // The histograms may rank their cohorts differently, so they're
+        // matched up by name. The first value of each bin is the total.
+
+        let mut values: BTreeMap<(TimeBin, String), [f64; 3]> = BTreeMap::new();
+
+        for (i, hist) in [ commits, authors, changes ].iter().enumerate()
+        {
+            for (ym, bins) in hist.to_vecs()
+            {
+                for (cohort, value) in bins.iter().skip(1)
+                {
+                    values.entry((ym, hist.get_cohort_name(*cohort))).or_insert([0.0; 3])[i] += value;
+                }
+            }
+        }
+
+        let rows = values.into_iter()
+            .filter(|(_, v)| v.iter().any(|x| *x != 0.0))
+            .map(|((ym, cohort), v)|
+            {
+                let (month, day) = match ym
+                {
+                    TimeBin::Month(_, m) => (Some(m as i32 + 1), None),
+                    TimeBin::Day(date) => (Some(date.month() as i32), Some(date.day() as i32)),
+                    _ => (None, None)
+                };
+
+                AggregateRecord
+                {
+                    year: ym.year(),
+                    month,
+                    day,
+                    cohort,
+                    n_commits: v[0],
+                    n_authors: v[1],
+                    n_changes: v[2]
+                }
+            })
+            .collect();
+
+        AggregateExport { rows }
+    }
+
+    pub fn n_rows(&self) -> usize
+    {
+        self.rows.len()
+    }
+
+    pub fn to_csv(&self, delimiter: u8) -> String
+    {
+        let mut rows = vec![ csv_header(&[ "year", "month", "day", "cohort", "n_commits", "n_authors",
+                                           "n_changes" ]) ];
+
+        rows.extend(self.rows.iter()
+                    .map(|r| vec![ r.year.to_string(),
+                                   r.month.map_or("".to_string(), |m| m.to_string()),
+                                   r.day.map_or("".to_string(), |d| d.to_string()),
+                                   r.cohort.clone(), r.n_commits.to_string(), r.n_authors.to_string(),
+                                   r.n_changes.to_string() ]));
+        format_csv(&rows, delimiter)
+    }
+
+    pub fn write(&self, out_path: &Path, format: ExportFormat, delimiter: u8) -> Result<()>
+    {
+        let r = &self.rows;
+        let content = match format
+        {
+            ExportFormat::Csv => self.to_csv(delimiter),
+            ExportFormat::Json => serde_json::to_string_pretty(&self.rows)
+                .chain_err(|| "Could not serialize aggregates")?,
+            ExportFormat::Parquet => return write_parquet(out_path, "aggregates", &[
+                ("year", ParquetColumn::Int(r.iter().map(|r| r.year).collect())),
+                ("month", ParquetColumn::OptInt(r.iter().map(|r| r.month).collect())),
+                ("day", ParquetColumn::OptInt(r.iter().map(|r| r.day).collect())),
+                ("cohort", ParquetColumn::Str(r.iter().map(|r| r.cohort.clone()).collect())),
+                ("n_commits", ParquetColumn::Double(r.iter().map(|r| r.n_commits).collect())),
+                ("n_authors", ParquetColumn::Double(r.iter().map(|r| r.n_authors).collect())),
+                ("n_changes", ParquetColumn::Double(r.iter().map(|r| r.n_changes).collect())) ])
+        };
+
+        fs::write(out_path, content + "\n").chain_err(|| "Could not write aggregates")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_aggregates_by_cohort_name() {
+        let mut commits = CohortHist::new();
+        commits.set_value(TimeBin::Year(2020), 0, 3.0);
+        commits.set_cohort_name(0, "suse.de");
+
+        let mut authors = CohortHist::new();
+        authors.set_value(TimeBin::Year(2020), 0, 1.0);
+        authors.set_value(TimeBin::Year(2020), 1, 2.0);
+        authors.set_cohort_name(0, "gnome.org");
+        authors.set_cohort_name(1, "suse.de");
+
+        let export = AggregateExport::new(&commits, &authors, &CohortHist::new());
+        assert_eq!(export.to_csv(b','), "year,month,day,cohort,n_commits,n_authors,n_changes\n\
+                                         2020,,,gnome.org,0,1,0\n\
+                                         2020,,,suse.de,3,2,0");
+    }
+}
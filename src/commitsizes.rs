@@ -0,0 +1,167 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* ------------ *
+ * Commit sizes *
+ * ------------ */
+
+use serde::{Serialize};
+use std::fs;
+use std::path::*;
+use crate::common::ExportFormat;
+use crate::csvformat::{ csv_header, format_csv };
+use crate::errors::*;
+use crate::parquetexport::{ ParquetColumn, write_parquet };
+use crate::timebin::TimeBin;
+
+#[derive(Serialize, Debug)]
+struct CommitSizeRow
+{
+    interval: String,
+    cohort: String,
+    n_commits: i64,
+    mean: f64,
+    p10: i64,
+    p25: i64,
+    p50: i64,
+    p75: i64,
+    p90: i64,
+    max: i64
+}
+
+// The distribution of commit sizes (lines inserted plus deleted) per
+// interval and cohort, to compare how big the patches of each cohort are.
+
+pub struct CommitSizes
+{
+    rows: Vec<CommitSizeRow>
+}
+
+fn percentile(sorted: &[i64], p: usize) -> i64
+{
+    // Nearest rank, so every percentile is a size that actually occurred.
+
+    let rank = (p * sorted.len()).div_ceil(100);
+    sorted[rank.max(1) - 1]
+}
+
+impl CommitSizes
+{
+    pub fn new(sizes: &[(TimeBin, i32, String, i64)]) -> CommitSizes
+    {
+        // Sizes arrive ordered by interval, cohort rank and size, so each
+        // group is a sorted run.
+
+        let mut rows = Vec::new();
+        let mut start = 0;
+
+        while start < sizes.len()
+        {
+            let (ym, rank, cohort, _) = &sizes[start];
+            let end = start + sizes[start..].iter()
+                .take_while(|(y, r, _, _)| y == ym && r == rank)
+                .count();
+            let run: Vec<i64> = sizes[start..end].iter().map(|(_, _, _, size)| *size).collect();
+
+            rows.push(CommitSizeRow
+            {
+                interval: ym.to_string(),
+                cohort: cohort.clone(),
+                n_commits: run.len() as i64,
+                mean: run.iter().sum::<i64>() as f64 / run.len() as f64,
+                p10: percentile(&run, 10),
+                p25: percentile(&run, 25),
+                p50: percentile(&run, 50),
+                p75: percentile(&run, 75),
+                p90: percentile(&run, 90),
+                max: run[run.len() - 1]
+            });
+
+            start = end;
+        }
+
+        CommitSizes { rows }
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.rows.is_empty()
+    }
+
+    pub fn to_csv(&self, delimiter: u8) -> String
+    {
+        let mut rows = vec![ csv_header(&[ "interval", "cohort", "n_commits", "mean", "p10", "p25", "p50",
+                                           "p75", "p90", "max" ]) ];
+
+        rows.extend(self.rows.iter()
+                    .map(|r| vec![ r.interval.clone(), r.cohort.clone(), r.n_commits.to_string(),
+                                   format!("{:.1}", r.mean), r.p10.to_string(), r.p25.to_string(),
+                                   r.p50.to_string(), r.p75.to_string(), r.p90.to_string(),
+                                   r.max.to_string() ]));
+        format_csv(&rows, delimiter)
+    }
+
+    pub fn write(&self, out_path: &Path, format: ExportFormat, delimiter: u8) -> Result<()>
+    {
+        let content = match format
+        {
+            ExportFormat::Csv => self.to_csv(delimiter),
+            ExportFormat::Json => serde_json::to_string_pretty(&self.rows)
+                .chain_err(|| "Could not serialize commit sizes")?,
+            ExportFormat::Parquet =>
+            {
+                let r = &self.rows;
+                let long = |f: fn(&CommitSizeRow) -> i64| ParquetColumn::Long(r.iter().map(f).collect());
+                return write_parquet(out_path, "commit_sizes", &[
+                    ("interval", ParquetColumn::Str(r.iter().map(|r| r.interval.clone()).collect())),
+                    ("cohort", ParquetColumn::Str(r.iter().map(|r| r.cohort.clone()).collect())),
+                    ("n_commits", long(|r| r.n_commits)),
+                    ("mean", ParquetColumn::Double(r.iter().map(|r| r.mean).collect())),
+                    ("p10", long(|r| r.p10)),
+                    ("p25", long(|r| r.p25)),
+                    ("p50", long(|r| r.p50)),
+                    ("p75", long(|r| r.p75)),
+                    ("p90", long(|r| r.p90)),
+                    ("max", long(|r| r.max)) ]);
+            }
+        };
+
+        fs::write(out_path, content + "\n").chain_err(|| "Could not write commit sizes")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_sizes_per_cohort() {
+        let y2020 = TimeBin::Year(2020);
+        let sizes: Vec<_> = [ 1, 2, 3, 4, 10, 20, 30, 40, 50, 100 ].iter()
+            .map(|s| (y2020, 0, "corp.com".to_string(), *s))
+            .chain(vec![ (y2020, 1, "Other".to_string(), 7) ])
+            .collect();
+
+        assert_eq!(CommitSizes::new(&sizes).to_csv(b','),
+                   "interval,cohort,n_commits,mean,p10,p25,p50,p75,p90,max\n\
+                    2020,corp.com,10,26.0,1,3,10,40,50,100\n\
+                    2020,Other,1,7.0,7,7,7,7,7,7");
+    }
+}
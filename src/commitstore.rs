@@ -0,0 +1,196 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* ----------- *
+ * CommitStore *
+ * ----------- */
+
+use chrono::{ DateTime, Utc };
+use std::path::{ Path, PathBuf };
+use crate::commitdb::CommitDb;
+use crate::common::DbProfile;
+use crate::errors::*;
+use crate::remotedb::is_http_url;
+use crate::gitcommitreader::{ RawCommit, RawMerge };
+
+// The operations ingestion needs from a database. SQLite is the default;
+// with the "postgres" feature, commits can also be ingested into PostgreSQL
+// using the same table layout, for joining with other data there. Queries
+// run on SQLite either way: a PostgreSQL database is copied into memory
+// first, so charts and exports come out the same from both.
+
+pub trait CommitStore
+{
+    fn insert_raw_commit(&mut self, commit: &RawCommit) -> Result<()>;
+    fn insert_merge(&mut self, merge: &RawMerge) -> Result<()>;
+    fn get_last_author_time(&mut self, repo_name: &str) -> DateTime<Utc>;
+    fn begin_repo_ingest(&mut self, repo_name: &str) -> Result<()>;
+    fn finish_repo_ingest(&mut self, repo_name: &str) -> Result<()>;
+    fn get_repo_branches(&mut self, repo_name: &str) -> Result<Option<Vec<String>>>;
+    fn set_repo_branches(&mut self, repo_name: &str, branches: &[String]) -> Result<()>;
+    fn set_changes_unreliable(&mut self, repo_name: &str) -> Result<()>;
+    fn set_sample_rate(&mut self, sample_rate: u32) -> Result<()>;
+    fn mark_ingested(&mut self) -> Result<()>;
+    fn into_query_db(self: Box<Self>) -> Result<CommitDb>;
+}
+
+impl CommitStore for CommitDb
+{
+    fn insert_raw_commit(&mut self, commit: &RawCommit) -> Result<()>
+    {
+        CommitDb::insert_raw_commit(self, commit)
+    }
+
+    fn insert_merge(&mut self, merge: &RawMerge) -> Result<()>
+    {
+        CommitDb::insert_merge(self, merge)
+    }
+
+    fn get_last_author_time(&mut self, repo_name: &str) -> DateTime<Utc>
+    {
+        CommitDb::get_last_author_time(self, repo_name)
+    }
+
+    fn begin_repo_ingest(&mut self, repo_name: &str) -> Result<()>
+    {
+        CommitDb::begin_repo_ingest(self, repo_name)
+    }
+
+    fn finish_repo_ingest(&mut self, repo_name: &str) -> Result<()>
+    {
+        CommitDb::finish_repo_ingest(self, repo_name)
+    }
+
+    fn get_repo_branches(&mut self, repo_name: &str) -> Result<Option<Vec<String>>>
+    {
+        CommitDb::get_repo_branches(self, repo_name)
+    }
+
+    fn set_repo_branches(&mut self, repo_name: &str, branches: &[String]) -> Result<()>
+    {
+        CommitDb::set_repo_branches(self, repo_name, branches)
+    }
+
+    fn set_changes_unreliable(&mut self, repo_name: &str) -> Result<()>
+    {
+        CommitDb::set_changes_unreliable(self, repo_name)
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) -> Result<()>
+    {
+        CommitDb::set_sample_rate(self, sample_rate)
+    }
+
+    fn mark_ingested(&mut self) -> Result<()>
+    {
+        CommitDb::mark_ingested(self)
+    }
+
+    fn into_query_db(self: Box<Self>) -> Result<CommitDb>
+    {
+        Ok(*self)
+    }
+}
+
+pub fn is_postgres_url(db_path: &Path) -> bool
+{
+    let s = db_path.to_string_lossy();
+    s.starts_with("postgres://") || s.starts_with("postgresql://")
+}
+
+// Opens a PostgreSQL database for connection URLs, and SQLite otherwise. The
+// profile only applies to SQLite; PostgreSQL durability is up to the server.
+
+pub fn open_store(db_path: PathBuf, profile: DbProfile) -> Result<Box<dyn CommitStore>>
+{
+    if is_http_url(&db_path)
+    {
+        bail!("Can't ingest into a database given by HTTP URL");
+    }
+
+    if is_postgres_url(&db_path)
+    {
+        open_postgres(&db_path.to_string_lossy())
+    }
+    else
+    {
+        Ok(Box::new(CommitDb::open_with_profile(db_path, profile)?))
+    }
+}
+
+// Opens a database for plotting and other commands that only read from it.
+// Postprocessing a PostgreSQL database's in-memory copy leaves the original
+// as it is.
+
+pub fn open_for_queries(db_path: PathBuf) -> Result<CommitDb>
+{
+    if is_postgres_url(&db_path)
+    {
+        connect_postgres(&db_path.to_string_lossy())?.into_query_db()
+    }
+    else
+    {
+        CommitDb::open(db_path)
+    }
+}
+
+// Like open_store(), but leaves the database as it is, for reading the
+// watermarks and remembered branches on a dry run.
+
+pub fn open_store_read_only(db_path: PathBuf) -> Result<Box<dyn CommitStore>>
+{
+    if is_http_url(&db_path)
+    {
+        bail!("Can't ingest into a database given by HTTP URL");
+    }
+
+    if is_postgres_url(&db_path)
+    {
+        connect_postgres(&db_path.to_string_lossy())
+    }
+    else
+    {
+        Ok(Box::new(CommitDb::open_read_only(db_path)?))
+    }
+}
+
+#[cfg(feature = "postgres")]
+fn open_postgres(url: &str) -> Result<Box<dyn CommitStore>>
+{
+    Ok(Box::new(crate::pgcommitdb::PgCommitDb::open(url)?))
+}
+
+#[cfg(feature = "postgres")]
+fn connect_postgres(url: &str) -> Result<Box<dyn CommitStore>>
+{
+    Ok(Box::new(crate::pgcommitdb::PgCommitDb::connect(url)?))
+}
+
+#[cfg(not(feature = "postgres"))]
+fn open_postgres(_url: &str) -> Result<Box<dyn CommitStore>>
+{
+    bail!("PostgreSQL support is not enabled; rebuild with --features postgres")
+}
+
+#[cfg(not(feature = "postgres"))]
+fn connect_postgres(url: &str) -> Result<Box<dyn CommitStore>>
+{
+    open_postgres(url)
+}
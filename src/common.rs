@@ -22,6 +22,7 @@
  * Common types *
  * ------------ */
 
+use std::str::FromStr;
 use structopt::clap::arg_enum;
 use structopt::StructOpt;
 
@@ -31,10 +32,100 @@ arg_enum!
     pub enum CohortType
     {
         FirstYear,
+        FirstSuffix,
         Domain,
         Repo,
         Prefix,
-        Suffix
+        Suffix,
+        WorkHours,
+        Cadence,
+        CrossDomain,
+        Country,
+        Epoch,
+        CommitType,
+        FirstYearDomain
+    }
+}
+
+// Nested cohorts are given as "<outer>:<inner>", e.g. "firstyear:domain",
+// which splits each first-year generation by domain. That's the only
+// nesting supported so far.
+
+pub fn parse_cohort_type(s: &str) -> Result<CohortType, String>
+{
+    match s.to_lowercase().as_str()
+    {
+        "firstyear:domain" => Ok(CohortType::FirstYearDomain),
+        _ => s.parse()
+    }
+}
+
+// A single cohort given as "<type>=<name>", e.g. "domain=suse.de".
+
+#[derive(Debug, Clone)]
+pub struct CohortSelector
+{
+    pub cohort: CohortType,
+    pub name: String
+}
+
+impl FromStr for CohortSelector
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CohortSelector, String>
+    {
+        let (cohort, name) = s.split_at(s.find('=')
+            .ok_or_else(|| format!("Expected <type>=<name>, got '{}'", s))?);
+
+        Ok(CohortSelector { cohort: cohort.parse()?, name: name[1..].to_string() })
+    }
+}
+
+// An inclusive span of years given as "2018..2020", or a single year.
+
+#[derive(Debug, Copy, Clone)]
+pub struct YearRange
+{
+    pub first: i32,
+    pub last: i32
+}
+
+impl YearRange
+{
+    pub fn n_years(&self) -> i32
+    {
+        self.last - self.first + 1
+    }
+}
+
+impl std::fmt::Display for YearRange
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        if self.first == self.last { write!(f, "{}", self.first) }
+        else { write!(f, "{}-{}", self.first, self.last) }
+    }
+}
+
+impl FromStr for YearRange
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<YearRange, String>
+    {
+        let invalid = || format!("Expected <year> or <year>..<year>, got '{}'", s);
+        let mut parts = s.splitn(2, "..");
+        let first: i32 = parts.next().unwrap().parse().map_err(|_| invalid())?;
+        let last: i32 = match parts.next()
+        {
+            Some(l) => l.parse().map_err(|_| invalid())?,
+            None => first
+        };
+
+        if last < first { return Err(invalid()); }
+
+        Ok(YearRange { first, last })
     }
 }
 
@@ -44,8 +135,155 @@ arg_enum!
     pub enum UnitType
     {
         Authors,
+        ActiveAuthors,
         Commits,
-        Changes
+        Changes,
+        Reverts,
+        WeightedCommits,
+        CommitShare
+    }
+}
+
+impl UnitType
+{
+    pub fn decimals(&self) -> usize
+    {
+        // Authors who commit to several cohorts in an interval are split
+        // between them, so author counts can be fractional. Everything else
+        // is a whole number.
+
+        match self
+        {
+            UnitType::Authors | UnitType::ActiveAuthors => 2,
+            UnitType::WeightedCommits | UnitType::CommitShare => 1,
+            _ => 0
+        }
+    }
+
+    pub fn counts_commits(&self) -> bool
+    {
+        // Whether values are sums over commits, and so can be scaled up to
+        // estimate the full history from a sample. Author counts and
+        // shares can't.
+
+        !matches!(self, UnitType::Authors | UnitType::ActiveAuthors | UnitType::CommitShare)
+    }
+}
+
+arg_enum!
+{
+    #[derive(StructOpt, Debug, Copy, Clone)]
+    pub enum TenureCurve
+    {
+        Linear,
+        Sqrt,
+        Log
+    }
+}
+
+impl TenureCurve
+{
+    pub fn weight(&self, years: f64) -> f64
+    {
+        // A newcomer's commit counts once; it grows from there with the
+        // number of years since the author's first commit.
+
+        let years = years.max(0.0);
+
+        match self
+        {
+            TenureCurve::Linear => 1.0 + years,
+            TenureCurve::Sqrt => 1.0 + years.sqrt(),
+            TenureCurve::Log => 1.0 + years.ln_1p()
+        }
+    }
+}
+
+arg_enum!
+{
+    #[derive(StructOpt, Debug, Copy, Clone)]
+    pub enum ChartType
+    {
+        Stacked,
+        Cumulative,
+        CohortDetail,
+        Lorenz,
+        ContributorsStrip,
+        Seasonal,
+        Growth,
+        Diversity,
+        IdentitySkew
+    }
+}
+
+// Chart types may be written with dashes between words, e.g.
+// "identity-skew" or "cohort-detail".
+
+pub fn parse_chart_type(s: &str) -> Result<ChartType, String>
+{
+    s.replace('-', "").parse()
+}
+
+arg_enum!
+{
+    #[derive(StructOpt, Debug, Copy, Clone)]
+    pub enum SeasonalView
+    {
+        Profile,
+        Adjusted
+    }
+}
+
+arg_enum!
+{
+    #[derive(StructOpt, Debug, Copy, Clone)]
+    pub enum FacetType
+    {
+        Repo
+    }
+}
+
+arg_enum!
+{
+    #[derive(StructOpt, Debug, Copy, Clone)]
+    pub enum HideType
+    {
+        Other,
+        Brief,
+        Both
+    }
+}
+
+arg_enum!
+{
+    #[derive(StructOpt, Debug, Copy, Clone)]
+    pub enum BotSplit
+    {
+        Remove,
+        Cohort
+    }
+}
+
+arg_enum!
+{
+    #[derive(StructOpt, Debug, Copy, Clone)]
+    pub enum AccessibleStyle
+    {
+        Palette,
+        Patterns
+    }
+}
+
+impl HideType
+{
+    pub fn hides_other(&self) -> bool
+    {
+        matches!(self, HideType::Other | HideType::Both)
+    }
+
+    pub fn hides_brief(&self) -> bool
+    {
+        matches!(self, HideType::Brief | HideType::Both)
     }
 }
 
@@ -54,7 +292,69 @@ arg_enum!
     #[derive(StructOpt, Debug, Copy, Clone)]
     pub enum IntervalType
     {
+        Day,
         Month,
         Year
     }
 }
+
+arg_enum!
+{
+    #[derive(StructOpt, Debug, Copy, Clone)]
+    pub enum ExportFormat
+    {
+        Csv,
+        Json,
+        Parquet
+    }
+}
+
+arg_enum!
+{
+    #[derive(StructOpt, Debug, Copy, Clone)]
+    pub enum ReportFormat
+    {
+        Markdown,
+        Html
+    }
+}
+
+arg_enum!
+{
+    #[derive(StructOpt, Debug, Copy, Clone, PartialEq)]
+    pub enum BucketTz
+    {
+        Local,
+        Utc
+    }
+}
+
+arg_enum!
+{
+    #[derive(StructOpt, Debug, Copy, Clone)]
+    pub enum OverlayType
+    {
+        Forecast
+    }
+}
+
+arg_enum!
+{
+    #[derive(StructOpt, Debug, Copy, Clone)]
+    pub enum ForecastModel
+    {
+        Linear,
+        HoltWinters
+    }
+}
+
+arg_enum!
+{
+    #[derive(StructOpt, Debug, Copy, Clone, PartialEq)]
+    pub enum DbProfile
+    {
+        Safe,
+        Fast,
+        Bulk
+    }
+}
@@ -0,0 +1,165 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+/* -------------- *
+ * PrefixCoupling *
+ * -------------- */
+
+use std::collections::{ BTreeSet, HashMap };
+use crate::csvformat::{ csv_header, format_csv };
+
+// Top-level directories (prefixes) that tend to change in the same commits.
+// A pair's coupling is the Jaccard index of the commits touching either
+// directory, i.e. the share of them that touched both. Pairs sharing fewer
+// than min_shared commits are left out, since two commits in a row would
+// otherwise look like perfect coupling.
+
+pub struct PrefixCoupling
+{
+    pub years: Vec<i32>,
+    pub pairs: Vec<(String, String)>,
+    counts: HashMap<(i32, String, String), i64>
+}
+
+impl PrefixCoupling
+{
+    pub fn new(rows: &[(i32, String, String, i64)], n_pairs: usize, min_shared: i64) -> PrefixCoupling
+    {
+        // Rows hold commits per year for each pair ordered a <= b, with a
+        // prefix paired with itself giving its own commit count.
+
+        let years = rows.iter().map(|(year, _, _, _)| *year).collect::<BTreeSet<i32>>().into_iter().collect();
+        let counts = rows.iter()
+            .map(|(year, a, b, n)| ((*year, a.clone(), b.clone()), *n))
+            .collect::<HashMap<(i32, String, String), i64>>();
+        let mut coupling = PrefixCoupling { years, pairs: Vec::new(), counts };
+
+        let mut pairs = rows.iter()
+            .filter(|(_, a, b, _)| a != b)
+            .map(|(_, a, b, _)| (a.clone(), b.clone()))
+            .collect::<BTreeSet<(String, String)>>()
+            .into_iter()
+            .filter(|(a, b)| coupling.shared(None, a, b) >= min_shared)
+            .collect::<Vec<(String, String)>>();
+        pairs.sort_by(|x, y| coupling.jaccard(None, &y.0, &y.1).partial_cmp(&coupling.jaccard(None, &x.0, &x.1)).unwrap()
+                      .then(coupling.shared(None, &y.0, &y.1).cmp(&coupling.shared(None, &x.0, &x.1)))
+                      .then(x.cmp(y)));
+        pairs.truncate(n_pairs);
+
+        coupling.pairs = pairs;
+        coupling
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.pairs.is_empty()
+    }
+
+    // Commits touching both a and b, in one year or all of them.
+
+    pub fn shared(&self, year: Option<i32>, a: &str, b: &str) -> i64
+    {
+        let (a, b) = if a <= b { (a, b) } else { (b, a) };
+
+        self.years.iter()
+            .filter(|y| year.is_none_or(|year| **y == year))
+            .map(|y| self.counts.get(&(*y, a.to_string(), b.to_string())).cloned().unwrap_or(0))
+            .sum()
+    }
+
+    pub fn jaccard(&self, year: Option<i32>, a: &str, b: &str) -> f64
+    {
+        let shared = self.shared(year, a, b);
+        let union = self.shared(year, a, a) + self.shared(year, b, b) - shared;
+        if union > 0 { shared as f64 / union as f64 } else { 0.0 }
+    }
+
+    pub fn to_table(&self) -> String
+    {
+        // The most closely coupled pairs, with the year they were closest.
+
+        format!("{:<28} {:<28} {:>8} {:>8} {:>6}\n", "Directory", "Directory", "Shared", "Jaccard", "Peak")
+            + &self.pairs.iter()
+                .map(|(a, b)|
+                {
+                    let peak = self.years.iter()
+                        .max_by(|x, y| self.jaccard(Some(**x), a, b).partial_cmp(&self.jaccard(Some(**y), a, b)).unwrap()
+                                .then(y.cmp(x)))
+                        .unwrap();
+                    format!("{:<28} {:<28} {:>8} {:>8.3} {:>6}\n",
+                            a, b, self.shared(None, a, b), self.jaccard(None, a, b), peak)
+                })
+                .collect::<String>()
+    }
+
+    pub fn to_csv(&self, delimiter: u8) -> String
+    {
+        let mut rows = vec![ csv_header(&[ "Year", "Directory", "Other directory", "Shared commits", "Jaccard" ]) ];
+
+        for year in &self.years
+        {
+            for (a, b) in &self.pairs
+            {
+                rows.push(vec![ year.to_string(), a.clone(), b.clone(),
+                                self.shared(Some(*year), a, b).to_string(),
+                                format!("{:.4}", self.jaccard(Some(*year), a, b)) ]);
+            }
+        }
+
+        format_csv(&rows, delimiter)
+    }
+
+    pub fn to_series_csv(&self) -> String
+    {
+        // One column per pair, for plotting.
+
+        let header = self.pairs.iter()
+            .map(|(a, b)| format!("{} + {}", a, b))
+            .collect::<Vec<String>>()
+            .join("|");
+
+        format!("Year|{}\n", header)
+            + &self.years.iter()
+                .map(|year| format!("{}|{}", year, self.pairs.iter()
+                                    .map(|(a, b)| format!("{:.4}", self.jaccard(Some(*year), a, b)))
+                                    .collect::<Vec<String>>()
+                                    .join("|")))
+                .collect::<Vec<String>>()
+                .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn couples_prefixes_by_shared_commits() {
+        let row = |year, a: &str, b: &str, n| (year, a.to_string(), b.to_string(), n);
+        let coupling = PrefixCoupling::new(&[ row(2019, "gdk", "gdk", 10), row(2019, "gtk", "gtk", 20),
+                                              row(2019, "gdk", "gtk", 5), row(2020, "gdk", "gdk", 4),
+                                              row(2020, "gtk", "gtk", 4), row(2020, "gdk", "gtk", 4),
+                                              row(2020, "docs", "docs", 1), row(2020, "docs", "gtk", 1) ], 10, 2);
+
+        assert_eq!(coupling.pairs, vec![ ("gdk".to_string(), "gtk".to_string()) ]);
+        assert!((coupling.jaccard(None, "gtk", "gdk") - 9.0 / 29.0).abs() < 1e-9);
+        assert_eq!(coupling.to_csv(b'|').lines().nth(2), Some("2020|gdk|gtk|4|1.0000"));
+        assert!(coupling.to_table().ends_with("  2020\n"));
+    }
+}
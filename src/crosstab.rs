@@ -0,0 +1,194 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* -------------- *
+ * DomainCrossTab *
+ * -------------- */
+
+use std::collections::HashMap;
+use crate::csvformat::{ csv_header, format_csv };
+
+const N_TABLE_ROWS: usize = 20;
+
+// Commit counts by committer domain and author domain, as read from the
+// database for one era.
+
+pub type DomainPairCounts = Vec<(String, String, i64)>;
+
+// Commit counts by committer domain and author domain, per era. The same
+// domains are used for every era, so the eras can be compared side by side.
+// Counts are indexed by era, committer domain and author domain.
+
+pub struct DomainCrossTab
+{
+    pub eras: Vec<String>,
+    pub committer_domains: Vec<String>,
+    pub author_domains: Vec<String>,
+    pub counts: Vec<Vec<Vec<i64>>>
+}
+
+impl DomainCrossTab
+{
+    pub fn new(eras: Vec<(String, DomainPairCounts)>,
+               n_domains: usize, other_label: &str) -> DomainCrossTab
+    {
+        let committer_domains = top_domains(eras.iter().flat_map(|(_, rows)| rows.iter().map(|r| (&r.0, r.2))),
+                                            n_domains, other_label);
+        let author_domains = top_domains(eras.iter().flat_map(|(_, rows)| rows.iter().map(|r| (&r.1, r.2))),
+                                         n_domains, other_label);
+
+        let index = |domains: &Vec<String>, name: &str|
+        {
+            domains.iter().position(|d| d == name).unwrap_or(domains.len() - 1)
+        };
+
+        let mut counts = Vec::new();
+
+        for (_, rows) in &eras
+        {
+            let mut era_counts = vec![vec![0; author_domains.len()]; committer_domains.len()];
+
+            for (committer_domain, author_domain, n) in rows
+            {
+                era_counts[index(&committer_domains, committer_domain)]
+                          [index(&author_domains, author_domain)] += n;
+            }
+
+            counts.push(era_counts);
+        }
+
+        DomainCrossTab
+        {
+            eras: eras.into_iter().map(|(name, _)| name).collect(),
+            committer_domains,
+            author_domains,
+            counts
+        }
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.counts.iter().flatten().flatten().all(|n| *n == 0)
+    }
+
+    pub fn to_table(&self) -> String
+    {
+        // The busiest pairs in each era, with their share of the era's commits.
+
+        let mut table = String::new();
+
+        for (era, era_counts) in self.eras.iter().zip(&self.counts)
+        {
+            let total: i64 = era_counts.iter().flatten().sum();
+            if total == 0 { continue; }
+
+            let mut cells = self.cells(era_counts);
+            cells.sort_by(|x, y| y.2.cmp(&x.2).then(x.0.cmp(y.0)).then(x.1.cmp(y.1)));
+
+            table += &format!("{}\n{:<32} {:<32} {:>10} {:>7}\n", era, "Committer domain", "Author domain",
+                              "Commits", "Share");
+
+            for (committer_domain, author_domain, n) in cells.iter().take(N_TABLE_ROWS)
+            {
+                table += &format!("{:<32} {:<32} {:>10} {:>6.1}%\n", committer_domain, author_domain, n,
+                                  *n as f64 / total as f64 * 100.0);
+            }
+
+            table += "\n";
+        }
+
+        table
+    }
+
+    pub fn to_csv(&self, delimiter: u8) -> String
+    {
+        let mut rows = vec![ csv_header(&[ "Era", "Committer domain", "Author domain", "Commits" ]) ];
+
+        rows.extend(self.eras.iter().zip(&self.counts)
+                    .flat_map(|(era, era_counts)|
+                              self.cells(era_counts).into_iter()
+                                  .map(move |(c, a, n)| vec![ era.to_string(), c.to_string(), a.to_string(), n.to_string() ])));
+        format_csv(&rows, delimiter)
+    }
+
+    fn cells(&self, era_counts: &[Vec<i64>]) -> Vec<(&str, &str, i64)>
+    {
+        let mut cells = Vec::new();
+
+        for (c, row) in era_counts.iter().enumerate()
+        {
+            for (a, n) in row.iter().enumerate()
+            {
+                if *n > 0
+                {
+                    cells.push((self.committer_domains[c].as_str(), self.author_domains[a].as_str(), *n));
+                }
+            }
+        }
+
+        cells
+    }
+}
+
+fn top_domains<'a>(counts: impl Iterator<Item = (&'a String, i64)>,
+                   n_domains: usize, other_label: &str) -> Vec<String>
+{
+    // The most active domains, busiest first, with the rest folded into a
+    // trailing "Other".
+
+    let mut totals: HashMap<&String, i64> = HashMap::new();
+    for (domain, n) in counts
+    {
+        *totals.entry(domain).or_insert(0) += n;
+    }
+
+    let mut totals = totals.into_iter().collect::<Vec<(&String, i64)>>();
+    totals.sort_by(|x, y| y.1.cmp(&x.1).then(x.0.cmp(y.0)));
+
+    let has_other = totals.len() > n_domains;
+    let mut domains = totals.into_iter().take(n_domains).map(|(d, _)| d.clone()).collect::<Vec<String>>();
+    if has_other || domains.is_empty() { domains.push(other_label.to_string()); }
+
+    domains
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_small_domains() {
+        let row = |c: &str, a: &str, n| (c.to_string(), a.to_string(), n);
+        let tab = DomainCrossTab::new(
+            vec![("2010-2014".to_string(), vec![row("redhat.com", "redhat.com", 10),
+                                                row("redhat.com", "gmail.com", 5),
+                                                row("suse.de", "tiny.org", 1)]),
+                 ("2015-2019".to_string(), vec![row("suse.de", "gmail.com", 2)])],
+            2, "Other");
+
+        assert_eq!(tab.committer_domains, vec!["redhat.com", "suse.de"]);
+        assert_eq!(tab.author_domains, vec!["redhat.com", "gmail.com", "Other"]);
+        assert_eq!(tab.to_csv(b'|'), "Era|Committer domain|Author domain|Commits\n\
+                                  2010-2014|redhat.com|redhat.com|10\n\
+                                  2010-2014|redhat.com|gmail.com|5\n\
+                                  2010-2014|suse.de|Other|1\n\
+                                  2015-2019|suse.de|gmail.com|2");
+    }
+}
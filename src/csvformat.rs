@@ -0,0 +1,92 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* ---------- *
+ * CSV format *
+ * ---------- */
+
+use csv::{ Terminator, WriterBuilder };
+use crate::errors::*;
+
+// Gnuplot is told to split its data on pipes, which are rare in names.
+// Exported tables use commas unless asked otherwise; see --csv-delimiter.
+
+pub const GNUPLOT_DELIMITER: u8 = b'|';
+
+pub fn parse_delimiter(s: &str) -> Result<u8>
+{
+    match s
+    {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ if s.len() == 1 && s.is_ascii() && s != "\"" && s != "\n" => Ok(s.as_bytes()[0]),
+        _ => bail!("Delimiter must be a single ASCII character or \"tab\"")
+    }
+}
+
+pub fn csv_header(names: &[&str]) -> Vec<String>
+{
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+pub fn format_csv<S: AsRef<str>>(rows: &[Vec<S>], delimiter: u8) -> String
+{
+    // Fields holding the delimiter, quotes or line breaks are quoted. Rows
+    // end in a newline, except for the last one.
+
+    let mut writer = WriterBuilder::new()
+        .delimiter(delimiter)
+        .terminator(Terminator::Any(b'\n'))
+        .flexible(true)
+        .from_writer(Vec::new());
+
+    for row in rows
+    {
+        // Writing to memory can't fail.
+        writer.write_record(row.iter().map(|field| field.as_ref())).unwrap();
+    }
+
+    let mut text = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+    if text.ends_with('\n') { text.pop(); }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_fields_as_needed() {
+        let rows = vec![ vec![ "Cohort", "Commits" ],
+                         vec![ "a|b", "1" ],
+                         vec![ "c,d", "2" ],
+                         vec![ "say \"hi\"", "3" ] ];
+
+        assert_eq!(format_csv(&rows, b','), "Cohort,Commits\na|b,1\n\"c,d\",2\n\"say \"\"hi\"\"\",3");
+        assert_eq!(format_csv(&rows, b'|'), "Cohort|Commits\n\"a|b\"|1\nc,d|2\n\"say \"\"hi\"\"\"|3");
+    }
+
+    #[test]
+    fn parses_delimiters() {
+        assert_eq!(parse_delimiter(";").unwrap(), b';');
+        assert_eq!(parse_delimiter("tab").unwrap(), b'\t');
+        assert!(parse_delimiter("ab").is_err());
+        assert!(parse_delimiter("\"").is_err());
+    }
+}
@@ -0,0 +1,231 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+
+/* ----- *
+ * Fleet *
+ * ----- */
+
+use rusqlite::{ Connection, NO_PARAMS };
+use serde::{Deserialize};
+use std::fs;
+use std::path::*;
+use crate::cohorthist::CohortHist;
+use crate::common::IntervalType;
+use crate::errors::*;
+use crate::remotedb::{ is_http_url, resolve_db_path };
+use crate::timebin::TimeBin;
+
+#[derive(Deserialize, Debug)]
+pub struct FleetProject
+{
+    pub name: String,
+    pub db: PathBuf
+}
+
+// A collection of project databases to be analyzed together, e.g:
+//
+// { "name": "Desktop", "projects": [ { "name": "GNOME", "db": "gnome.sqlite" },
+//                                    { "name": "KDE", "db": "kde.sqlite" } ] }
+//
+// Relative database paths are resolved against the fleet file's directory.
+// HTTP(S) URLs are fetched to the cache.
+
+#[derive(Deserialize, Debug)]
+pub struct FleetConfig
+{
+    pub name: Option<String>,
+    pub projects: Vec<FleetProject>
+}
+
+impl FleetConfig
+{
+    pub fn from_file(filename: &Path) -> Result<FleetConfig>
+    {
+        let content = fs::read_to_string(filename).chain_err(|| "Could not read fleet file")?;
+        let mut fc: FleetConfig = serde_json::from_str(&content).chain_err(|| "Failed to parse fleet file")?;
+        let base = filename.parent().unwrap_or_else(|| Path::new(""));
+
+        if fc.projects.is_empty()
+        {
+            bail!("Fleet file lists no projects");
+        }
+
+        for project in fc.projects.iter_mut()
+        {
+            project.db = if is_http_url(&project.db) { resolve_db_path(project.db.clone())? }
+                         else { base.join(&project.db) };
+        }
+
+        Ok(fc)
+    }
+}
+
+pub struct FleetSummary
+{
+    pub n_contributors: i64,
+    pub n_shared_contributors: i64,
+    pub shared_domains: Vec<(String, i64, i64)>
+}
+
+// Commits from all projects are copied into a single in-memory table, with
+// authors keyed on their normalized e-mail address so they can be matched
+// across databases.
+
+pub struct Fleet
+{
+    conn: Connection,
+    project_names: Vec<String>
+}
+
+impl Fleet
+{
+    pub fn open(config: &FleetConfig) -> Result<Fleet>
+    {
+        let conn = Connection::open_in_memory().chain_err(|| "Failed to open database")?;
+
+        conn.execute_batch("
+            create table fleet_commits (
+                project text,
+                email text,
+                domain text,
+                author_year int,
+                author_month int);
+        ").chain_err(|| "Failed to create tables")?;
+
+        for project in &config.projects
+        {
+            if !project.db.exists()
+            {
+                bail!("Database {} for {} does not exist", project.db.display(), project.name);
+            }
+
+            conn.execute("attach database ?1 as project;", &[project.db.to_string_lossy()])
+                .chain_err(|| format!("Could not attach {}", project.db.display()))?;
+            conn.execute("
+                insert into fleet_commits
+                    select ?1, lower(trim(author_email)), author_domain, author_year, author_month
+                    from project.raw_commits;", &[&project.name])
+                .chain_err(|| format!("Could not read commits from {}", project.db.display()))?;
+            conn.execute("detach database project;", NO_PARAMS)
+                .chain_err(|| format!("Could not detach {}", project.db.display()))?;
+        }
+
+        conn.execute_batch("
+            create index index_fleet_email on fleet_commits (email);
+            create index index_fleet_domain on fleet_commits (domain);
+        ").chain_err(|| "Failed to create indexes")?;
+
+        Ok(Fleet
+        {
+            conn,
+            project_names: config.projects.iter().map(|p| p.name.clone()).collect()
+        })
+    }
+
+    pub fn get_summary(&self) -> Result<FleetSummary>
+    {
+        let n_contributors: i64 = self.conn.query_row(
+            "select count(distinct email) from fleet_commits;", NO_PARAMS, |r| r.get(0))
+            .chain_err(|| "Could not query database")?;
+        let n_shared_contributors: i64 = self.conn.query_row("
+            select count(*) from (
+                select email from fleet_commits
+                group by email
+                having count(distinct project) >= 2);", NO_PARAMS, |r| r.get(0))
+            .chain_err(|| "Could not query database")?;
+
+        let mut stmt = self.conn.prepare("
+            select domain, count(distinct project), count(distinct email)
+            from fleet_commits
+            group by domain
+            having count(distinct project) >= 2
+            order by count(distinct email) desc
+            limit 20;").chain_err(|| "Could not query database")?;
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut shared_domains = Vec::new();
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            shared_domains.push((r.get::<_, Option<String>>(0).unwrap().unwrap_or_default(),
+                                 r.get(1).unwrap(), r.get(2).unwrap()));
+        }
+
+        Ok(FleetSummary { n_contributors, n_shared_contributors, shared_domains })
+    }
+
+    pub fn get_hist(&self, interval: IntervalType) -> Result<CohortHist>
+    {
+        // Distinct contributors per interval, split by project. Those who
+        // were active in more than one project in the same interval get
+        // their own cohort, so the total doesn't count anyone twice.
+
+        let interval_str = match interval
+        {
+            IntervalType::Day => bail!("Fleet charts by day are not supported"),
+            IntervalType::Month => "author_year, author_month",
+            IntervalType::Year => "author_year"
+        };
+
+        let mut stmt = self.conn.prepare(&format!("
+            with presence as (
+                select {interval}, email,
+                       count(distinct project) as n_projects,
+                       min(project) as project
+                from fleet_commits
+                group by {interval}, email)
+            select {interval},
+                   case when n_projects > 1 then null else project end as cohort,
+                   count(*)
+            from presence
+            group by {interval}, cohort;",
+            interval = interval_str)).chain_err(|| "Could not query database")?;
+
+        let mut rows = stmt.query(NO_PARAMS).chain_err(|| "Could not query database")?;
+        let mut hist = CohortHist::new();
+        let n_projects = self.project_names.len() as i32;
+
+        while let Some(r) = rows.next().chain_err(|| "Could not query database")?
+        {
+            let (ym, first_col) = match interval
+            {
+                IntervalType::Day => unreachable!(),
+                IntervalType::Month => (TimeBin::Month(r.get(0).unwrap(), r.get(1).unwrap()), 2),
+                IntervalType::Year => (TimeBin::Year(r.get(0).unwrap()), 1)
+            };
+            let project: Option<String> = r.get(first_col).unwrap();
+            let cohort = match project
+            {
+                Some(p) => self.project_names.iter().position(|n| *n == p).unwrap() as i32,
+                None => n_projects
+            };
+
+            hist.set_value(ym, cohort, r.get::<_, i64>(first_col + 1).unwrap() as f64);
+        }
+
+        for (i, name) in self.project_names.iter().enumerate()
+        {
+            hist.set_cohort_name(i as i32, name);
+        }
+        hist.set_cohort_name(n_projects, "Multiple projects");
+
+        Ok(hist)
+    }
+}
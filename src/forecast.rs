@@ -0,0 +1,171 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* -------- *
+ * Forecast *
+ * -------- */
+
+use crate::common::ForecastModel;
+
+// Smoothing factors tried when fitting Holt-Winters, for the level, trend
+// and season alike.
+const SMOOTHING_STEPS: [f64; 9] = [ 0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9 ];
+
+// Extrapolates a series n steps past its end. Seasonal models use the given
+// period, e.g. 12 for monthly data; a period of 1 means no seasonality.
+// Projections can't go below zero, since they're counts.
+
+pub fn forecast(series: &[f64], model: ForecastModel, period: usize, n: usize) -> Vec<f64>
+{
+    let values = match model
+    {
+        ForecastModel::Linear => linear(series, n),
+        ForecastModel::HoltWinters =>
+        {
+            // Seasons can only be estimated from at least two whole periods.
+
+            let period = if series.len() >= period * 2 { period } else { 1 };
+            holt_winters(series, period, n)
+        }
+    };
+
+    values.into_iter().map(|v| v.max(0.0)).collect()
+}
+
+fn linear(series: &[f64], n: usize) -> Vec<f64>
+{
+    // Least squares line through the whole series.
+
+    let len = series.len() as f64;
+    if series.is_empty() { return vec![0.0; n]; }
+
+    let mean_x = (len - 1.0) / 2.0;
+    let mean_y = series.iter().sum::<f64>() / len;
+    let (mut sxy, mut sxx) = (0.0, 0.0);
+
+    for (x, y) in series.iter().enumerate()
+    {
+        sxy += (x as f64 - mean_x) * (y - mean_y);
+        sxx += (x as f64 - mean_x) * (x as f64 - mean_x);
+    }
+
+    let slope = if sxx > 0.0 { sxy / sxx } else { 0.0 };
+    (0..n).map(|h| mean_y + slope * (len + h as f64 - mean_x)).collect()
+}
+
+struct HoltWinters
+{
+    level: f64,
+    trend: f64,
+    season: Vec<f64>,
+    sse: f64
+}
+
+fn fit_holt_winters(series: &[f64], period: usize, alpha: f64, beta: f64, gamma: f64) -> HoltWinters
+{
+    // Additive model. The level and trend start from the first one or two
+    // periods, and the seasonal terms from the first period's deviations.
+
+    let first_mean = series[..period].iter().sum::<f64>() / period as f64;
+    let (level, trend) = if period > 1
+    {
+        let second_mean = series[period..period * 2].iter().sum::<f64>() / period as f64;
+        (first_mean, (second_mean - first_mean) / period as f64)
+    }
+    else
+    {
+        (series[0], series[1] - series[0])
+    };
+    let mut fit = HoltWinters
+    {
+        level,
+        trend,
+        season: series[..period].iter().map(|y| if period > 1 { y - first_mean } else { 0.0 }).collect(),
+        sse: 0.0
+    };
+
+    for (t, y) in series.iter().enumerate().skip(period)
+    {
+        let season = fit.season[t - period];
+        let predicted = fit.level + fit.trend + season;
+        let level = alpha * (y - season) + (1.0 - alpha) * (fit.level + fit.trend);
+
+        fit.sse += (y - predicted) * (y - predicted);
+        fit.trend = beta * (level - fit.level) + (1.0 - beta) * fit.trend;
+        fit.level = level;
+        fit.season.push(if period > 1 { gamma * (y - level) + (1.0 - gamma) * season } else { 0.0 });
+    }
+
+    fit
+}
+
+fn holt_winters(series: &[f64], period: usize, n: usize) -> Vec<f64>
+{
+    // Picks the smoothing factors with the smallest one-step-ahead error.
+
+    if series.len() < 2 { return linear(series, n); }
+
+    let gammas: &[f64] = if period > 1 { &SMOOTHING_STEPS } else { &[ 0.0 ] };
+    let mut best: Option<HoltWinters> = None;
+
+    for alpha in SMOOTHING_STEPS.iter()
+    {
+        for beta in SMOOTHING_STEPS.iter()
+        {
+            for gamma in gammas
+            {
+                let fit = fit_holt_winters(series, period, *alpha, *beta, *gamma);
+                if best.as_ref().is_none_or(|b| fit.sse < b.sse) { best = Some(fit); }
+            }
+        }
+    }
+
+    let fit = best.unwrap();
+    let len = series.len();
+
+    (1..=n).map(|h| fit.level + fit.trend * h as f64 + fit.season[len - period + (h - 1) % period]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_continues_the_line() {
+        let series = [ 10.0, 12.0, 14.0, 16.0 ];
+
+        assert_eq!(forecast(&series, ForecastModel::Linear, 1, 2), vec![ 18.0, 20.0 ]);
+        assert_eq!(forecast(&[ 4.0, 2.0 ], ForecastModel::Linear, 1, 3), vec![ 0.0, 0.0, 0.0 ]);
+    }
+
+    #[test]
+    fn holt_winters_keeps_the_season() {
+        // A steady climb with a peak every fourth interval.
+
+        let series: Vec<f64> = (0..16).map(|t| 20.0 + t as f64 + if t % 4 == 3 { 10.0 } else { 0.0 }).collect();
+        let projected = forecast(&series, ForecastModel::HoltWinters, 4, 4);
+
+        for (h, value) in projected.iter().enumerate()
+        {
+            let expected = 36.0 + h as f64 + if h == 3 { 10.0 } else { 0.0 };
+            assert!((value - expected).abs() < 1.0, "{} vs {}", value, expected);
+        }
+    }
+}
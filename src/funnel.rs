@@ -0,0 +1,131 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* ------ *
+ * Funnel *
+ * ------ */
+
+use serde::{Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::*;
+use crate::common::ExportFormat;
+use crate::csvformat::{ csv_header, format_csv };
+use crate::errors::*;
+use crate::parquetexport::{ ParquetColumn, write_parquet };
+
+const SECONDS_PER_YEAR: i64 = 365 * 24 * 60 * 60;
+
+#[derive(Serialize, Debug, Default)]
+struct FunnelRow
+{
+    first_year: i32,
+    min_1_commits: i64,
+    min_10_commits: i64,
+    min_100_commits: i64,
+    min_1_years: i64,
+    min_2_years: i64,
+    min_5_years: i64
+}
+
+// How far the authors of each first-year cohort got: how many made at least
+// 1, 10 and 100 commits, and how many stayed active (first to last commit)
+// for at least 1, 2 and 5 years. Recent cohorts haven't had the time to
+// reach the later stages yet.
+
+pub struct RetentionFunnel
+{
+    rows: Vec<FunnelRow>
+}
+
+impl RetentionFunnel
+{
+    pub fn new(authors: &[(i32, i64, i64)]) -> RetentionFunnel
+    {
+        let mut rows: BTreeMap<i32, FunnelRow> = BTreeMap::new();
+
+        for (first_year, n_commits, active_time) in authors
+        {
+            let row = rows.entry(*first_year).or_insert_with(|| FunnelRow { first_year: *first_year,
+                                                                             ..Default::default() });
+            let years = active_time / SECONDS_PER_YEAR;
+
+            row.min_1_commits += (*n_commits >= 1) as i64;
+            row.min_10_commits += (*n_commits >= 10) as i64;
+            row.min_100_commits += (*n_commits >= 100) as i64;
+            row.min_1_years += (years >= 1) as i64;
+            row.min_2_years += (years >= 2) as i64;
+            row.min_5_years += (years >= 5) as i64;
+        }
+
+        RetentionFunnel { rows: rows.into_values().collect() }
+    }
+
+    pub fn to_csv(&self, delimiter: u8) -> String
+    {
+        let mut rows = vec![ csv_header(&[ "first_year", "min_1_commits", "min_10_commits", "min_100_commits",
+                                           "min_1_years", "min_2_years", "min_5_years" ]) ];
+
+        rows.extend(self.rows.iter()
+                    .map(|r| vec![ r.first_year.to_string(), r.min_1_commits.to_string(),
+                                   r.min_10_commits.to_string(), r.min_100_commits.to_string(),
+                                   r.min_1_years.to_string(), r.min_2_years.to_string(),
+                                   r.min_5_years.to_string() ]));
+        format_csv(&rows, delimiter)
+    }
+
+    pub fn write(&self, out_path: &Path, format: ExportFormat, delimiter: u8) -> Result<()>
+    {
+        let r = &self.rows;
+        let content = match format
+        {
+            ExportFormat::Csv => self.to_csv(delimiter),
+            ExportFormat::Json => serde_json::to_string_pretty(&self.rows)
+                .chain_err(|| "Could not serialize funnel")?,
+            ExportFormat::Parquet => return write_parquet(out_path, "funnel", &[
+                ("first_year", ParquetColumn::Int(r.iter().map(|r| r.first_year).collect())),
+                ("min_1_commits", ParquetColumn::Long(r.iter().map(|r| r.min_1_commits).collect())),
+                ("min_10_commits", ParquetColumn::Long(r.iter().map(|r| r.min_10_commits).collect())),
+                ("min_100_commits", ParquetColumn::Long(r.iter().map(|r| r.min_100_commits).collect())),
+                ("min_1_years", ParquetColumn::Long(r.iter().map(|r| r.min_1_years).collect())),
+                ("min_2_years", ParquetColumn::Long(r.iter().map(|r| r.min_2_years).collect())),
+                ("min_5_years", ParquetColumn::Long(r.iter().map(|r| r.min_5_years).collect())) ])
+        };
+
+        fs::write(out_path, content + "\n").chain_err(|| "Could not write funnel")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_funnel_stages() {
+        let year = SECONDS_PER_YEAR;
+        let funnel = RetentionFunnel::new(&[ (2015, 1, 0), (2015, 12, year + 1), (2015, 150, 6 * year),
+                                             (2016, 10, 2 * year) ]);
+
+        assert_eq!(funnel.to_csv(b'|'),
+                   "first_year|min_1_commits|min_10_commits|min_100_commits|min_1_years|min_2_years|min_5_years\n\
+                    2015|3|2|1|2|1|1\n\
+                    2016|1|1|0|1|1|0");
+    }
+}
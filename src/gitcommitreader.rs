@@ -25,7 +25,7 @@
 use chrono::prelude::Utc;
 use chrono::{DateTime, FixedOffset};
 use regex::Regex;
-use std::collections::HashMap;
+use std::collections::{ HashMap, HashSet };
 use std::io::{BufRead, BufReader, Split};
 use std::iter::Peekable;
 use std::process::{Command, Stdio, ChildStdout};
@@ -45,7 +45,25 @@ pub struct RawCommit
     pub n_insertions: i32,
     pub n_deletions: i32,
     pub n_changes_per_prefix: HashMap<String, i32>,
-    pub n_changes_per_suffix: HashMap<String, i32>
+    pub n_changes_per_suffix: HashMap<String, i32>,
+    pub n_insertions_per_suffix: HashMap<String, i32>,
+    pub n_deletions_per_suffix: HashMap<String, i32>,
+    pub subject: String,
+    pub has_body: Option<bool>,
+    pub is_revert: bool,
+    pub parents: Vec<String>
+}
+
+// Merges are kept apart from regular commits, which they'd otherwise
+// double-count. We only need them for the shape of the history.
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct RawMerge
+{
+    pub id: String,
+    pub repo_name: String,
+    pub author_time: DateTime::<FixedOffset>,
+    pub parents: Vec<String>
 }
 
 pub struct GitCommitReader
@@ -54,17 +72,15 @@ pub struct GitCommitReader
     insertions_re: Regex,
     deletions_re: Regex,
     commit_re: Regex,
-    rename_path_elements_re: Regex,
-    file_changes_re: Regex,
-    file_changes_bin_re: Regex,
-    prefix_re: Regex,
-    suffix_re: Regex,
+    paths: PathClassifier,
+    revert_re: Regex,
     line_splitter: Peekable<Split<BufReader<ChildStdout>>>
 }
 
 impl GitCommitReader
 {
-    pub fn new(repo_path: std::path::PathBuf, repo_name: &str, since: DateTime<Utc>, use_stat: bool) -> Result<GitCommitReader>
+    pub fn new(repo_path: std::path::PathBuf, repo_name: &str, since: DateTime<Utc>, use_stat: bool,
+               branches: Option<&[String]>) -> Result<GitCommitReader>
     {
         let repo_path = repo_path.canonicalize().unwrap();
         let mut cmd;
@@ -74,14 +90,13 @@ impl GitCommitReader
            .arg(&repo_path)
            .arg("log")
            .arg("--no-merges")
-           .arg("--branches")
-           .arg("--remotes")
-           .arg("--pretty=format:%H__sep__%aD__sep__%aN__sep__%aE__sep__%cD__sep__%cN__sep__%cE")
+           .arg("--pretty=format:%H__sep__%aD__sep__%aN__sep__%aE__sep__%cD__sep__%cN__sep__%cE__sep__%P__sep__%s")
            .arg("--reverse")
            .arg("--since")
            .arg(since.to_rfc2822())
-           .arg("--date-order")
-           .arg("HEAD");
+           .arg("--date-order");
+
+        add_ref_args(&mut cmd, branches);
 
         if use_stat
         {
@@ -101,44 +116,237 @@ impl GitCommitReader
             insertions_re: Regex::new(r"([0-9]+) insertions?").unwrap(),
             deletions_re: Regex::new(r"([0-9]+) deletions?").unwrap(),
             commit_re: Regex::new(r"^[0-9a-f]+__sep__").unwrap(),
-            rename_path_elements_re: Regex::new(r"\{.* => (?P<newname>.*)\}").unwrap(),
-            file_changes_re: Regex::new(r"^ +( => )?([^ ]+) +[|] +([0-9]+)").unwrap(),
-            file_changes_bin_re: Regex::new(r"^ ( => )?+([^ ]+) +[|] +Bin").unwrap(),
-            prefix_re: Regex::new(r"^([^/]+)").unwrap(),
-            suffix_re: Regex::new(r".*[./](.+)$").unwrap(),
+            paths: PathClassifier::new(),
+            revert_re: Regex::new(r#"^Revert ".*"$"#).unwrap(),
             line_splitter: reader.split(b'\n').peekable()
         };
 
         Ok(gcr)
     }
 
-    fn add_path_changes(&mut self, commit: &mut RawCommit, path: &str, n_changes: i32)
+    // Number of commits a reader with the same arguments would return.
+    // Cheap compared to the log itself, so it's used for progress estimates.
+
+    pub fn count_commits(repo_path: &std::path::Path, since: DateTime<Utc>,
+                         branches: Option<&[String]>) -> Result<u64>
     {
-        let prefix =
-            if self.prefix_re.is_match(path)
-            {
-                self.prefix_re.captures(path).unwrap()[1].to_string()
-            }
-            else
-            {
-                path.to_string()
-            };
+        let mut cmd = Command::new("git");
 
-        let suffix =
-            if self.suffix_re.is_match(path)
-            {
-                self.suffix_re.captures(path).unwrap()[1].to_string()
-            }
-            else
+        cmd.arg("-C")
+           .arg(repo_path)
+           .arg("rev-list")
+           .arg("--count")
+           .arg("--no-merges")
+           .arg("--since")
+           .arg(since.to_rfc2822());
+        add_ref_args(&mut cmd, branches);
+
+        let output = cmd.output().chain_err(|| "Could not run git")?;
+        if !output.status.success()
+        {
+            bail!("Could not count commits in {}", repo_path.display());
+        }
+
+        std::str::from_utf8(&output.stdout).chain_err(|| "Invalid git output")?
+            .trim().parse().chain_err(|| "Invalid git output")
+    }
+
+    // Paths of the initialized submodules, nested ones included, relative
+    // to the superproject. Bare repos have no checked-out submodules.
+
+    pub fn list_submodules(repo_path: &std::path::Path) -> Result<Vec<std::path::PathBuf>>
+    {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(repo_path)
+            .arg("rev-parse")
+            .arg("--is-bare-repository")
+            .output().chain_err(|| "Could not run git")?;
+        if std::str::from_utf8(&output.stdout).chain_err(|| "Invalid git output")?.trim() == "true"
+        {
+            return Ok(Vec::new());
+        }
+
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(repo_path)
+            .arg("submodule")
+            .arg("foreach")
+            .arg("--quiet")
+            .arg("--recursive")
+            .arg("printf '%s\\n' \"$displaypath\"")
+            .output().chain_err(|| "Could not run git")?;
+        if !output.status.success()
+        {
+            bail!("Could not list submodules in {}", repo_path.display());
+        }
+
+        Ok(std::str::from_utf8(&output.stdout).chain_err(|| "Invalid git output")?
+           .lines()
+           .filter(|l| !l.is_empty())
+           .map(std::path::PathBuf::from)
+           .collect())
+    }
+
+    pub fn read_merges(repo_path: &std::path::Path, repo_name: &str, since: DateTime<Utc>,
+                       branches: Option<&[String]>) -> Result<Vec<RawMerge>>
+    {
+        let mut cmd = Command::new("git");
+
+        cmd.arg("-C")
+           .arg(repo_path)
+           .arg("log")
+           .arg("--merges")
+           .arg("--pretty=format:%H__sep__%aD__sep__%P")
+           .arg("--since")
+           .arg(since.to_rfc2822());
+        add_ref_args(&mut cmd, branches);
+
+        let output = cmd.output().chain_err(|| "Could not run git")?;
+        if !output.status.success()
+        {
+            bail!("Could not read merges in {}", repo_path.display());
+        }
+
+        let mut merges = Vec::new();
+
+        for line in String::from_utf8_lossy(&output.stdout).lines()
+        {
+            let split = line.splitn(3, "__sep__").collect::<Vec<&str>>();
+            if split.len() < 3 { continue; }
+
+            merges.push(RawMerge
             {
-                path.to_string()
-            };
+                id: split[0].to_string(),
+                repo_name: repo_name.to_string(),
+                author_time: DateTime::parse_from_rfc2822(split[1]).chain_err(|| "Invalid git output")?,
+                parents: split[2].split_whitespace().map(|x| x.to_string()).collect()
+            });
+        }
 
-        *commit.n_changes_per_prefix.entry(prefix.clone()).or_insert(0) += n_changes;
-        *commit.n_changes_per_suffix.entry(suffix.clone()).or_insert(0) += n_changes;
+        Ok(merges)
     }
 
-    fn finalize_paths(&mut self, commit: &mut RawCommit)
+    // Commits whose messages have a body. Reading the bodies takes a pass
+    // of its own, since they'd get in the way of parsing the main log.
+
+    pub fn read_bodied_commits(repo_path: &std::path::Path, since: DateTime<Utc>,
+                               branches: Option<&[String]>) -> Result<HashSet<String>>
+    {
+        let mut cmd = Command::new("git");
+
+        cmd.arg("-C")
+           .arg(repo_path)
+           .arg("log")
+           .arg("--no-merges")
+           .arg("--pretty=format:%H%x1f%b%x1e")
+           .arg("--since")
+           .arg(since.to_rfc2822());
+        add_ref_args(&mut cmd, branches);
+
+        let output = cmd.output().chain_err(|| "Could not run git")?;
+        if !output.status.success()
+        {
+            bail!("Could not read commit messages in {}", repo_path.display());
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).split('\x1e')
+           .filter_map(|record| record.trim_start().split_once('\x1f'))
+           .filter(|(_, body)| has_message_body(body))
+           .map(|(id, _)| id.to_string())
+           .collect())
+    }
+}
+
+// Attributes changes in a commit to the top-level directory (prefix) and
+// file type (suffix) of each path.
+
+pub struct PathClassifier
+{
+    prefix_re: Regex,
+    suffix_re: Regex,
+    rename_path_elements_re: Regex,
+    file_changes_re: Regex,
+    file_changes_bin_re: Regex
+}
+
+impl PathClassifier
+{
+    pub fn new() -> PathClassifier
+    {
+        PathClassifier
+        {
+            prefix_re: Regex::new(r"^([^/]+)").unwrap(),
+            suffix_re: Regex::new(r".*[./](.+)$").unwrap(),
+            rename_path_elements_re: Regex::new(r"\{.* => (?P<newname>.*)\}").unwrap(),
+            file_changes_re: Regex::new(r"^ +(.+?) +[|] +([0-9]+) ?([+-]*)").unwrap(),
+            file_changes_bin_re: Regex::new(r"^ +(.+?) +[|] +Bin").unwrap()
+        }
+    }
+
+    pub fn parse_stat_line(&self, line: &str) -> Option<(String, Option<(i32, i32)>)>
+    {
+        // A file's line in --stat output: its path, and its insertions and
+        // deletions, or None for binary files. Paths may contain spaces,
+        // and renames are resolved to the new path, so both
+        // "foo/{old => new}/bar" and "old => new" give the latter.
+
+        let line = self.rename_path_elements_re.replace_all(line, "$newname");
+        let new_path = |path: &str| path.rsplit(" => ").next().unwrap().to_string();
+
+        if let Some(caps) = self.file_changes_re.captures(&line)
+        {
+            let n_changes = caps[2].parse::<i32>().unwrap();
+            Some((new_path(&caps[1]), Some(split_stat_graph(n_changes, &caps[3]))))
+        }
+        else
+        {
+            self.file_changes_bin_re.captures(&line).map(|caps| (new_path(&caps[1]), None))
+        }
+    }
+
+    pub fn add_path_changes(&self, commit: &mut RawCommit, path: &str, n_changes: i32)
+    {
+        let (prefix, suffix) = self.classify(path);
+
+        *commit.n_changes_per_prefix.entry(prefix).or_insert(0) += n_changes;
+        *commit.n_changes_per_suffix.entry(suffix).or_insert(0) += n_changes;
+    }
+
+    pub fn add_path_line_changes(&self, commit: &mut RawCommit, path: &str,
+                                 n_insertions: i32, n_deletions: i32)
+    {
+        // Like add_path_changes(), but also keeps the insertions and
+        // deletions apart per suffix.
+
+        self.add_path_changes(commit, path, n_insertions + n_deletions);
+
+        let (_, suffix) = self.classify(path);
+        *commit.n_insertions_per_suffix.entry(suffix.clone()).or_insert(0) += n_insertions;
+        *commit.n_deletions_per_suffix.entry(suffix).or_insert(0) += n_deletions;
+    }
+
+    fn classify(&self, path: &str) -> (String, String)
+    {
+        // The prefix and suffix of a path, once it's been unquoted and made
+        // relative with forward slashes.
+
+        let path = normalize_path(path);
+        let prefix = match self.prefix_re.captures(&path)
+        {
+            Some(caps) => caps[1].to_string(),
+            None => path.clone()
+        };
+        let suffix = match self.suffix_re.captures(&path)
+        {
+            Some(caps) => caps[1].to_string(),
+            None => path.clone()
+        };
+
+        (prefix, suffix)
+    }
+
+    pub fn finalize_paths(&self, commit: &mut RawCommit)
     {
         // Every commit must have at least one prefix and one suffix change,
         // otherwise the per-prefix author etc. counts won't add up to the full total.
@@ -155,6 +363,91 @@ impl GitCommitReader
     }
 }
 
+// Git quotes paths with unusual characters C-style, and (unless
+// core.quotePath is off) writes non-ASCII ones as octal escapes of their
+// UTF-8 bytes, e.g. "docs/\303\251t\303\251.txt". Other paths are left as
+// they are.
+
+pub fn unquote_path(path: &str) -> String
+{
+    let inner = match path.strip_prefix('"').and_then(|p| p.strip_suffix('"'))
+    {
+        Some(inner) => inner.as_bytes(),
+        None => return path.to_string()
+    };
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut i = 0;
+
+    while i < inner.len()
+    {
+        if inner[i] != b'\\' || i + 1 == inner.len()
+        {
+            bytes.push(inner[i]);
+            i += 1;
+            continue;
+        }
+
+        let escaped = inner[i + 1];
+        i += 2;
+
+        bytes.push(match escaped
+        {
+            b'0'..=b'7' =>
+            {
+                let mut n = (escaped - b'0') as u32;
+                for _ in 0..2
+                {
+                    if i == inner.len() || !(b'0'..=b'7').contains(&inner[i]) { break; }
+                    n = n * 8 + (inner[i] - b'0') as u32;
+                    i += 1;
+                }
+                n as u8
+            },
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b'f' => 0x0c,
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'v' => 0x0b,
+            other => other
+        });
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// Imports made on Windows can leave paths with backslashes and a drive
+// letter, which would otherwise all land in one prefix and suffix. Paths
+// are unquoted and made relative with forward slashes.
+
+pub fn normalize_path(path: &str) -> String
+{
+    let path = unquote_path(path.trim()).replace('\\', "/");
+    let path = match path.as_bytes()
+    {
+        [drive, b':', ..] if drive.is_ascii_alphabetic() => &path[2..],
+        _ => &path
+    };
+
+    path.trim_start_matches('/').to_string()
+}
+
+// The +/- graph in --stat output is drawn to scale unless the change is
+// too large to fit, in which case it's shrunk. Use it as a ratio then.
+
+fn split_stat_graph(n_changes: i32, graph: &str) -> (i32, i32)
+{
+    let n_plus = graph.chars().filter(|c| *c == '+').count() as i64;
+    let n_minus = graph.chars().filter(|c| *c == '-').count() as i64;
+
+    if n_plus + n_minus == 0 { return (0, 0); }
+    if n_plus + n_minus == n_changes as i64 { return (n_plus as i32, n_minus as i32); }
+
+    let n_insertions = ((n_changes as i64 * n_plus + (n_plus + n_minus) / 2) / (n_plus + n_minus)) as i32;
+    (n_insertions, n_changes - n_insertions)
+}
+
 impl Iterator for GitCommitReader
 {
     type Item = RawCommit;
@@ -172,7 +465,8 @@ impl Iterator for GitCommitReader
 
             if self.commit_re.is_match(&line)
             {
-                let split = line.split("__sep__").map(|x| x.to_string()).collect::<Vec<String>>();
+                // The subject comes last, and may itself contain the separator.
+                let split = line.splitn(9, "__sep__").map(|x| x.to_string()).collect::<Vec<String>>();
 
                 commit.id = split[0].clone();
                 commit.repo_name = self.repo_name.clone();
@@ -182,6 +476,9 @@ impl Iterator for GitCommitReader
                 commit.committer_time = Some(DateTime::parse_from_rfc2822(&split[4]).unwrap());
                 commit.committer_name = split[5].clone();
                 commit.committer_email = split[6].to_lowercase();
+                commit.parents = split[7].split_whitespace().map(|x| x.to_string()).collect();
+                commit.subject = split.get(8).cloned().unwrap_or_default();
+                commit.is_revert = self.revert_re.is_match(&commit.subject);
                 break;
             }
 
@@ -194,7 +491,7 @@ impl Iterator for GitCommitReader
         let mut next_seg = self.line_splitter.peek();
         while next_seg.is_some()
         {
-            let line = String::from_utf8_lossy(&next_seg.unwrap().as_ref().unwrap());
+            let line = String::from_utf8_lossy(next_seg.unwrap().as_ref().unwrap());
 
             // Beginning of next commit?
             if self.commit_re.is_match(&line) { break; }
@@ -209,19 +506,12 @@ impl Iterator for GitCommitReader
                 commit.n_deletions += self.deletions_re.captures(&line).unwrap()[1].parse::<i32>().unwrap();
             }
 
-            // Resolve "foo/{old_path_elt => new_path_elt}/bar" to "foo/new_path_elt/bar"
-            let line = self.rename_path_elements_re.replace_all(&line, "$newname");
-
-            if self.file_changes_re.is_match(&line)
-            {
-                let path = self.file_changes_re.captures(&line).unwrap()[2].to_string();
-                let n_changes = self.file_changes_re.captures(&line).unwrap()[3].parse::<i32>().unwrap();
-                self.add_path_changes(&mut commit, &path, n_changes);
-            }
-            else if self.file_changes_bin_re.is_match(&line)
+            match self.paths.parse_stat_line(&line)
             {
-                let path = self.file_changes_bin_re.captures(&line).unwrap()[2].to_string();
-                self.add_path_changes(&mut commit, &path, 1);
+                Some((path, Some((n_insertions, n_deletions)))) =>
+                    self.paths.add_path_line_changes(&mut commit, &path, n_insertions, n_deletions),
+                Some((path, None)) => self.paths.add_path_changes(&mut commit, &path, 1),
+                None => ()
             }
 
             self.line_splitter.next();
@@ -232,7 +522,122 @@ impl Iterator for GitCommitReader
 
 //        println!("{:?}", commit);
 
-        self.finalize_paths(&mut commit);
+        self.paths.finalize_paths(&mut commit);
         Some(commit)
     }
 }
+
+// Whether a commit message body says anything, i.e. has text besides a
+// closing block of trailers like "Signed-off-by: ...".
+
+// Whether a commit falls in a 1/sample_rate sample. Decided by the commit
+// hash, so the same commits are picked on every ingest.
+
+pub fn is_in_sample(commit_id: &str, sample_rate: u32) -> bool
+{
+    let prefix = &commit_id[..commit_id.len().min(8)];
+    u32::from_str_radix(prefix, 16).map_or(true, |n| n % sample_rate == 0)
+}
+
+pub fn has_message_body(body: &str) -> bool
+{
+    let paragraphs = body.split("\n\n")
+        .map(|p| p.trim())
+        .filter(|p| !p.is_empty())
+        .collect::<Vec<&str>>();
+
+    match paragraphs.last()
+    {
+        None => false,
+        Some(last) if last.lines().all(is_trailer) => paragraphs.len() > 1,
+        Some(_) => true
+    }
+}
+
+fn is_trailer(line: &str) -> bool
+{
+    match line.split_once(": ")
+    {
+        Some((key, _)) => !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'),
+        None => false
+    }
+}
+
+// Without an explicit branch list, we take in everything: local branches,
+// remote refs and HEAD. Otherwise each entry is a glob passed to --branches,
+// with HEAD accepted as-is.
+
+fn add_ref_args(cmd: &mut Command, branches: Option<&[String]>)
+{
+    match branches
+    {
+        Some(branches) =>
+        {
+            for b in branches
+            {
+                if b == "HEAD" { cmd.arg("HEAD"); }
+                else { cmd.arg(format!("--branches={}", b)); }
+            }
+        },
+        None =>
+        {
+            cmd.arg("--branches")
+               .arg("--remotes")
+               .arg("HEAD");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_stat_lines() {
+        let paths = PathClassifier::new();
+
+        assert_eq!(paths.parse_stat_line(" src/main.rs | 12 +++++++-----"),
+                   Some(("src/main.rs".to_string(), Some((7, 5)))));
+        assert_eq!(paths.parse_stat_line(" My Documents/report final.doc | Bin 0 -> 1234 bytes"),
+                   Some(("My Documents/report final.doc".to_string(), None)));
+        assert_eq!(paths.parse_stat_line(" lib/{old => new}/util.c | 2 +-"),
+                   Some(("lib/new/util.c".to_string(), Some((1, 1)))));
+        assert_eq!(paths.parse_stat_line(" README => README.md | 0"),
+                   Some(("README.md".to_string(), Some((0, 0)))));
+        assert_eq!(paths.parse_stat_line(" 3 files changed, 5 insertions(+)"), None);
+    }
+
+    #[test]
+    fn unquotes_paths() {
+        assert_eq!(unquote_path(r#""docs/\303\251t\303\251 notes.txt""#), "docs/été notes.txt");
+        assert_eq!(unquote_path(r#""say \"hi\"\tnow""#), "say \"hi\"\tnow");
+        assert_eq!(unquote_path("plain/path.c"), "plain/path.c");
+    }
+
+    #[test]
+    fn classifies_windows_paths() {
+        let paths = PathClassifier::new();
+        let mut commit = RawCommit::default();
+
+        for line in &[ r#" "C:\\Users\\dev\\Project\\Foo.cs" | 4 ++--"#,
+                       r" Project\Bar.cs | 2 ++",
+                       r#" "Project/\303\234bersicht.resx" | 1 +"# ]
+        {
+            let (path, lines) = paths.parse_stat_line(line).unwrap();
+            let (n_insertions, n_deletions) = lines.unwrap();
+            paths.add_path_line_changes(&mut commit, &path, n_insertions, n_deletions);
+        }
+
+        let sorted = |map: &HashMap<String, i32>|
+        {
+            let mut v: Vec<(String, i32)> = map.iter().map(|(k, v)| (k.clone(), *v)).collect();
+            v.sort();
+            v
+        };
+
+        assert_eq!(sorted(&commit.n_changes_per_prefix),
+                   vec![ ("Project".to_string(), 3), ("Users".to_string(), 4) ]);
+        assert_eq!(sorted(&commit.n_changes_per_suffix),
+                   vec![ ("cs".to_string(), 6), ("resx".to_string(), 1) ]);
+    }
+}
@@ -0,0 +1,131 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* -------------- *
+ * MessageHygiene *
+ * -------------- */
+
+use crate::cohorthist::CohortHist;
+use crate::commitdb::MessageStats;
+use crate::csvformat::{ csv_header, format_csv };
+use crate::timebin::TimeBin;
+
+// Trends in commit message quality per cohort: the share of commits whose
+// messages have a body, and the mean subject length.
+
+pub struct MessageHygiene
+{
+    stats: Vec<(TimeBin, i32, String, MessageStats)>
+}
+
+impl MessageHygiene
+{
+    pub fn new(stats: Vec<(TimeBin, i32, String, MessageStats)>) -> MessageHygiene
+    {
+        MessageHygiene { stats }
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.stats.is_empty()
+    }
+
+    pub fn has_bodies(&self) -> bool
+    {
+        self.stats.iter().any(|(_, _, _, s)| s.n_body_known > 0)
+    }
+
+    pub fn to_table(&self) -> String
+    {
+        let mut table = format!("{:<10} {:<30} {:>10} {:>10} {:>10}\n",
+                                "Interval", "Cohort", "Commits", "Bodies", "Subject");
+
+        for (ym, _, cohort, s) in &self.stats
+        {
+            table += &format!("{:<10} {:<30} {:>10} {:>10} {:>10}\n",
+                              ym.to_string(), cohort, s.n_commits,
+                              s.body_share().map_or("-".to_string(), |b| format!("{:.1}%", b * 100.0)),
+                              s.mean_subject_length().map_or("-".to_string(), |l| format!("{:.1}", l)));
+        }
+
+        table
+    }
+
+    pub fn to_csv(&self, delimiter: u8) -> String
+    {
+        let mut rows = vec![ csv_header(&[ "Interval", "Cohort", "Commits", "Body share", "Mean subject length" ]) ];
+
+        rows.extend(self.stats.iter()
+                    .map(|(ym, _, cohort, s)|
+                         vec![ ym.to_string(), cohort.clone(), s.n_commits.to_string(),
+                               s.body_share().map_or("NaN".to_string(), |b| b.to_string()),
+                               s.mean_subject_length().map_or("NaN".to_string(), |l| l.to_string()) ]));
+        format_csv(&rows, delimiter)
+    }
+
+    fn to_hist<F>(&self, value: F) -> CohortHist
+        where F: Fn(&MessageStats) -> Option<f64>
+    {
+        let mut hist = CohortHist::new();
+
+        for (ym, rank, cohort, s) in &self.stats
+        {
+            if let Some(v) = value(s)
+            {
+                hist.set_value(*ym, *rank, v);
+            }
+            hist.set_cohort_name(*rank, cohort);
+        }
+
+        hist.set_decimals(Some(1));
+        hist
+    }
+
+    pub fn body_share_hist(&self) -> CohortHist
+    {
+        self.to_hist(|s| s.body_share().map(|b| b * 100.0))
+    }
+
+    pub fn subject_length_hist(&self) -> CohortHist
+    {
+        self.to_hist(|s| s.mean_subject_length())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_bodies_are_left_out() {
+        let ym = TimeBin::Year(2020);
+        let hygiene = MessageHygiene::new(vec![
+            (ym, 0, "gnome.org".to_string(),
+             MessageStats { n_commits: 4, n_with_body: 1, n_body_known: 2, subject_length_sum: 120, n_subject_known: 4 }),
+            (ym, 1, "Other".to_string(),
+             MessageStats { n_commits: 1, n_with_body: 0, n_body_known: 0, subject_length_sum: 0, n_subject_known: 0 })
+        ]);
+
+        assert!(hygiene.has_bodies());
+        assert_eq!(hygiene.to_csv(b'|'), "Interval|Cohort|Commits|Body share|Mean subject length\n\
+                                      2020|gnome.org|4|0.5|30\n\
+                                      2020|Other|1|NaN|NaN");
+    }
+}
@@ -0,0 +1,266 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* ---------- *
+ * Identities *
+ * ---------- */
+
+use serde::{ Deserialize, Serialize };
+use std::collections::{ BTreeMap, BTreeSet };
+use std::fs;
+use std::io::{ BufRead, Write };
+use std::path::*;
+use crate::errors::*;
+
+// Decisions about which e-mail addresses belong to the same person, as made
+// with resolve-identities. The file is a JSON object, e.g:
+//
+// { "merge": [ [ "sven@gimp.org", "neo@wintermute.org" ] ],
+//   "split": [ [ "john@smith.org", "jsmith@example.com" ] ] }
+//
+// The addresses in a "merge" group are counted as one author under their
+// most common name. Those in a "split" group are kept apart by giving each
+// a name of its own, e.g. "John Smith (john@smith.org)".
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Identities
+{
+    #[serde(default)]
+    pub merge: Vec<Vec<String>>,
+    #[serde(default)]
+    pub split: Vec<Vec<String>>
+}
+
+impl Identities
+{
+    pub fn from_file(filename: &Path) -> Result<Identities>
+    {
+        let content = fs::read_to_string(filename).chain_err(|| "Could not read identities file")?;
+        serde_json::from_str(&content).chain_err(|| "Failed to parse identities file")
+    }
+
+    pub fn from_file_or_new(filename: &Path) -> Result<Identities>
+    {
+        if filename.exists() { Identities::from_file(filename) } else { Ok(Identities::default()) }
+    }
+
+    pub fn write(&self, filename: &Path) -> Result<()>
+    {
+        let json = serde_json::to_string_pretty(self).chain_err(|| "Could not serialize identities")?;
+        fs::write(filename, json + "\n").chain_err(|| "Could not write identities file")
+    }
+
+    pub fn is_decided(&self, emails: &[String]) -> bool
+    {
+        // A cluster has been decided on if all its addresses are in the same
+        // group. One that has picked up a new address is asked about again.
+
+        self.merge.iter().chain(self.split.iter())
+            .any(|group| emails.iter().all(|email| group.contains(email)))
+    }
+
+    pub fn decide(&mut self, emails: &[String], same_person: bool)
+    {
+        // The new decision replaces any made on a smaller cluster.
+
+        let covered = |group: &Vec<String>| group.iter().all(|email| emails.contains(email));
+        self.merge.retain(|group| !covered(group));
+        self.split.retain(|group| !covered(group));
+
+        if same_person { &mut self.merge } else { &mut self.split }.push(emails.to_vec());
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ClusterKind
+{
+    SameName,
+    SimilarNames
+}
+
+// Addresses that may or may not belong to the same person: a name used with
+// several addresses, which are counted as one author, or names that only
+// differ in case, spacing or punctuation, which are not. Members are given
+// as name, address and number of commits.
+
+#[derive(Debug)]
+pub struct IdentityCluster
+{
+    pub kind: ClusterKind,
+    pub members: Vec<(String, String, i64)>
+}
+
+impl IdentityCluster
+{
+    pub fn emails(&self) -> Vec<String>
+    {
+        self.members.iter().map(|(_, email, _)| email.clone()).collect::<BTreeSet<_>>().into_iter().collect()
+    }
+
+    fn n_commits(&self) -> i64
+    {
+        self.members.iter().map(|(_, _, n)| n).sum()
+    }
+
+    pub fn describe(&self) -> String
+    {
+        let mut text = match self.kind
+        {
+            ClusterKind::SameName => "One name, several addresses:".to_string(),
+            ClusterKind::SimilarNames => "Similar names:".to_string()
+        };
+
+        for (name, email, n_commits) in &self.members
+        {
+            text += &format!("\n    {:<30} {:<40} {:>6} commits", name, email, n_commits);
+        }
+
+        text
+    }
+}
+
+fn normalize_name(name: &str) -> String
+{
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn find_clusters(identities: &[(String, String, i64)]) -> Vec<IdentityCluster>
+{
+    let mut by_name: BTreeMap<&str, Vec<(String, String, i64)>> = BTreeMap::new();
+    let mut by_normalized_name: BTreeMap<String, Vec<(String, String, i64)>> = BTreeMap::new();
+
+    for (name, email, n_commits) in identities
+    {
+        let member = (name.clone(), email.clone(), *n_commits);
+        by_name.entry(name).or_default().push(member.clone());
+        by_normalized_name.entry(normalize_name(name)).or_default().push(member);
+    }
+
+    let mut clusters: Vec<IdentityCluster> = by_name.into_values()
+        .filter(|members| members.len() > 1)
+        .map(|members| IdentityCluster { kind: ClusterKind::SameName, members })
+        .chain(by_normalized_name.into_values()
+               .filter(|members| members.iter().any(|(name, _, _)| *name != members[0].0))
+               .map(|members| IdentityCluster { kind: ClusterKind::SimilarNames, members }))
+        .collect();
+
+    // The clusters with the most commits matter most, so they go first.
+
+    for cluster in clusters.iter_mut()
+    {
+        cluster.members.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.1.cmp(&b.1)));
+    }
+
+    clusters.sort_by_key(|cluster| -cluster.n_commits());
+    clusters
+}
+
+pub fn resolve_interactively<R: BufRead, W: Write>(clusters: &[IdentityCluster], identities: &mut Identities,
+                                                   path: &Path, mut input: R, mut output: W) -> Result<usize>
+{
+    // Asks about each cluster in turn, and saves the file after every
+    // answer, so quitting halfway loses nothing. Returns the number of
+    // decisions made.
+
+    let mut n_decided = 0;
+
+    for (i, cluster) in clusters.iter().enumerate()
+    {
+        writeln!(output, "\n[{}/{}] {}", i + 1, clusters.len(), cluster.describe())
+            .chain_err(|| "Could not write prompt")?;
+
+        let same_person = loop
+        {
+            write!(output, "  Same person? (y)es, (n)o, (s)kip, (q)uit: ").chain_err(|| "Could not write prompt")?;
+            output.flush().chain_err(|| "Could not write prompt")?;
+
+            let mut answer = String::new();
+            if input.read_line(&mut answer).chain_err(|| "Could not read answer")? == 0
+            {
+                return Ok(n_decided);
+            }
+
+            match answer.trim().to_lowercase().as_str()
+            {
+                "y" | "yes" => break Some(true),
+                "n" | "no" => break Some(false),
+                "s" | "skip" => break None,
+                "q" | "quit" => return Ok(n_decided),
+                _ => continue
+            }
+        };
+
+        if let Some(same_person) = same_person
+        {
+            identities.decide(&cluster.emails(), same_person);
+            identities.write(path)?;
+            n_decided += 1;
+        }
+    }
+
+    Ok(n_decided)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(name: &str, email: &str, n_commits: i64) -> (String, String, i64) {
+        (name.to_string(), email.to_string(), n_commits)
+    }
+
+    #[test]
+    fn finds_clusters() {
+        let clusters = find_clusters(&[ member("John Smith", "john@smith.org", 5),
+                                        member("John Smith", "jsmith@example.com", 10),
+                                        member("sven neumann", "neo@wintermute.org", 1),
+                                        member("Sven  Neumann", "sven@gimp.org", 20),
+                                        member("Dude", "dude@lebowski.com", 100) ]);
+
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].kind, ClusterKind::SimilarNames);
+        assert_eq!(clusters[0].emails(), [ "neo@wintermute.org", "sven@gimp.org" ]);
+        assert_eq!(clusters[1].kind, ClusterKind::SameName);
+        assert_eq!(clusters[1].members[0], member("John Smith", "jsmith@example.com", 10));
+    }
+
+    #[test]
+    fn records_answers() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let clusters = find_clusters(&[ member("A", "a@x.org", 2), member("A", "a@y.org", 1),
+                                        member("B", "b@x.org", 2), member("b", "b@y.org", 1) ]);
+        let mut identities = Identities { merge: vec![ vec![ "a@x.org".to_string() ] ], split: vec![] };
+        let mut output = Vec::new();
+
+        let n = resolve_interactively(&clusters, &mut identities, file.path(),
+                                      "maybe\nn\ny\n".as_bytes(), &mut output).unwrap();
+
+        assert_eq!(n, 2);
+        assert_eq!(identities.merge, [ [ "b@x.org", "b@y.org" ] ]);
+        assert_eq!(identities.split, [ [ "a@x.org", "a@y.org" ] ]);
+        assert_eq!(Identities::from_file(file.path()).unwrap(), identities);
+        assert!(identities.is_decided(&[ "b@y.org".to_string() ]));
+        assert!(!identities.is_decided(&[ "a@x.org".to_string(), "b@x.org".to_string() ]));
+    }
+}
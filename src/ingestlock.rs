@@ -0,0 +1,195 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* ---------- *
+ * IngestLock *
+ * ---------- */
+
+use chrono::Local;
+use std::fs::{ self, OpenOptions };
+use std::io::{ ErrorKind, Write };
+use std::path::{ Path, PathBuf };
+use std::thread::sleep;
+use std::time::Duration;
+use crate::errors::*;
+
+// SQLite's exclusive locking makes a second ingest into the same database
+// fail somewhere in the middle. Ingests take this lock file next to the
+// database first, so they can either give up with a useful message or
+// queue behind each other. The file names the process holding it, and is
+// taken over if that process is gone.
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct IngestLock
+{
+    path: PathBuf
+}
+
+impl IngestLock
+{
+    pub fn acquire(db_path: &Path, wait: bool) -> Result<IngestLock>
+    {
+        let mut path = db_path.as_os_str().to_owned();
+        path.push(".lock");
+        let path = PathBuf::from(path);
+        let mut announced = false;
+
+        loop
+        {
+            match OpenOptions::new().write(true).create_new(true).open(&path)
+            {
+                Ok(mut file) =>
+                {
+                    writeln!(file, "{}\n{}", std::process::id(), Local::now().format("%Y-%m-%d %H:%M:%S"))
+                        .chain_err(|| format!("Could not write lock file {}", path.display()))?;
+                    return Ok(IngestLock { path });
+                },
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {},
+                Err(e) => return Err(e).chain_err(|| format!("Could not create lock file {}", path.display()))
+            }
+
+            let holder = read_holder(&path);
+
+            if let Some((pid, _)) = &holder
+            {
+                if !is_running(*pid)
+                {
+                    eprintln!("Removing stale lock {} left by pid {}.", path.display(), pid);
+                    remove_stale(&path, *pid);
+                    continue;
+                }
+            }
+
+            let who = match &holder
+            {
+                Some((pid, started)) => format!("pid {}, started at {}", pid, started),
+                None => "unknown process".to_string()
+            };
+
+            if !wait
+            {
+                bail!("Another ingest is running ({}). Use --wait to queue behind it, or remove {} if it's stale",
+                      who, path.display());
+            }
+
+            if !announced
+            {
+                eprintln!("Another ingest is running ({}); waiting for it to finish.", who);
+                announced = true;
+            }
+
+            sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for IngestLock
+{
+    fn drop(&mut self)
+    {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+fn read_holder(path: &Path) -> Option<(u32, String)>
+{
+    // The pid and start time, or None if the file is unreadable or still
+    // being written.
+
+    let text = fs::read_to_string(path).ok()?;
+    let mut lines = text.lines();
+    let pid = lines.next()?.trim().parse().ok()?;
+
+    Some((pid, lines.next().unwrap_or("an unknown time").trim().to_string()))
+}
+
+fn remove_stale(path: &Path, stale_pid: u32)
+{
+    // Another ingest may have taken over the same stale lock and written
+    // its own since we looked. Move the file aside first, so it can be
+    // checked without racing, and put it back if it isn't the stale one.
+
+    let mut aside = path.as_os_str().to_owned();
+    aside.push(format!(".{}", std::process::id()));
+    let aside = PathBuf::from(aside);
+
+    if fs::rename(path, &aside).is_err() { return; }
+
+    if read_holder(&aside).map(|(pid, _)| pid) != Some(stale_pid)
+    {
+        // Linking fails rather than replace a lock taken in the meantime.
+        fs::hard_link(&aside, path).ok();
+    }
+
+    fs::remove_file(&aside).ok();
+}
+
+fn is_running(pid: u32) -> bool
+{
+    // Without /proc we can't tell, so assume the holder is still there.
+
+    if !Path::new("/proc/self").exists() { return true; }
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_second_ingest() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("db.sqlite");
+
+        let lock = IngestLock::acquire(&db_path, false).unwrap();
+        let err = IngestLock::acquire(&db_path, false).err().unwrap().to_string();
+        assert!(err.contains(&format!("pid {}", std::process::id())));
+
+        drop(lock);
+        assert!(!dir.path().join("db.sqlite.lock").exists());
+        IngestLock::acquire(&db_path, false).unwrap();
+    }
+
+    #[test]
+    fn takes_over_stale_lock() {
+        if !Path::new("/proc/self").exists() { return; }
+
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("db.sqlite");
+        fs::write(dir.path().join("db.sqlite.lock"), "999999999\n2020-01-01 00:00:00\n").unwrap();
+
+        IngestLock::acquire(&db_path, false).unwrap();
+    }
+
+    #[test]
+    fn keeps_lock_taken_over_by_another() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.sqlite.lock");
+        fs::write(&path, "1234\n2020-01-01 00:00:00\n").unwrap();
+
+        remove_stale(&path, 999999999);
+        assert_eq!(read_holder(&path).unwrap().0, 1234);
+
+        remove_stale(&path, 1234);
+        assert!(!path.exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,108 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+
+/* ------ *
+ * Lorenz *
+ * ------ */
+
+use std::fs;
+use std::path::*;
+use crate::csvformat::{ csv_header, format_csv };
+use crate::errors::*;
+
+// Lorenz curve of contributions: the cumulative share of the total held by
+// the bottom x share of contributors, along with the Gini coefficient.
+
+pub struct Lorenz
+{
+    pub points: Vec<(f64, f64)>,
+    pub gini: f64
+}
+
+impl Lorenz
+{
+    pub fn new(counts: &[i64]) -> Lorenz
+    {
+        let mut counts = counts.to_vec();
+        counts.sort_unstable();
+
+        let n = counts.len() as f64;
+        let total: i64 = counts.iter().sum();
+        let mut points = vec![(0.0, 0.0)];
+        let mut running = 0;
+        let mut area = 0.0;
+
+        for (i, count) in counts.iter().enumerate()
+        {
+            let (x0, y0) = points[i];
+
+            running += count;
+            let x = (i + 1) as f64 / n;
+            let y = if total > 0 { running as f64 / total as f64 } else { x };
+
+            area += (x - x0) * (y + y0) / 2.0;
+            points.push((x, y));
+        }
+
+        let gini = if counts.is_empty() { 0.0 } else { 1.0 - 2.0 * area };
+
+        Lorenz { points, gini }
+    }
+
+    pub fn to_csv(&self, delimiter: u8) -> String
+    {
+        let mut rows = vec![ csv_header(&[ "Authors", "Commits" ]) ];
+
+        rows.extend(self.points.iter().map(|(x, y)| vec![ x.to_string(), y.to_string() ]));
+        format_csv(&rows, delimiter)
+    }
+
+    pub fn write_csv(&self, out_path: &Path, delimiter: u8) -> Result<()>
+    {
+        fs::write(out_path, self.to_csv(delimiter) + "\n").chain_err(|| "Could not write Lorenz curve")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_contributions() {
+        let lorenz = Lorenz::new(&[5, 5, 5, 5]);
+
+        assert!(lorenz.gini.abs() < 1e-9);
+        assert_eq!(lorenz.points[2], (0.5, 0.5));
+    }
+
+    #[test]
+    fn single_dominant_contributor() {
+        let lorenz = Lorenz::new(&[0, 0, 0, 100]);
+
+        assert!((lorenz.gini - 0.75).abs() < 1e-9);
+        assert_eq!(lorenz.points.last(), Some(&(1.0, 1.0)));
+    }
+
+    #[test]
+    fn unsorted_input() {
+        assert_eq!(Lorenz::new(&[3, 1, 2]).points, Lorenz::new(&[1, 2, 3]).points);
+    }
+}
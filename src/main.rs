@@ -21,6 +21,8 @@
 // 'error_chain!' can recurse deeply
 #![recursion_limit = "1024"]
 
+// error-chain checks a cfg of its own that newer compilers don't know about
+#[allow(unexpected_cfgs)]
 mod errors
 {
     // Create the Error, ErrorKind, ResultExt, and Result types
@@ -29,22 +31,85 @@ mod errors
 
 mod cohorthist;
 mod commitdb;
+mod commitsizes;
+mod commitexport;
+mod commitstore;
 mod common;
+mod coupling;
+mod crosstab;
+mod csvformat;
+mod fleet;
+mod forecast;
+mod funnel;
 mod gitcommitreader;
+mod hygiene;
+mod identities;
+mod ingestlock;
+mod lorenz;
+mod manifest;
+mod mboxreader;
+mod onboarding;
+mod overlap;
+mod parquetexport;
+#[cfg(feature = "postgres")]
+mod pgcommitdb;
+mod pipeline;
 mod plotter;
+mod plotstream;
+mod pngmeta;
 mod projectmeta;
+mod remotedb;
+mod repomap;
+mod report;
+mod sankey;
+mod seasonal;
+mod snapshotdiff;
 mod statuslogger;
+mod stickiness;
+mod timebin;
 
-use std::path::PathBuf;
+use chrono::{ Datelike, DateTime, TimeZone };
+use chrono::prelude::Utc;
+use std::path::{ Path, PathBuf };
 use std::process::Command;
+use std::time::Instant;
 use structopt::StructOpt;
 use errors::*;
+use crate::cohorthist::{ CohortHist, LegendTotal };
 use crate::commitdb::CommitDb;
-use crate::common::{ CohortType, IntervalType, UnitType };
-use crate::gitcommitreader::GitCommitReader;
+use crate::commitsizes::CommitSizes;
+use crate::commitstore::{ is_postgres_url, open_for_queries, open_store, open_store_read_only };
+use crate::common::{ AccessibleStyle, BotSplit, BucketTz, ChartType, CohortSelector, CohortType, DbProfile, ExportFormat, FacetType,
+                     ForecastModel, HideType, IntervalType, OverlayType, ReportFormat, SeasonalView, TenureCurve,
+                     UnitType, YearRange, parse_chart_type, parse_cohort_type };
+use crate::coupling::PrefixCoupling;
+use crate::crosstab::DomainCrossTab;
+use crate::csvformat::{ csv_header, format_csv, parse_delimiter, GNUPLOT_DELIMITER };
+use crate::fleet::{ Fleet, FleetConfig };
+use crate::funnel::RetentionFunnel;
+use crate::identities::{ Identities, IdentityCluster, find_clusters, resolve_interactively };
+use crate::gitcommitreader::{ is_in_sample, GitCommitReader };
+use crate::hygiene::MessageHygiene;
+use crate::ingestlock::IngestLock;
+use crate::lorenz::Lorenz;
+use crate::manifest::{ Manifest, hash_file };
+use crate::mboxreader::MboxReader;
+use crate::onboarding::{ Onboarding, read_author_events };
+use crate::pipeline::PipelineConfig;
 use crate::plotter::Plotter;
-use crate::projectmeta::ProjectMeta;
+use crate::plotstream::emit_hist;
+use crate::projectmeta::{ ProjectMeta, find_unknown_fields };
+use crate::remotedb::resolve_db_path;
+use crate::repomap::RepoMap;
+use crate::report::{ Period, Report };
+use crate::commitexport::{ AggregateExport, CommitExport };
+use crate::overlap::AuthorOverlap;
+use crate::sankey::Sankey;
+use crate::seasonal::{ SeasonalProfile, seasonally_adjust };
+use crate::snapshotdiff::SnapshotDiff;
 use crate::statuslogger::StatusLogger;
+use crate::stickiness::Stickiness;
+use crate::timebin::TimeBin;
 
 #[macro_use]
 extern crate error_chain;
@@ -60,6 +125,10 @@ struct Args
     #[structopt(short, long, parse(from_os_str))]
     meta: Option<PathBuf>,
 
+    /// Field delimiter for CSV output, e.g. ";" or "tab"
+    #[structopt(long, default_value = ",", parse(try_from_str = parse_delimiter))]
+    csv_delimiter: u8,
+
     #[structopt(subcommand)]
     cmd: MainCommand
 }
@@ -67,33 +136,126 @@ struct Args
 #[derive(StructOpt, Debug)]
 enum MainCommand
 {
-    Ingest
+    Ingest(IngestArgs),
+    IngestMbox
     {
         /// Path to SQLite database (will be created if nonexistent)
         #[structopt(parse(from_os_str))]
         db_path: PathBuf,
 
-        /// Paths to Git repositories to ingest
+        /// Paths to mbox files with mailed patches
+        #[structopt(parse(from_os_str))]
+        mbox_paths: Vec<PathBuf>,
+
+        /// Repository name to store the patches under. Defaults to
+        /// "mbox:<file name>" for each file
+        #[structopt(short, long)]
+        name: Option<String>,
+
+        /// If another ingest into the database is running, wait for it to
+        /// finish instead of failing
+        #[structopt(long)]
+        wait: bool,
+
+        /// How SQLite trades durability for speed: safe, fast, or bulk for
+        /// an unjournaled ingest into a scratch database
+        #[structopt(long, default_value = "fast")]
+        db_profile: DbProfile
+    },
+    BenchIngest
+    {
+        /// Paths to Git repositories to read
         #[structopt(parse(from_os_str))]
         repo_tree_paths: Vec<PathBuf>
     },
-    Plot
+    RenameRepo
     {
         /// Path to SQLite database previously created by ingestion
         #[structopt(parse(from_os_str))]
         db_path: PathBuf,
 
-        /// Output path for PNG image
+        /// Current repository name
+        old_name: String,
+
+        /// New repository name
+        new_name: String
+    },
+    DedupeCommits
+    {
+        /// Path to SQLite database previously created by ingestion
+        #[structopt(parse(from_os_str))]
+        db_path: PathBuf,
+
+        /// Comma-separated repo globs in order of precedence. Commits found
+        /// in several repos are counted for the first match
+        #[structopt(short, long, use_delimiter = true)]
+        prefer: Option<Vec<String>>
+    },
+    ResolveIdentities
+    {
+        /// Path to SQLite database previously created by ingestion
+        #[structopt(parse(from_os_str))]
+        db_path: PathBuf,
+
+        /// Identities file to read earlier decisions from and write new ones
+        /// to (will be created if nonexistent)
+        #[structopt(parse(from_os_str))]
+        identities_path: PathBuf,
+
+        /// Ask about each undecided cluster in turn instead of listing them
+        #[structopt(long)]
+        interactive: bool
+    },
+    DbInfo
+    {
+        /// Path to SQLite database previously created by ingestion
+        #[structopt(parse(from_os_str))]
+        db_path: PathBuf
+    },
+    InspectCommit
+    {
+        /// Path to SQLite database previously created by ingestion
+        #[structopt(parse(from_os_str))]
+        db_path: PathBuf,
+
+        /// Commit id, which may be abbreviated
+        id: String
+    },
+    Snapshot
+    {
+        /// Path to SQLite database previously created by ingestion
+        #[structopt(parse(from_os_str))]
+        db_path: PathBuf,
+
+        /// Path to the new snapshot database
         #[structopt(parse(from_os_str))]
         out_path: PathBuf,
 
-        /// Cohorts to use (firstyear, domain, repo, prefix or suffix)
-        #[structopt(short, long, default_value = "firstyear")]
-        cohort: CohortType,
+        /// Replace author names, email local parts and subjects with
+        /// pseudonyms
+        #[structopt(long)]
+        anonymize: bool,
+
+        /// Only keep commits from this many most recent years
+        #[structopt(long)]
+        limit_years: Option<i32>
+    },
+    CheckMeta
+    {
+        /// Path to SQLite database previously created by ingestion
+        #[structopt(parse(from_os_str))]
+        db_path: PathBuf
+    },
+    Plot(Box<PlotArgs>),
+    Fleet
+    {
+        /// Path to fleet JSON file listing project databases
+        #[structopt(parse(from_os_str))]
+        fleet_path: PathBuf,
 
-        /// Y axis data type (authors, commits, or changes)
-        #[structopt(short, long, default_value = "authors")]
-        unit: UnitType,
+        /// Output path for PNG image
+        #[structopt(parse(from_os_str))]
+        out_path: PathBuf,
 
         /// X axis granularity (month or year)
         #[structopt(short, long, default_value = "year")]
@@ -106,127 +268,2843 @@ enum MainCommand
         /// Last year to show
         #[structopt(short, long)]
         to: Option<i32>
+    },
+    ExportSankey
+    {
+        /// Path to SQLite database previously created by ingestion
+        #[structopt(parse(from_os_str))]
+        db_path: PathBuf,
+
+        /// Output path for edge list
+        #[structopt(parse(from_os_str))]
+        out_path: PathBuf,
+
+        /// Cohorts to trace (domain or repo)
+        #[structopt(short, long, default_value = "domain")]
+        cohort: CohortType,
+
+        /// Comma-separated years at which periods begin, e.g. 2010,2015,2020
+        #[structopt(short, long, use_delimiter = true, required = true)]
+        periods: Vec<i32>,
+
+        /// Output format (csv or json)
+        #[structopt(long, default_value = "csv")]
+        format: ExportFormat
+    },
+    ExportCommits(ExportCommitsArgs),
+    Funnel
+    {
+        /// Path to SQLite database previously created by ingestion
+        #[structopt(parse(from_os_str))]
+        db_path: PathBuf,
+
+        /// Output path for the funnel table. If omitted, it is printed as CSV
+        #[structopt(parse(from_os_str))]
+        out_path: Option<PathBuf>,
+
+        /// Output format (csv, json or parquet)
+        #[structopt(long, default_value = "csv")]
+        format: ExportFormat
+    },
+    Stickiness
+    {
+        /// Path to SQLite database previously created by ingestion
+        #[structopt(parse(from_os_str))]
+        db_path: PathBuf,
+
+        /// Output path for the stickiness table. If omitted, it is printed as CSV
+        #[structopt(parse(from_os_str))]
+        out_path: Option<PathBuf>,
+
+        /// Output format (csv, json or parquet)
+        #[structopt(long, default_value = "csv")]
+        format: ExportFormat
+    },
+    ImportAuthorEvents
+    {
+        /// Path to SQLite database previously created by ingestion
+        #[structopt(parse(from_os_str))]
+        db_path: PathBuf,
+
+        /// CSV file with "email" and "date" columns, and optionally "kind"
+        #[structopt(parse(from_os_str))]
+        events_path: PathBuf,
+
+        /// Kind of event for rows without a "kind" column
+        #[structopt(long, default_value = "event")]
+        kind: String
+    },
+    Onboarding
+    {
+        /// Path to SQLite database previously created by ingestion
+        #[structopt(parse(from_os_str))]
+        db_path: PathBuf,
+
+        /// Output path for the onboarding table. If omitted, it is printed as CSV
+        #[structopt(parse(from_os_str))]
+        out_path: Option<PathBuf>,
+
+        /// Kind of imported event to compare first commits to
+        #[structopt(long, default_value = "event")]
+        kind: String,
+
+        /// Summarize by event year instead of listing each author
+        #[structopt(long)]
+        summary: bool,
+
+        /// Output format (csv, json or parquet)
+        #[structopt(long, default_value = "csv")]
+        format: ExportFormat
+    },
+    ExportAuthors
+    {
+        /// Path to SQLite database previously created by ingestion
+        #[structopt(parse(from_os_str))]
+        db_path: PathBuf,
+
+        /// Output path for JSON file
+        #[structopt(parse(from_os_str))]
+        out_path: PathBuf
+    },
+    Breakdown(BreakdownArgs),
+    Diff(DiffArgs),
+    CrossTab(CrossTabArgs),
+    Overlap
+    {
+        /// Path to SQLite database previously created by ingestion
+        #[structopt(parse(from_os_str))]
+        db_path: PathBuf,
+
+        /// Output path for a heatmap (PNG). If it ends in .csv, the data is
+        /// written instead
+        #[structopt(parse(from_os_str))]
+        out_path: Option<PathBuf>,
+
+        /// What to compare the author bases of (repo or domain)
+        #[structopt(long, default_value = "repo")]
+        by: CohortType,
+
+        /// Number of repos or domains to compare, those with the most
+        /// authors first
+        #[structopt(long, default_value = "15")]
+        top: usize,
+
+        /// Leave out repos matching this glob (may be repeated)
+        #[structopt(long, number_of_values = 1)]
+        exclude_repo: Vec<String>
+    },
+    Coupling(CouplingArgs),
+    Hygiene(HygieneArgs),
+    ExportCommitSizes(ExportCommitSizesArgs),
+    Precompute(PrecomputeArgs),
+    GraphStats
+    {
+        /// Path to SQLite database previously created by ingestion
+        #[structopt(parse(from_os_str))]
+        db_path: PathBuf,
+
+        /// Output path for a chart (PNG). If it ends in .csv, the data is
+        /// written instead
+        #[structopt(parse(from_os_str))]
+        out_path: Option<PathBuf>,
+
+        /// Interval to compute metrics for (month or year)
+        #[structopt(short, long, default_value = "year")]
+        interval: IntervalType
+    },
+    Report
+    {
+        /// Path to SQLite database previously created by ingestion
+        #[structopt(parse(from_os_str))]
+        db_path: PathBuf,
+
+        /// Directory to write the report and its plots to
+        #[structopt(parse(from_os_str))]
+        out_dir: PathBuf,
+
+        /// Period to report on, e.g. 2020 or 2020-Q3
+        #[structopt(short, long)]
+        period: Period,
+
+        /// Output format (markdown or html)
+        #[structopt(long, default_value = "markdown")]
+        format: ReportFormat,
+
+        /// Embed the plots in the HTML report instead of writing them next
+        /// to it, so it's a single file
+        #[structopt(long)]
+        self_contained: bool
+    },
+    Run
+    {
+        /// Path to pipeline JSON file listing repositories and outputs
+        #[structopt(long, parse(from_os_str))]
+        config: PathBuf,
+
+        /// Use the database as it is, without ingesting
+        #[structopt(long)]
+        skip_ingest: bool,
+
+        /// Carry on with the remaining outputs if one of them fails
+        #[structopt(long)]
+        keep_going: bool,
+
+        /// If another ingest into the database is running, wait for it to
+        /// finish instead of failing
+        #[structopt(long)]
+        wait: bool
     }
 }
 
-/* ---- *
- * Main *
- * ---- */
+#[derive(StructOpt, Debug)]
+pub struct IngestArgs
+{
+    /// Path to SQLite database (will be created if nonexistent)
+    #[structopt(parse(from_os_str))]
+    db_path: PathBuf,
 
-fn main()
+    /// Paths to Git repositories to ingest
+    #[structopt(parse(from_os_str))]
+    repo_tree_paths: Vec<PathBuf>,
+
+    /// Name to store the repository under (single repository only)
+    #[structopt(short, long)]
+    name: Option<String>,
+
+    /// Path to JSON file mapping repository paths to names
+    #[structopt(long, parse(from_os_str))]
+    repo_map: Option<PathBuf>,
+
+    /// Comma-separated branch globs to ingest (HEAD for the checked-out
+    /// branch only). Remembered for subsequent ingests of the same repo
+    #[structopt(short, long, use_delimiter = true)]
+    branches: Option<Vec<String>>,
+
+    /// Also record whether commit messages have a body, for `hygiene`.
+    /// Takes an extra pass over the log
+    #[structopt(long)]
+    message_stats: bool,
+
+    /// Also ingest initialized submodules, each as a repo named after its
+    /// path in the superproject
+    #[structopt(long)]
+    recurse_submodules: bool,
+
+    /// Only ingest a sample of commits, e.g. 1/10 for every tenth, for
+    /// a quick preview. Commit-based counts are scaled up to match
+    #[structopt(long, parse(try_from_str = parse_sample_rate))]
+    sample: Option<u32>,
+
+    /// Only report how many new commits each repo has since its last
+    /// ingest, without writing anything
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// If another ingest into the database is running, wait for it to
+    /// finish instead of failing
+    #[structopt(long)]
+    wait: bool,
+
+    /// How SQLite trades durability for speed: safe, fast, or bulk for
+    /// an unjournaled ingest into a scratch database
+    #[structopt(long, default_value = "fast")]
+    db_profile: DbProfile
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ExportCommitsArgs
 {
-    if let Err(ref e) = run()
-    {
-        eprintln!("error: {}", e);
+    /// Path to SQLite database previously created by ingestion
+    #[structopt(parse(from_os_str))]
+    db_path: PathBuf,
 
-        for e in e.iter().skip(1)
-        {
-            eprintln!("caused by: {}", e);
-        }
+    /// Output path for commit list
+    #[structopt(parse(from_os_str))]
+    out_path: PathBuf,
 
-        // Run with `RUST_BACKTRACE=1` to get a backtrace.
+    /// Output format (csv, json or parquet)
+    #[structopt(long, default_value = "csv")]
+    format: ExportFormat,
 
-        if let Some(backtrace) = e.backtrace()
-        {
-            eprintln!("backtrace: {:?}", backtrace);
-        }
+    /// Also write per-interval totals for these cohorts, next to the
+    /// commit list as <name>-aggregates.<ext>
+    #[structopt(long)]
+    aggregates: Option<CohortType>,
 
-        ::std::process::exit(1);
-    }
+    /// Interval for --aggregates (day, month or year)
+    #[structopt(short, long, default_value = "year")]
+    interval: IntervalType,
+
+    /// Add the ISO week of each commit (e.g. 2020-W53)
+    #[structopt(long)]
+    iso_week: bool,
+
+    /// Time zone to assign commits to intervals by (local or utc)
+    #[structopt(long, default_value = "local")]
+    bucket_tz: BucketTz,
+
+    /// Leave out repos matching this glob (may be repeated)
+    #[structopt(long, number_of_values = 1)]
+    exclude_repo: Vec<String>
 }
 
-fn run() -> Result<()>
+#[derive(StructOpt, Debug)]
+pub struct BreakdownArgs
 {
-    let args = Args::from_args();
-    let meta = 
-        match args.meta
-        {
-            Some(m) => { ProjectMeta::from_file(&m)? },
-            None => { ProjectMeta::new() }
-        };
+    /// Path to SQLite database previously created by ingestion
+    #[structopt(parse(from_os_str))]
+    db_path: PathBuf,
 
-    match args.cmd
-    {
-        MainCommand::Ingest { db_path, repo_tree_paths } =>
-        {
-            run_ingest(db_path, repo_tree_paths, &meta)
-        },
-        MainCommand::Plot { db_path, out_path, cohort, unit, interval, from, to } =>
-        {
-            run_plot(db_path, out_path, &meta, cohort, unit, interval, from, to)
-        }
-    }
+    /// Output path for PNG image. If omitted, the data is printed as CSV
+    #[structopt(parse(from_os_str))]
+    out_path: Option<PathBuf>,
+
+    /// Cohort to break down, e.g. domain=suse.de
+    #[structopt(short, long)]
+    cohort: CohortSelector,
+
+    /// What to break it down by (domain, repo, prefix or suffix)
+    #[structopt(short, long, default_value = "repo")]
+    by: CohortType,
+
+    /// Unit of measurement
+    #[structopt(short, long, default_value = "commits")]
+    unit: UnitType,
+
+    /// X axis granularity (day, month or year)
+    #[structopt(short, long, default_value = "year")]
+    interval: IntervalType,
+
+    /// First year to show
+    #[structopt(short, long)]
+    from: Option<i32>,
+
+    /// Last year to show
+    #[structopt(short, long)]
+    to: Option<i32>
 }
 
-fn run_ingest(db_path: PathBuf, repo_tree_paths: Vec<PathBuf>, _meta: &ProjectMeta) -> Result<()>
+#[derive(StructOpt, Debug)]
+pub struct DiffArgs
 {
-    let mut cdb = CommitDb::open(db_path).unwrap();
-    let mut sl = StatusLogger::new();
+    /// Path to SQLite database previously created by ingestion
+    #[structopt(parse(from_os_str))]
+    db_path: PathBuf,
 
-    for path in repo_tree_paths.iter()
-    {
-        let repo_name =
-            path.canonicalize().unwrap()
-            .file_name().unwrap()
-            .to_string_lossy()
-            .into_owned();
+    /// Output path for a slope chart (PNG). If it ends in .csv, the data is
+    /// written instead
+    #[structopt(parse(from_os_str))]
+    out_path: Option<PathBuf>,
 
-        sl.begin_repo(&repo_name);
+    /// First window of years, e.g. 2018..2020
+    #[structopt(long = "a")]
+    a: YearRange,
 
-        // Check for promisor for origin remote; we interpret its presence
-        // as a preference for remote storage. If found, we turn off --stat
-        // collection, since that would cause git to fetch all the remote
-        // blobs (slowly).
-        //
-        // This will break change counts. Author and commit counts will still
-        // work.
+    /// Second window of years, e.g. 2021..2023
+    #[structopt(long = "b")]
+    b: YearRange,
 
-        let mut cmd;
-        cmd = Command::new("git");
-        cmd.arg("-C").arg(&path).arg("config").arg("remote.origin.promisor");
-        let output = cmd.output().unwrap();
-        let has_promisor = std::str::from_utf8(&output.stdout).unwrap().trim() == "true";
+    /// Cohorts to compare (domain, repo, prefix, suffix, firstyear, firstsuffix or cadence)
+    #[structopt(short, long, default_value = "domain")]
+    cohort: CohortType,
 
-        if has_promisor
-        {
-            sl.log_warning("origin has a promisor; change details omitted.");
-        }
+    /// Unit of measurement
+    #[structopt(short, long, default_value = "commits")]
+    unit: UnitType,
 
-        let gcr = GitCommitReader::new(path.clone(),
-                                       &repo_name,
-                                       cdb.get_last_author_time(&repo_name),
-                                       !has_promisor)?;
+    /// Leave out repos matching this glob (may be repeated)
+    #[structopt(long, number_of_values = 1)]
+    exclude_repo: Vec<String>
+}
 
-        for commit in gcr
-        {
-            cdb.insert_raw_commit(&commit)?;
-            sl.log_commit(&commit);
-        }
+#[derive(StructOpt, Debug)]
+pub struct CrossTabArgs
+{
+    /// Path to SQLite database previously created by ingestion
+    #[structopt(parse(from_os_str))]
+    db_path: PathBuf,
 
-        sl.end_repo();
-    }
+    /// Output path for a heatmap (PNG). If it ends in .csv, the data is
+    /// written instead
+    #[structopt(parse(from_os_str))]
+    out_path: Option<PathBuf>,
 
-    Ok(())
+    /// Span of years to tabulate, e.g. 2010..2014 (may be repeated; all
+    /// years if not given)
+    #[structopt(long, number_of_values = 1)]
+    era: Vec<YearRange>,
+
+    /// Number of committer and author domains to show; the rest are
+    /// folded into "Other"
+    #[structopt(long, default_value = "10")]
+    top_domains: usize,
+
+    /// Leave out repos matching this glob (may be repeated)
+    #[structopt(long, number_of_values = 1)]
+    exclude_repo: Vec<String>
 }
 
-fn run_plot(db_path: PathBuf, out_path: PathBuf, meta: &ProjectMeta,
-            cohort: CohortType, unit: UnitType, interval: IntervalType,
-            from: Option<i32>, to: Option<i32>) -> Result<()>
+#[derive(StructOpt, Debug)]
+pub struct CouplingArgs
 {
-    let mut cdb = CommitDb::open(db_path)?;
-    cdb.postprocess(&meta.domains)?; // FIXME: Skip if metadata is unchanged
-    let hist = cdb.get_hist(cohort, unit, interval).chain_err(|| "")?;
-    let plotter = Plotter { };
+    /// Path to SQLite database previously created by ingestion
+    #[structopt(parse(from_os_str))]
+    db_path: PathBuf,
 
-    match interval
-    {
-        IntervalType::Month =>
-        {
-            plotter.plot_monthly_cohorts(&meta, &unit.to_string(), &hist, &out_path, from, to)
-        },
-        IntervalType::Year =>
-        {
-            plotter.plot_yearly_cohorts(&meta, &unit.to_string(), &hist, &out_path, from, to)
-        }
-    }
+    /// Output path for a chart of coupling per year (PNG). If it ends in
+    /// .csv, the data is written instead
+    #[structopt(parse(from_os_str))]
+    out_path: Option<PathBuf>,
+
+    /// Number of directory pairs to show
+    #[structopt(long, default_value = "10")]
+    top: usize,
+
+    /// Least number of commits a pair must share to be shown
+    #[structopt(long, default_value = "5")]
+    min_shared: i64,
+
+    /// Leave out commits touching more than this many directories
+    #[structopt(long, default_value = "20")]
+    max_dirs: usize,
+
+    /// Leave out repos matching this glob (may be repeated)
+    #[structopt(long, number_of_values = 1)]
+    exclude_repo: Vec<String>
+}
+
+#[derive(StructOpt, Debug)]
+pub struct PrecomputeArgs
+{
+    /// Path to SQLite database previously created by ingestion
+    #[structopt(parse(from_os_str))]
+    db_path: PathBuf,
+
+    /// Directory to write the tables to, one CSV file per cohort and unit
+    #[structopt(parse(from_os_str))]
+    out_dir: PathBuf,
+
+    /// Comma-separated cohorts to compute tables for
+    #[structopt(short, long, use_delimiter = true, default_value = "firstyear,domain,repo",
+                parse(try_from_str = parse_cohort_type))]
+    cohorts: Vec<CohortType>,
+
+    /// Comma-separated units to compute tables for
+    #[structopt(short, long, use_delimiter = true, default_value = "authors,commits,changes")]
+    units: Vec<UnitType>,
+
+    /// Time interval of each histogram bin (year, month or day)
+    #[structopt(short, long, default_value = "year")]
+    interval: IntervalType,
+
+    /// Time zone to assign commits to intervals by (local or utc)
+    #[structopt(long, default_value = "local")]
+    bucket_tz: BucketTz,
+
+    /// Minimum commits per interval for an author to count as active
+    #[structopt(long, default_value = "5")]
+    min_activity: u32,
+
+    /// Leave out repos matching this glob (may be repeated)
+    #[structopt(long, number_of_values = 1)]
+    exclude_repo: Vec<String>
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ExportCommitSizesArgs
+{
+    /// Path to SQLite database previously created by ingestion
+    #[structopt(parse(from_os_str))]
+    db_path: PathBuf,
+
+    /// Output path for the size table. If omitted, it is printed as CSV
+    #[structopt(parse(from_os_str))]
+    out_path: Option<PathBuf>,
+
+    /// Cohorts to compare (domain, country, epoch, committype, repo, firstyear, firstsuffix or cadence)
+    #[structopt(short, long, default_value = "domain")]
+    cohort: CohortType,
+
+    /// Number of cohorts to show; the rest are folded into "Other"
+    #[structopt(long, default_value = "8")]
+    top_cohorts: usize,
+
+    /// Interval to compute distributions for (day, month or year)
+    #[structopt(short, long, default_value = "year")]
+    interval: IntervalType,
+
+    /// Leave out repos matching this glob (may be repeated)
+    #[structopt(long, number_of_values = 1)]
+    exclude_repo: Vec<String>,
+
+    /// Output format (csv, json or parquet)
+    #[structopt(long, default_value = "csv")]
+    format: ExportFormat
+}
+
+#[derive(StructOpt, Debug)]
+pub struct HygieneArgs
+{
+    /// Path to SQLite database previously created by ingestion
+    #[structopt(parse(from_os_str))]
+    db_path: PathBuf,
+
+    /// Output path for a chart (PNG). If it ends in .csv, the data is
+    /// written instead
+    #[structopt(parse(from_os_str))]
+    out_path: Option<PathBuf>,
+
+    /// Cohorts to compare (domain, repo, firstyear, firstsuffix or cadence)
+    #[structopt(short, long, default_value = "domain")]
+    cohort: CohortType,
+
+    /// Number of cohorts to show; the rest are folded into "Other"
+    #[structopt(long, default_value = "8")]
+    top_cohorts: usize,
+
+    /// Interval to compute metrics for (month or year)
+    #[structopt(short, long, default_value = "year")]
+    interval: IntervalType,
+
+    /// Leave out repos matching this glob (may be repeated)
+    #[structopt(long, number_of_values = 1)]
+    exclude_repo: Vec<String>
+}
+
+#[derive(StructOpt, Debug)]
+pub struct PlotArgs
+{
+    /// Path to SQLite database previously created by ingestion
+    #[structopt(parse(from_os_str))]
+    db_path: PathBuf,
+
+    /// Output path for PNG image
+    #[structopt(parse(from_os_str))]
+    out_path: PathBuf,
+
+    /// Chart type (stacked, cumulative, cohortdetail, lorenz, contributorsstrip, seasonal, growth, diversity
+    /// or identity-skew)
+    #[structopt(long, default_value = "stacked", parse(try_from_str = parse_chart_type))]
+    chart: ChartType,
+
+    /// What a seasonal chart shows (profile or adjusted)
+    #[structopt(long, default_value = "profile")]
+    seasonal_view: SeasonalView,
+
+    /// Draw one small chart per repo on a shared scale (stacked only)
+    #[structopt(long)]
+    facet_by: Option<FacetType>,
+
+    /// Maximum number of facets; the repos with the most commits are shown
+    #[structopt(long, default_value = "12")]
+    max_facets: usize,
+
+    /// Cohort to show in detail, e.g. a domain or repo name (cohortdetail only)
+    #[structopt(long)]
+    cohort_name: Option<String>,
+
+    /// Cohorts to use (firstyear, firstsuffix, domain, repo, prefix, suffix, workhours, cadence, crossdomain,
+    /// country, epoch, committype or firstyear:domain)
+    #[structopt(short, long, default_value = "firstyear", parse(try_from_str = parse_cohort_type))]
+    cohort: CohortType,
+
+    /// Y axis data type (authors, activeauthors, commits, changes, reverts, weightedcommits or commitshare)
+    #[structopt(short, long, default_value = "authors")]
+    unit: UnitType,
+
+    /// How weighted commits grow with the author's tenure (linear, sqrt or log)
+    #[structopt(long, default_value = "linear")]
+    tenure_curve: TenureCurve,
+
+    /// Number of authors to show in a contributors strip
+    #[structopt(long, default_value = "30")]
+    top_authors: u32,
+
+    /// Minimum commits per interval for an author to count as active
+    #[structopt(long, default_value = "5")]
+    min_activity: u32,
+
+    /// Minimum commits per interval an author must make to a cohort to be
+    /// counted in it, when counting authors
+    #[structopt(long, default_value = "1")]
+    min_cohort_commits: u32,
+
+    /// Pick the top cohorts from each year rather than over all time,
+    /// taking up to n per year
+    #[structopt(long)]
+    top_per_interval: Option<usize>,
+
+    /// Leave out the catch-all cohorts (other, brief or both)
+    #[structopt(long)]
+    hide: Option<HideType>,
+
+    /// Take likely bots out of the cohorts, and either remove them or show
+    /// them as a cohort of their own (remove or cohort)
+    #[structopt(long)]
+    split_bots: Option<BotSplit>,
+
+    /// Count commits that were cherry-picked to other branches once in
+    /// commit and change units
+    #[structopt(long)]
+    dedupe_cherry_picks: bool,
+
+    /// Add a footnote naming the repos whose commits start later or end
+    /// earlier than the rest, with the years they cover
+    #[structopt(long)]
+    repo_coverage: bool,
+
+    /// X axis granularity (day, month or year)
+    #[structopt(short, long, default_value = "year")]
+    interval: IntervalType,
+
+    /// Time zone to assign commits to intervals by (local or utc)
+    #[structopt(long, default_value = "local")]
+    bucket_tz: BucketTz,
+
+    /// First month (1-12) of the fiscal year. Years are then fiscal years,
+    /// named after the calendar year they end in
+    #[structopt(long)]
+    fiscal_year_start: Option<u32>,
+
+    /// First year to show
+    #[structopt(short, long)]
+    from: Option<i32>,
+
+    /// Last year to show
+    #[structopt(short, long)]
+    to: Option<i32>,
+
+    /// Leave out repos matching this glob (may be repeated)
+    #[structopt(long, number_of_values = 1)]
+    exclude_repo: Vec<String>,
+
+    /// Only show suffixes matching this glob, e.g. rs (may be repeated)
+    #[structopt(long, number_of_values = 1)]
+    include_suffix: Vec<String>,
+
+    /// Leave out suffixes matching this glob, e.g. po (may be repeated)
+    #[structopt(long, number_of_values = 1)]
+    exclude_suffix: Vec<String>,
+
+    /// Domain name to use for e-mail addresses without a valid domain
+    #[structopt(long, default_value = "Unknown")]
+    unknown_domain: String,
+
+    /// Leave out commits from e-mail addresses without a valid domain
+    #[structopt(long)]
+    drop_unknown_domains: bool,
+
+    /// Render a version and date caption into the image
+    #[structopt(long)]
+    stamp: bool,
+
+    /// Show each cohort's total for the plotted range in the legend
+    #[structopt(long)]
+    legend_totals: bool,
+
+    /// Label every nth year (month in daily charts) on the x axis (default depends on the range)
+    #[structopt(long)]
+    xtick_every: Option<u32>,
+
+    /// Rotate x axis labels by this many degrees (default depends on the range)
+    #[structopt(long, allow_hyphen_values = true)]
+    xtick_rotate: Option<i32>,
+
+    /// Draw with a colorblind-safe palette, or with pattern fills that also
+    /// survive grayscale printing (palette or patterns)
+    #[structopt(long)]
+    accessible: Option<AccessibleStyle>,
+
+    /// Label the n largest cohorts directly on stacked charts
+    #[structopt(long)]
+    direct_labels: Option<usize>,
+
+    /// Draw an overlay on yearly or monthly stacked and cumulative charts;
+    /// "forecast n" extrapolates the total n intervals past the data
+    #[structopt(long, number_of_values = 2, value_names = &["kind", "n"])]
+    overlay: Vec<String>,
+
+    /// Model to extrapolate forecasts with (linear or holtwinters)
+    #[structopt(long, default_value = "linear")]
+    forecast_model: ForecastModel,
+
+    /// Extend the plot with empty intervals through the current date
+    #[structopt(long)]
+    pad_to_now: bool,
+
+    /// CSV file of extra markers (date,label,row), added to any in the metadata
+    #[structopt(long, parse(from_os_str))]
+    events: Option<PathBuf>,
+
+    /// Also send the plotted data to this Unix socket or named pipe, as
+    /// length-prefixed JSON records
+    #[structopt(long, parse(from_os_str))]
+    emit_stream: Option<PathBuf>,
+
+    /// Don't replot if the output is newer than the database and metadata
+    #[structopt(long)]
+    skip_if_fresh: bool,
+
+    // The arguments the plot was asked for with, when they aren't the
+    // process's own, e.g. for outputs of a pipeline run.
+    #[structopt(skip)]
+    command_line: Option<Vec<String>>
+}
+
+/* ---- *
+ * Main *
+ * ---- */
+
+fn main()
+{
+    if let Err(ref e) = run()
+    {
+        eprintln!("error: {}", e);
+
+        for e in e.iter().skip(1)
+        {
+            eprintln!("caused by: {}", e);
+        }
+
+        // Run with `RUST_BACKTRACE=1` to get a backtrace.
+
+        if let Some(backtrace) = e.backtrace()
+        {
+            eprintln!("backtrace: {:?}", backtrace);
+        }
+
+        ::std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()>
+{
+    run_args(Args::from_args())
+}
+
+fn run_args(args: Args) -> Result<()>
+{
+    let mut meta =
+        match &args.meta
+        {
+            Some(m) => { ProjectMeta::from_file(m)? },
+            None => { ProjectMeta::new() }
+        };
+
+    match args.cmd
+    {
+        MainCommand::Ingest(ingest_args) =>
+        {
+            run_ingest(ingest_args, &meta)
+        },
+        MainCommand::IngestMbox { db_path, mbox_paths, name, wait, db_profile } =>
+        {
+            run_ingest_mbox(db_path, mbox_paths, name, wait, db_profile, &meta)
+        },
+        MainCommand::BenchIngest { repo_tree_paths } =>
+        {
+            run_bench_ingest(repo_tree_paths)
+        },
+        MainCommand::RenameRepo { db_path, old_name, new_name } =>
+        {
+            run_rename_repo(db_path, &old_name, &new_name)
+        },
+        MainCommand::DedupeCommits { db_path, prefer } =>
+        {
+            run_dedupe_commits(db_path, prefer)
+        },
+        MainCommand::ResolveIdentities { db_path, identities_path, interactive } =>
+        {
+            run_resolve_identities(db_path, identities_path, interactive, &mut meta)
+        },
+        MainCommand::DbInfo { db_path } =>
+        {
+            run_db_info(db_path)
+        },
+        MainCommand::InspectCommit { db_path, id } =>
+        {
+            run_inspect_commit(db_path, &id, &meta)
+        },
+        MainCommand::Snapshot { db_path, out_path, anonymize, limit_years } =>
+        {
+            run_snapshot(db_path, out_path, anonymize, limit_years, args.meta.as_ref())
+        },
+        MainCommand::CheckMeta { db_path } =>
+        {
+            run_check_meta(db_path, &meta, args.meta.as_ref())
+        },
+        MainCommand::Plot(plot_args) =>
+        {
+            if let Some(events) = &plot_args.events
+            {
+                meta.add_events_from_file(events)?;
+            }
+
+            run_plot(&plot_args, &meta, args.meta.as_ref(), args.csv_delimiter)
+        },
+        MainCommand::Fleet { fleet_path, out_path, interval, from, to } =>
+        {
+            run_fleet(fleet_path, out_path, &meta, interval, from, to)
+        },
+        MainCommand::ExportSankey { db_path, out_path, cohort, periods, format } =>
+        {
+            run_export_sankey(db_path, out_path, &meta, cohort, periods, format, args.csv_delimiter)
+        },
+        MainCommand::ExportCommits(cmd_args) =>
+        {
+            run_export_commits(cmd_args, &meta, args.csv_delimiter)
+        },
+        MainCommand::ExportAuthors { db_path, out_path } =>
+        {
+            run_export_authors(db_path, out_path, &meta)
+        },
+        MainCommand::Breakdown(cmd_args) =>
+        {
+            run_breakdown(cmd_args, &meta, args.csv_delimiter)
+        },
+        MainCommand::Diff(cmd_args) =>
+        {
+            run_diff(cmd_args, &meta, args.csv_delimiter)
+        },
+        MainCommand::CrossTab(cmd_args) =>
+        {
+            run_cross_tab(cmd_args, &meta, args.csv_delimiter)
+        },
+        MainCommand::Overlap { db_path, out_path, by, top, exclude_repo } =>
+        {
+            run_overlap(db_path, out_path, &meta, by, top, &exclude_repo, args.csv_delimiter)
+        },
+        MainCommand::Funnel { db_path, out_path, format } =>
+        {
+            run_funnel(db_path, out_path, &meta, format, args.csv_delimiter)
+        },
+        MainCommand::Stickiness { db_path, out_path, format } =>
+        {
+            run_stickiness(db_path, out_path, &meta, format, args.csv_delimiter)
+        },
+        MainCommand::ImportAuthorEvents { db_path, events_path, kind } =>
+        {
+            run_import_author_events(db_path, events_path, &kind)
+        },
+        MainCommand::Onboarding { db_path, out_path, kind, summary, format } =>
+        {
+            run_onboarding(db_path, out_path, &meta, &kind, summary, format, args.csv_delimiter)
+        },
+        MainCommand::Coupling(cmd_args) =>
+        {
+            run_coupling(cmd_args, &meta, args.csv_delimiter)
+        },
+        MainCommand::Hygiene(cmd_args) =>
+        {
+            run_hygiene(cmd_args, &meta, args.csv_delimiter)
+        },
+        MainCommand::ExportCommitSizes(cmd_args) =>
+        {
+            run_export_commit_sizes(cmd_args, &meta, args.csv_delimiter)
+        },
+        MainCommand::Precompute(cmd_args) =>
+        {
+            run_precompute(cmd_args, &meta, args.csv_delimiter)
+        },
+        MainCommand::GraphStats { db_path, out_path, interval } =>
+        {
+            run_graph_stats(db_path, out_path, &meta, interval, args.csv_delimiter)
+        },
+        MainCommand::Report { db_path, out_dir, period, format, self_contained } =>
+        {
+            run_report(db_path, out_dir, &meta, args.meta.as_ref(), period, format, self_contained)
+        },
+        MainCommand::Run { config, skip_ingest, keep_going, wait } =>
+        {
+            run_pipeline(config, skip_ingest, keep_going, wait, args.meta.as_ref())
+        }
+    }
+}
+
+fn run_ingest(args: IngestArgs, meta: &ProjectMeta) -> Result<()>
+{
+    let IngestArgs { db_path, repo_tree_paths, name, repo_map, branches, message_stats,
+                     recurse_submodules, sample, dry_run, wait, db_profile } = args;
+    let sample_rate = sample.unwrap_or(1);
+
+    if name.is_some() && repo_tree_paths.len() != 1
+    {
+        bail!("--name can only be used when ingesting a single repository");
+    }
+
+    let repo_map =
+        match repo_map
+        {
+            Some(m) => { RepoMap::from_file(&m)? },
+            None => { RepoMap::new() }
+        };
+
+    let mut repos = Vec::new();
+
+    for path in repo_tree_paths.iter()
+    {
+        // An explicit name takes precedence over the map, which in turn
+        // takes precedence over the directory name. Aliases in the metadata
+        // apply on top of that.
+
+        let repo_name =
+            if let Some(n) = &name { n.clone() }
+            else if let Some(n) = repo_map.get_name(path) { n.clone() }
+            else { repo_name_from_path(path)? };
+
+        repos.push((path.clone(), meta.canonical_repo_name(&repo_name)));
+
+        // Submodules are ingested as repos of their own, named by their path
+        // in the superproject, e.g. gnome/subprojects/glib, unless mapped.
+
+        if recurse_submodules
+        {
+            for sub_path in GitCommitReader::list_submodules(path)?
+            {
+                let full_path = path.join(&sub_path);
+                let sub_name =
+                    if let Some(n) = repo_map.get_name(&full_path) { n.clone() }
+                    else { format!("{}/{}", repo_name, sub_path.to_string_lossy()) };
+
+                repos.push((full_path, meta.canonical_repo_name(&sub_name)));
+            }
+        }
+    }
+
+    if dry_run
+    {
+        return preview_ingest(db_path, &repos, &branches, sample_rate);
+    }
+
+    let _lock = lock_ingest(&db_path, wait)?;
+    let mut cdb = open_store(db_path, db_profile)?;
+    cdb.set_sample_rate(sample_rate)?;
+    let mut sl = StatusLogger::new();
+
+    for (path, repo_name) in repos.iter()
+    {
+        sl.begin_repo(repo_name);
+        cdb.begin_repo_ingest(repo_name)?;
+
+        // Check for promisor for origin remote; we interpret its presence
+        // as a preference for remote storage. If found, we turn off --stat
+        // collection, since that would cause git to fetch all the remote
+        // blobs (slowly).
+        //
+        // This will break change counts. Author and commit counts will still
+        // work.
+
+        let mut cmd;
+        cmd = Command::new("git");
+        cmd.arg("-C").arg(path).arg("config").arg("remote.origin.promisor");
+        let output = cmd.output().unwrap();
+        let has_promisor = std::str::from_utf8(&output.stdout).unwrap().trim() == "true";
+
+        if has_promisor
+        {
+            sl.log_warning("origin has a promisor; change details omitted.");
+            cdb.set_changes_unreliable(repo_name)?;
+        }
+
+        // Use the same ref set as the last time this repo was ingested,
+        // unless we're told otherwise.
+
+        let repo_branches =
+            match &branches
+            {
+                Some(b) =>
+                {
+                    cdb.set_repo_branches(repo_name, b)?;
+                    Some(b.clone())
+                },
+                None => cdb.get_repo_branches(repo_name)?
+            };
+
+        // A failed count only costs us the ETA.
+
+        let since = cdb.get_last_author_time(repo_name);
+
+        if let Ok(n) = GitCommitReader::count_commits(path, since, repo_branches.as_deref())
+        {
+            sl.set_expected_commits(n / sample_rate as u64);
+        }
+
+        let gcr = GitCommitReader::new(path.clone(),
+                                       repo_name,
+                                       since,
+                                       !has_promisor,
+                                       repo_branches.as_deref())?;
+
+        let bodied_commits =
+            if message_stats { Some(GitCommitReader::read_bodied_commits(path, since, repo_branches.as_deref())?) }
+            else { None };
+
+        for mut commit in gcr
+        {
+            if !is_in_sample(&commit.id, sample_rate) { continue; }
+
+            if let Some(bodied) = &bodied_commits
+            {
+                commit.has_body = Some(bodied.contains(&commit.id));
+            }
+
+            cdb.insert_raw_commit(&commit)?;
+            sl.log_commit(&commit);
+        }
+
+        for merge in GitCommitReader::read_merges(path, repo_name, since, repo_branches.as_deref())?
+        {
+            if !is_in_sample(&merge.id, sample_rate) { continue; }
+
+            cdb.insert_merge(&merge)?;
+        }
+
+        cdb.finish_repo_ingest(repo_name)?;
+        sl.end_repo();
+    }
+
+    sl.print_summary();
+    cdb.mark_ingested()
+}
+
+fn lock_ingest(db_path: &Path, wait: bool) -> Result<Option<IngestLock>>
+{
+    // PostgreSQL takes care of concurrent writers itself.
+
+    if is_postgres_url(db_path) { return Ok(None); }
+    IngestLock::acquire(db_path, wait).map(Some)
+}
+
+fn preview_ingest(db_path: PathBuf, repos: &[(PathBuf, String)], branches: &Option<Vec<String>>,
+                  sample_rate: u32) -> Result<()>
+{
+    // Count the commits an ingest would read, going by the same watermarks
+    // and remembered branches. The database is only read from, and one that
+    // doesn't exist yet is left uncreated; everything in it would be new.
+
+    let mut cdb = if is_postgres_url(&db_path) || db_path.exists() { Some(open_store_read_only(db_path)?) } else { None };
+    let epoch = Utc.timestamp_opt(0, 0).unwrap();
+    let mut total = 0;
+
+    for (path, repo_name) in repos
+    {
+        let (since, repo_branches) = match cdb.as_mut()
+        {
+            Some(cdb) => (cdb.get_last_author_time(repo_name),
+                          match branches
+                          {
+                              Some(b) => Some(b.clone()),
+                              // Older databases may not have the table yet.
+                              None => cdb.get_repo_branches(repo_name).unwrap_or(None)
+                          }),
+            None => (epoch, branches.clone())
+        };
+        let n = GitCommitReader::count_commits(path, since, repo_branches.as_deref())? / sample_rate as u64;
+
+        println!("{:<40} {:>8} commits to read{}", repo_name, n,
+                 if since == epoch { " (full ingest)".to_string() }
+                 else { format!(" since {}", since.format("%Y-%m-%d %H:%M")) });
+        total += n;
+    }
+
+    println!("{:<40} {:>8} commits to read", "Total", total);
+    Ok(())
+}
+
+fn run_ingest_mbox(db_path: PathBuf, mbox_paths: Vec<PathBuf>,
+                   name: Option<String>, wait: bool, db_profile: DbProfile,
+                   meta: &ProjectMeta) -> Result<()>
+{
+    // Unlike git ingests, archives are always read in full; commits are
+    // keyed on the Message-ID, so patches seen before are just replaced.
+
+    let _lock = lock_ingest(&db_path, wait)?;
+    let mut cdb = open_store(db_path, db_profile)?;
+    cdb.set_sample_rate(1)?;
+    let mut sl = StatusLogger::new();
+
+    for path in mbox_paths.iter()
+    {
+        let repo_name = match &name
+        {
+            Some(n) => n.clone(),
+            None => format!("mbox:{}", path.file_stem()
+                            .chain_err(|| format!("Could not derive name from {}", path.display()))?
+                            .to_string_lossy())
+        };
+        let repo_name = meta.canonical_repo_name(&repo_name);
+
+        sl.begin_repo(&repo_name);
+
+        let (commits, n_undated) = MboxReader::new(&repo_name).read_file(path)?;
+        if n_undated > 0
+        {
+            sl.log_warning(&format!("{} patches without a usable date skipped.", n_undated));
+        }
+
+        sl.set_expected_commits(commits.len() as u64);
+        cdb.begin_repo_ingest(&repo_name)?;
+
+        for commit in commits
+        {
+            cdb.insert_raw_commit(&commit)?;
+            sl.log_commit(&commit);
+        }
+
+        cdb.finish_repo_ingest(&repo_name)?;
+        sl.end_repo();
+    }
+
+    sl.print_summary();
+    cdb.mark_ingested()
+}
+
+fn parse_sample_rate(s: &str) -> std::result::Result<u32, String>
+{
+    // Sample rates are written as fractions, e.g. 1/10.
+
+    match s.trim().strip_prefix("1/").map(|n| n.trim().parse::<u32>())
+    {
+        Some(Ok(n)) if n > 0 => Ok(n),
+        _ => Err(format!("Invalid sample rate '{}'; expected e.g. 1/10", s))
+    }
+}
+
+fn repo_name_from_path(path: &Path) -> Result<String>
+{
+    Ok(path.canonicalize().chain_err(|| format!("Could not resolve {}", path.display()))?
+       .file_name().chain_err(|| format!("Could not derive name from {}", path.display()))?
+       .to_string_lossy()
+       .into_owned())
+}
+
+fn run_bench_ingest(repo_tree_paths: Vec<PathBuf>) -> Result<()>
+{
+    // Time each ingestion stage on its own: reading and parsing git output,
+    // inserting the commit rows, and inserting the per-path stats. The
+    // database is in memory so disk speed doesn't skew the results.
+
+    let mut cdb = CommitDb::open(PathBuf::from(":memory:"))?;
+    let mut commits = Vec::new();
+    let start = Instant::now();
+
+    for path in repo_tree_paths.iter()
+    {
+        let repo_name = repo_name_from_path(path)?;
+        let gcr = GitCommitReader::new(path.clone(), &repo_name,
+                                       cdb.get_last_author_time(&repo_name), true, None)?;
+        commits.extend(gcr);
+    }
+
+    let read_time = start.elapsed();
+    let start = Instant::now();
+
+    for commit in &commits
+    {
+        cdb.insert_commit_row(commit)?;
+    }
+
+    let insert_time = start.elapsed();
+    let start = Instant::now();
+
+    for commit in &commits
+    {
+        cdb.insert_path_stats(commit)?;
+    }
+
+    let path_stats_time = start.elapsed();
+
+    for (stage, time) in &[ ("Read", read_time), ("Insert", insert_time), ("Path stats", path_stats_time) ]
+    {
+        println!("{:<12} {:>8} commits in {:>8.3}s ({:>10.0} commits/s)",
+                 format!("{}:", stage), commits.len(), time.as_secs_f64(),
+                 commits.len() as f64 / time.as_secs_f64().max(1e-9));
+    }
+
+    Ok(())
+}
+
+fn run_rename_repo(db_path: PathBuf, old_name: &str, new_name: &str) -> Result<()>
+{
+    let mut cdb = CommitDb::open(db_path)?;
+    let n_commits = cdb.rename_repo(old_name, new_name)?;
+
+    if n_commits == 0
+    {
+        bail!("No commits found for repository '{}'", old_name);
+    }
+
+    eprintln!("{}: renamed to {} ({} commits)", old_name, new_name, n_commits);
+    Ok(())
+}
+
+fn run_resolve_identities(db_path: PathBuf, identities_path: PathBuf, interactive: bool,
+                          meta: &mut ProjectMeta) -> Result<()>
+{
+    // Earlier decisions are applied before looking for clusters, so only
+    // new ones are brought up. The file given here takes the place of any
+    // named in the metadata.
+
+    meta.identities = Some(Identities::from_file_or_new(&identities_path)?);
+    let mut cdb = CommitDb::open(db_path)?;
+    cdb.select_bucket_tz(BucketTz::Local)?;
+    cdb.postprocess(meta, "Unknown", false)?;
+
+    let mut identities = meta.identities.take().unwrap();
+    let clusters: Vec<IdentityCluster> = find_clusters(&cdb.get_author_identities()?).into_iter()
+        .filter(|cluster| !identities.is_decided(&cluster.emails()))
+        .collect();
+
+    if clusters.is_empty()
+    {
+        println!("No undecided identities.");
+        return Ok(());
+    }
+
+    if !interactive
+    {
+        for cluster in &clusters
+        {
+            println!("{}\n", cluster.describe());
+        }
+
+        println!("{} undecided cluster(s); use --interactive to resolve them.", clusters.len());
+        return Ok(());
+    }
+
+    let stdin = std::io::stdin();
+    let n_decided = resolve_interactively(&clusters, &mut identities, &identities_path,
+                                          stdin.lock(), std::io::stdout())?;
+
+    eprintln!("{}: recorded {} decision(s); name it as \"identities\" in the metadata file to apply them.",
+              identities_path.display(), n_decided);
+    Ok(())
+}
+
+fn run_dedupe_commits(db_path: PathBuf, prefer: Option<Vec<String>>) -> Result<()>
+{
+    if !db_path.exists()
+    {
+        bail!("Database {} does not exist", db_path.display());
+    }
+
+    let mut cdb = CommitDb::open(db_path)?;
+    let shared = cdb.get_shared_commits()?;
+
+    if shared.is_empty()
+    {
+        println!("No commits are shared between repos.");
+    }
+
+    for (repo_a, repo_b, n_commits) in &shared
+    {
+        println!("{:<32} {:<32} {:>8} shared commits", repo_a, repo_b, n_commits);
+    }
+
+    if let Some(prefer) = prefer
+    {
+        cdb.set_repo_precedence(&prefer)?;
+        eprintln!("Shared commits now attributed by precedence: {}", prefer.join(", "));
+    }
+
+    Ok(())
+}
+
+fn run_db_info(db_path: PathBuf) -> Result<()>
+{
+    let db_path = resolve_db_path(db_path)?;
+    if !db_path.exists()
+    {
+        bail!("Database {} does not exist", db_path.display());
+    }
+
+    let file_size = std::fs::metadata(&db_path).chain_err(|| "Could not stat database")?.len();
+    let mut cdb = CommitDb::open_read_only(db_path)?;
+    let info = cdb.get_info()?;
+
+    println!("Schema version:   {}", info.schema_version);
+    println!("File size:        {} bytes", file_size);
+    println!("Last ingest:      {}", info.last_ingest.as_deref().unwrap_or("never"));
+    println!("Last postprocess: {}", info.last_postprocess.as_deref().unwrap_or("never"));
+    if let Some(version) = &info.snapshot_version
+    {
+        println!("Snapshot of:      fornalder {}, meta {}",
+                 version, info.snapshot_meta_hash.as_deref().unwrap_or("none"));
+    }
+
+    println!("\nTables:");
+    for (name, n_rows) in &info.table_rows
+    {
+        println!("  {:<32} {:>12} rows", name, n_rows);
+    }
+
+    println!("\nRepositories:");
+    for (name, n_commits, first, last) in &info.repo_coverage
+    {
+        println!("  {:<32} {:>12} commits  {} to {}", name, n_commits, first, last);
+    }
+
+    println!("\nStorage:");
+    for (name, kind, size) in &info.object_sizes
+    {
+        println!("  {:<32} {:<8} {:>12} bytes", name, kind, size);
+    }
+
+    Ok(())
+}
+
+fn run_inspect_commit(db_path: PathBuf, id: &str, meta: &ProjectMeta) -> Result<()>
+{
+    let mut cdb = open_for_queries(db_path)?;
+    cdb.select_bucket_tz(BucketTz::Local)?;
+    cdb.postprocess(meta, "Unknown", false)?;
+    let details = cdb.inspect_commit(id)?;
+
+    println!("Commit:");
+    for (name, value) in &details.fields
+    {
+        println!("  {:<24} {}", name, value);
+    }
+
+    println!("\nRepositories:");
+    for name in &details.repos
+    {
+        println!("  {}", name);
+    }
+
+    println!("\nPrefixes:");
+    for (prefix, n_changes) in &details.prefixes
+    {
+        println!("  {:<32} {:>8} changes", prefix, n_changes);
+    }
+
+    println!("\nSuffixes:");
+    for (suffix, n_changes, n_insertions, n_deletions) in &details.suffixes
+    {
+        println!("  {:<32} {:>8} changes {:>8} insertions {:>8} deletions",
+                 suffix, n_changes, n_insertions, n_deletions);
+    }
+
+    println!("\nAuthor:");
+    for (name, value) in &details.author
+    {
+        println!("  {:<24} {}", name, value);
+    }
+
+    println!("\nCohorts:");
+    for (cohort, name) in &details.cohorts
+    {
+        println!("  {:<24} {}", cohort, name);
+    }
+
+    Ok(())
+}
+
+fn run_snapshot(db_path: PathBuf, out_path: PathBuf, anonymize: bool, limit_years: Option<i32>,
+                meta_path: Option<&PathBuf>) -> Result<()>
+{
+    if let Some(n) = limit_years
+    {
+        if n < 1
+        {
+            bail!("--limit-years must be at least 1");
+        }
+    }
+
+    let meta_hash =
+        match meta_path
+        {
+            Some(p) => Some(hash_file(p).chain_err(|| "Could not read meta file")?),
+            None => None
+        };
+
+    let mut cdb = open_for_queries(db_path)?;
+    cdb.write_snapshot(&out_path, anonymize, limit_years, meta_hash)?;
+
+    let size = std::fs::metadata(&out_path).chain_err(|| "Could not stat snapshot")?.len();
+    println!("Wrote {} ({} bytes)", out_path.display(), size);
+    Ok(())
+}
+
+fn run_check_meta(db_path: PathBuf, meta: &ProjectMeta, meta_path: Option<&PathBuf>) -> Result<()>
+{
+    let meta_path = meta_path.chain_err(|| "check-meta requires --meta")?;
+
+    if !db_path.exists()
+    {
+        bail!("Database {} does not exist", db_path.display());
+    }
+
+    let content = std::fs::read_to_string(meta_path).chain_err(|| "Could not read meta file")?;
+    let mut problems: Vec<String> = find_unknown_fields(&content)?.iter()
+        .map(|field| format!("unknown field \"{}\"", field))
+        .collect();
+    let mut cdb = open_for_queries(db_path)?;
+
+    problems.extend(meta.check(&mut cdb)?);
+
+    for problem in &problems
+    {
+        println!("{}: {}", meta_path.display(), problem);
+    }
+
+    if !problems.is_empty()
+    {
+        bail!("Found {} problem(s) in {}", problems.len(), meta_path.display());
+    }
+
+    Ok(())
+}
+
+fn is_fresh(out_path: &Path, data_time: Option<DateTime<Utc>>, meta_path: Option<&PathBuf>) -> bool
+{
+    // True if the output was written after the last change to the ingested
+    // data and the metadata file. We can't go by the database file's own
+    // timestamp, since plotting writes derived tables to it.
+
+    let out_time = match std::fs::metadata(out_path).and_then(|m| m.modified())
+    {
+        Ok(t) => DateTime::<Utc>::from(t),
+        Err(_) => return false
+    };
+
+    let meta_time = meta_path.map(|p| std::fs::metadata(p).and_then(|m| m.modified()));
+
+    match (data_time, meta_time)
+    {
+        (None, _) => false,
+        (_, Some(Err(_))) => false,
+        (Some(d), Some(Ok(m))) => d < out_time && DateTime::<Utc>::from(m) < out_time,
+        (Some(d), None) => d < out_time
+    }
+}
+
+fn parse_overlay(values: &[String]) -> Result<Option<(OverlayType, usize)>>
+{
+    // --overlay takes a kind and a count, e.g. "forecast 3".
+
+    match values
+    {
+        [] => Ok(None),
+        [kind, n] =>
+        {
+            let kind: OverlayType = kind.parse().map_err(|e| format!("Invalid overlay '{}'; {}", kind, e))?;
+            let n: usize = n.parse().chain_err(|| format!("Invalid overlay interval count '{}'", n))?;
+            if n == 0 { bail!("--overlay needs at least one interval to draw"); }
+
+            Ok(Some((kind, n)))
+        },
+        _ => bail!("--overlay can only be given once")
+    }
+}
+
+fn run_plot_lorenz(args: &PlotArgs, meta: &ProjectMeta, meta_path: Option<&PathBuf>,
+                   cdb: &mut CommitDb, delimiter: u8) -> Result<()>
+{
+    let from = args.from.or(meta.first_year);
+    let to = args.to.or(meta.last_year);
+    let lorenz = Lorenz::new(&cdb.get_author_commit_counts(from, to, &args.exclude_repo)?);
+    let manifest = Manifest::new(args, cdb.get_content_hash()?, meta_path)?;
+
+    // A .csv output path gets the curve itself rather than a plot.
+
+    if args.out_path.extension().is_some_and(|e| e == "csv")
+    {
+        lorenz.write_csv(&args.out_path, delimiter)?;
+    }
+    else
+    {
+        let plotter = Plotter
+        {
+            stamp: if args.stamp { Some(manifest.caption()) } else { None },
+            footnote: plot_footnote(cdb, args)?,
+            pad_to_now: false,
+            xtick_every: None,
+            xtick_rotate: None,
+            fiscal_year_start: None,
+            accessible: args.accessible,
+            direct_labels: None,
+            forecast: None
+        };
+        let range = match (from, to)
+        {
+            (None, None) => meta.label("all years"),
+            _ => format!("{}-{}",
+                         from.map_or("".to_string(), |y| y.to_string()),
+                         to.map_or("".to_string(), |y| y.to_string()))
+        };
+
+        plotter.plot_lorenz(meta, &lorenz, &args.out_path, &range)?;
+    }
+
+    manifest.write(&args.out_path)
+}
+
+fn plot_footnote(cdb: &mut CommitDb, args: &PlotArgs) -> Result<Option<String>>
+{
+    let notes: Vec<String> = vec![ sample_footnote(cdb, args)?, changes_footnote(cdb, args)?,
+                                   coverage_footnote(cdb, args)? ]
+        .into_iter().flatten().collect();
+
+    Ok(if notes.is_empty() { None } else { Some(notes.join("; ")) })
+}
+
+fn sample_footnote(cdb: &mut CommitDb, args: &PlotArgs) -> Result<Option<String>>
+{
+    // Databases ingested with --sample have commit-based counts scaled up
+    // by the sample rate. Author counts and per-author charts can't be
+    // scaled, and only reflect the sample.
+
+    let sample_rate = cdb.get_sample_rate()?;
+    if sample_rate == 1 { return Ok(None); }
+
+    let is_scaled = match args.chart
+    {
+        ChartType::Growth => true,
+        ChartType::Stacked | ChartType::Seasonal => args.unit.counts_commits(),
+        _ => false
+    };
+
+    Ok(Some(if is_scaled { format!("Sampled 1/{}: values are scaled estimates", sample_rate) }
+            else { format!("Sampled 1/{}: values cover the sample only", sample_rate) }))
+}
+
+fn changes_footnote(cdb: &mut CommitDb, args: &PlotArgs) -> Result<Option<String>>
+{
+    // Repos ingested without --stat have no change counts, so charts that
+    // show changes undercount them. Say so on the chart.
+
+    let shows_changes = matches!((args.chart, args.unit),
+                                 (ChartType::CohortDetail, _) | (ChartType::Growth, _)
+                                     | (ChartType::Stacked, UnitType::Changes));
+
+    if !shows_changes { return Ok(None); }
+
+    let repos = cdb.get_changes_unreliable_repos()?;
+
+    Ok(match repos.len()
+    {
+        0 => None,
+        1..=3 => Some(format!("Change counts missing for {}", repos.join(", "))),
+        n => Some(format!("Change counts missing for {} and {} other repos", repos[..2].join(", "), n - 2))
+    })
+}
+
+fn coverage_footnote(cdb: &mut CommitDb, args: &PlotArgs) -> Result<Option<String>>
+{
+    // With --repo-coverage, name the repos whose history starts later or
+    // ends earlier than the rest, since their absence shapes the chart too.
+
+    if !args.repo_coverage { return Ok(None); }
+
+    let coverage = cdb.get_repo_coverage()?;
+    let first = coverage.iter().map(|(_, _, f, _)| &f[..4]).min();
+    let last = coverage.iter().map(|(_, _, _, l)| &l[..4]).max();
+    let partial: Vec<String> = coverage.iter()
+        .filter(|(_, _, f, l)| Some(&f[..4]) != first || Some(&l[..4]) != last)
+        .map(|(name, _, f, l)| format!("{} {}-{}", name, &f[..4], &l[..4]))
+        .collect();
+
+    Ok(match partial.len()
+    {
+        0 => None,
+        1..=3 => Some(format!("Partial coverage: {}", partial.join(", "))),
+        n => Some(format!("Partial coverage: {} and {} other repos", partial[..2].join(", "), n - 2))
+    })
+}
+
+fn run_plot_contributors_strip(args: &PlotArgs, meta: &ProjectMeta, meta_path: Option<&PathBuf>,
+                               cdb: &mut CommitDb) -> Result<()>
+{
+    let from = args.from.or(meta.first_year);
+    let to = args.to.or(meta.last_year);
+    let mut hist = cdb.get_top_author_hist(args.unit, args.interval, args.top_authors,
+                                           from, to, &args.exclude_repo)?;
+    if hist.get_bounds().is_none()
+    {
+        bail!("No data to plot");
+    }
+
+    let manifest = Manifest::new(args, cdb.get_content_hash()?, meta_path)?;
+    let unit = match args.unit
+    {
+        UnitType::Changes => UnitType::Changes,
+        _ => UnitType::Commits
+    };
+    let plotter = Plotter
+    {
+        stamp: if args.stamp { Some(manifest.caption()) } else { None },
+        footnote: plot_footnote(cdb, args)?,
+        pad_to_now: false,
+        xtick_every: args.xtick_every,
+        xtick_rotate: args.xtick_rotate,
+        fiscal_year_start: args.fiscal_year_start,
+        accessible: args.accessible,
+        direct_labels: args.direct_labels,
+        forecast: None
+    };
+
+    hist.relabel_cohorts(|name| meta.label(name));
+    plotter.plot_contributors_strip(meta, &meta.label(&unit.to_string()), &hist, &args.out_path, from, to)?;
+    manifest.write(&args.out_path)
+}
+
+fn run_plot_seasonal(args: &PlotArgs, meta: &ProjectMeta, meta_path: Option<&PathBuf>,
+                     cdb: &mut CommitDb) -> Result<()>
+{
+    // Seasonality only shows up in monthly data, whatever --interval says.
+
+    let mut hist = cdb.get_hist(args.cohort, args.unit, IntervalType::Month, args.min_activity,
+                                &args.exclude_repo)?;
+    if hist.get_bounds().is_none()
+    {
+        bail!("No data to plot");
+    }
+
+    let manifest = Manifest::new(args, cdb.get_content_hash()?, meta_path)?;
+    let plotter = Plotter
+    {
+        stamp: if args.stamp { Some(manifest.caption()) } else { None },
+        footnote: plot_footnote(cdb, args)?,
+        pad_to_now: false,
+        xtick_every: args.xtick_every,
+        xtick_rotate: args.xtick_rotate,
+        fiscal_year_start: args.fiscal_year_start,
+        accessible: args.accessible,
+        direct_labels: args.direct_labels,
+        forecast: None
+    };
+    let unit_label = meta.label(&args.unit.to_string());
+
+    hist.relabel_cohorts(|name| meta.label(name));
+
+    match args.seasonal_view
+    {
+        SeasonalView::Profile =>
+        {
+            let profile = SeasonalProfile::new(&hist, args.from.or(meta.first_year), args.to.or(meta.last_year))?;
+            plotter.plot_seasonal_profile(meta, &unit_label, &profile, &args.out_path)?;
+        },
+        SeasonalView::Adjusted =>
+        {
+            let adjusted = seasonally_adjust(&hist)?;
+            plotter.plot_monthly_cohorts(meta, &format!("{} ({})", unit_label, meta.label("seasonally adjusted")),
+                                         &adjusted, &args.out_path, args.from, args.to)?;
+        }
+    }
+
+    manifest.write(&args.out_path)
+}
+
+// Suffixes shown in growth charts. More lines than this get hard to tell apart.
+const GROWTH_N_SUFFIXES: i32 = 8;
+
+fn run_plot_growth(args: &PlotArgs, meta: &ProjectMeta, meta_path: Option<&PathBuf>,
+                   cdb: &mut CommitDb) -> Result<()>
+{
+    // Always broken down by suffix; that's where insertions and deletions
+    // are kept apart.
+
+    let (mut net_hist, mut churn_hist) = cdb.get_suffix_growth_hists(args.interval, GROWTH_N_SUFFIXES,
+                                                                     &args.exclude_repo)?;
+
+    let manifest = Manifest::new(args, cdb.get_content_hash()?, meta_path)?;
+    let plotter = Plotter
+    {
+        stamp: if args.stamp { Some(manifest.caption()) } else { None },
+        footnote: plot_footnote(cdb, args)?,
+        pad_to_now: args.pad_to_now,
+        xtick_every: args.xtick_every,
+        xtick_rotate: args.xtick_rotate,
+        fiscal_year_start: args.fiscal_year_start,
+        accessible: args.accessible,
+        direct_labels: args.direct_labels,
+        forecast: None
+    };
+
+    // Net growth on top, churn below. A suffix with high churn and negative
+    // growth is being cut down.
+
+    net_hist.relabel_cohorts(|name| meta.label(name));
+    churn_hist.relabel_cohorts(|name| meta.label(name));
+    plotter.plot_line_pair(meta, (&meta.label("Net growth (lines)"), &net_hist),
+                           (&meta.label("Churn (lines)"), &churn_hist),
+                           &args.out_path, args.from, args.to)?;
+
+    manifest.write(&args.out_path)
+}
+
+fn run_plot_diversity(args: &PlotArgs, meta: &ProjectMeta, meta_path: Option<&PathBuf>,
+                      cdb: &mut CommitDb) -> Result<()>
+{
+    let mut hist = cdb.get_diversity_hist(args.cohort, args.interval, &args.exclude_repo)?;
+    if hist.get_bounds().is_none()
+    {
+        bail!("No data to plot");
+    }
+
+    let manifest = Manifest::new(args, cdb.get_content_hash()?, meta_path)?;
+    let plotter = Plotter
+    {
+        stamp: if args.stamp { Some(manifest.caption()) } else { None },
+        footnote: plot_footnote(cdb, args)?,
+        pad_to_now: args.pad_to_now,
+        xtick_every: args.xtick_every,
+        xtick_rotate: args.xtick_rotate,
+        fiscal_year_start: args.fiscal_year_start,
+        accessible: args.accessible,
+        direct_labels: args.direct_labels,
+        forecast: None
+    };
+
+    let unit_label = meta.label(&format!("Active {}", hist.get_cohort_name(0).to_lowercase()));
+    hist.relabel_cohorts(|name| meta.label(name));
+    plotter.plot_lines(meta, &unit_label, &hist, &args.out_path, args.from, args.to)?;
+
+    manifest.write(&args.out_path)
+}
+
+fn run_plot_identity_skew(args: &PlotArgs, meta: &ProjectMeta, meta_path: Option<&PathBuf>,
+                          cdb: &mut CommitDb) -> Result<()>
+{
+    let mut hist = cdb.get_identity_skew_hist(args.interval, &args.exclude_repo)?;
+    if hist.get_bounds().is_none()
+    {
+        bail!("No data to plot");
+    }
+
+    let manifest = Manifest::new(args, cdb.get_content_hash()?, meta_path)?;
+    let plotter = Plotter
+    {
+        stamp: if args.stamp { Some(manifest.caption()) } else { None },
+        footnote: plot_footnote(cdb, args)?,
+        pad_to_now: args.pad_to_now,
+        xtick_every: args.xtick_every,
+        xtick_rotate: args.xtick_rotate,
+        fiscal_year_start: args.fiscal_year_start,
+        accessible: args.accessible,
+        direct_labels: args.direct_labels,
+        forecast: None
+    };
+
+    hist.relabel_cohorts(|name| meta.label(name));
+    plotter.plot_lines(meta, &meta.label("Distinct authors"), &hist, &args.out_path, args.from, args.to)?;
+
+    manifest.write(&args.out_path)
+}
+
+fn run_plot(args: &PlotArgs, meta: &ProjectMeta, meta_path: Option<&PathBuf>, delimiter: u8) -> Result<()>
+{
+    if args.skip_if_fresh
+    {
+        let mut cdb = open_for_queries(args.db_path.clone())?;
+
+        let last_change = cdb.get_last_change()?;
+
+        if is_fresh(&args.out_path, last_change, meta_path)
+            && (args.events.is_none() || is_fresh(&args.out_path, last_change, args.events.as_ref()))
+            && (meta.identities_path().is_none() || is_fresh(&args.out_path, last_change, meta.identities_path()))
+        {
+            eprintln!("{}: up to date", args.out_path.display());
+            return Ok(());
+        }
+    }
+
+    if let Some(month) = args.fiscal_year_start
+    {
+        if !(1..=12).contains(&month)
+        {
+            bail!("--fiscal-year-start must be a month from 1 to 12");
+        }
+
+        if !matches!(args.interval, IntervalType::Year)
+            || !matches!(args.chart, ChartType::Stacked | ChartType::Cumulative | ChartType::CohortDetail)
+        {
+            bail!("--fiscal-year-start is only supported for yearly stacked, cumulative and cohort detail charts");
+        }
+    }
+
+    let mut cdb = open_for_queries(args.db_path.clone())?;
+    cdb.select_buckets(args.bucket_tz, args.fiscal_year_start)?;
+    cdb.postprocess(meta, &args.unknown_domain,
+                    args.drop_unknown_domains)?; // FIXME: Skip if metadata is unchanged
+    cdb.set_hidden_cohorts(args.hide);
+    cdb.set_tenure_curve(args.tenure_curve)?;
+    cdb.set_suffix_filter(&args.include_suffix, &args.exclude_suffix);
+    cdb.set_min_cohort_commits(args.min_cohort_commits);
+    cdb.set_top_per_year(args.top_per_interval);
+    cdb.set_bot_split(args.split_bots);
+    cdb.set_dedupe_cherry_picks(args.dedupe_cherry_picks);
+
+    if let Some(weights) = &meta.suffix_weights
+    {
+        cdb.set_suffix_weights(weights)?;
+    }
+
+    if (!args.include_suffix.is_empty() || !args.exclude_suffix.is_empty())
+        && !matches!(args.cohort, CohortType::Suffix)
+    {
+        bail!("--include-suffix and --exclude-suffix only apply to the suffix cohort");
+    }
+
+    if args.split_bots.is_some() && !matches!(args.chart, ChartType::Stacked | ChartType::Seasonal)
+    {
+        bail!("--split-bots is only supported for stacked and seasonal charts");
+    }
+
+    if args.dedupe_cherry_picks && !matches!(args.chart, ChartType::Stacked | ChartType::Seasonal)
+    {
+        bail!("--dedupe-cherry-picks is only supported for stacked and seasonal charts");
+    }
+
+    if args.top_per_interval.is_some() && matches!(args.chart, ChartType::Cumulative)
+    {
+        bail!("--top-per-interval is not supported for cumulative charts");
+    }
+
+    if matches!(args.unit, UnitType::CommitShare)
+        && (!matches!(args.chart, ChartType::Stacked) || !matches!(args.cohort, CohortType::FirstYear))
+    {
+        bail!("--unit commitshare is only supported for stacked charts of the firstyear cohort");
+    }
+
+    if matches!(args.cohort, CohortType::FirstYearDomain)
+        && (!matches!(args.chart, ChartType::Stacked) || args.facet_by.is_some())
+    {
+        bail!("--cohort firstyear:domain is only supported for stacked charts without facets");
+    }
+
+    if args.facet_by.is_some() && !matches!(args.chart, ChartType::Stacked)
+    {
+        bail!("--facet-by is only supported for stacked charts");
+    }
+
+    if args.emit_stream.is_some()
+        && (!matches!(args.chart, ChartType::Stacked | ChartType::Cumulative | ChartType::CohortDetail)
+            || args.facet_by.is_some())
+    {
+        bail!("--emit-stream is only supported for stacked, cumulative and cohort detail charts without facets");
+    }
+
+    if let IntervalType::Day = args.interval
+    {
+        if !matches!(args.chart, ChartType::Stacked | ChartType::Cumulative) || args.facet_by.is_some()
+        {
+            bail!("Daily intervals are only supported for stacked and cumulative charts without facets");
+        }
+    }
+
+    let overlay = parse_overlay(&args.overlay)?;
+    if overlay.is_some()
+    {
+        if !matches!(args.chart, ChartType::Stacked | ChartType::Cumulative) || args.facet_by.is_some()
+            || matches!(args.interval, IntervalType::Day)
+        {
+            bail!("--overlay is only supported for yearly and monthly stacked and cumulative charts without facets");
+        }
+
+        if args.pad_to_now
+        {
+            bail!("--overlay can't be combined with --pad-to-now");
+        }
+    }
+
+    if let ChartType::Lorenz = args.chart
+    {
+        return run_plot_lorenz(args, meta, meta_path, &mut cdb, delimiter);
+    }
+
+    if let ChartType::ContributorsStrip = args.chart
+    {
+        return run_plot_contributors_strip(args, meta, meta_path, &mut cdb);
+    }
+
+    if let ChartType::Seasonal = args.chart
+    {
+        return run_plot_seasonal(args, meta, meta_path, &mut cdb);
+    }
+
+    if let ChartType::Growth = args.chart
+    {
+        return run_plot_growth(args, meta, meta_path, &mut cdb);
+    }
+
+    if let ChartType::Diversity = args.chart
+    {
+        return run_plot_diversity(args, meta, meta_path, &mut cdb);
+    }
+
+    if let ChartType::IdentitySkew = args.chart
+    {
+        return run_plot_identity_skew(args, meta, meta_path, &mut cdb);
+    }
+
+    let (mut hist, unit_label) = match args.chart
+    {
+        ChartType::Stacked =>
+        {
+            (cdb.get_hist(args.cohort, args.unit, args.interval, args.min_activity,
+                          &args.exclude_repo).chain_err(|| "")?,
+             meta.label(&args.unit.to_string()))
+        },
+        ChartType::Cumulative =>
+        {
+            (cdb.get_cumulative_hist(args.cohort, args.interval, &args.exclude_repo)?,
+             meta.label("Total authors"))
+        },
+        ChartType::Lorenz | ChartType::ContributorsStrip | ChartType::Seasonal | ChartType::Growth
+            | ChartType::Diversity | ChartType::IdentitySkew => unreachable!(),
+        ChartType::CohortDetail =>
+        {
+            let cohort_name = args.cohort_name.as_ref()
+                .chain_err(|| "--cohort-name is required for cohort detail charts")?;
+            (cdb.get_cohort_detail_hist(args.cohort, cohort_name, args.interval, &args.exclude_repo)?,
+             cohort_name.clone())
+        }
+    };
+    if let Some(hide) = args.hide
+    {
+        // Not every histogram is generated with the catch-alls left out,
+        // so drop any that remain.
+
+        for (name, hidden) in &[ ("Other", hide.hides_other()), ("Brief", hide.hides_brief()) ]
+        {
+            if !hidden { continue; }
+
+            if let Some(cohort) = hist.find_cohort(name)
+            {
+                hist.remove_cohort(cohort);
+            }
+        }
+    }
+
+    if hist.get_bounds().is_none()
+    {
+        bail!("No data to plot");
+    }
+
+    let mut facets = match args.facet_by
+    {
+        Some(FacetType::Repo) => get_repo_facets(args, &mut cdb, &hist)?,
+        None => Vec::new()
+    };
+
+    if let ChartType::Stacked = args.chart
+    {
+        // Intervals the metadata marks as broken would otherwise dwarf the
+        // rest of the chart.
+
+        for (begin, end, _) in meta.excluded_ranges()
+        {
+            hist.clear_between(begin, end);
+            for (_, facet_hist) in facets.iter_mut()
+            {
+                facet_hist.clear_between(begin, end);
+            }
+        }
+    }
+
+    let manifest = Manifest::new(args, cdb.get_content_hash()?, meta_path)?;
+    hist.relabel_cohorts(|name| meta.label(name));
+    for (name, facet_hist) in facets.iter_mut()
+    {
+        *name = meta.label(name);
+        facet_hist.relabel_cohorts(|name| meta.label(name));
+    }
+    let plotter = Plotter
+    {
+        stamp: if args.stamp { Some(manifest.caption()) } else { None },
+        footnote: plot_footnote(&mut cdb, args)?,
+        pad_to_now: args.pad_to_now,
+        xtick_every: args.xtick_every,
+        xtick_rotate: args.xtick_rotate,
+        fiscal_year_start: args.fiscal_year_start,
+        accessible: args.accessible,
+        direct_labels: args.direct_labels,
+        forecast: overlay.map(|(OverlayType::Forecast, n)| (args.forecast_model, n))
+    };
+
+    if plotter.forecast.is_some()
+    {
+        // Project from the last interval shown, leaving out any incomplete
+        // year after it.
+
+        let (_, last_year) = plotter.year_range(meta, &hist, args.interval, args.from, args.to);
+        hist.truncate_after(match args.interval
+        {
+            IntervalType::Year => TimeBin::Year(last_year),
+            _ => TimeBin::Month(last_year, 11)
+        });
+    }
+
+    if args.pad_to_now
+    {
+        let now = Utc::now();
+        let ym = match args.interval
+        {
+            IntervalType::Day => TimeBin::Day(now.date_naive()),
+            IntervalType::Month => TimeBin::Month(now.year(), now.month0()),
+            IntervalType::Year =>
+            {
+                let fiscal = args.fiscal_year_start.is_some_and(|m| m > 1 && now.month() >= m);
+                TimeBin::Year(now.year() + fiscal as i32)
+            }
+        };
+
+        hist.pad_to(ym);
+        for (_, facet_hist) in facets.iter_mut()
+        {
+            facet_hist.pad_to(ym);
+        }
+    }
+
+    if facets.is_empty() && !matches!(args.chart, ChartType::CohortDetail)
+    {
+        // Cohorts with nothing in the range shown would still get a color
+        // and a place in the key. Facets share a key, so leave them be.
+
+        let (first_year, last_year) = plotter.year_range(meta, &hist, args.interval, args.from, args.to);
+        hist.drop_empty_cohorts(first_year, last_year);
+    }
+
+    if let Some(path) = &args.emit_stream
+    {
+        emit_hist(path, &unit_label, &hist)?;
+    }
+
+    if args.legend_totals && facets.is_empty() && !matches!(args.chart, ChartType::CohortDetail)
+    {
+        // Author counts and shares can't be added up across intervals, and
+        // cumulative charts are running totals already.
+
+        let total = match (args.chart, args.unit)
+        {
+            (ChartType::Cumulative, _) => LegendTotal::Last,
+            (_, UnitType::Authors) | (_, UnitType::ActiveAuthors) | (_, UnitType::CommitShare) => LegendTotal::Peak,
+            _ => LegendTotal::Sum
+        };
+        let (first_year, last_year) = plotter.year_range(meta, &hist, args.interval, args.from, args.to);
+        hist.append_totals_to_names(first_year, last_year, total);
+    }
+
+    if !facets.is_empty()
+    {
+        plotter.plot_faceted_cohorts(meta, &unit_label, &facets, &args.out_path, args.from, args.to)?;
+        return manifest.write(&args.out_path);
+    }
+
+    if let ChartType::CohortDetail = args.chart
+    {
+        plotter.plot_cohort_detail(meta, &unit_label, &hist, &args.out_path, args.from, args.to)?;
+        return manifest.write(&args.out_path);
+    }
+
+    match args.interval
+    {
+        IntervalType::Day =>
+        {
+            plotter.plot_daily_cohorts(meta, &unit_label, &hist, &args.out_path, args.from, args.to)?
+        },
+        IntervalType::Month =>
+        {
+            plotter.plot_monthly_cohorts(meta, &unit_label, &hist, &args.out_path, args.from, args.to)?
+        },
+        IntervalType::Year =>
+        {
+            plotter.plot_yearly_cohorts(meta, &unit_label, &hist, &args.out_path, args.from, args.to)?
+        }
+    }
+
+    manifest.write(&args.out_path)
+}
+
+fn get_repo_facets(args: &PlotArgs, cdb: &mut CommitDb, reference: &CohortHist) -> Result<Vec<(String, CohortHist)>>
+{
+    // Each repo's histogram picks its own top cohorts, so align them with
+    // those of the whole project to get one legend.
+
+    let mut facets = Vec::new();
+
+    for repo_name in cdb.get_top_repos(args.max_facets, &args.exclude_repo)?
+    {
+        let hist = cdb.get_repo_hist(&repo_name, args.cohort, args.unit, args.interval,
+                                     args.min_activity, &args.exclude_repo)?;
+        if hist.get_bounds().is_none() { continue; }
+
+        facets.push((repo_name, hist.align_to(reference)));
+    }
+
+    if facets.is_empty()
+    {
+        bail!("No data to plot");
+    }
+
+    Ok(facets)
+}
+
+fn run_export_sankey(db_path: PathBuf, out_path: PathBuf, meta: &ProjectMeta,
+                     cohort: CohortType, mut periods: Vec<i32>, format: ExportFormat,
+                     delimiter: u8) -> Result<()>
+{
+    let column = match cohort
+    {
+        CohortType::Domain => "author_domain",
+        CohortType::Repo => "repo_name",
+        _ => bail!("Sankey export only supports domain and repo cohorts")
+    };
+
+    periods.sort_unstable();
+    periods.dedup();
+
+    if periods.len() < 2
+    {
+        bail!("At least two period boundaries are needed");
+    }
+
+    let mut cdb = open_for_queries(db_path)?;
+    cdb.select_bucket_tz(BucketTz::Local)?;
+    cdb.postprocess(meta, "Unknown", false)?;
+    let dominant = cdb.get_dominant_items(column, &periods)?;
+
+    Sankey::new(&periods, &dominant).write(&out_path, format, delimiter)
+}
+
+fn run_funnel(db_path: PathBuf, out_path: Option<PathBuf>, meta: &ProjectMeta,
+              format: ExportFormat, delimiter: u8) -> Result<()>
+{
+    let mut cdb = open_for_queries(db_path)?;
+    cdb.select_bucket_tz(BucketTz::Local)?;
+    cdb.postprocess(meta, "Unknown", false)?;
+    let funnel = RetentionFunnel::new(&cdb.get_author_tenures()?);
+
+    match out_path
+    {
+        Some(p) => funnel.write(&p, format, delimiter),
+        None =>
+        {
+            println!("{}", funnel.to_csv(delimiter));
+            Ok(())
+        }
+    }
+}
+
+fn run_stickiness(db_path: PathBuf, out_path: Option<PathBuf>, meta: &ProjectMeta,
+                  format: ExportFormat, delimiter: u8) -> Result<()>
+{
+    let mut cdb = open_for_queries(db_path)?;
+    cdb.select_bucket_tz(BucketTz::Local)?;
+    cdb.postprocess(meta, "Unknown", false)?;
+    let stickiness = Stickiness::new(&cdb.get_second_commit_gaps()?);
+
+    match out_path
+    {
+        Some(p) => stickiness.write(&p, format, delimiter),
+        None =>
+        {
+            println!("{}", stickiness.to_csv(delimiter));
+            Ok(())
+        }
+    }
+}
+
+fn run_import_author_events(db_path: PathBuf, events_path: PathBuf, kind: &str) -> Result<()>
+{
+    let events = read_author_events(&events_path, kind)?;
+    let mut cdb = CommitDb::open(db_path)?;
+    let n_events = cdb.import_author_events(&events)?;
+
+    eprintln!("{}: imported {} events", events_path.display(), n_events);
+    Ok(())
+}
+
+fn run_onboarding(db_path: PathBuf, out_path: Option<PathBuf>, meta: &ProjectMeta, kind: &str,
+                  summary: bool, format: ExportFormat, delimiter: u8) -> Result<()>
+{
+    let mut cdb = open_for_queries(db_path)?;
+    cdb.postprocess(meta, "Unknown", false)?;
+    let onboarding = Onboarding::new(&cdb.get_onboarding(kind)?);
+
+    match out_path
+    {
+        Some(p) => onboarding.write(&p, summary, format, delimiter),
+        None =>
+        {
+            println!("{}", onboarding.to_csv(summary, delimiter));
+            Ok(())
+        }
+    }
+}
+
+fn run_fleet(fleet_path: PathBuf, out_path: PathBuf, meta: &ProjectMeta,
+             interval: IntervalType, from: Option<i32>, to: Option<i32>) -> Result<()>
+{
+    let config = FleetConfig::from_file(&fleet_path)?;
+    let fleet = Fleet::open(&config)?;
+    let summary = fleet.get_summary()?;
+
+    println!("{}: {} projects", config.name.as_deref().unwrap_or("Fleet"), config.projects.len());
+    println!("Contributors:                  {}", summary.n_contributors);
+    println!("Contributors to 2+ projects:   {}", summary.n_shared_contributors);
+    println!("\nShared domains:");
+    for (domain, n_projects, n_authors) in &summary.shared_domains
+    {
+        println!("  {:<32} {:>4} projects {:>8} authors", domain, n_projects, n_authors);
+    }
+
+    let mut hist = fleet.get_hist(interval)?;
+    match hist.get_bounds()
+    {
+        None => bail!("No commits found in any of the fleet's projects"),
+        Some((first, last, _, _)) if from.is_some_and(|y| y > last.year())
+                                     || to.is_some_and(|y| y < first.year()) =>
+        {
+            bail!("No commits found in the selected years")
+        },
+        _ => ()
+    }
+    hist.relabel_cohorts(|name| meta.label(name));
+    let plotter = Plotter { stamp: None, footnote: None, pad_to_now: false,
+                            xtick_every: None, xtick_rotate: None,
+                            fiscal_year_start: None,
+                            accessible: None, direct_labels: None, forecast: None };
+    let unit_label = meta.label("Authors");
+
+    match interval
+    {
+        IntervalType::Day => unreachable!(),
+        IntervalType::Month =>
+        {
+            plotter.plot_monthly_cohorts(meta, &unit_label, &hist, &out_path, from, to)
+        },
+        IntervalType::Year =>
+        {
+            plotter.plot_yearly_cohorts(meta, &unit_label, &hist, &out_path, from, to)
+        }
+    }
+}
+
+fn run_export_commits(args: ExportCommitsArgs, meta: &ProjectMeta, delimiter: u8) -> Result<()>
+{
+    let ExportCommitsArgs { db_path, out_path, format, aggregates, interval, iso_week, bucket_tz,
+                            exclude_repo } = args;
+
+    let mut cdb = open_for_queries(db_path)?;
+    cdb.select_bucket_tz(bucket_tz)?;
+    cdb.postprocess(meta, "Unknown", false)?;
+
+    let export = CommitExport::new(cdb.get_commit_records(iso_week, &exclude_repo)?, iso_week);
+    export.write(&out_path, format, delimiter)?;
+    eprintln!("{}: {} commits", out_path.display(), export.n_commits());
+
+    if let Some(cohort) = aggregates
+    {
+        let hist = |cdb: &mut CommitDb, unit| cdb.get_hist(cohort, unit, interval, 5, &exclude_repo);
+        let export = AggregateExport::new(&hist(&mut cdb, UnitType::Commits)?,
+                                          &hist(&mut cdb, UnitType::Authors)?,
+                                          &hist(&mut cdb, UnitType::Changes)?);
+        let stem = out_path.file_stem().map_or("commits".into(), |s| s.to_string_lossy());
+        let agg_path = match out_path.extension()
+        {
+            Some(ext) => out_path.with_file_name(format!("{}-aggregates.{}", stem, ext.to_string_lossy())),
+            None => out_path.with_file_name(format!("{}-aggregates", stem))
+        };
+
+        export.write(&agg_path, format, delimiter)?;
+        eprintln!("{}: {} rows", agg_path.display(), export.n_rows());
+    }
+
+    Ok(())
+}
+
+fn run_export_authors(db_path: PathBuf, out_path: PathBuf, meta: &ProjectMeta) -> Result<()>
+{
+    let mut cdb = open_for_queries(db_path)?;
+    cdb.select_bucket_tz(BucketTz::Local)?;
+    cdb.postprocess(meta, "Unknown", false)?;
+
+    let authors = cdb.get_authors()?;
+    let json = serde_json::to_string_pretty(&authors).chain_err(|| "Could not serialize authors")?;
+
+    std::fs::write(&out_path, json + "\n").chain_err(|| "Could not write authors")?;
+    eprintln!("{}: {} authors", out_path.display(), authors.len());
+    Ok(())
+}
+
+fn run_breakdown(args: BreakdownArgs, meta: &ProjectMeta, delimiter: u8) -> Result<()>
+{
+    let BreakdownArgs { db_path, out_path, cohort, by, unit, interval, from, to } = args;
+
+    let mut cdb = open_for_queries(db_path)?;
+    cdb.select_bucket_tz(BucketTz::Local)?;
+    cdb.postprocess(meta, "Unknown", false)?;
+
+    let mut hist = cdb.get_breakdown_hist(cohort.cohort, &cohort.name, by, unit, interval, &[])?;
+    hist.relabel_cohorts(|name| meta.label(name));
+
+    let out_path = match out_path
+    {
+        Some(p) => p,
+        None =>
+        {
+            println!("{}", hist.to_csv(delimiter));
+            return Ok(());
+        }
+    };
+
+    let unit_label = format!("{} ({})", meta.label(&unit.to_string()), cohort.name);
+    let plotter = Plotter { stamp: None, footnote: None, pad_to_now: false,
+                            xtick_every: None, xtick_rotate: None,
+                            fiscal_year_start: None,
+                            accessible: None, direct_labels: None, forecast: None };
+
+    match interval
+    {
+        IntervalType::Day =>
+        {
+            plotter.plot_daily_cohorts(meta, &unit_label, &hist, &out_path, from, to)
+        },
+        IntervalType::Month =>
+        {
+            plotter.plot_monthly_cohorts(meta, &unit_label, &hist, &out_path, from, to)
+        },
+        IntervalType::Year =>
+        {
+            plotter.plot_yearly_cohorts(meta, &unit_label, &hist, &out_path, from, to)
+        }
+    }
+}
+
+fn run_report(db_path: PathBuf, out_dir: PathBuf, meta: &ProjectMeta, meta_path: Option<&PathBuf>,
+              period: Period, format: ReportFormat, self_contained: bool) -> Result<()>
+{
+    if self_contained && !matches!(format, ReportFormat::Html)
+    {
+        bail!("--self-contained is only supported for HTML reports");
+    }
+
+    let mut parameters = vec![ ("Database".to_string(), db_path.display().to_string()),
+                               ("Period".to_string(), period.name()) ];
+    if let Some(p) = meta_path
+    {
+        parameters.push(("Metadata".to_string(), p.display().to_string()));
+    }
+    parameters.push(("Fornalder version".to_string(), env!("CARGO_PKG_VERSION").to_string()));
+
+    let mut cdb = open_for_queries(db_path)?;
+    cdb.select_bucket_tz(BucketTz::Local)?;
+    cdb.postprocess(meta, "Unknown", false)?;
+
+    std::fs::create_dir_all(&out_dir).chain_err(|| "Could not create output directory")?;
+
+    // Embedded plots are drawn in a scratch directory that goes away
+    // afterwards.
+
+    let scratch_dir =
+        if self_contained { Some(tempfile::tempdir().chain_err(|| "Could not create temporary directory")?) }
+        else { None };
+    let plot_dir = scratch_dir.as_ref().map_or(out_dir.as_path(), |d| d.path());
+
+    // Quarterly reports get monthly plots so the quarter is visible.
+
+    let interval = if period.is_quarter() { IntervalType::Month } else { IntervalType::Year };
+    let plotter = Plotter { stamp: None, footnote: None, pad_to_now: false,
+                            xtick_every: None, xtick_rotate: None,
+                            fiscal_year_start: None,
+                            accessible: None, direct_labels: None, forecast: None };
+    let mut plots = Vec::new();
+
+    for (cohort, unit, caption, file_name) in
+        &[ (CohortType::FirstYear, UnitType::Authors, "Authors by first year", "authors-by-firstyear.png"),
+           (CohortType::Domain, UnitType::Commits, "Commits by domain", "commits-by-domain.png") ]
+    {
+        let mut hist = cdb.get_hist(*cohort, *unit, interval, 5, &[])?;
+        if hist.get_bounds().is_none()
+        {
+            bail!("No data to report on");
+        }
+
+        hist.relabel_cohorts(|name| meta.label(name));
+        let unit_label = meta.label(&unit.to_string());
+        let out_path = plot_dir.join(file_name);
+
+        match interval
+        {
+            IntervalType::Day => unreachable!(),
+            IntervalType::Month =>
+            {
+                plotter.plot_monthly_cohorts(meta, &unit_label, &hist, &out_path, None, Some(period.last.year()))?
+            },
+            IntervalType::Year =>
+            {
+                plotter.plot_yearly_cohorts(meta, &unit_label, &hist, &out_path, None, Some(period.last.year()))?
+            }
+        }
+
+        plots.push((meta.label(caption), file_name.to_string()));
+    }
+
+    let mut report = Report::new(meta.name.as_deref().unwrap_or("Activity report"), period,
+                                 cdb.get_period_stats(&period)?,
+                                 &cdb.get_period_domain_commits(&period)?,
+                                 &cdb.get_period_domain_commits(&period.previous())?,
+                                 plots, parameters);
+    if self_contained
+    {
+        report.embed_plots(plot_dir)?;
+    }
+
+    let out_path = out_dir.join(match format
+    {
+        ReportFormat::Markdown => "report.md",
+        ReportFormat::Html => "report.html"
+    });
+
+    report.write(&out_path, format)?;
+    eprintln!("{}", out_path.display());
+    Ok(())
+}
+
+fn run_diff(args: DiffArgs, meta: &ProjectMeta, delimiter: u8) -> Result<()>
+{
+    let DiffArgs { db_path, out_path, a, b, cohort, unit, exclude_repo } = args;
+
+    let mut cdb = open_for_queries(db_path)?;
+    cdb.select_bucket_tz(BucketTz::Local)?;
+    cdb.postprocess(meta, "Unknown", false)?;
+
+    let mut hist = cdb.get_window_hist(cohort, unit, a.first.min(b.first), a.last.max(b.last),
+                                       5, &exclude_repo)?;
+    hist.relabel_cohorts(|name| meta.label(name));
+
+    let diff = SnapshotDiff::new(&hist, a, b);
+    if diff.rows.is_empty()
+    {
+        bail!("No data in either window");
+    }
+
+    print!("{}", diff.to_table());
+
+    match out_path
+    {
+        Some(p) if p.extension().is_some_and(|e| e == "csv") =>
+        {
+            std::fs::write(&p, diff.to_csv(delimiter) + "\n").chain_err(|| "Could not write diff")
+        },
+        Some(p) =>
+        {
+            let plotter = Plotter { stamp: None, footnote: None, pad_to_now: false,
+                                    xtick_every: None, xtick_rotate: None,
+                                    fiscal_year_start: None,
+                                    accessible: None, direct_labels: None, forecast: None };
+            plotter.plot_slope(meta, &meta.label(&unit.to_string()), &diff, &p)
+        },
+        None => Ok(())
+    }
+}
+
+fn run_cross_tab(args: CrossTabArgs, meta: &ProjectMeta, delimiter: u8) -> Result<()>
+{
+    let CrossTabArgs { db_path, out_path, era: eras, top_domains, exclude_repo } = args;
+
+    let mut cdb = open_for_queries(db_path)?;
+    cdb.select_bucket_tz(BucketTz::Local)?;
+    cdb.postprocess(meta, "Unknown", false)?;
+
+    let mut era_rows = Vec::new();
+    if eras.is_empty()
+    {
+        era_rows.push((meta.label("All years"), cdb.get_domain_crosstab(None, &exclude_repo)?));
+    }
+    for era in &eras
+    {
+        era_rows.push((era.to_string(), cdb.get_domain_crosstab(Some(*era), &exclude_repo)?));
+    }
+
+    let tab = DomainCrossTab::new(era_rows, top_domains, &meta.label("Other"));
+    if tab.is_empty()
+    {
+        bail!("No commits found");
+    }
+
+    print!("{}", tab.to_table());
+
+    match out_path
+    {
+        Some(p) if p.extension().is_some_and(|e| e == "csv") =>
+        {
+            std::fs::write(&p, tab.to_csv(delimiter) + "\n").chain_err(|| "Could not write cross-tab")
+        },
+        Some(p) =>
+        {
+            let plotter = Plotter { stamp: None, footnote: None, pad_to_now: false,
+                                    xtick_every: None, xtick_rotate: None,
+                                    fiscal_year_start: None,
+                                    accessible: None, direct_labels: None, forecast: None };
+            plotter.plot_domain_crosstab(meta, &tab, &p)
+        },
+        None => Ok(())
+    }
+}
+
+fn run_overlap(db_path: PathBuf, out_path: Option<PathBuf>, meta: &ProjectMeta,
+               by: CohortType, top: usize, exclude_repos: &[String], delimiter: u8) -> Result<()>
+{
+    let mut cdb = open_for_queries(db_path)?;
+    cdb.select_bucket_tz(BucketTz::Local)?;
+    cdb.postprocess(meta, "Unknown", false)?;
+
+    let mut overlap = AuthorOverlap::new(&cdb.get_author_overlap(by, exclude_repos)?, top);
+    if overlap.is_empty()
+    {
+        bail!("No commits found");
+    }
+    for item in overlap.items.iter_mut()
+    {
+        *item = meta.label(item);
+    }
+
+    print!("{}", overlap.to_table());
+
+    match out_path
+    {
+        Some(p) if p.extension().is_some_and(|e| e == "csv") =>
+        {
+            std::fs::write(&p, overlap.to_csv(delimiter) + "\n").chain_err(|| "Could not write overlap")
+        },
+        Some(p) =>
+        {
+            let plotter = Plotter { stamp: None, footnote: None, pad_to_now: false,
+                                    xtick_every: None, xtick_rotate: None,
+                                    fiscal_year_start: None,
+                                    accessible: None, direct_labels: None, forecast: None };
+            plotter.plot_author_overlap(meta, &overlap, &p)
+        },
+        None => Ok(())
+    }
+}
+
+fn run_coupling(args: CouplingArgs, meta: &ProjectMeta, delimiter: u8) -> Result<()>
+{
+    let CouplingArgs { db_path, out_path, top, min_shared, max_dirs, exclude_repo } = args;
+
+    let mut cdb = open_for_queries(db_path)?;
+    cdb.select_bucket_tz(BucketTz::Local)?;
+    cdb.postprocess(meta, "Unknown", false)?;
+
+    let coupling = PrefixCoupling::new(&cdb.get_prefix_coupling(max_dirs, &exclude_repo)?, top, min_shared);
+    if coupling.is_empty()
+    {
+        bail!("No directories were changed together in at least {} commits", min_shared);
+    }
+
+    print!("{}", coupling.to_table());
+
+    match out_path
+    {
+        Some(p) if p.extension().is_some_and(|e| e == "csv") =>
+        {
+            std::fs::write(&p, coupling.to_csv(delimiter) + "\n").chain_err(|| "Could not write coupling")
+        },
+        Some(p) =>
+        {
+            let plotter = Plotter { stamp: None, footnote: None, pad_to_now: false,
+                                    xtick_every: None, xtick_rotate: None,
+                                    fiscal_year_start: None,
+                                    accessible: None, direct_labels: None, forecast: None };
+            plotter.plot_coupling(meta, &coupling.to_series_csv(), coupling.pairs.len(), &p)
+        },
+        None => Ok(())
+    }
+}
+
+fn run_precompute(args: PrecomputeArgs, meta: &ProjectMeta, delimiter: u8) -> Result<()>
+{
+    let PrecomputeArgs { db_path, out_dir, cohorts, units, interval, bucket_tz, min_activity,
+                         exclude_repo } = args;
+
+    // Postprocess once and write a table for every cohort and unit, instead
+    // of paying for postprocessing with each plot.
+
+    let start = Instant::now();
+    let mut cdb = open_for_queries(db_path)?;
+    cdb.select_bucket_tz(bucket_tz)?;
+    cdb.postprocess(meta, "Unknown", false)?;
+
+    let requests: Vec<(CohortType, UnitType)> = cohorts.iter()
+        .flat_map(|cohort| units.iter().map(move |unit| (*cohort, *unit)))
+        .collect();
+    let hists = cdb.get_hists(&requests, interval, min_activity, &exclude_repo)?;
+
+    std::fs::create_dir_all(&out_dir).chain_err(|| format!("Could not create {}", out_dir.display()))?;
+
+    for ((cohort, unit), mut hist) in requests.iter().zip(hists)
+    {
+        hist.relabel_cohorts(|name| meta.label(name));
+
+        let path = out_dir.join(format!("{}-{}.csv", cohort.to_string().to_lowercase(),
+                                        unit.to_string().to_lowercase()));
+        std::fs::write(&path, hist.to_csv(delimiter) + "\n")
+            .chain_err(|| format!("Could not write {}", path.display()))?;
+    }
+
+    eprintln!("{}: wrote {} tables in {:.1}s", out_dir.display(), requests.len(), start.elapsed().as_secs_f64());
+    Ok(())
+}
+
+fn run_export_commit_sizes(args: ExportCommitSizesArgs, meta: &ProjectMeta, delimiter: u8) -> Result<()>
+{
+    let ExportCommitSizesArgs { db_path, out_path, cohort, top_cohorts, interval, exclude_repo,
+                                format } = args;
+
+    let mut cdb = open_for_queries(db_path)?;
+    cdb.select_bucket_tz(BucketTz::Local)?;
+    cdb.postprocess(meta, "Unknown", false)?;
+
+    let mut sizes = cdb.get_commit_sizes(cohort, interval, top_cohorts, &exclude_repo)?;
+    for (_, _, name, _) in sizes.iter_mut()
+    {
+        *name = meta.label(name);
+    }
+
+    let commit_sizes = CommitSizes::new(&sizes);
+    if commit_sizes.is_empty()
+    {
+        bail!("No commits found");
+    }
+
+    match out_path
+    {
+        Some(p) => commit_sizes.write(&p, format, delimiter),
+        None =>
+        {
+            println!("{}", commit_sizes.to_csv(delimiter));
+            Ok(())
+        }
+    }
+}
+
+fn run_hygiene(args: HygieneArgs, meta: &ProjectMeta, delimiter: u8) -> Result<()>
+{
+    let HygieneArgs { db_path, out_path, cohort, top_cohorts, interval, exclude_repo } = args;
+
+    if let IntervalType::Day = interval
+    {
+        bail!("Hygiene stats by day are not supported");
+    }
+
+    let mut cdb = open_for_queries(db_path)?;
+    cdb.select_bucket_tz(BucketTz::Local)?;
+    cdb.postprocess(meta, "Unknown", false)?;
+
+    let mut stats = cdb.get_message_stats(cohort, interval, top_cohorts, &exclude_repo)?;
+    for (_, _, name, _) in stats.iter_mut()
+    {
+        *name = meta.label(name);
+    }
+
+    let hygiene = MessageHygiene::new(stats);
+    if hygiene.is_empty()
+    {
+        bail!("No commits found");
+    }
+    if !hygiene.has_bodies()
+    {
+        eprintln!("No message bodies recorded; ingest with --message-stats to include them.");
+    }
+
+    print!("{}", hygiene.to_table());
+
+    match out_path
+    {
+        Some(p) if p.extension().is_some_and(|e| e == "csv") =>
+        {
+            std::fs::write(&p, hygiene.to_csv(delimiter) + "\n").chain_err(|| "Could not write message stats")
+        },
+        Some(p) =>
+        {
+            let plotter = Plotter { stamp: None, footnote: None, pad_to_now: false,
+                                    xtick_every: None, xtick_rotate: None,
+                                    fiscal_year_start: None,
+                                    accessible: None, direct_labels: None, forecast: None };
+            plotter.plot_line_pair(meta, (&meta.label("Commits with a body (%)"), &hygiene.body_share_hist()),
+                                   (&meta.label("Mean subject length"), &hygiene.subject_length_hist()),
+                                   &p, None, None)
+        },
+        None => Ok(())
+    }
+}
+
+fn run_graph_stats(db_path: PathBuf, out_path: Option<PathBuf>, meta: &ProjectMeta,
+                   interval: IntervalType, delimiter: u8) -> Result<()>
+{
+    let mut cdb = open_for_queries(db_path)?;
+    let stats = cdb.get_graph_stats(interval)?;
+    let rows: Vec<(String, f64, f64, Option<f64>)> = stats.iter()
+        .map(|(ym, s)|
+        {
+            let n_total = (s.n_commits + s.n_merges) as f64;
+            (ym.to_string(), s.n_merges as f64 / n_total, s.n_mainline as f64 / n_total, s.mean_branch_days)
+        })
+        .collect();
+
+    println!("{:<10} {:>10} {:>10} {:>11} {:>12} {:>10}",
+             "Interval", "Commits", "Merges", "Merge rate", "Branch days", "Linearity");
+    for ((label, merge_rate, linearity, branch_days), (_, s)) in rows.iter().zip(&stats)
+    {
+        println!("{:<10} {:>10} {:>10} {:>10.1}% {:>12} {:>9.1}%",
+                 label, s.n_commits, s.n_merges, merge_rate * 100.0,
+                 branch_days.map_or("-".to_string(), |d| format!("{:.1}", d)),
+                 linearity * 100.0);
+    }
+
+    let table: Vec<Vec<String>> = std::iter::once(csv_header(&[ "Interval", "Merge rate", "Linearity", "Branch days" ]))
+        .chain(rows.iter()
+               .map(|(label, merge_rate, linearity, branch_days)|
+                    vec![ label.clone(), merge_rate.to_string(), linearity.to_string(),
+                          branch_days.map_or("NaN".to_string(), |d| d.to_string()) ]))
+        .collect();
+
+    match out_path
+    {
+        Some(p) if p.extension().is_some_and(|e| e == "csv") =>
+        {
+            std::fs::write(&p, format_csv(&table, delimiter) + "\n").chain_err(|| "Could not write graph stats")
+        },
+        Some(p) =>
+        {
+            let plotter = Plotter { stamp: None, footnote: None, pad_to_now: false,
+                                    xtick_every: None, xtick_rotate: None,
+                                    fiscal_year_start: None,
+                                    accessible: None, direct_labels: None, forecast: None };
+            plotter.plot_graph_stats(meta, &format_csv(&table, GNUPLOT_DELIMITER), &p)
+        },
+        None => Ok(())
+    }
+}
+
+fn run_pipeline(config_path: PathBuf, skip_ingest: bool, keep_going: bool, wait: bool,
+                meta_path: Option<&PathBuf>) -> Result<()>
+{
+    let config = PipelineConfig::from_file(&config_path)?;
+    let meta_path = config.meta.as_ref().or(meta_path);
+    let meta = match meta_path
+    {
+        Some(m) => ProjectMeta::from_file(m)?,
+        None => ProjectMeta::new()
+    };
+
+    // Parse all the outputs up front, so a typo in the last one doesn't
+    // surface only after a long ingest.
+
+    let mut outputs = Vec::new();
+
+    for output in &config.outputs
+    {
+        let mut argv = vec![ "fornalder".to_string() ];
+        if let Some(m) = meta_path
+        {
+            argv.push("--meta".to_string());
+            argv.push(m.to_string_lossy().into_owned());
+        }
+        argv.extend(output.to_args(&config.db));
+
+        let mut args = Args::from_iter_safe(&argv)
+            .chain_err(|| format!("Invalid pipeline output '{}'", output.describe()))?;
+
+        if let MainCommand::Plot(plot_args) = &mut args.cmd
+        {
+            plot_args.command_line = Some(argv);
+        }
+
+        match args.cmd
+        {
+            MainCommand::Ingest(_) | MainCommand::IngestMbox { .. } | MainCommand::BenchIngest { .. }
+                | MainCommand::RenameRepo { .. } | MainCommand::DedupeCommits { .. }
+                | MainCommand::ResolveIdentities { .. }
+                | MainCommand::ImportAuthorEvents { .. } | MainCommand::Fleet { .. } | MainCommand::Run { .. } =>
+            {
+                bail!("'{}' can't be used as a pipeline output", output.command);
+            },
+            _ => outputs.push((output.describe(), args))
+        }
+    }
+
+    let mut steps: Vec<(String, bool, f64)> = Vec::new();
+
+    if !skip_ingest && !config.repos.is_empty()
+    {
+        let start = Instant::now();
+        let db_profile: DbProfile = config.db_profile.as_deref().unwrap_or("fast").parse()?;
+        let ingest_args = IngestArgs
+        {
+            db_path: config.db.clone(),
+            repo_tree_paths: config.repos.clone(),
+            name: None,
+            repo_map: config.repo_map.clone(),
+            branches: config.branches.clone(),
+            message_stats: config.message_stats,
+            recurse_submodules: config.recurse_submodules,
+            sample: None,
+            dry_run: false,
+            wait,
+            db_profile
+        };
+        run_ingest(ingest_args, &meta)?;
+        steps.push((format!("ingest ({} repo(s))", config.repos.len()), true, start.elapsed().as_secs_f64()));
+    }
+
+    // Every output postprocesses on its own, but doing it once here catches
+    // metadata problems before any outputs are written.
+
+    let start = Instant::now();
+    let mut cdb = open_for_queries(resolve_db_path(config.db.clone())?)?;
+    cdb.postprocess(&meta, "Unknown", false)?;
+    drop(cdb);
+    steps.push(("postprocess".to_string(), true, start.elapsed().as_secs_f64()));
+
+    let n_outputs = outputs.len();
+    let mut n_run = 0;
+    let mut n_failed = 0;
+
+    for (name, args) in outputs
+    {
+        let start = Instant::now();
+        n_run += 1;
+        let result = run_args(args);
+
+        if let Err(e) = &result
+        {
+            eprintln!("error in {}: {}", name, e);
+            for e in e.iter().skip(1)
+            {
+                eprintln!("caused by: {}", e);
+            }
+            n_failed += 1;
+        }
+
+        steps.push((name, result.is_ok(), start.elapsed().as_secs_f64()));
+
+        if result.is_err() && !keep_going
+        {
+            break;
+        }
+    }
+
+    println!("\nPipeline summary:");
+    for (name, ok, secs) in &steps
+    {
+        println!("  {:<48} {:<8} {:>8.1}s", name, if *ok { "ok" } else { "FAILED" }, secs);
+    }
+
+    if n_run < n_outputs
+    {
+        println!("  {} output(s) skipped", n_outputs - n_run);
+    }
+
+    if n_failed > 0
+    {
+        bail!("{} of {} pipeline outputs failed", n_failed, n_outputs);
+    }
+
+    Ok(())
 }
@@ -0,0 +1,246 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+
+/* -------- *
+ * Manifest *
+ * -------- */
+
+use chrono::prelude::Utc;
+use serde::{Serialize};
+use sha2::{ Digest, Sha256 };
+use std::fs;
+use std::path::*;
+use crate::errors::*;
+use crate::pngmeta;
+use crate::PlotArgs;
+
+// A record of everything that went into a plot, written as a sidecar JSON
+// file next to the image so results can be reproduced later.
+
+#[derive(Serialize, Debug)]
+pub struct Manifest
+{
+    fornalder_version: String,
+    generated_at: String,
+    command_line: String,
+    db_path: String,
+    db_hash: String,
+    meta_path: Option<String>,
+    meta_hash: Option<String>,
+    events_path: Option<String>,
+    events_hash: Option<String>,
+    params: PlotParams
+}
+
+#[derive(Serialize, Debug)]
+struct PlotParams
+{
+    chart: String,
+    facet_by: Option<String>,
+    max_facets: usize,
+    seasonal_view: String,
+    cohort: String,
+    cohort_name: Option<String>,
+    unit: String,
+    tenure_curve: String,
+    min_activity: u32,
+    min_cohort_commits: u32,
+    top_per_interval: Option<usize>,
+    hide: Option<String>,
+    split_bots: Option<String>,
+    dedupe_cherry_picks: bool,
+    repo_coverage: bool,
+    top_authors: u32,
+    interval: String,
+    bucket_tz: String,
+    fiscal_year_start: Option<u32>,
+    from: Option<i32>,
+    to: Option<i32>,
+    exclude_repos: Vec<String>,
+    include_suffixes: Vec<String>,
+    exclude_suffixes: Vec<String>,
+    unknown_domain: String,
+    drop_unknown_domains: bool,
+    pad_to_now: bool,
+    legend_totals: bool,
+    xtick_every: Option<u32>,
+    xtick_rotate: Option<i32>,
+    accessible: Option<String>,
+    direct_labels: Option<usize>,
+    overlay: Vec<String>,
+    forecast_model: String
+}
+
+impl Manifest
+{
+    pub fn new(args: &PlotArgs, db_hash: String, meta_path: Option<&PathBuf>) -> Result<Manifest>
+    {
+        let meta_hash =
+            match meta_path
+            {
+                Some(p) => Some(hash_file(p).chain_err(|| "Could not read meta file")?),
+                None => None
+            };
+        let events_hash =
+            match &args.events
+            {
+                Some(p) => Some(hash_file(p).chain_err(|| "Could not read events file")?),
+                None => None
+            };
+
+        Ok(Manifest
+        {
+            fornalder_version: env!("CARGO_PKG_VERSION").to_string(),
+            generated_at: Utc::now().to_rfc3339(),
+            command_line: match &args.command_line
+            {
+                Some(argv) => format_command_line(argv.iter().cloned()),
+                None => format_command_line(std::env::args())
+            },
+            db_path: args.db_path.to_string_lossy().into_owned(),
+            db_hash,
+            meta_path: meta_path.map(|p| p.to_string_lossy().into_owned()),
+            meta_hash,
+            events_path: args.events.as_ref().map(|p| p.to_string_lossy().into_owned()),
+            events_hash,
+            params: PlotParams
+            {
+                chart: args.chart.to_string().to_lowercase(),
+                facet_by: args.facet_by.map(|f| f.to_string().to_lowercase()),
+                max_facets: args.max_facets,
+                seasonal_view: args.seasonal_view.to_string().to_lowercase(),
+                cohort: args.cohort.to_string().to_lowercase(),
+                cohort_name: args.cohort_name.clone(),
+                unit: args.unit.to_string().to_lowercase(),
+                tenure_curve: args.tenure_curve.to_string().to_lowercase(),
+                min_activity: args.min_activity,
+                min_cohort_commits: args.min_cohort_commits,
+                top_per_interval: args.top_per_interval,
+                hide: args.hide.map(|h| h.to_string().to_lowercase()),
+                split_bots: args.split_bots.map(|b| b.to_string().to_lowercase()),
+                dedupe_cherry_picks: args.dedupe_cherry_picks,
+                repo_coverage: args.repo_coverage,
+                top_authors: args.top_authors,
+                interval: args.interval.to_string().to_lowercase(),
+                bucket_tz: args.bucket_tz.to_string().to_lowercase(),
+                fiscal_year_start: args.fiscal_year_start,
+                from: args.from,
+                to: args.to,
+                exclude_repos: args.exclude_repo.clone(),
+                include_suffixes: args.include_suffix.clone(),
+                exclude_suffixes: args.exclude_suffix.clone(),
+                unknown_domain: args.unknown_domain.clone(),
+                drop_unknown_domains: args.drop_unknown_domains,
+                pad_to_now: args.pad_to_now,
+                legend_totals: args.legend_totals,
+                xtick_every: args.xtick_every,
+                xtick_rotate: args.xtick_rotate,
+                accessible: args.accessible.map(|a| a.to_string().to_lowercase()),
+                direct_labels: args.direct_labels,
+                overlay: args.overlay.clone(),
+                forecast_model: args.forecast_model.to_string().to_lowercase()
+            }
+        })
+    }
+
+    pub fn caption(&self) -> String
+    {
+        format!("fornalder {} | {} | db {}",
+                self.fornalder_version,
+                &self.generated_at[..10],
+                &self.db_hash[..12])
+    }
+
+    pub fn write(&self, out_path: &Path) -> Result<()>
+    {
+        // Sidecar files tend to get lost when charts are copied around, so
+        // the image carries the same record.
+
+        self.write_sidecar(out_path)?;
+        self.embed(out_path)
+    }
+
+    fn embed(&self, out_path: &Path) -> Result<()>
+    {
+        let json = serde_json::to_string(self).chain_err(|| "Could not serialize manifest")?;
+
+        pngmeta::embed_text(out_path,
+                            &[ ("Software", format!("fornalder {}", self.fornalder_version)),
+                               ("Creation Time", self.generated_at.clone()),
+                               ("Source", self.command_line.clone()),
+                               ("fornalder:db-hash", self.db_hash.clone()),
+                               ("fornalder:manifest", json) ])
+    }
+
+    fn write_sidecar(&self, out_path: &Path) -> Result<()>
+    {
+        let mut sidecar_path = out_path.as_os_str().to_os_string();
+        sidecar_path.push(".json");
+
+        let json = serde_json::to_string_pretty(self).chain_err(|| "Could not serialize manifest")?;
+        fs::write(&sidecar_path, json + "\n").chain_err(|| "Could not write manifest")?;
+
+        Ok(())
+    }
+}
+
+fn format_command_line(args: impl Iterator<Item = String>) -> String
+{
+    // Quote just enough that the line can be pasted back into a shell.
+
+    args.map(|a| {
+        if !a.is_empty() && a.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=,+@%".contains(c)) { a }
+        else { format!("'{}'", a.replace('\'', "'\\''")) }
+    }).collect::<Vec<_>>().join(" ")
+}
+
+pub fn hash_file(path: &Path) -> std::io::Result<String>
+{
+    Ok(format!("{:x}", Sha256::digest(&fs::read(path)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_command_line() {
+        let args = [ "fornalder", "plot", "--cohort-name", "Red Hat", "-o", "it's.png" ];
+
+        assert_eq!(format_command_line(args.iter().map(|a| a.to_string())),
+                   "fornalder plot --cohort-name 'Red Hat' -o 'it'\\''s.png'");
+    }
+
+    #[test]
+    fn records_pipeline_command_line() {
+        use structopt::StructOpt;
+
+        let mut args = PlotArgs::from_iter(&[ "plot", "x.db", "x.png", "--split-bots", "cohort",
+                                              "--include-suffix", "rs" ]);
+        args.command_line = Some(vec![ "fornalder".to_string(), "plot".to_string(), "x.db".to_string(),
+                                       "x.png".to_string() ]);
+        let manifest = Manifest::new(&args, "0".repeat(64), None).unwrap();
+
+        assert_eq!(manifest.command_line, "fornalder plot x.db x.png");
+        assert_eq!(manifest.params.split_bots.as_deref(), Some("cohort"));
+        assert_eq!(manifest.params.include_suffixes, [ "rs" ]);
+    }
+}
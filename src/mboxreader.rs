@@ -0,0 +1,486 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* ---------- *
+ * MboxReader *
+ * ---------- */
+
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone};
+use regex::Regex;
+use sha2::{ Digest, Sha256 };
+use std::collections::HashMap;
+use std::path::Path;
+use crate::errors::*;
+use crate::gitcommitreader::{ has_message_body, unquote_path, PathClassifier, RawCommit };
+
+// Reads patches mailed to a list, for history that predates version control.
+// Each message with a unified diff becomes a commit attributed to its
+// sender; replies and messages without a diff are skipped. Since mail has no
+// commit ids, one is derived from the Message-ID so that re-ingesting an
+// archive replaces the commits instead of duplicating them.
+
+pub struct MboxReader
+{
+    repo_name: String,
+    paths: PathClassifier,
+    hunk_re: Regex,
+    encoded_word_re: Regex,
+    encoded_word_gap_re: Regex,
+    revert_re: Regex
+}
+
+impl MboxReader
+{
+    pub fn new(repo_name: &str) -> MboxReader
+    {
+        MboxReader
+        {
+            repo_name: repo_name.to_string(),
+            paths: PathClassifier::new(),
+            hunk_re: Regex::new(r"^@@ -[0-9]+(,([0-9]+))? \+[0-9]+(,([0-9]+))? @@").unwrap(),
+            encoded_word_re: Regex::new(r"=\?([^?]+)\?([qQbB])\?([^?]*)\?=").unwrap(),
+            encoded_word_gap_re: Regex::new(r"\?=\s+=\?").unwrap(),
+            revert_re: Regex::new(r#"^Revert ".*"$"#).unwrap()
+        }
+    }
+
+    // Returns the commits found, and the number of patches that were
+    // skipped because their date could not be parsed.
+
+    pub fn read_file(&self, path: &Path) -> Result<(Vec<RawCommit>, u32)>
+    {
+        let bytes = std::fs::read(path).chain_err(|| format!("Could not read {}", path.display()))?;
+        Ok(self.parse_mbox(&String::from_utf8_lossy(&bytes)))
+    }
+
+    pub fn parse_mbox(&self, text: &str) -> (Vec<RawCommit>, u32)
+    {
+        let mut commits = Vec::new();
+        let mut n_undated = 0;
+        let mut message: Vec<&str> = Vec::new();
+        let mut prev_blank = true;
+
+        for line in text.lines().map(|l| l.trim_end_matches('\r')).chain(std::iter::once(""))
+        {
+            // Messages start with a "From " line. Bodies have such lines
+            // escaped, but we also require a blank line before it, as not all
+            // archivers are careful about that.
+
+            if prev_blank && line.starts_with("From ")
+            {
+                self.flush_message(&mut message, &mut commits, &mut n_undated);
+                message.push(line);
+            }
+            else if !message.is_empty()
+            {
+                message.push(line);
+            }
+
+            prev_blank = line.is_empty();
+        }
+
+        self.flush_message(&mut message, &mut commits, &mut n_undated);
+        (commits, n_undated)
+    }
+
+    fn flush_message(&self, message: &mut Vec<&str>, commits: &mut Vec<RawCommit>, n_undated: &mut u32)
+    {
+        if message.is_empty() { return; }
+
+        match self.parse_message(message)
+        {
+            Ok(Some(commit)) => commits.push(commit),
+            Ok(None) => { },
+            Err(_) => *n_undated += 1
+        }
+
+        message.clear();
+    }
+
+    fn parse_message(&self, lines: &[&str]) -> Result<Option<RawCommit>>
+    {
+        // Unfold headers; continuation lines start with whitespace.
+
+        let mut headers: HashMap<String, String> = HashMap::new();
+        let mut last_header = String::new();
+        let mut body_start = lines.len();
+
+        for (i, line) in lines.iter().enumerate().skip(1)
+        {
+            if line.is_empty() { body_start = i + 1; break; }
+
+            if line.starts_with(' ') || line.starts_with('\t')
+            {
+                if let Some(value) = headers.get_mut(&last_header)
+                {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+            }
+            else if let Some(colon) = line.find(':')
+            {
+                last_header = line[..colon].trim().to_lowercase();
+                headers.entry(last_header.clone()).or_insert_with(|| line[colon + 1..].trim().to_string());
+            }
+        }
+
+        let subject = self.decode_words(headers.get("subject").map_or("", |s| s.as_str()));
+        if subject.to_lowercase().starts_with("re:") { return Ok(None); }
+
+        let mut commit = RawCommit::default();
+        if !self.read_diff(&mut commit, &lines[body_start.min(lines.len())..]) { return Ok(None); }
+
+        let author_time = headers.get("date").and_then(|d| parse_date(d))
+            .or_else(|| parse_from_line_date(lines[0]))
+            .chain_err(|| "Unparseable date")?;
+        let (author_name, author_email) = self.parse_address(headers.get("from").map_or("", |s| s.as_str()));
+        let message_id = match headers.get("message-id")
+        {
+            Some(id) => id.clone(),
+            None => format!("{}|{}|{}", author_email, author_time, subject)
+        };
+
+        commit.id = format!("{:x}", Sha256::digest(format!("mbox:{}", message_id).as_bytes()))[..40].to_string();
+        commit.repo_name = self.repo_name.clone();
+        commit.author_name = author_name.clone();
+        commit.author_email = author_email.clone();
+        commit.author_time = Some(author_time);
+        commit.committer_name = author_name;
+        commit.committer_email = author_email;
+        commit.committer_time = Some(author_time);
+        commit.subject = strip_subject_tags(&subject);
+        commit.is_revert = self.revert_re.is_match(&commit.subject);
+
+        // The description ends where git format-patch puts its diffstat
+        // separator, or where the diff starts if there is none.
+
+        let description: Vec<&str> = lines[body_start.min(lines.len())..].iter()
+            .take_while(|l| **l != "---" && !l.starts_with("--- ") && !l.starts_with("diff ") && !l.starts_with("Index:"))
+            .cloned().collect();
+        commit.has_body = Some(has_message_body(&description.join("\n")));
+
+        self.paths.finalize_paths(&mut commit);
+        Ok(Some(commit))
+    }
+
+    fn read_diff(&self, commit: &mut RawCommit, body: &[&str]) -> bool
+    {
+        // Count changed lines hunk by hunk, using the line counts in the hunk
+        // headers to tell where each hunk ends. Returns whether any hunks
+        // were found.
+
+        let mut old_path = "";
+        let mut path = String::new();
+        let mut found_hunks = false;
+        let mut i = 0;
+
+        while i < body.len()
+        {
+            let line = body[i];
+            i += 1;
+
+            if let Some(p) = line.strip_prefix("--- ")
+            {
+                old_path = p;
+                continue;
+            }
+            else if let Some(new_path) = line.strip_prefix("+++ ")
+            {
+                path = strip_path(if new_path.starts_with("/dev/null") { old_path } else { new_path });
+                continue;
+            }
+
+            let captures = match self.hunk_re.captures(line)
+            {
+                Some(c) => c,
+                None => continue
+            };
+
+            let count = |n: Option<regex::Match>| n.map_or(1, |m| m.as_str().parse::<i32>().unwrap_or(0));
+            let (mut n_old, mut n_new) = (count(captures.get(2)), count(captures.get(4)));
+            let (mut n_insertions, mut n_deletions) = (0, 0);
+
+            while i < body.len() && (n_old > 0 || n_new > 0)
+            {
+                let hunk_line = body[i];
+                i += 1;
+
+                if hunk_line.starts_with('+') { n_new -= 1; n_insertions += 1; }
+                else if hunk_line.starts_with('-') { n_old -= 1; n_deletions += 1; }
+                else if hunk_line.starts_with('\\') { }
+                else { n_old -= 1; n_new -= 1; }
+            }
+
+            commit.n_insertions += n_insertions;
+            commit.n_deletions += n_deletions;
+            if n_insertions + n_deletions > 0
+            {
+                self.paths.add_path_line_changes(commit, &path, n_insertions, n_deletions);
+            }
+            found_hunks = true;
+        }
+
+        found_hunks
+    }
+
+    fn parse_address(&self, from: &str) -> (String, String)
+    {
+        // Handles "Name <user@host>", "user@host (Name)" and a bare address.
+        // List archives often obfuscate addresses as "user at host".
+
+        let from = self.decode_words(from);
+        let (name, email) =
+            if let (Some(lt), Some(gt)) = (from.find('<'), from.rfind('>'))
+            {
+                (from[..lt].to_string(), from[lt + 1..gt.max(lt + 1)].to_string())
+            }
+            else if let (Some(lp), Some(rp)) = (from.find('('), from.rfind(')'))
+            {
+                (from[lp + 1..rp.max(lp + 1)].to_string(), from[..lp].to_string())
+            }
+            else
+            {
+                (String::new(), from.clone())
+            };
+
+        let email = email.trim().replace(" at ", "@").to_lowercase();
+        let name = name.trim().trim_matches('"').trim().to_string();
+        let name = if name.is_empty() { email.split('@').next().unwrap_or("").to_string() } else { name };
+
+        (name, email)
+    }
+
+    fn decode_words(&self, text: &str) -> String
+    {
+        // RFC 2047 encoded words, e.g. "=?UTF-8?q?J=C3=B6rg?=". Whitespace
+        // between adjacent encoded words is dropped.
+
+        let text = self.encoded_word_gap_re.replace_all(text, "?==?");
+
+        self.encoded_word_re.replace_all(&text, |c: &regex::Captures|
+        {
+            let bytes = match &c[2]
+            {
+                "q" | "Q" => decode_q(&c[3]),
+                _ => decode_base64(&c[3])
+            };
+
+            if c[1].to_lowercase().starts_with("utf") { String::from_utf8_lossy(&bytes).into_owned() }
+            else { bytes.iter().map(|b| *b as char).collect() }
+        }).into_owned()
+    }
+}
+
+fn parse_date(date: &str) -> Option<DateTime<FixedOffset>>
+{
+    // Drop trailing comments like "(PST)".
+
+    let date = match date.find('(') { Some(i) => &date[..i], None => date };
+    DateTime::parse_from_rfc2822(date.trim()).ok()
+}
+
+fn parse_from_line_date(from_line: &str) -> Option<DateTime<FixedOffset>>
+{
+    // The envelope line ends in an asctime() date in UTC, e.g.
+    // "From user@host Mon Feb  3 12:34:56 1997".
+
+    let fields = from_line.split_whitespace().collect::<Vec<&str>>();
+    if fields.len() < 5 { return None; }
+
+    let date = fields[fields.len() - 5..].join(" ");
+    NaiveDateTime::parse_from_str(&date, "%a %b %e %H:%M:%S %Y").ok()
+        .map(|dt| FixedOffset::east_opt(0).unwrap().from_utc_datetime(&dt))
+}
+
+fn strip_path(path: &str) -> String
+{
+    // Drop the trailing timestamp of classic diffs, and the leading
+    // directory, like "patch -p1" would ("a/", "linux-2.0.1/" etc). Git
+    // quotes the whole path, leading directory included.
+
+    let path = unquote_path(path.split('\t').next().unwrap().trim());
+    let path = path.as_str();
+    match path.find('/')
+    {
+        Some(i) if i + 1 < path.len() => path[i + 1..].to_string(),
+        _ => path.to_string()
+    }
+}
+
+fn strip_subject_tags(subject: &str) -> String
+{
+    // "[PATCH v2 3/7] foo: Fix bar" -> "foo: Fix bar"
+
+    let mut subject = subject.trim();
+    while subject.starts_with('[')
+    {
+        match subject.find(']')
+        {
+            Some(i) => subject = subject[i + 1..].trim_start(),
+            None => break
+        }
+    }
+
+    subject.to_string()
+}
+
+fn decode_q(text: &str) -> Vec<u8>
+{
+    let bytes = text.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len()
+    {
+        match bytes[i]
+        {
+            b'_' => out.push(b' '),
+            b'=' if text.get(i + 1..i + 3).is_some_and(|h| u8::from_str_radix(h, 16).is_ok()) =>
+            {
+                out.push(u8::from_str_radix(&text[i + 1..i + 3], 16).unwrap());
+                i += 2;
+            },
+            b => out.push(b)
+        }
+
+        i += 1;
+    }
+
+    out
+}
+
+fn decode_base64(text: &str) -> Vec<u8>
+{
+    let value = |c: u8| match c
+    {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None
+    };
+
+    let mut out = Vec::new();
+    let mut acc: u32 = 0;
+    let mut n_bits = 0;
+
+    for v in text.bytes().filter_map(value)
+    {
+        acc = (acc << 6) | v as u32;
+        n_bits += 6;
+
+        if n_bits >= 8
+        {
+            n_bits -= 8;
+            out.push((acc >> n_bits) as u8);
+            acc &= (1 << n_bits) - 1;
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MBOX: &str = "\
+From jorg@example.org Mon Feb  3 12:34:56 1997
+From: =?UTF-8?q?J=C3=B6rg?= Smith <Jorg@Example.org>
+Date: Mon, 3 Feb 1997 12:34:56 +0100 (MET)
+Subject: [PATCH 1/2] Fix the
+ frobnicator
+Message-ID: <1@example.org>
+
+Long description.
+
+---
+ drivers/frob.c | 3 ++-
+ 1 file changed, 2 insertions(+), 1 deletion(-)
+
+--- linux.orig/drivers/frob.c\tMon Feb  3 12:00:00 1997
++++ linux/drivers/frob.c\tMon Feb  3 12:30:00 1997
+@@ -1,2 +1,3 @@
+ int frob()
+-{ return 0; }
++{
++  return 1; }
+--- a/README
++++ b/README
+@@ -1 +1 @@
+-old
++new
+-- 
+2.0
+
+From hacker at example.net Tue Feb  4 10:00:00 1997
+From: hacker at example.net (A. Hacker)
+Date: Tue, 4 Feb 1997 10:00:00 -0800
+Subject: Re: [PATCH 1/2] Fix the frobnicator
+Message-ID: <2@example.net>
+
+> --- a/README
+> +++ b/README
+> @@ -1 +1 @@
+
+From hacker at example.net Wed Feb  5 10:00:00 1997
+From: hacker at example.net (A. Hacker)
+Date: sometime
+Subject: New file
+
+--- /dev/null
++++ b/docs/new.txt
+@@ -0,0 +1,2 @@
++one
++two
+";
+
+    #[test]
+    fn parses_patches() {
+        let (commits, n_undated) = MboxReader::new("mbox").parse_mbox(MBOX);
+
+        assert_eq!(n_undated, 0);
+        assert_eq!(commits.len(), 2);
+
+        let c = &commits[0];
+        assert_eq!((c.author_name.as_str(), c.author_email.as_str()), ("Jörg Smith", "jorg@example.org"));
+        assert_eq!(c.subject, "Fix the frobnicator");
+        assert_eq!(c.has_body, Some(true));
+        assert_eq!(c.author_time.unwrap().to_rfc3339(), "1997-02-03T12:34:56+01:00");
+        assert_eq!((c.n_insertions, c.n_deletions), (3, 2));
+        assert_eq!(c.n_changes_per_prefix.get("drivers"), Some(&3));
+        assert_eq!(c.n_changes_per_suffix.get("README"), Some(&2));
+        assert_eq!((c.n_insertions_per_suffix.get("c"), c.n_deletions_per_suffix.get("c")), (Some(&2), Some(&1)));
+
+        // Falls back to the envelope date.
+        let c = &commits[1];
+        assert_eq!((c.author_name.as_str(), c.author_email.as_str()), ("A. Hacker", "hacker@example.net"));
+        assert_eq!(c.author_time.unwrap().to_rfc3339(), "1997-02-05T10:00:00+00:00");
+        assert_eq!(c.n_changes_per_prefix.get("docs"), Some(&2));
+        assert_eq!(c.has_body, Some(false));
+        assert_ne!(commits[0].id, c.id);
+    }
+
+    #[test]
+    fn decodes_base64() {
+        assert_eq!(decode_base64("SsO2cmc="), "Jörg".as_bytes());
+    }
+}
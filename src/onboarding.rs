@@ -0,0 +1,273 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* ---------- *
+ * Onboarding *
+ * ---------- */
+
+use chrono::{ DateTime, NaiveDate, TimeZone, Utc };
+use serde::{Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::*;
+use crate::common::ExportFormat;
+use crate::csvformat::{ csv_header, format_csv };
+use crate::errors::*;
+use crate::parquetexport::{ ParquetColumn, write_parquet };
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+// Something that happened to an author outside of version control, like
+// their first mailing list post or signing a CLA.
+
+#[derive(Debug, PartialEq)]
+pub struct AuthorEvent
+{
+    pub email: String,
+    pub kind: String,
+    pub time: i64
+}
+
+pub fn read_author_events(path: &Path, default_kind: &str) -> Result<Vec<AuthorEvent>>
+{
+    // The CSV file needs a header naming its "email" and "date" columns.
+    // Dates are YYYY-MM-DD or RFC 3339. An optional "kind" column overrides
+    // the kind given on the command line.
+
+    let content = fs::read_to_string(path).chain_err(|| "Could not read events file")?;
+    parse_author_events(&content, default_kind)
+        .chain_err(|| format!("{}: Could not parse author events", path.display()))
+}
+
+fn parse_author_events(content: &str, default_kind: &str) -> Result<Vec<AuthorEvent>>
+{
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(content.as_bytes());
+    let headers = reader.headers().chain_err(|| "Missing header")?.clone();
+    let column = |name: &str| headers.iter().position(|h| h.eq_ignore_ascii_case(name));
+    let email_col = column("email").chain_err(|| "No \"email\" column")?;
+    let date_col = column("date").chain_err(|| "No \"date\" column")?;
+    let kind_col = column("kind");
+    let mut events = Vec::new();
+
+    for (i, record) in reader.records().enumerate()
+    {
+        let record = record.chain_err(|| format!("Line {}: Malformed row", i + 2))?;
+        let email = record.get(email_col).unwrap_or("").to_lowercase();
+        let date = record.get(date_col).unwrap_or("");
+
+        if email.is_empty() { continue; }
+
+        events.push(AuthorEvent
+        {
+            email,
+            kind: kind_col.and_then(|c| record.get(c)).filter(|k| !k.is_empty())
+                .unwrap_or(default_kind).to_string(),
+            time: parse_date(date).chain_err(|| format!("Line {}: Invalid date '{}'", i + 2, date))?
+        });
+    }
+
+    Ok(events)
+}
+
+fn parse_date(date: &str) -> Option<i64>
+{
+    match NaiveDate::parse_from_str(date, "%Y-%m-%d")
+    {
+        Ok(d) => Some(Utc.from_utc_datetime(&d.and_hms_opt(0, 0, 0)?).timestamp()),
+        Err(_) => DateTime::parse_from_rfc3339(date).ok().map(|dt| dt.timestamp())
+    }
+}
+
+fn format_date(time: i64) -> String
+{
+    Utc.timestamp_opt(time, 0).unwrap().format("%Y-%m-%d").to_string()
+}
+
+// An author's e-mail address, name, event time and first commit time, if
+// they have committed at all.
+
+pub type AuthorOnboarding = (String, Option<String>, i64, Option<i64>);
+
+#[derive(Serialize, Debug)]
+struct OnboardingRow
+{
+    email: String,
+    author_name: Option<String>,
+    event_date: String,
+    first_commit_date: Option<String>,
+    latency_days: Option<i64>
+}
+
+#[derive(Serialize, Debug, Default)]
+struct OnboardingSummaryRow
+{
+    event_year: i32,
+    n_authors: i64,
+    n_committed: i64,
+    n_committed_before: i64,
+    median_latency_days: Option<i64>
+}
+
+// How long it took authors to make their first commit after an external
+// onboarding event. Latencies are negative for authors who committed first.
+
+pub struct Onboarding
+{
+    rows: Vec<OnboardingRow>,
+    summary: Vec<OnboardingSummaryRow>
+}
+
+impl Onboarding
+{
+    pub fn new(authors: &[AuthorOnboarding]) -> Onboarding
+    {
+        let rows: Vec<OnboardingRow> = authors.iter()
+            .map(|(email, name, event_time, first_time)| OnboardingRow
+            {
+                email: email.clone(),
+                author_name: name.clone(),
+                event_date: format_date(*event_time),
+                first_commit_date: first_time.map(format_date),
+                latency_days: first_time.map(|t| (t - event_time).div_euclid(SECONDS_PER_DAY))
+            })
+            .collect();
+
+        let mut latencies: BTreeMap<i32, (i64, Vec<i64>)> = BTreeMap::new();
+
+        for row in &rows
+        {
+            let entry = latencies.entry(row.event_date[..4].parse().unwrap()).or_default();
+            entry.0 += 1;
+            entry.1.extend(row.latency_days);
+        }
+
+        let summary = latencies.into_iter()
+            .map(|(event_year, (n_authors, mut days))|
+            {
+                days.sort_unstable();
+                OnboardingSummaryRow
+                {
+                    event_year,
+                    n_authors,
+                    n_committed: days.len() as i64,
+                    n_committed_before: days.iter().filter(|d| **d < 0).count() as i64,
+                    median_latency_days: days.get(days.len() / 2).copied()
+                }
+            })
+            .collect();
+
+        Onboarding { rows, summary }
+    }
+
+    pub fn to_csv(&self, summary: bool, delimiter: u8) -> String
+    {
+        let opt = |v: &Option<i64>| v.map(|v| v.to_string()).unwrap_or_default();
+
+        if summary
+        {
+            let mut rows = vec![ csv_header(&[ "event_year", "n_authors", "n_committed", "n_committed_before",
+                                               "median_latency_days" ]) ];
+
+            rows.extend(self.summary.iter()
+                        .map(|r| vec![ r.event_year.to_string(), r.n_authors.to_string(),
+                                       r.n_committed.to_string(), r.n_committed_before.to_string(),
+                                       opt(&r.median_latency_days) ]));
+            return format_csv(&rows, delimiter);
+        }
+
+        let mut rows = vec![ csv_header(&[ "email", "author_name", "event_date", "first_commit_date",
+                                           "latency_days" ]) ];
+
+        rows.extend(self.rows.iter()
+                    .map(|r| vec![ r.email.clone(), r.author_name.clone().unwrap_or_default(),
+                                   r.event_date.clone(), r.first_commit_date.clone().unwrap_or_default(),
+                                   opt(&r.latency_days) ]));
+        format_csv(&rows, delimiter)
+    }
+
+    pub fn write(&self, out_path: &Path, summary: bool, format: ExportFormat, delimiter: u8) -> Result<()>
+    {
+        let content = match (format, summary)
+        {
+            (ExportFormat::Csv, _) => self.to_csv(summary, delimiter),
+            (ExportFormat::Json, false) => serde_json::to_string_pretty(&self.rows)
+                .chain_err(|| "Could not serialize onboarding table")?,
+            (ExportFormat::Json, true) => serde_json::to_string_pretty(&self.summary)
+                .chain_err(|| "Could not serialize onboarding table")?,
+            (ExportFormat::Parquet, false) =>
+            {
+                let r = &self.rows;
+                return write_parquet(out_path, "onboarding", &[
+                    ("email", ParquetColumn::Str(r.iter().map(|r| r.email.clone()).collect())),
+                    ("author_name", ParquetColumn::OptStr(r.iter().map(|r| r.author_name.clone()).collect())),
+                    ("event_date", ParquetColumn::Str(r.iter().map(|r| r.event_date.clone()).collect())),
+                    ("first_commit_date", ParquetColumn::OptStr(r.iter().map(|r| r.first_commit_date.clone()).collect())),
+                    ("latency_days", ParquetColumn::OptInt(r.iter().map(|r| r.latency_days.map(|d| d as i32)).collect())) ]);
+            },
+            (ExportFormat::Parquet, true) =>
+            {
+                let r = &self.summary;
+                return write_parquet(out_path, "onboarding", &[
+                    ("event_year", ParquetColumn::Int(r.iter().map(|r| r.event_year).collect())),
+                    ("n_authors", ParquetColumn::Long(r.iter().map(|r| r.n_authors).collect())),
+                    ("n_committed", ParquetColumn::Long(r.iter().map(|r| r.n_committed).collect())),
+                    ("n_committed_before", ParquetColumn::Long(r.iter().map(|r| r.n_committed_before).collect())),
+                    ("median_latency_days", ParquetColumn::OptInt(r.iter().map(|r| r.median_latency_days.map(|d| d as i32)).collect())) ]);
+            }
+        };
+
+        fs::write(out_path, content + "\n").chain_err(|| "Could not write onboarding table")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_author_events() {
+        let events = parse_author_events("Email,Date,Kind\n Dude@Lebowski.com ,2020-01-02,\n\
+                                          walter@sobchak.com,2020-01-03T12:00:00+00:00,cla\n", "post").unwrap();
+
+        assert_eq!(events, vec![ AuthorEvent { email: "dude@lebowski.com".to_string(), kind: "post".to_string(),
+                                               time: 1577923200 },
+                                 AuthorEvent { email: "walter@sobchak.com".to_string(), kind: "cla".to_string(),
+                                               time: 1578052800 } ]);
+        assert!(parse_author_events("email,date\ndude@lebowski.com,yesterday\n", "post").is_err());
+        assert!(parse_author_events("email,when\n", "post").is_err());
+    }
+
+    #[test]
+    fn summarizes_latencies() {
+        let day = SECONDS_PER_DAY;
+        let onboarding = Onboarding::new(&[ ("a@x.org".to_string(), Some("A".to_string()), 1577923200, Some(1577923200 + 10 * day)),
+                                            ("b@x.org".to_string(), Some("B".to_string()), 1577923200, Some(1577923200 - day)),
+                                            ("c@x.org".to_string(), None, 1577923200, None) ]);
+
+        assert_eq!(onboarding.to_csv(false, b','),
+                   "email,author_name,event_date,first_commit_date,latency_days\n\
+                    a@x.org,A,2020-01-02,2020-01-12,10\n\
+                    b@x.org,B,2020-01-02,2020-01-01,-1\n\
+                    c@x.org,,2020-01-02,,");
+        assert_eq!(onboarding.to_csv(true, b','),
+                   "event_year,n_authors,n_committed,n_committed_before,median_latency_days\n\
+                    2020,3,2,1,10");
+    }
+}
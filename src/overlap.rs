@@ -0,0 +1,133 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+
+/* ------------- *
+ * AuthorOverlap *
+ * ------------- */
+
+use std::collections::HashMap;
+use crate::csvformat::{ csv_header, format_csv };
+
+const N_TABLE_ROWS: usize = 20;
+
+// Authors shared between each pair of items (e.g. repos), for the items with
+// the most authors. The diagonal holds each item's own author count. The
+// Jaccard index is the share of the pair's combined authors who contributed
+// to both.
+
+pub struct AuthorOverlap
+{
+    pub items: Vec<String>,
+    pub shared: Vec<Vec<i64>>
+}
+
+impl AuthorOverlap
+{
+    pub fn new(pairs: &[(String, String, i64)], n_items: usize) -> AuthorOverlap
+    {
+        let mut sizes = pairs.iter()
+            .filter(|(a, b, _)| a == b)
+            .map(|(a, _, n)| (a.clone(), *n))
+            .collect::<Vec<(String, i64)>>();
+        sizes.sort_by(|x, y| y.1.cmp(&x.1).then(x.0.cmp(&y.0)));
+
+        let items = sizes.into_iter().take(n_items).map(|(a, _)| a).collect::<Vec<String>>();
+        let index: HashMap<&str, usize> = items.iter().enumerate().map(|(i, a)| (a.as_str(), i)).collect();
+        let mut shared = vec![vec![0; items.len()]; items.len()];
+
+        for (a, b, n) in pairs
+        {
+            if let (Some(i), Some(j)) = (index.get(a.as_str()), index.get(b.as_str()))
+            {
+                shared[*i][*j] = *n;
+            }
+        }
+
+        AuthorOverlap { items, shared }
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.items.is_empty()
+    }
+
+    pub fn jaccard(&self, i: usize, j: usize) -> f64
+    {
+        let union = self.shared[i][i] + self.shared[j][j] - self.shared[i][j];
+        if union > 0 { self.shared[i][j] as f64 / union as f64 } else { 0.0 }
+    }
+
+    pub fn to_table(&self) -> String
+    {
+        // The most closely overlapping pairs.
+
+        let mut pairs = Vec::new();
+        for i in 0..self.items.len()
+        {
+            for j in i + 1..self.items.len()
+            {
+                if self.shared[i][j] > 0 { pairs.push((i, j)); }
+            }
+        }
+        pairs.sort_by(|x, y| self.jaccard(y.0, y.1).partial_cmp(&self.jaccard(x.0, x.1)).unwrap()
+                      .then(self.shared[y.0][y.1].cmp(&self.shared[x.0][x.1])));
+
+        format!("{:<32} {:<32} {:>8} {:>8}\n", "Item", "Item", "Shared", "Jaccard")
+            + &pairs.iter().take(N_TABLE_ROWS)
+                .map(|(i, j)| format!("{:<32} {:<32} {:>8} {:>8.3}\n",
+                                      self.items[*i], self.items[*j], self.shared[*i][*j], self.jaccard(*i, *j)))
+                .collect::<String>()
+    }
+
+    pub fn to_csv(&self, delimiter: u8) -> String
+    {
+        let mut rows = vec![ csv_header(&[ "Item", "Other item", "Shared authors", "Jaccard" ]) ];
+
+        for i in 0..self.items.len()
+        {
+            for j in 0..self.items.len()
+            {
+                rows.push(vec![ self.items[i].clone(), self.items[j].clone(),
+                                self.shared[i][j].to_string(), format!("{:.4}", self.jaccard(i, j)) ]);
+            }
+        }
+
+        format_csv(&rows, delimiter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlap_of_top_items() {
+        let row = |a: &str, b: &str, n| (a.to_string(), b.to_string(), n);
+        let overlap = AuthorOverlap::new(&[ row("gtk", "gtk", 10), row("glib", "glib", 6), row("tiny", "tiny", 1),
+                                            row("gtk", "glib", 4), row("glib", "gtk", 4),
+                                            row("gtk", "tiny", 1), row("tiny", "gtk", 1) ], 2);
+
+        assert_eq!(overlap.items, vec!["gtk", "glib"]);
+        assert_eq!(overlap.shared, vec![vec![10, 4], vec![4, 6]]);
+        assert!((overlap.jaccard(0, 1) - 4.0 / 12.0).abs() < 1e-9);
+        assert_eq!(overlap.to_csv(b'|').lines().nth(2), Some("gtk|glib|4|0.3333"));
+    }
+}
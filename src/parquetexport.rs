@@ -0,0 +1,172 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+/* -------------- *
+ * Parquet export *
+ * -------------- */
+
+use std::path::*;
+use crate::errors::*;
+
+// One column of a table to be written, with its values in row order.
+// Optional columns are stored as nullable.
+
+#[cfg_attr(not(feature = "parquet"), allow(dead_code))]
+pub enum ParquetColumn
+{
+    Str(Vec<String>),
+    OptStr(Vec<Option<String>>),
+    Int(Vec<i32>),
+    OptInt(Vec<Option<i32>>),
+    Long(Vec<i64>),
+    Double(Vec<f64>)
+}
+
+#[cfg(feature = "parquet")]
+impl ParquetColumn
+{
+    fn schema_field(&self, name: &str) -> String
+    {
+        match self
+        {
+            ParquetColumn::Str(_) => format!("required binary {} (UTF8);", name),
+            ParquetColumn::OptStr(_) => format!("optional binary {} (UTF8);", name),
+            ParquetColumn::Int(_) => format!("required int32 {};", name),
+            ParquetColumn::OptInt(_) => format!("optional int32 {};", name),
+            ParquetColumn::Long(_) => format!("required int64 {};", name),
+            ParquetColumn::Double(_) => format!("required double {};", name)
+        }
+    }
+
+    fn len(&self) -> usize
+    {
+        match self
+        {
+            ParquetColumn::Str(v) => v.len(),
+            ParquetColumn::OptStr(v) => v.len(),
+            ParquetColumn::Int(v) => v.len(),
+            ParquetColumn::OptInt(v) => v.len(),
+            ParquetColumn::Long(v) => v.len(),
+            ParquetColumn::Double(v) => v.len()
+        }
+    }
+}
+
+// Rows per row group. Readers like Spark parallelize over row groups, so
+// big exports shouldn't end up as a single one.
+
+#[cfg(feature = "parquet")]
+const ROWS_PER_GROUP: usize = 1 << 20;
+
+#[cfg(feature = "parquet")]
+pub fn write_parquet(out_path: &Path, table_name: &str, columns: &[(&str, ParquetColumn)]) -> Result<()>
+{
+    use parquet::basic::Compression;
+    use parquet::data_type::{ ByteArray, ByteArrayType, DoubleType, Int32Type, Int64Type };
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+    use std::sync::Arc;
+
+    let schema = format!("message {} {{ {} }}", table_name,
+                         columns.iter().map(|(name, col)| col.schema_field(name)).collect::<Vec<String>>().join(" "));
+    let schema = Arc::new(parse_message_type(&schema).chain_err(|| "Invalid Parquet schema")?);
+    let props = Arc::new(WriterProperties::builder().set_compression(Compression::SNAPPY).build());
+    let file = std::fs::File::create(out_path).chain_err(|| "Could not create Parquet file")?;
+    let mut writer = SerializedFileWriter::new(file, schema, props).chain_err(|| "Could not write Parquet file")?;
+    let n_rows = columns.first().map_or(0, |(_, col)| col.len());
+
+    // Nullable columns get a definition level per value; 0 means null.
+
+    fn def_levels<T>(values: &[Option<T>]) -> Vec<i16>
+    {
+        values.iter().map(|v| if v.is_some() { 1 } else { 0 }).collect()
+    }
+
+    let mut start = 0;
+    while start < n_rows || (start == 0 && n_rows == 0)
+    {
+        let end = (start + ROWS_PER_GROUP).min(n_rows);
+        let mut row_group = writer.next_row_group().chain_err(|| "Could not write Parquet file")?;
+
+        for (_, col) in columns
+        {
+            let mut cw = row_group.next_column().chain_err(|| "Could not write Parquet file")?
+                .chain_err(|| "Parquet schema has too few columns")?;
+
+            match col
+            {
+                ParquetColumn::Str(v) =>
+                {
+                    let values: Vec<ByteArray> = v[start..end].iter().map(|s| ByteArray::from(s.as_str())).collect();
+                    cw.typed::<ByteArrayType>().write_batch(&values, None, None)
+                },
+                ParquetColumn::OptStr(v) =>
+                {
+                    let values: Vec<ByteArray> = v[start..end].iter().flatten().map(|s| ByteArray::from(s.as_str())).collect();
+                    cw.typed::<ByteArrayType>().write_batch(&values, Some(&def_levels(&v[start..end])), None)
+                },
+                ParquetColumn::Int(v) => cw.typed::<Int32Type>().write_batch(&v[start..end], None, None),
+                ParquetColumn::OptInt(v) =>
+                {
+                    let values: Vec<i32> = v[start..end].iter().flatten().cloned().collect();
+                    cw.typed::<Int32Type>().write_batch(&values, Some(&def_levels(&v[start..end])), None)
+                },
+                ParquetColumn::Long(v) => cw.typed::<Int64Type>().write_batch(&v[start..end], None, None),
+                ParquetColumn::Double(v) => cw.typed::<DoubleType>().write_batch(&v[start..end], None, None)
+            }.chain_err(|| "Could not write Parquet column")?;
+
+            cw.close().chain_err(|| "Could not write Parquet column")?;
+        }
+
+        row_group.close().chain_err(|| "Could not write Parquet file")?;
+
+        if end == n_rows { break; }
+        start = end;
+    }
+
+    writer.close().chain_err(|| "Could not finish Parquet file")?;
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+pub fn write_parquet(_out_path: &Path, _table_name: &str, _columns: &[(&str, ParquetColumn)]) -> Result<()>
+{
+    bail!("Parquet support is not enabled; rebuild with --features parquet")
+}
+
+#[cfg(all(test, feature = "parquet"))]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{ FileReader, SerializedFileReader };
+
+    #[test]
+    fn writes_nullable_columns() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("t.parquet");
+
+        write_parquet(&path, "t", &[ ("name", ParquetColumn::Str(vec![ "a".to_string(), "b".to_string() ])),
+                                     ("week", ParquetColumn::OptStr(vec![ None, Some("2020-W53".to_string()) ])),
+                                     ("n", ParquetColumn::Long(vec![ 1, 2 ])) ]).unwrap();
+
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let rows: Vec<String> = reader.get_row_iter(None).unwrap().map(|r| r.unwrap().to_string()).collect();
+        assert_eq!(rows, vec![ "{name: \"a\", week: null, n: 1}", "{name: \"b\", week: \"2020-W53\", n: 2}" ]);
+    }
+}
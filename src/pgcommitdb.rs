@@ -0,0 +1,581 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* ---------- *
+ * PgCommitDb *
+ * ---------- */
+
+use chrono::{ Datelike, DateTime, TimeZone, Utc };
+use postgres::{ Client, NoTls, Row, Transaction };
+use postgres::types::Type;
+use rusqlite::types::Value;
+use crate::commitdb::{ CommitDb, commit_type, email_to_domain };
+use crate::commitstore::CommitStore;
+use crate::errors::*;
+use crate::gitcommitreader::{ RawCommit, RawMerge };
+
+// Ingestion into PostgreSQL. The tables mirror the SQLite ones column for
+// column, so queries against either see the same data. Charts and the other
+// commands that read from a database copy it into an in-memory SQLite one
+// and postprocess that; the columns this fills in (bot flags, countries and
+// so on) are left null here.
+
+pub struct PgCommitDb
+{
+    client: Client
+}
+
+// Kept to syntax SQLite accepts too, so the tests can check that the layout
+// matches CommitDb's.
+
+const SCHEMA: &str = "
+    create table if not exists raw_commits (
+        id text primary key,
+        repo_name text not null,
+        author_name text,
+        author_email text,
+        author_domain text,
+        author_time bigint,
+        author_year int,
+        author_month int,
+        author_day int,
+        committer_name text,
+        committer_email text,
+        committer_domain text,
+        committer_time bigint,
+        n_insertions int,
+        n_deletions int,
+        show_domain bool,
+        subject text,
+        has_body bool,
+        is_revert bool,
+        is_reverted bool,
+        is_bot bool,
+        is_cherry_pick bool,
+        in_excluded_span bool not null default false,
+        author_country text,
+        author_epoch text,
+        commit_type text,
+        author_year_local int,
+        author_month_local int,
+        author_year_utc int,
+        author_month_utc int,
+        author_utc_offset int,
+        author_day_local int,
+        author_day_utc int);
+    create index if not exists index_repo_name on raw_commits (repo_name);
+    create index if not exists index_author_name on raw_commits (author_name);
+    create index if not exists index_author_email on raw_commits (author_email);
+    create index if not exists index_author_domain on raw_commits (author_domain);
+    create index if not exists index_author_time on raw_commits (author_time);
+    create index if not exists index_author_year on raw_commits (author_year);
+    create index if not exists index_author_month on raw_commits (author_month);
+
+    create table if not exists db_meta (
+        key text primary key,
+        value text);
+
+    create table if not exists repos (
+        repo_name text primary key,
+        branches text,
+        changes_unreliable bool);
+
+    create table if not exists commit_repos (
+        commit_id text,
+        repo_name text,
+        primary key (commit_id, repo_name));
+
+    create table if not exists commit_parents (
+        commit_id text,
+        parent_id text,
+        parent_index int,
+        primary key (commit_id, parent_index));
+    create index if not exists index_parent_id on commit_parents (parent_id);
+
+    create table if not exists ingest_watermarks (
+        repo_name text primary key,
+        last_author_time bigint,
+        completed_at text);
+
+    create table if not exists merges (
+        id text primary key,
+        repo_name text not null,
+        author_time bigint,
+        author_year int,
+        author_month int);
+
+    create table if not exists prefixes (
+        commit_id text,
+        prefix text,
+        n_changes int);
+    create index if not exists index_prefix_commit_id on prefixes (commit_id);
+
+    create table if not exists suffix_names (
+        id serial primary key,
+        suffix text unique);
+
+    create table if not exists suffix_stats (
+        commit_id text,
+        suffix_id int,
+        n_changes int,
+        n_insertions int,
+        n_deletions int);
+    create index if not exists index_suffix_id on suffix_stats (suffix_id);
+    create index if not exists index_suffix_commit_id on suffix_stats (commit_id);
+";
+
+// Columns raw_commits didn't have when it was first created here. Older
+// databases get them added on open.
+
+const ADDED_COLUMNS: &[(&str, &str)] =
+    &[ ("author_day", "int"),
+       ("committer_domain", "text"),
+       ("has_body", "bool"),
+       ("is_bot", "bool"),
+       ("is_cherry_pick", "bool"),
+       ("in_excluded_span", "bool not null default false"),
+       ("author_country", "text"),
+       ("author_epoch", "text"),
+       ("commit_type", "text"),
+       ("author_day_local", "int"),
+       ("author_day_utc", "int") ];
+
+// The tables in SCHEMA, copied over for queries.
+
+const TABLES: &[&str] =
+    &[ "raw_commits", "db_meta", "repos", "commit_repos", "commit_parents", "ingest_watermarks",
+       "merges", "prefixes", "suffix_names", "suffix_stats" ];
+
+const SUFFIXES_VIEW: &str = "
+    select commit_id, suffix, n_changes, n_insertions, n_deletions
+    from suffix_stats, suffix_names
+    where suffix_names.id = suffix_stats.suffix_id";
+
+impl PgCommitDb
+{
+    pub fn connect(url: &str) -> Result<PgCommitDb>
+    {
+        // For looking at what's there without creating or migrating
+        // anything, e.g. on a dry run.
+
+        let client = Client::connect(url, NoTls).chain_err(|| "Failed to connect to database")?;
+        Ok(PgCommitDb { client })
+    }
+
+    pub fn open(url: &str) -> Result<PgCommitDb>
+    {
+        let mut client = Client::connect(url, NoTls).chain_err(|| "Failed to connect to database")?;
+
+        client.batch_execute(SCHEMA).chain_err(|| "Failed to create tables")?;
+
+        for (column, decl) in ADDED_COLUMNS
+        {
+            client.batch_execute(&format!("alter table raw_commits add column if not exists {} {};",
+                                          column, decl))
+                .chain_err(|| "Failed to migrate raw_commits")?;
+        }
+
+        client.batch_execute("create index if not exists index_committer_domain on raw_commits (committer_domain);")
+            .chain_err(|| "Failed to create indexes")?;
+
+        // Suffixes are kept once in suffix_names, as in SQLite. Databases
+        // ingested before that have them as text in every row.
+
+        let n: i64 = client.query_one("
+            select count(*) from information_schema.tables
+                where table_schema = current_schema() and table_name = 'suffixes'
+                    and table_type = 'BASE TABLE';", &[])
+            .chain_err(|| "Could not query database")?
+            .get(0);
+
+        if n > 0
+        {
+            client.batch_execute("
+                alter table suffixes add column if not exists n_insertions int;
+                alter table suffixes add column if not exists n_deletions int;
+                insert into suffix_names (suffix)
+                    select distinct suffix from suffixes
+                    on conflict do nothing;
+                insert into suffix_stats (commit_id, suffix_id, n_changes, n_insertions, n_deletions)
+                    select commit_id, suffix_names.id, n_changes, n_insertions, n_deletions
+                    from suffixes, suffix_names
+                    where suffix_names.suffix = suffixes.suffix;
+                drop table suffixes;
+            ").chain_err(|| "Failed to migrate suffixes")?;
+        }
+
+        client.batch_execute(&format!("create or replace view suffixes as {};", SUFFIXES_VIEW))
+            .chain_err(|| "Failed to create views")?;
+
+        Ok(PgCommitDb { client })
+    }
+
+    fn set_db_meta(&mut self, key: &str, value: &str) -> Result<()>
+    {
+        self.client.execute("
+            insert into db_meta (key, value) values ($1, $2)
+                on conflict (key) do update set value = excluded.value;",
+            &[&key, &value])
+            .chain_err(|| "Failed to update database metadata")?;
+
+        Ok(())
+    }
+}
+
+impl CommitStore for PgCommitDb
+{
+    fn insert_raw_commit(&mut self, commit: &RawCommit) -> Result<()>
+    {
+        let (author_time, author_year, author_month, author_day,
+             author_year_utc, author_month_utc, author_day_utc, author_utc_offset) =
+            match commit.author_time
+            {
+                Some(t) =>
+                {
+                    let t_utc = t.with_timezone(&Utc);
+                    (t.timestamp(), t.year(), t.month0() as i32, t.day0() as i32,
+                     t_utc.year(), t_utc.month0() as i32, t_utc.day0() as i32,
+                     Some(t.offset().local_minus_utc()))
+                },
+                None => (0, 1970, 0, 0, 1970, 0, 0, None)
+            };
+        let committer_time = commit.committer_time.map(|t| t.timestamp()).unwrap_or(0);
+
+        // Everything for one commit goes in together, like a replace in the
+        // SQLite backend.
+
+        let mut tx = self.client.transaction().chain_err(|| "Failed to begin transaction")?;
+
+        tx.execute("
+            insert into raw_commits (
+                id, repo_name, author_name, author_email, author_domain,
+                author_time, author_year, author_month,
+                committer_name, committer_email, committer_time,
+                n_insertions, n_deletions, show_domain, subject, is_revert,
+                author_year_local, author_month_local, author_year_utc, author_month_utc,
+                author_utc_offset, committer_domain, has_body,
+                author_day, author_day_local, author_day_utc, commit_type
+            ) values
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, true, $14, $15, $7, $8, $16, $17, $18, $19, $20,
+             $21, $21, $22, $23)
+            on conflict (id) do update set
+                repo_name = excluded.repo_name,
+                author_name = excluded.author_name,
+                author_email = excluded.author_email,
+                author_domain = excluded.author_domain,
+                author_time = excluded.author_time,
+                author_year = excluded.author_year,
+                author_month = excluded.author_month,
+                committer_name = excluded.committer_name,
+                committer_email = excluded.committer_email,
+                committer_domain = excluded.committer_domain,
+                committer_time = excluded.committer_time,
+                n_insertions = excluded.n_insertions,
+                n_deletions = excluded.n_deletions,
+                show_domain = true,
+                subject = excluded.subject,
+                has_body = excluded.has_body,
+                is_revert = excluded.is_revert,
+                is_reverted = null,
+                author_year_local = excluded.author_year_local,
+                author_month_local = excluded.author_month_local,
+                author_year_utc = excluded.author_year_utc,
+                author_month_utc = excluded.author_month_utc,
+                author_utc_offset = excluded.author_utc_offset,
+                author_day = excluded.author_day,
+                author_day_local = excluded.author_day_local,
+                author_day_utc = excluded.author_day_utc,
+                commit_type = excluded.commit_type;",
+            &[&commit.id, &commit.repo_name, &commit.author_name, &commit.author_email,
+              &email_to_domain(&commit.author_email),
+              &author_time, &author_year, &author_month,
+              &commit.committer_name, &commit.committer_email, &committer_time,
+              &commit.n_insertions, &commit.n_deletions, &commit.subject, &commit.is_revert,
+              &author_year_utc, &author_month_utc, &author_utc_offset,
+              &email_to_domain(&commit.committer_email), &commit.has_body,
+              &author_day, &author_day_utc, &commit_type(&commit.subject)])
+            .chain_err(|| "Failed to insert commit")?;
+
+        tx.execute("
+            insert into commit_repos (commit_id, repo_name) values ($1, $2)
+                on conflict do nothing;",
+            &[&commit.id, &commit.repo_name])
+            .chain_err(|| "Failed to insert commit repo")?;
+
+        insert_parents(&mut tx, &commit.id, &commit.parents)?;
+
+        tx.execute("delete from prefixes where commit_id = $1;", &[&commit.id])
+            .chain_err(|| "Failed to delete old path stats")?;
+        tx.execute("delete from suffix_stats where commit_id = $1;", &[&commit.id])
+            .chain_err(|| "Failed to delete old path stats")?;
+
+        for (prefix, n_changes) in &commit.n_changes_per_prefix
+        {
+            tx.execute("insert into prefixes (commit_id, prefix, n_changes) values ($1, $2, $3);",
+                       &[&commit.id, prefix, n_changes])
+                .chain_err(|| "Failed to insert prefix stats")?;
+        }
+
+        for (suffix, n_changes) in &commit.n_changes_per_suffix
+        {
+            let n_insertions = commit.n_insertions_per_suffix.get(suffix).cloned().unwrap_or(0);
+            let n_deletions = commit.n_deletions_per_suffix.get(suffix).cloned().unwrap_or(0);
+            tx.execute("insert into suffix_names (suffix) values ($1) on conflict do nothing;", &[suffix])
+                .chain_err(|| "Failed to insert suffix name")?;
+            tx.execute("insert into suffix_stats (commit_id, suffix_id, n_changes, n_insertions, n_deletions)
+                        values ($1, (select id from suffix_names where suffix = $2), $3, $4, $5);",
+                       &[&commit.id, suffix, n_changes, &n_insertions, &n_deletions])
+                .chain_err(|| "Failed to insert suffix stats")?;
+        }
+
+        tx.commit().chain_err(|| "Failed to commit transaction")
+    }
+
+    fn insert_merge(&mut self, merge: &RawMerge) -> Result<()>
+    {
+        let mut tx = self.client.transaction().chain_err(|| "Failed to begin transaction")?;
+
+        tx.execute("
+            insert into merges (id, repo_name, author_time, author_year, author_month)
+                values ($1, $2, $3, $4, $5)
+            on conflict (id) do update set
+                repo_name = excluded.repo_name,
+                author_time = excluded.author_time,
+                author_year = excluded.author_year,
+                author_month = excluded.author_month;",
+            &[&merge.id, &merge.repo_name, &merge.author_time.timestamp(),
+              &merge.author_time.year(), &(merge.author_time.month0() as i32)])
+            .chain_err(|| "Failed to insert merge")?;
+        insert_parents(&mut tx, &merge.id, &merge.parents)?;
+
+        tx.commit().chain_err(|| "Failed to commit transaction")
+    }
+
+    fn get_last_author_time(&mut self, repo_name: &str) -> DateTime<Utc>
+    {
+        let t = self.client.query_opt(
+            "select last_author_time from ingest_watermarks where repo_name = $1;",
+            &[&repo_name])
+            .ok().flatten()
+            .and_then(|r| r.get::<_, Option<i64>>(0))
+            .unwrap_or(0);
+
+        Utc.timestamp_opt(t, 0).unwrap()
+    }
+
+    fn begin_repo_ingest(&mut self, _repo_name: &str) -> Result<()>
+    {
+        // Commits are inserted in transactions of their own, so a partial
+        // ingest stays behind. It's harmless: the watermark isn't moved
+        // until the repo is done, and re-ingested commits replace the old.
+
+        Ok(())
+    }
+
+    fn finish_repo_ingest(&mut self, repo_name: &str) -> Result<()>
+    {
+        self.client.execute("
+            insert into ingest_watermarks (repo_name, last_author_time, completed_at)
+                select $1, max(author_time), $2
+                from raw_commits, commit_repos
+                where commit_repos.commit_id = raw_commits.id
+                    and commit_repos.repo_name = $1
+                on conflict (repo_name) do update
+                    set last_author_time = excluded.last_author_time,
+                        completed_at = excluded.completed_at;",
+            &[&repo_name, &Utc::now().to_rfc3339()])
+            .chain_err(|| "Failed to update ingest watermark")?;
+
+        Ok(())
+    }
+
+    fn get_repo_branches(&mut self, repo_name: &str) -> Result<Option<Vec<String>>>
+    {
+        let row = self.client.query_opt("
+            select branches from repos
+                where repo_name = $1 and branches is not null;",
+            &[&repo_name]).chain_err(|| "Could not query database")?;
+
+        Ok(row.map(|r| r.get::<_, String>(0).lines().map(|b| b.to_string()).collect()))
+    }
+
+    fn set_repo_branches(&mut self, repo_name: &str, branches: &[String]) -> Result<()>
+    {
+        self.client.execute("
+            insert into repos (repo_name, branches) values ($1, $2)
+                on conflict (repo_name) do update set branches = excluded.branches;",
+            &[&repo_name, &branches.join("\n")])
+            .chain_err(|| "Failed to record repository branches")?;
+
+        Ok(())
+    }
+
+    fn set_changes_unreliable(&mut self, repo_name: &str) -> Result<()>
+    {
+        self.client.execute("
+            insert into repos (repo_name, changes_unreliable) values ($1, true)
+                on conflict (repo_name) do update set changes_unreliable = true;",
+            &[&repo_name])
+            .chain_err(|| "Failed to flag repository")?;
+
+        Ok(())
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) -> Result<()>
+    {
+        let current = self.client.query_opt("select value from db_meta where key = 'sample_rate';", &[])
+            .chain_err(|| "Could not query database")?
+            .and_then(|r| r.get::<_, Option<String>>(0))
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(1);
+        let n_commits: i64 = self.client.query_one("select count(*) from raw_commits;", &[])
+            .chain_err(|| "Could not query database")?
+            .get(0);
+
+        if current != sample_rate && n_commits > 0
+        {
+            bail!("Database holds commits sampled at 1/{}; can't add commits sampled at 1/{} \
+                   (use a new database)", current, sample_rate);
+        }
+
+        self.set_db_meta("sample_rate", &sample_rate.to_string())
+    }
+
+    fn mark_ingested(&mut self) -> Result<()>
+    {
+        let now = Utc::now().to_rfc3339();
+
+        self.set_db_meta("last_ingest", &now)?;
+        self.set_db_meta("last_change", &now)
+    }
+
+    fn into_query_db(mut self: Box<Self>) -> Result<CommitDb>
+    {
+        let mut cdb = CommitDb::open(":memory:".into())?;
+
+        for table in TABLES
+        {
+            let stmt = self.client.prepare(&format!("select * from {};", table))
+                .chain_err(|| format!("Could not read {}", table))?;
+            let columns: Vec<String> = stmt.columns().iter().map(|c| c.name().to_string()).collect();
+            let rows: Vec<Vec<Value>> = self.client.query(&stmt, &[])
+                .chain_err(|| format!("Could not read {}", table))?
+                .iter()
+                .map(|r| (0..columns.len()).map(|i| sqlite_value(r, i)).collect())
+                .collect();
+
+            cdb.insert_rows(table, &columns, &rows)?;
+        }
+
+        Ok(cdb)
+    }
+}
+
+fn sqlite_value(row: &Row, i: usize) -> Value
+{
+    // The schema only uses these types. SQLite keeps booleans as integers.
+
+    let ty = row.columns()[i].type_();
+    let value =
+        if *ty == Type::BOOL { row.get::<_, Option<bool>>(i).map(|b| Value::Integer(b as i64)) }
+        else if *ty == Type::INT4 { row.get::<_, Option<i32>>(i).map(|n| Value::Integer(n as i64)) }
+        else if *ty == Type::INT8 { row.get::<_, Option<i64>>(i).map(Value::Integer) }
+        else { row.get::<_, Option<String>>(i).map(Value::Text) };
+
+    value.unwrap_or(Value::Null)
+}
+
+fn insert_parents(tx: &mut Transaction, commit_id: &str, parents: &[String]) -> Result<()>
+{
+    for (i, parent) in parents.iter().enumerate()
+    {
+        tx.execute("
+            insert into commit_parents (commit_id, parent_id, parent_index) values ($1, $2, $3)
+                on conflict (commit_id, parent_index) do update set parent_id = excluded.parent_id;",
+            &[&commit_id, parent, &(i as i32)])
+            .chain_err(|| "Failed to insert commit parent")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::{ Connection, NO_PARAMS };
+    use std::collections::BTreeSet;
+    use crate::commitdb::CommitDb;
+
+    fn table_columns(conn: &Connection) -> Vec<(String, BTreeSet<String>)> {
+        let mut stmt = conn.prepare("
+            select m.name, p.name from sqlite_master as m, pragma_table_info(m.name) as p
+                where m.type in ('table', 'view')
+                order by m.name;").unwrap();
+        let rows = stmt.query_map(NO_PARAMS, |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?))).unwrap();
+        let mut tables: Vec<(String, BTreeSet<String>)> = Vec::new();
+
+        for row in rows {
+            let (table, column) = row.unwrap();
+            match tables.last_mut() {
+                Some((name, columns)) if *name == table => { columns.insert(column); },
+                _ => tables.push((table, vec![ column ].into_iter().collect()))
+            }
+        }
+
+        tables
+    }
+
+    #[test]
+    fn schema_matches_sqlite() {
+        let pg = Connection::open_in_memory().unwrap();
+        pg.execute_batch(SCHEMA).unwrap();
+        pg.execute_batch(&format!("create view suffixes as {};", SUFFIXES_VIEW)).unwrap();
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        CommitDb::open(file.path().to_path_buf()).unwrap();
+        let sqlite_tables = table_columns(&Connection::open(file.path()).unwrap());
+
+        for (table, columns) in table_columns(&pg) {
+            let sqlite_columns = sqlite_tables.iter().find(|(name, _)| *name == table)
+                .map(|(_, columns)| columns);
+            assert_eq!(sqlite_columns, Some(&columns), "{} differs from SQLite", table);
+        }
+    }
+
+    #[test]
+    fn copies_every_table() {
+        let pg = Connection::open_in_memory().unwrap();
+        pg.execute_batch(SCHEMA).unwrap();
+        let tables: BTreeSet<String> = table_columns(&pg).into_iter().map(|(name, _)| name).collect();
+
+        assert_eq!(tables, TABLES.iter().map(|t| t.to_string()).collect());
+    }
+
+    #[test]
+    fn added_columns_are_in_schema() {
+        let pg = Connection::open_in_memory().unwrap();
+        pg.execute_batch(SCHEMA).unwrap();
+        let raw_commits = table_columns(&pg).into_iter().find(|(name, _)| name == "raw_commits").unwrap().1;
+
+        for (column, _) in ADDED_COLUMNS {
+            assert!(raw_commits.contains(*column), "{} missing from raw_commits", column);
+        }
+    }
+}
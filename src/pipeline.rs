@@ -0,0 +1,148 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+/* -------- *
+ * Pipeline *
+ * -------- */
+
+use serde::{Deserialize};
+use std::fs;
+use std::path::*;
+use crate::errors::*;
+use crate::remotedb::{ is_http_url, resolve_db_path };
+
+// A single output of a pipeline: a subcommand run against the pipeline's
+// database, with an optional output path and any further arguments, e.g:
+//
+// { "command": "plot", "out": "authors.png", "args": [ "--unit", "authors" ] }
+
+#[derive(Deserialize, Debug)]
+pub struct PipelineOutput
+{
+    pub command: String,
+    pub out: Option<PathBuf>,
+    #[serde(default)]
+    pub args: Vec<String>
+}
+
+impl PipelineOutput
+{
+    // The argument list as it would be given on the command line, minus
+    // the program name.
+
+    pub fn to_args(&self, db_path: &Path) -> Vec<String>
+    {
+        let mut args = vec![ self.command.clone(), db_path.to_string_lossy().into_owned() ];
+
+        if let Some(out) = &self.out
+        {
+            args.push(out.to_string_lossy().into_owned());
+        }
+
+        args.extend(self.args.iter().cloned());
+        args
+    }
+
+    pub fn describe(&self) -> String
+    {
+        match &self.out
+        {
+            Some(out) => format!("{} {}", self.command, out.display()),
+            None => self.command.clone()
+        }
+    }
+}
+
+// Everything needed to go from repositories to charts in one go, e.g:
+//
+// { "db": "gnome.sqlite", "meta": "gnome-meta.json",
+//   "repos": [ "src/gtk", "src/glib" ], "branches": [ "HEAD" ],
+//   "outputs": [ { "command": "plot", "out": "gnome.png", "args": [ "--cohort", "domain" ] },
+//                { "command": "export-authors", "out": "authors.csv" } ] }
+//
+// Relative paths are resolved against the config file's directory. Paths
+// in "args" are passed through as given.
+
+#[derive(Deserialize, Debug)]
+pub struct PipelineConfig
+{
+    pub db: PathBuf,
+    pub meta: Option<PathBuf>,
+    #[serde(default)]
+    pub repos: Vec<PathBuf>,
+    pub repo_map: Option<PathBuf>,
+    pub branches: Option<Vec<String>>,
+    #[serde(default)]
+    pub message_stats: bool,
+    #[serde(default)]
+    pub recurse_submodules: bool,
+    pub db_profile: Option<String>,
+    #[serde(default)]
+    pub outputs: Vec<PipelineOutput>
+}
+
+impl PipelineConfig
+{
+    pub fn from_file(filename: &Path) -> Result<PipelineConfig>
+    {
+        let content = fs::read_to_string(filename).chain_err(|| "Could not read pipeline config")?;
+        let mut pc: PipelineConfig = serde_json::from_str(&content).chain_err(|| "Failed to parse pipeline config")?;
+        pc.resolve_paths(filename.parent().unwrap_or_else(|| Path::new("")))?;
+        Ok(pc)
+    }
+
+    fn resolve_paths(&mut self, base: &Path) -> Result<()>
+    {
+        self.db = if is_http_url(&self.db) { resolve_db_path(self.db.clone())? }
+                  else { base.join(&self.db) };
+        self.meta = self.meta.as_ref().map(|m| base.join(m));
+        self.repo_map = self.repo_map.as_ref().map(|m| base.join(m));
+
+        for repo in self.repos.iter_mut()
+        {
+            *repo = base.join(&repo);
+        }
+
+        for output in self.outputs.iter_mut()
+        {
+            output.out = output.out.as_ref().map(|o| base.join(o));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_paths_against_config() {
+        let mut pc: PipelineConfig = serde_json::from_str(r#"
+            { "db": "gnome.sqlite", "repos": [ "src/gtk", "/abs/glib" ],
+              "outputs": [ { "command": "plot", "out": "gnome.png", "args": [ "--unit", "authors" ] },
+                           { "command": "check-meta" } ] }"#).unwrap();
+        pc.resolve_paths(Path::new("conf")).unwrap();
+
+        assert_eq!(pc.repos, vec![ PathBuf::from("conf/src/gtk"), PathBuf::from("/abs/glib") ]);
+        assert_eq!(pc.outputs[0].to_args(&pc.db),
+                   vec![ "plot", "conf/gnome.sqlite", "conf/gnome.png", "--unit", "authors" ]);
+        assert_eq!(pc.outputs[1].to_args(&pc.db), vec![ "check-meta", "conf/gnome.sqlite" ]);
+    }
+}
@@ -0,0 +1,163 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* ----------- *
+ * Plot stream *
+ * ----------- */
+
+use serde::{Serialize};
+use std::io::Write;
+use std::path::*;
+use crate::cohorthist::CohortHist;
+use crate::errors::*;
+
+// Plotted histograms can be sent to another program as they're computed.
+// Each record is a four-byte big-endian length followed by that many bytes
+// of JSON: a "begin" record naming the columns, a "bin" record with the
+// values for each interval, and an "end" record.
+
+#[cfg_attr(not(unix), allow(dead_code))]
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum StreamRecord
+{
+    Begin { unit: String, columns: Vec<String> },
+    Bin { period: String, values: Vec<f64> },
+    End
+}
+
+#[cfg_attr(not(unix), allow(dead_code))]
+pub fn hist_records(unit: &str, hist: &CohortHist) -> Vec<StreamRecord>
+{
+    // The same columns as the CSV the plot is drawn from: the sum, then
+    // each cohort in order.
+
+    let columns = std::iter::once("Sum".to_string()).chain(hist.get_cohort_names()).collect();
+
+    std::iter::once(StreamRecord::Begin { unit: unit.to_string(), columns })
+        .chain(hist.to_vecs().into_iter().map(|(ym, values)| StreamRecord::Bin
+        {
+            period: ym.to_string(),
+            values: values.into_iter().map(|(_, value)| value).collect()
+        }))
+        .chain(std::iter::once(StreamRecord::End))
+        .collect()
+}
+
+#[cfg_attr(not(unix), allow(dead_code))]
+pub fn write_hist<W: Write>(out: &mut W, unit: &str, hist: &CohortHist) -> Result<()>
+{
+    for record in hist_records(unit, hist)
+    {
+        let json = serde_json::to_vec(&record).chain_err(|| "Could not serialize stream record")?;
+
+        out.write_all(&(json.len() as u32).to_be_bytes()).chain_err(|| "Could not write to stream")?;
+        out.write_all(&json).chain_err(|| "Could not write to stream")?;
+    }
+
+    out.flush().chain_err(|| "Could not write to stream")
+}
+
+#[cfg(unix)]
+pub fn emit_hist(path: &Path, unit: &str, hist: &CohortHist) -> Result<()>
+{
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixStream;
+
+    // Sockets are connected to, and named pipes opened for writing, which
+    // waits for a reader to show up. Anything else is likely a typo.
+
+    let file_type = std::fs::metadata(path)
+        .chain_err(|| format!("No socket or named pipe at {}", path.display()))?
+        .file_type();
+
+    if file_type.is_socket()
+    {
+        let mut stream = UnixStream::connect(path)
+            .chain_err(|| format!("Could not connect to {}", path.display()))?;
+        write_hist(&mut stream, unit, hist)
+    }
+    else if file_type.is_fifo()
+    {
+        let mut pipe = OpenOptions::new().write(true).open(path)
+            .chain_err(|| format!("Could not open {}", path.display()))?;
+        write_hist(&mut pipe, unit, hist)
+    }
+    else
+    {
+        bail!("{} is not a socket or named pipe", path.display());
+    }
+}
+
+#[cfg(not(unix))]
+pub fn emit_hist(_path: &Path, _unit: &str, _hist: &CohortHist) -> Result<()>
+{
+    bail!("Streaming plots is only supported on Unix")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::os::unix::net::UnixListener;
+    use crate::cohorthist::NO_COHORT;
+    use crate::timebin::TimeBin;
+
+    #[test]
+    fn streams_length_prefixed_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plot.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut hist = CohortHist::new();
+
+        hist.set_value(TimeBin::Year(2020), 0, 2.0);
+        hist.set_value(TimeBin::Year(2021), 1, 3.0);
+        hist.set_value(TimeBin::Year(2021), NO_COHORT, 1.0);
+        hist.set_cohort_name(0, "a");
+        hist.set_cohort_name(1, "b");
+        hist.set_cohort_name(NO_COHORT, "Brief");
+
+        let reader = std::thread::spawn(move || {
+            let mut data = Vec::new();
+            listener.accept().unwrap().0.read_to_end(&mut data).unwrap();
+            data
+        });
+        emit_hist(&path, "Commits", &hist).unwrap();
+        let data = reader.join().unwrap();
+
+        let mut records = Vec::new();
+        let mut rest = &data[..];
+        while !rest.is_empty()
+        {
+            let len = u32::from_be_bytes([ rest[0], rest[1], rest[2], rest[3] ]) as usize;
+            records.push(String::from_utf8(rest[4..4 + len].to_vec()).unwrap());
+            rest = &rest[4 + len..];
+        }
+
+        assert_eq!(records, vec![
+            r#"{"kind":"begin","unit":"Commits","columns":["Sum","a","b","Brief"]}"#,
+            r#"{"kind":"bin","period":"2020","values":[2.0,2.0,0.0,0.0]}"#,
+            r#"{"kind":"bin","period":"2021","values":[4.0,0.0,3.0,1.0]}"#,
+            r#"{"kind":"end"}"# ]);
+
+        assert!(emit_hist(&dir.path().join("missing"), "Commits", &hist).is_err());
+    }
+}
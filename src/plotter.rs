@@ -22,62 +22,38 @@
  * Plotter *
  * ------- */
 
+use chrono::{ Datelike, NaiveDate, Utc };
+use std::collections::BTreeSet;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::Path;
 use std::process::Command;
 use tempfile::NamedTempFile;
 use crate::cohorthist::CohortHist;
+use crate::common::{ AccessibleStyle, ForecastModel, IntervalType };
+use crate::crosstab::DomainCrossTab;
+use crate::csvformat::GNUPLOT_DELIMITER;
 use crate::errors::*;
+use crate::forecast::forecast;
+use crate::lorenz::Lorenz;
+use crate::overlap::AuthorOverlap;
 use crate::projectmeta::ProjectMeta;
+use crate::seasonal::SeasonalProfile;
+use crate::snapshotdiff::SnapshotDiff;
+use crate::timebin::TimeBin;
 
-const GNUPLOT_COHORTS_COMMON: &str = "
-set style line 1 lt 1 lc rgb '#909090';
-set style line 2 lt 1 lc rgb '#505050';
-set style line 3 lt 1 lc rgb '#a6cee3';
-set style line 4 lt 1 lc rgb '#1f78b4';
-set style line 5 lt 1 lc rgb '#c2a5cf';
-set style line 6 lt 1 lc rgb '#9970ab';
-set style line 7 lt 1 lc rgb '#b2df8a';
-set style line 8 lt 1 lc rgb '#33a02c';
-set style line 9 lt 1 lc rgb '#fb9a99';
-set style line 10 lt 1 lc rgb '#e31a1c';
-set style line 11 lt 1 lc rgb '#fdbf6f';
-set style line 12 lt 1 lc rgb '#ff7f00';
-set style line 13 lt 1 lc rgb '#6b3d15';
-set style line 14 lt 1 lc rgb '#bf812d';
-set style line 15 lt 1 lc rgb '#458e81';
-set style line 16 lt 1 lc rgb '#34c0b5';
-set style line 17 lt 1 lc rgb '#40004b';
-set style line 18 lt 1 lc rgb '#762a83';
-set style line 19 lt 1 lc rgb '#00441b';
-set style line 20 lt 1 lc rgb '#1b7837';
-set style line 21 lt 1 lc rgb '#a50026';
-set style line 22 lt 1 lc rgb '#d73027';
-set style line 23 lt 1 lc rgb '#053061';
-set style line 24 lt 1 lc rgb '#2166ac';
-set style line 25 lt 1 lc rgb '#40004b';
-set style line 26 lt 1 lc rgb '#762a83';
-# -- Repeat --
-set style line 27 lt 1 lc rgb '#909090';
-set style line 28 lt 1 lc rgb '#505050';
-set style line 29 lt 1 lc rgb '#a6cee3';
-set style line 30 lt 1 lc rgb '#1f78b4';
-set style line 31 lt 1 lc rgb '#c2a5cf';
-set style line 32 lt 1 lc rgb '#9970ab';
-set style line 33 lt 1 lc rgb '#b2df8a';
-set style line 34 lt 1 lc rgb '#33a02c';
-set style line 35 lt 1 lc rgb '#fb9a99';
-set style line 36 lt 1 lc rgb '#e31a1c';
-set style line 37 lt 1 lc rgb '#fdbf6f';
-set style line 38 lt 1 lc rgb '#ff7f00';
-set style line 39 lt 1 lc rgb '#6b3d15';
-set style line 40 lt 1 lc rgb '#bf812d';
-set style line 41 lt 1 lc rgb '#458e81';
-set style line 42 lt 1 lc rgb '#34c0b5';
-set style line 43 lt 1 lc rgb '#40004b';
-set style line 44 lt 1 lc rgb '#762a83';
-set style line 45 lt 1 lc rgb '#00441b';
+// Cohort colors, in the order cohorts are stacked. Charts with more cohorts
+// than colors cycle through them again.
+const COHORT_COLORS: [&str; 26] =
+    [ "#909090", "#505050", "#a6cee3", "#1f78b4", "#c2a5cf", "#9970ab",
+      "#b2df8a", "#33a02c", "#fb9a99", "#e31a1c", "#fdbf6f", "#ff7f00",
+      "#6b3d15", "#bf812d", "#458e81", "#34c0b5", "#40004b", "#762a83",
+      "#00441b", "#1b7837", "#a50026", "#d73027", "#053061", "#2166ac",
+      "#40004b", "#762a83" ];
+
+// Line styles are defined for this many cohorts.
+const N_COHORT_STYLES: usize = 45;
 
+const GNUPLOT_COHORTS_COMMON: &str = "
 set terminal pngcairo size 2560,1200 enhanced background rgb 'white' font 'Verdana,25';
 set datafile separator '|';
 set rmargin 1.1;
@@ -100,27 +76,389 @@ set key reverse Left horizontal nobox bmargin left width 1.1;
 set ytics textcolor rgb \"0xff000000\" scale 0;
 ";
 
+// Okabe-Ito colors, which stay apart under the common forms of color
+// blindness. Black is left for borders and text.
+const ACCESSIBLE_PALETTE: [&str; 7] =
+    [ "#e69f00", "#56b4e9", "#009e73", "#f0e442", "#0072b2", "#d55e00", "#cc79a7" ];
+
+// Gnuplot fill patterns that read as distinct hatchings. Pattern 3 is solid
+// and would hide the band borders, so it's left out.
+const ACCESSIBLE_PATTERNS: &str = "1 2 4 5 6 7";
+
 pub struct Plotter
 {
+    pub stamp: Option<String>,
+    pub footnote: Option<String>,
+    pub pad_to_now: bool,
+    pub xtick_every: Option<u32>,
+    pub xtick_rotate: Option<i32>,
+    pub fiscal_year_start: Option<u32>,
+    pub accessible: Option<AccessibleStyle>,
+    pub direct_labels: Option<usize>,
+    pub forecast: Option<(ForecastModel, usize)>
 }
 
+// Year labels we can fit across the x axis before they run together.
+const MAX_XTICK_LABELS: i32 = 24;
+
+// Longest range a daily chart can show before the bars get too thin to see.
+const MAX_DAILY_YEARS: i32 = 3;
+
 impl Plotter
 {
+    fn format_captions(&self) -> String
+    {
+        // The stamp goes in the bottom right corner, and any footnote about
+        // the data in the bottom left.
+
+        let stamp = match &self.stamp
+        {
+            Some(text) => format!("set label \"{}\" at screen 0.995, screen 0.015 right front \
+                                   font ',14' tc rgb '#909090' noenhanced;",
+                                  text.replace('"', "'")),
+            None => "".to_string()
+        };
+        let footnote = match &self.footnote
+        {
+            Some(text) => format!("set label \"{}\" at screen 0.005, screen 0.015 left front \
+                                   font ',14' tc rgb '#909090' noenhanced;",
+                                  text.replace('"', "'")),
+            None => "".to_string()
+        };
+
+        stamp + &footnote
+    }
+
+    fn palette(&self) -> &'static [&'static str]
+    {
+        if self.accessible.is_some() { &ACCESSIBLE_PALETTE } else { &COHORT_COLORS }
+    }
+
+    fn format_style_line(&self, style: usize, color: &str) -> String
+    {
+        // The accessible styles draw thick separators between the bands, so
+        // neighbors can be told apart without relying on hue alone.
+
+        format!("set style line {} lt 1 lc rgb '{}'{};",
+                style, color, if self.accessible.is_some() { " lw 3" } else { "" })
+    }
+
+    fn cohorts_setup(&self) -> String
+    {
+        let palette = self.palette();
+        let styles = (1..=N_COHORT_STYLES)
+            .map(|i| self.format_style_line(i, palette[(i - 1) % palette.len()]))
+            .collect::<Vec<String>>()
+            .join("\n");
+        let fill = match self.accessible
+        {
+            Some(AccessibleStyle::Patterns) => "set style fill pattern border lc rgb 'black';",
+            Some(AccessibleStyle::Palette) => "set style fill solid border lc rgb 'white';",
+            None => ""
+        };
+
+        format!("{}{}{}", styles, GNUPLOT_COHORTS_COMMON, fill)
+    }
+
+    fn format_group_styles(&self, hist: &CohortHist) -> String
+    {
+        // Nested cohorts get shades of their group's color, from full
+        // strength for the first one to a lighter tint for the last, so
+        // e.g. the domains in a first-year generation read as one band.
+
+        let (first_cohort, last_cohort) = match hist.get_bounds()
+        {
+            Some((_, _, first, last)) => (first, last),
+            None => return "".to_string()
+        };
+        let groups: Vec<i32> = (first_cohort..=last_cohort)
+            .filter_map(|cohort| hist.get_cohort_group(cohort))
+            .collect::<BTreeSet<i32>>()
+            .into_iter()
+            .collect();
+        let palette = self.palette();
+
+        (first_cohort..=last_cohort)
+            .filter_map(|cohort|
+            {
+                let group = hist.get_cohort_group(cohort)?;
+                let members: Vec<i32> = (first_cohort..=last_cohort)
+                    .filter(|c| hist.get_cohort_group(*c) == Some(group))
+                    .collect();
+                let shade = members.iter().position(|c| *c == cohort).unwrap() as f64 / members.len() as f64;
+                let base = palette[groups.binary_search(&group).unwrap() % palette.len()];
+
+                Some(self.format_style_line((cohort - first_cohort + 1) as usize, &lighten(base, 0.6 * shade)))
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn band_style(&self, column: &str, first_col: usize) -> String
+    {
+        // Per-band fill for stacked plots; only the pattern style needs
+        // one, cycling through the patterns as the palette cycles colors.
+
+        match self.accessible
+        {
+            Some(AccessibleStyle::Patterns) =>
+                format!("fs pattern int(word('{}', ({}-{})%{}+1)) border lc rgb 'black'",
+                        ACCESSIBLE_PATTERNS, column, first_col,
+                        ACCESSIBLE_PATTERNS.split(' ').count()),
+            _ => "".to_string()
+        }
+    }
+
+    fn format_direct_labels(&self, hist: &CohortHist, first_row: i64, last_row: i64) -> String
+    {
+        // Name the largest bands in the visible rows on the chart itself,
+        // in the middle of the band where it's thickest.
+
+        let n = match self.direct_labels
+        {
+            Some(n) => n,
+            None => return "".to_string()
+        };
+        let rows: Vec<(i64, Vec<(i32, f64)>)> = hist.to_vecs().into_iter()
+            .enumerate()
+            .map(|(row, (_, values))| (row as i64, values))
+            .filter(|(row, _)| *row >= first_row && *row <= last_row)
+            .collect();
+        if rows.is_empty() { return "".to_string(); }
+
+        // The first value in each row is the sum; the bands follow in
+        // stacking order.
+
+        let mut totals: Vec<(usize, f64)> = (1..rows[0].1.len())
+            .map(|band| (band, rows.iter().map(|(_, values)| values[band].1).sum()))
+            .collect();
+        totals.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+
+        totals.iter()
+            .take(n)
+            .filter(|(_, total)| *total > 0.0)
+            .map(|(band, _)|
+            {
+                let (row, values) = rows.iter()
+                    .max_by(|a, b| a.1[*band].1.partial_cmp(&b.1[*band].1).unwrap())
+                    .unwrap();
+                let base: f64 = values[1..*band].iter().map(|(_, v)| v).sum();
+
+                format!("set label '{}' center at {}, {} front noenhanced tc rgb 'black' boxed;",
+                        hist.get_cohort_name(values[*band].0).replace('\'', "''"),
+                        row, base + values[*band].1 / 2.0)
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    pub fn year_range(&self, meta: &ProjectMeta, hist: &CohortHist, interval: IntervalType,
+                      first_year: Option<i32>, last_year: Option<i32>) -> (i32, i32)
+    {
+        // The years a cohort chart will show, unless given explicitly.
+
+        let bounds = hist.get_bounds().unwrap();
+        let first_year = first_year.or(meta.first_year).unwrap_or(bounds.0.year());
+        let last_year = last_year.or(meta.last_year).unwrap_or_else(|| {
+            // The last year is usually incomplete, so leave it out of yearly
+            // charts unless we were explicitly asked to run up to the present.
+            if !matches!(interval, IntervalType::Year) || bounds.0.year() == bounds.1.year() || self.pad_to_now
+            {
+                bounds.1.year()
+            }
+            else { bounds.1.year() - 1 }
+        });
+
+        (first_year, last_year)
+    }
+
+    fn xtick_layout(&self, first_year: i32, last_year: i32) -> (i32, i32)
+    {
+        self.xtick_layout_for(last_year - first_year + 1)
+    }
+
+    fn xtick_layout_for(&self, n_labels: i32) -> (i32, i32)
+    {
+        // Label every nth year (or month, in daily charts), and rotate the
+        // labels if there are still too many of them. Both can be overridden.
+
+        let every = self.xtick_every.map(|n| n.max(1) as i32)
+            .unwrap_or_else(|| (n_labels + MAX_XTICK_LABELS - 1) / MAX_XTICK_LABELS)
+            .max(1);
+        let rotate = self.xtick_rotate
+            .unwrap_or(if n_labels / every > MAX_XTICK_LABELS { 45 } else { 0 });
+
+        (every, rotate)
+    }
+
+    fn year_label(&self) -> &'static str
+    {
+        // Gnuplot expression for the label of a yearly row. Fiscal years
+        // get a prefix so they aren't mistaken for calendar years.
+
+        match self.fiscal_year_start
+        {
+            Some(_) => "sprintf(\"FY%s\", stringcolumn(1))",
+            None => "stringcolumn(1)"
+        }
+    }
+
+    fn fiscal_month_shift(&self) -> i32
+    {
+        // Months from the start of a calendar year to the start of the
+        // fiscal year named after it, i.e. how far to move a calendar date
+        // to place it on a fiscal year axis.
+
+        self.fiscal_year_start.map_or(0, |m| (13 - m as i32) % 12)
+    }
+
+    fn format_xtick_rotation(rotate: i32) -> String
+    {
+        // Labels hang from their tick: counterclockwise rotations end at the
+        // tick, clockwise ones start there.
+
+        if rotate == 0 { "".to_string() }
+        else { format!("set xtics rotate by {} {} offset 0,0;", rotate, if rotate > 0 { "right" } else { "left" }) }
+    }
+
+    fn run_gnuplot(&self, gnuplot_cmd: &str) -> Result<()>
+    {
+        let mut file = NamedTempFile::new().chain_err(|| "Could not write gnuplot script")?;
+        writeln!(file, "{}", gnuplot_cmd).chain_err(|| "Could not write gnuplot script")?;
+
+        // println!("{}", gnuplot_cmd);
+
+        let output = Command::new("gnuplot")
+            .arg(file.path())
+            .output()
+            .chain_err(|| "Failed to execute gnuplot")?;
+
+        match output.status.success()
+        {
+            false => { Err(format!("In program: {}
+Gnuplot reported error: {}",
+                                   gnuplot_cmd,
+                                   String::from_utf8_lossy(&output.stderr)).into()) },
+            true => { Ok(()) }
+        }
+    }
+
+    fn format_spans<F>(meta: &ProjectMeta, bin: F) -> String
+        where F: Fn(NaiveDate) -> f32
+    {
+        Plotter::format_epochs(meta, &bin) + &Plotter::format_excluded(meta, &bin)
+    }
+
+    fn format_epochs<F>(meta: &ProjectMeta, bin: F) -> String
+        where F: Fn(NaiveDate) -> f32
+    {
+        // Shade every other epoch, so that eras like "CVS" and "git" can be
+        // told apart, and name each one at the top. Epochs that are still
+        // going are shaded up to today; the rest is clipped by the x range.
+
+        let today = Utc::now().date_naive().succ_opt().unwrap();
+
+        meta.epoch_ranges().iter().enumerate()
+            .map(|(i, (begin, end, name))|
+            {
+                let x0 = bin(*begin) - 0.5;
+                let x1 = bin(end.unwrap_or(today).pred_opt().unwrap()) + 0.5;
+
+                let shade = if i % 2 == 1 { "".to_string() } else { format!("
+                    set object rect from {x0}, graph 0 to {x1}, graph 1 \
+                        behind fillstyle transparent solid 0.06 noborder fc rgb '#000000';",
+                    x0 = x0, x1 = x1) };
+
+                shade + &format!("
+                    set label '{}' left at {}, graph 1 offset 0.5,-1 front tc rgb '#606060';",
+                        name.replace('\'', "''"), x0)
+            })
+            .collect()
+    }
+
+    fn format_excluded<F>(meta: &ProjectMeta, bin: F) -> String
+        where F: Fn(NaiveDate) -> f32
+    {
+        // Hatch the bins of excluded intervals, which have been zeroed, and
+        // say why. The bin closure gives the x position of the bin a day
+        // falls in.
+
+        meta.excluded_ranges().iter()
+            .map(|(begin, end, text)|
+            {
+                let x0 = bin(*begin) - 0.5;
+                let x1 = bin(end.pred_opt().unwrap()) + 0.5;
+
+                format!("
+                    set object rect from {x0}, graph 0 to {x1}, graph 1 \
+                        behind fillstyle pattern 4 noborder fc rgb '#a0a0a0';",
+                    x0 = x0, x1 = x1)
+                    + &text.as_ref().map(|text| format!("
+                    set label '{}' center at {}, graph 0.5 rotate by 90 front tc ls 0 boxed;",
+                        text.replace('\'', "''"), (x0 + x1) / 2.0)).unwrap_or_default()
+            })
+            .collect()
+    }
+
+    fn format_forecast(&self, meta: &ProjectMeta, hist: &CohortHist, first_row: i64,
+                       period: usize) -> (String, String, i64)
+    {
+        // Extrapolate the total from the shown rows, and draw it as a
+        // dashed step line on a shaded background past the last row. Gives
+        // the setup to run before the first plot, the plot itself and the
+        // last row it reaches.
+
+        let (model, n) = match self.forecast
+        {
+            Some(forecast) => forecast,
+            None => return ("".to_string(), "".to_string(), 0)
+        };
+        let series: Vec<f64> = hist.to_vecs().into_iter()
+            .skip(first_row.max(0) as usize)
+            .map(|(_, values)| values[0].1)
+            .collect();
+        let last_row = first_row.max(0) + series.len() as i64 - 1;
+        let projected = forecast(&series, model, period, n);
+        let rows: Vec<String> = series.last().iter().map(|v| **v)
+            .chain(projected.iter().cloned())
+            .enumerate()
+            .map(|(i, value)| format!("{}{}{}", last_row + i as i64, GNUPLOT_DELIMITER as char, value))
+            .collect();
+        let y_max = projected.iter().cloned().fold(0.0, f64::max);
+
+        let setup = format!("
+            set yrange [:{y_max}<*] writeback;
+            set object rect from {x0}, graph 0 to {x1}, graph 1 behind fillstyle solid noborder fc rgb '#f0f0f0';",
+            y_max = y_max,
+            x0 = last_row as f32 + 0.5,
+            x1 = (last_row + n as i64) as f32 + 0.5);
+        let plot = format!("
+$forecast << EOD
+{rows}
+EOD
+            set label '{label}' at {x}, graph 0.95 left front tc rgb '#606060' offset 0.5,0 noenhanced;
+            plot '$forecast' using 1:2 with histeps lc rgb 'black' lw 2 dt 2 notitle;",
+            rows = rows.join("\n"),
+            label = meta.label("Projection").replace('\'', "''"),
+            x = last_row as f32 + 0.5);
+
+        (setup, plot, last_row + n as i64)
+    }
+
     pub fn plot_yearly_cohorts(&self,
                                meta: &ProjectMeta,
                                unit: &str,
-                               hist: &CohortHist, out_file: &PathBuf,
+                               hist: &CohortHist, out_file: &Path,
                                first_year: Option<i32>, last_year: Option<i32>) -> Result<()>
     {
         let bounds = hist.get_bounds().unwrap();
-        let first_year = first_year.or(meta.first_year).unwrap_or(bounds.0.year);
-        let last_year = last_year.or(meta.last_year).unwrap_or_else(|| {
-            if bounds.0.year == bounds.1.year { bounds.1.year }
-            else { bounds.1.year - 1 }
-        });
+        let (first_year, last_year) = self.year_range(meta, hist, IntervalType::Year, first_year, last_year);
+        let xtick_layout = self.xtick_layout(first_year, last_year);
         let markers = meta.markers_to_gnuplot();
+        let forecast = self.format_forecast(meta, hist, (first_year - bounds.0.year()) as i64, 1);
         let gnuplot_cmd = format!("
             {gnuplot_setup}
+            {group_styles}
             set style line {last_style_num} lt 1 lc rgb '#ffffd0';
 $data << EOD
 {history}
@@ -128,8 +466,12 @@ EOD
             set output \"{output}\";
             set ylabel \"{ylabel}\";
             set xrange [{xrange_0}:{xrange_1}];
+            {captions}
+            {xtick_rotation}
+            {forecast_setup}
             set multiplot;
-            plot for [i=3:{plot_range}] '$data' using i:xtic(stringcolumn(1)) ls i-2 title columnheader(i);
+            plot for [i=3:{plot_range}] '$data' using i:xtic((int($1) - {first_year}) % {xtick_every} == 0 \
+                ? {year_label} : \"\") ls i-2 {band_style} title columnheader(i);
             unset key;
             set style data histep;
             set xtics textcolor rgb \"0xff000000\" scale 1 0.5,1;
@@ -140,27 +482,40 @@ EOD
             {markers}
             {markers_extra}
             plot '$data' using 2 lc rgb 'black' lw 2 notitle;
+            {forecast_plot}
             unset multiplot;
             ",
-            gnuplot_setup = GNUPLOT_COHORTS_COMMON,
+            gnuplot_setup = self.cohorts_setup(),
+            group_styles = self.format_group_styles(hist),
+            captions = self.format_captions(),
             last_style_num = hist.get_n_cohorts() + 1,
-            history = &hist.to_csv(),
+            history = &hist.to_csv(GNUPLOT_DELIMITER),
             output = out_file.to_string_lossy().into_owned(),
             ylabel = unit,
-            xrange_0 = (first_year - bounds.0.year) as f32 - 0.5,
-            xrange_1 = (last_year - bounds.0.year) as f32 + 0.5,
+            first_year = first_year,
+            year_label = self.year_label(),
+            xtick_every = xtick_layout.0,
+            xtick_rotation = Plotter::format_xtick_rotation(xtick_layout.1),
+            xrange_0 = (first_year - bounds.0.year()) as f32 - 0.5,
+            xrange_1 = ((last_year - bounds.0.year()) as i64).max(forecast.2) as f32 + 0.5,
+            forecast_setup = forecast.0,
+            forecast_plot = forecast.1,
             plot_range = hist.get_n_cohorts() + 3,
-            markers = &markers.0,
+            band_style = self.band_style("i", 3),
+            markers = markers.0 + &Plotter::format_spans(meta, |date| (date.year() - bounds.0.year()) as f32)
+                + &self.format_direct_labels(hist, (first_year - bounds.0.year()) as i64,
+                                             (last_year - bounds.0.year()) as i64),
             markers_extra = if markers.1 > 0
             {
                 format!("
                     set for [i=0:{}:1] label left markers[int(i)*4+4] \
-                        at ((markers[int(i)*4+1]+{})*12+(markers[int(i)*4+2]-1))/12.0-(1.1/2.0), \
+                        at ((markers[int(i)*4+1]+{})*12+(markers[int(i)*4+2]-1)+{})/12.0-(1.1/2.0), \
                            (0.977-0.05*markers[int(i)*4+3])*GPVAL_Y_MAX \
                            front tc ls 0 boxed;
                     ",
                     markers.1 - 1,
-                    - bounds.0.year)
+                    - bounds.0.year(),
+                    self.fiscal_month_shift())
             }
             else
             {
@@ -168,44 +523,23 @@ EOD
             }
         );
 
-        let mut file = NamedTempFile::new().chain_err(|| "Could not write gnuplot script")?;
-        writeln!(file, "{}", gnuplot_cmd).chain_err(|| "Could not write gnuplot script")?;
-
-        // println!("{}", gnuplot_cmd);
-
-        let output = Command::new("gnuplot")
-            .arg(file.path())
-            .output()
-            .chain_err(|| "Failed to execute gnuplot")?;
-
-        match output.status.success()
-        {
-            false => { Err(format!("In program: {}
-Gnuplot reported error: {}",
-                                   gnuplot_cmd,
-                                   String::from_utf8_lossy(&output.stderr)).into()) },
-            true => { Ok(()) }
-        }
+        self.run_gnuplot(&gnuplot_cmd)
     }
 
     pub fn plot_monthly_cohorts(&self,
                                 meta: &ProjectMeta,
                                 unit: &str,
-                                hist: &CohortHist, out_file: &PathBuf,
+                                hist: &CohortHist, out_file: &Path,
                                 first_year: Option<i32>, last_year: Option<i32>) -> Result<()>
     {
         let bounds = hist.get_bounds().unwrap();
-        let first_year =
-            if first_year.is_some() { first_year.unwrap() }
-            else if meta.first_year.is_some() { meta.first_year.unwrap() }
-            else { bounds.0.year };
-        let last_year =
-            if last_year.is_some() { last_year.unwrap() }
-            else if meta.last_year.is_some() { meta.last_year.unwrap() }
-            else { bounds.1.year };
+        let (first_year, last_year) = self.year_range(meta, hist, IntervalType::Month, first_year, last_year);
+        let xtick_layout = self.xtick_layout(first_year, last_year);
         let markers = meta.markers_to_gnuplot();
+        let forecast = self.format_forecast(meta, hist, ((first_year - bounds.0.year()) * 12) as i64, 12);
         let gnuplot_cmd = format!("
             {gnuplot_setup}
+            {group_styles}
             set style line {last_style_num} lt 1 lc rgb '#ffffd0';
 $data << EOD
 {history}
@@ -213,9 +547,12 @@ EOD
             set output \"{output}\";
             set ylabel \"{ylabel}\";
             set xrange [{xrange_0}:{xrange_1}];
+            {captions}
+            {xtick_rotation}
+            {forecast_setup}
             set multiplot;
-            plot for [i=4:{plot_range}] '$data' using i:xtic($2==\"06\" \
-                ? stringcolumn(1) : \"\") ls i-3 title columnheader(i);
+            plot for [i=4:{plot_range}] '$data' using i:xtic($2==\"06\" && (int($1) - {first_year}) % {xtick_every} == 0 \
+                ? stringcolumn(1) : \"\") ls i-3 {band_style} title columnheader(i);
             unset key;
             set style data histep;
             set xtics scale 1 11.5,12 textcolor black;
@@ -227,17 +564,29 @@ EOD
             {markers}
             {markers_extra}
             plot '$data' using 3 lc rgb 'black' lw 2 notitle;
+            {forecast_plot}
             unset multiplot;
             ",
-            gnuplot_setup = GNUPLOT_COHORTS_COMMON,
+            gnuplot_setup = self.cohorts_setup(),
+            group_styles = self.format_group_styles(hist),
+            captions = self.format_captions(),
             last_style_num = hist.get_n_cohorts() + 1,
-            history = &hist.to_csv(),
+            history = &hist.to_csv(GNUPLOT_DELIMITER),
             output = out_file.to_string_lossy().into_owned(),
             ylabel = unit,
-            xrange_0 = ((first_year - bounds.0.year) * 12) as f32 - 0.5,
-            xrange_1 = ((last_year - bounds.0.year) * 12 + 12) as f32 - 0.5,
+            first_year = first_year,
+            xtick_every = xtick_layout.0,
+            xtick_rotation = Plotter::format_xtick_rotation(xtick_layout.1),
+            xrange_0 = ((first_year - bounds.0.year()) * 12) as f32 - 0.5,
+            xrange_1 = (((last_year - bounds.0.year()) * 12 + 11) as i64).max(forecast.2) as f32 + 0.5,
+            forecast_setup = forecast.0,
+            forecast_plot = forecast.1,
             plot_range = hist.get_n_cohorts() + 4,
-            markers = &markers.0,
+            band_style = self.band_style("i", 4),
+            markers = markers.0 + &Plotter::format_spans(meta, |date| ((date.year() - bounds.0.year()) * 12
+                                                                          + date.month0() as i32) as f32)
+                + &self.format_direct_labels(hist, ((first_year - bounds.0.year()) * 12) as i64,
+                                             ((last_year - bounds.0.year()) * 12 + 11) as i64),
             markers_extra = if markers.1 > 0
             {
                 format!("
@@ -247,7 +596,7 @@ EOD
                            front tc ls 0 boxed;
                     ",
                     markers.1 - 1,
-                    - bounds.0.year)
+                    - bounds.0.year())
             }
             else
             {
@@ -255,23 +604,796 @@ EOD
             }
         );
 
-        let mut file = NamedTempFile::new().chain_err(|| "Could not write gnuplot script")?;
-        writeln!(file, "{}", gnuplot_cmd).chain_err(|| "Could not write gnuplot script")?;
-
-        // println!("{}", gnuplot_cmd);
+        self.run_gnuplot(&gnuplot_cmd)
+    }
 
-        let output = Command::new("gnuplot")
-            .arg(file.path())
-            .output()
-            .chain_err(|| "Failed to execute gnuplot")?;
+    pub fn plot_daily_cohorts(&self,
+                              meta: &ProjectMeta,
+                              unit: &str,
+                              hist: &CohortHist, out_file: &Path,
+                              first_year: Option<i32>, last_year: Option<i32>) -> Result<()>
+    {
+        // Like the monthly charts, with one bar per day. Months vary in
+        // length, so ticks and markers are placed by date instead of at a
+        // fixed period. Rows start on January 1st of the first year.
 
-        match output.status.success()
+        let bounds = hist.get_bounds().unwrap();
+        let (first_year, last_year) = self.year_range(meta, hist, IntervalType::Day, first_year, last_year);
+        if last_year - first_year + 1 > MAX_DAILY_YEARS
         {
-            false => { Err(format!("In program: {}
-Gnuplot reported error: {}",
-                                   gnuplot_cmd,
-                                   String::from_utf8_lossy(&output.stderr)).into()) },
-            true => { Ok(()) }
+            bail!("Daily charts can cover at most {} years; narrow the range with --from and --to",
+                  MAX_DAILY_YEARS);
         }
+
+        let origin = NaiveDate::from_ymd_opt(bounds.0.year(), 1, 1).unwrap();
+        let row = |date: NaiveDate| (date - origin).num_days();
+        let (xtick_every, xtick_rotate) = self.xtick_layout_for((last_year - first_year + 1) * 12);
+        let months: Vec<NaiveDate> = (0..(last_year - first_year + 1) * 12)
+            .filter(|m| m % xtick_every == 0)
+            .map(|m| NaiveDate::from_ymd_opt(first_year + m / 12, (m % 12) as u32 + 1, 1).unwrap())
+            .collect();
+        let xtics = months.iter()
+            .map(|date| format!("\"{}\" {}", date.format("%Y-%m"), row(*date)))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let grid = months.iter()
+            .map(|date| format!("{}", row(*date) as f32 - 0.5))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let markers = meta.marker_dates().iter()
+            .filter(|(date, _, _)| date.year() >= first_year && date.year() <= last_year)
+            .map(|(date, marker_row, text)|
+                 format!("set label '{}' left at {}, (0.977-0.05*{})*GPVAL_Y_MAX front tc ls 0 boxed;",
+                         text.replace('\'', "''"), row(*date), marker_row))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let gnuplot_cmd = format!("
+            {gnuplot_setup}
+            {group_styles}
+            set style line {last_style_num} lt 1 lc rgb '#ffffd0';
+$data << EOD
+{history}
+EOD
+            set output \"{output}\";
+            set ylabel \"{ylabel}\";
+            set xrange [{xrange_0}:{xrange_1}];
+            set xtics ({xtics});
+            {captions}
+            {xtick_rotation}
+            set multiplot;
+            plot for [i=5:{plot_range}] '$data' using i ls i-4 {band_style} title columnheader(i);
+            unset key;
+            set style data histep;
+            set xtics ({grid}) scale 1 textcolor rgb \"0xff000000\";
+            set ytics textcolor rgb \"0x00000000\" scale default;
+            set grid xtics ytics front linestyle 101;
+            set yrange restore;
+            set style textbox opaque noborder;
+            {markers}
+            plot '$data' using 4 lc rgb 'black' lw 2 notitle;
+            unset multiplot;
+            ",
+            gnuplot_setup = self.cohorts_setup(),
+            group_styles = self.format_group_styles(hist),
+            captions = self.format_captions(),
+            last_style_num = hist.get_n_cohorts() + 1,
+            history = &hist.to_csv(GNUPLOT_DELIMITER),
+            output = out_file.to_string_lossy().into_owned(),
+            ylabel = unit,
+            xtics = xtics,
+            grid = grid,
+            xtick_rotation = Plotter::format_xtick_rotation(xtick_rotate),
+            xrange_0 = row(NaiveDate::from_ymd_opt(first_year, 1, 1).unwrap()) as f32 - 0.5,
+            xrange_1 = row(NaiveDate::from_ymd_opt(last_year + 1, 1, 1).unwrap()) as f32 - 0.5,
+            plot_range = hist.get_n_cohorts() + 5,
+            band_style = self.band_style("i", 5),
+            markers = markers + &Plotter::format_spans(meta, |date| row(date) as f32)
+                + &self.format_direct_labels(hist, row(NaiveDate::from_ymd_opt(first_year, 1, 1).unwrap()),
+                                             row(NaiveDate::from_ymd_opt(last_year + 1, 1, 1).unwrap()) - 1));
+
+        self.run_gnuplot(&gnuplot_cmd)
     }
+
+    pub fn plot_faceted_cohorts(&self,
+                                meta: &ProjectMeta,
+                                unit: &str,
+                                facets: &[(String, CohortHist)], out_file: &Path,
+                                first_year: Option<i32>, last_year: Option<i32>) -> Result<()>
+    {
+        // One small stacked chart per facet in a grid, all on the same x and
+        // y scales, with a single legend along the bottom. The histograms
+        // must share cohorts; see CohortHist::align_to().
+
+        let bounds: Vec<_> = facets.iter().map(|(_, hist)| hist.get_bounds().unwrap()).collect();
+        let is_monthly = !matches!(bounds[0].0, TimeBin::Year(_));
+        let min_year = bounds.iter().map(|b| b.0.year()).min().unwrap();
+        let max_year = bounds.iter().map(|b| b.1.year()).max().unwrap();
+        let first_year = first_year.or(meta.first_year).unwrap_or(min_year);
+        let last_year = last_year.or(meta.last_year).unwrap_or_else(|| {
+            // As with single charts, leave out the incomplete last year.
+            if is_monthly || min_year == max_year || self.pad_to_now { max_year }
+            else { max_year - 1 }
+        });
+        let xtick_layout = self.xtick_layout(first_year, last_year);
+
+        // Shared y scale: the tallest stack in the visible range.
+
+        let y_max = facets.iter()
+            .flat_map(|(_, hist)| hist.to_vecs())
+            .filter(|(ym, _)| ym.year() >= first_year && ym.year() <= last_year)
+            .map(|(_, values)| values.iter().skip(1).map(|(_, v)| v).sum::<f64>())
+            .fold(0.0, f64::max);
+
+        let n_cols = (facets.len() as f64).sqrt().ceil() as usize;
+        let n_rows = facets.len().div_ceil(n_cols);
+        let (panel_height, key_height) = (480, 160);
+        let height = n_rows * panel_height + key_height;
+        let key_size = key_height as f32 / height as f32;
+        let (first_col, n_cohorts) = (if is_monthly { 4 } else { 3 }, facets[0].1.get_n_cohorts());
+
+        let panels = facets.iter().zip(&bounds).enumerate()
+            .map(|(i, ((name, hist), bounds))|
+            {
+                let (row, col) = (i / n_cols, i % n_cols);
+                let (xrange_0, xrange_1) =
+                    if is_monthly { (((first_year - bounds.0.year()) * 12) as f32 - 0.5,
+                                     ((last_year - bounds.0.year()) * 12 + 12) as f32 - 0.5) }
+                    else { ((first_year - bounds.0.year()) as f32 - 0.5,
+                            (last_year - bounds.0.year()) as f32 + 0.5) };
+
+                format!("
+$facet{i} << EOD
+{history}
+EOD
+                    set origin {x},{y};
+                    set size {w},{h};
+                    set title \"{title}\" noenhanced;
+                    {ylabel}
+                    set xrange [{xrange_0}:{xrange_1}];
+                    plot for [j={first_col}:{last_col}] '$facet{i}' using j:xtic({xtic}) ls j-{style_offset} \
+                        {band_style} notitle;
+                    ",
+                    i = i,
+                    history = hist.to_csv(GNUPLOT_DELIMITER),
+                    x = col as f32 / n_cols as f32,
+                    y = key_size + (n_rows - 1 - row) as f32 * (1.0 - key_size) / n_rows as f32,
+                    w = 1.0 / n_cols as f32,
+                    h = (1.0 - key_size) / n_rows as f32,
+                    title = name.replace('"', "'"),
+                    ylabel = if col == 0 { format!("set ylabel \"{}\";", unit.replace('"', "'")) }
+                             else { "unset ylabel;".to_string() },
+                    xrange_0 = xrange_0,
+                    xrange_1 = xrange_1,
+                    first_col = first_col,
+                    last_col = first_col as i32 + n_cohorts,
+                    style_offset = first_col - 1,
+                    band_style = self.band_style("j", first_col),
+                    xtic = if is_monthly { format!("$2==\"06\" && (int($1) - {}) % {} == 0 ? stringcolumn(1) : \"\"",
+                                                   first_year, xtick_layout.0) }
+                           else { format!("(int($1) - {}) % {} == 0 ? {} : \"\"",
+                                          first_year, xtick_layout.0, self.year_label()) })
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        // The legend is a plot of its own below the grid, drawn out of
+        // range so only the key shows.
+
+        let gnuplot_cmd = format!("
+            {gnuplot_setup}
+            set terminal pngcairo size 2560,{height} enhanced background rgb 'white' font 'Verdana,20';
+            set output \"{output}\";
+            set tmargin 2.5;
+            set bmargin 3.0;
+            set style line {last_style_num} lt 1 lc rgb '#ffffd0';
+            set yrange [0:{y_max}];
+            unset key;
+            {captions}
+            {xtick_rotation}
+            set multiplot;
+            {panels}
+            set origin 0,0;
+            set size 1,{key_size};
+            unset title;
+            unset ylabel;
+            unset border;
+            unset xtics;
+            unset ytics;
+            set yrange [-2:-1];
+            set xrange [-0.5:0.5];
+            set key reverse Left horizontal nobox center center width 1.1;
+            plot for [j={first_col}:{last_col}] '$facet0' every ::0::0 using j ls j-{style_offset} \
+                {band_style} title columnheader(j);
+            unset multiplot;
+            ",
+            gnuplot_setup = self.cohorts_setup(),
+            height = height,
+            last_style_num = n_cohorts + 1,
+            output = out_file.to_string_lossy().into_owned(),
+            y_max = (y_max * 1.05).max(1.0),
+            xtick_rotation = Plotter::format_xtick_rotation(xtick_layout.1),
+            panels = panels,
+            key_size = key_size,
+            first_col = first_col,
+            last_col = first_col as i32 + n_cohorts,
+            style_offset = first_col - 1,
+            band_style = self.band_style("j", first_col),
+            captions = self.format_captions());
+
+        self.run_gnuplot(&gnuplot_cmd)
+    }
+
+    pub fn plot_seasonal_profile(&self,
+                                 meta: &ProjectMeta,
+                                 unit: &str,
+                                 profile: &SeasonalProfile, out_file: &Path) -> Result<()>
+    {
+        // Stacked like the yearly charts, but with one bar per month of
+        // the year, each the average over the years covered.
+
+        let gnuplot_cmd = format!("
+            {gnuplot_setup}
+            set style line {last_style_num} lt 1 lc rgb '#ffffd0';
+$data << EOD
+{profile}
+EOD
+            set output \"{output}\";
+            set ylabel \"{ylabel}\" noenhanced;
+            set xrange [-0.5:11.5];
+            {captions}
+            plot for [i=3:{plot_range}] '$data' using i:xtic(1) ls i-2 {band_style} title columnheader(i);
+            ",
+            gnuplot_setup = self.cohorts_setup(),
+            band_style = self.band_style("i", 3),
+            captions = self.format_captions(),
+            last_style_num = profile.n_cohorts + 1,
+            profile = profile.to_csv(GNUPLOT_DELIMITER),
+            output = out_file.to_string_lossy().into_owned(),
+            ylabel = format!("{} ({}, {} {})", unit, meta.label("monthly average"), profile.n_years,
+                             meta.label(if profile.n_years == 1 { "year" } else { "years" })).replace('"', "'"),
+            plot_range = profile.names.len() + 2);
+
+        self.run_gnuplot(&gnuplot_cmd)
+    }
+
+    pub fn plot_cohort_detail(&self,
+                              meta: &ProjectMeta,
+                              cohort_name: &str,
+                              hist: &CohortHist, out_file: &Path,
+                              first_year: Option<i32>, last_year: Option<i32>) -> Result<()>
+    {
+        // One line per unit, each scaled to its own peak. The histogram is
+        // expected to hold the units as its only cohorts.
+
+        let bounds = hist.get_bounds().unwrap();
+        let first_year = first_year.or(meta.first_year).unwrap_or(bounds.0.year());
+        let last_year = last_year.or(meta.last_year).unwrap_or(bounds.1.year());
+        let (first_col, xtic, bins_per_year) = match bounds.0
+        {
+            TimeBin::Year(_) => (3, format!("({})", self.year_label()), 1),
+            _ => (4, "($2==\"06\" ? stringcolumn(1) : \"\")".to_string(), 12)
+        };
+
+        let gnuplot_cmd = format!("
+            {gnuplot_setup}
+$data << EOD
+{history}
+EOD
+            set output \"{output}\";
+            set ylabel \"{ylabel}\" noenhanced;
+            set xrange [{xrange_0}:{xrange_1}];
+            set yrange [0:105];
+            set format y \"%.0f%%\";
+            set style data lines;
+            set xtics textcolor rgb \"0x00000000\" scale 0;
+            set ytics textcolor rgb \"0x00000000\" scale default;
+            set grid xtics ytics front linestyle 101;
+            {captions}
+            plot for [i={first_col}:{last_col}] '$data' using 0:(column(i)*100):xtic{xtic} \
+                ls (i-{first_col})*4+4 lw 4 title columnheader(i);
+            ",
+            gnuplot_setup = self.cohorts_setup(),
+            history = &hist.to_csv(GNUPLOT_DELIMITER),
+            output = out_file.to_string_lossy().into_owned(),
+            ylabel = format!("{} ({})", cohort_name, meta.label("% of peak")).replace('"', "'"),
+            xrange_0 = ((first_year - bounds.0.year()) * bins_per_year) as f32 - 0.5,
+            xrange_1 = ((last_year - bounds.0.year() + 1) * bins_per_year) as f32 - 0.5,
+            captions = self.format_captions(),
+            first_col = first_col,
+            last_col = first_col + hist.get_n_cohorts() - 1,
+            xtic = xtic);
+
+        self.run_gnuplot(&gnuplot_cmd)
+    }
+
+    pub fn plot_line_pair(&self,
+                          meta: &ProjectMeta,
+                          (top_label, top_hist): (&str, &CohortHist),
+                          (bottom_label, bottom_hist): (&str, &CohortHist), out_file: &Path,
+                          first_year: Option<i32>, last_year: Option<i32>) -> Result<()>
+    {
+        // Two measures of the same cohorts stacked one above the other, one
+        // line per cohort, sharing the x axis and key.
+
+        let bounds = top_hist.get_bounds().unwrap();
+        let first_year = first_year.or(meta.first_year).unwrap_or(bounds.0.year());
+        let last_year = last_year.or(meta.last_year).unwrap_or(bounds.1.year());
+        let (first_col, xtic, bins_per_year) = match bounds.0
+        {
+            TimeBin::Year(_) => (3, format!("({})", self.year_label()), 1),
+            _ => (4, "($2==\"06\" ? stringcolumn(1) : \"\")".to_string(), 12)
+        };
+
+        let gnuplot_cmd = format!("
+            {gnuplot_setup}
+$top << EOD
+{top}
+EOD
+$bottom << EOD
+{bottom}
+EOD
+            set output \"{output}\";
+            set xrange [{xrange_0}:{xrange_1}];
+            set style data lines;
+            set xtics textcolor rgb \"0x00000000\" scale 0;
+            set ytics textcolor rgb \"0x00000000\" scale default;
+            set grid xtics ytics front linestyle 101;
+            set multiplot;
+            set key off;
+            set origin 0,0.55;
+            set size 1,0.45;
+            set bmargin 1;
+            set xzeroaxis linestyle 101;
+            set ylabel \"{top_ylabel}\" noenhanced;
+            plot for [i={first_col}:{last_col}] '$top' using 0:i ls (i-{first_col})*2+4 lw 4;
+            set key on;
+            set origin 0,0;
+            set size 1,0.55;
+            set bmargin 7;
+            set ylabel \"{bottom_ylabel}\" noenhanced;
+            {captions}
+            plot for [i={first_col}:{last_col}] '$bottom' using 0:i:xtic{xtic} \
+                ls (i-{first_col})*2+4 lw 4 title columnheader(i);
+            unset multiplot;
+            ",
+            gnuplot_setup = self.cohorts_setup(),
+            top = &top_hist.to_csv(GNUPLOT_DELIMITER),
+            bottom = &bottom_hist.to_csv(GNUPLOT_DELIMITER),
+            output = out_file.to_string_lossy().into_owned(),
+            top_ylabel = top_label.replace('"', "'"),
+            bottom_ylabel = bottom_label.replace('"', "'"),
+            xrange_0 = ((first_year - bounds.0.year()) * bins_per_year) as f32 - 0.5,
+            xrange_1 = ((last_year - bounds.0.year() + 1) * bins_per_year) as f32 - 0.5,
+            captions = self.format_captions(),
+            first_col = first_col,
+            last_col = first_col + top_hist.get_n_cohorts() - 1,
+            xtic = xtic);
+
+        self.run_gnuplot(&gnuplot_cmd)
+    }
+
+    pub fn plot_lines(&self,
+                      meta: &ProjectMeta,
+                      unit: &str,
+                      hist: &CohortHist, out_file: &Path,
+                      first_year: Option<i32>, last_year: Option<i32>) -> Result<()>
+    {
+        // One line per cohort on a shared y axis, for measures that can't
+        // be stacked.
+
+        let bounds = hist.get_bounds().unwrap();
+        let first_year = first_year.or(meta.first_year).unwrap_or(bounds.0.year());
+        let last_year = last_year.or(meta.last_year).unwrap_or(bounds.1.year());
+        let (first_col, xtic, bins_per_year) = match bounds.0
+        {
+            TimeBin::Year(_) => (3, format!("({})", self.year_label()), 1),
+            _ => (4, "($2==\"06\" ? stringcolumn(1) : \"\")".to_string(), 12)
+        };
+
+        let gnuplot_cmd = format!("
+            {gnuplot_setup}
+$data << EOD
+{history}
+EOD
+            set output \"{output}\";
+            set ylabel \"{ylabel}\" noenhanced;
+            set xrange [{xrange_0}:{xrange_1}];
+            set yrange [0:*];
+            set style data lines;
+            set xtics textcolor rgb \"0x00000000\" scale 0;
+            set ytics textcolor rgb \"0x00000000\" scale default;
+            set grid xtics ytics front linestyle 101;
+            {captions}
+            plot for [i={first_col}:{last_col}] '$data' using 0:i:xtic{xtic} \
+                ls (i-{first_col})*4+4 lw 4 title columnheader(i);
+            ",
+            gnuplot_setup = self.cohorts_setup(),
+            history = &hist.to_csv(GNUPLOT_DELIMITER),
+            output = out_file.to_string_lossy().into_owned(),
+            ylabel = unit.replace('"', "'"),
+            xrange_0 = ((first_year - bounds.0.year()) * bins_per_year) as f32 - 0.5,
+            xrange_1 = ((last_year - bounds.0.year() + 1) * bins_per_year) as f32 - 0.5,
+            captions = self.format_captions(),
+            first_col = first_col,
+            last_col = first_col + hist.get_n_cohorts() - 1,
+            xtic = xtic);
+
+        self.run_gnuplot(&gnuplot_cmd)
+    }
+
+    pub fn plot_lorenz(&self,
+                       meta: &ProjectMeta,
+                       lorenz: &Lorenz, out_file: &Path,
+                       range: &str) -> Result<()>
+    {
+        let gnuplot_cmd = format!("
+            set terminal pngcairo size 1600,1600 enhanced background rgb 'white' font 'Verdana,25';
+            set datafile separator '|';
+            set border 3 lw 2;
+            set key off;
+            set size square;
+            set xrange [0:100];
+            set yrange [0:100];
+            set format x \"%.0f%%\";
+            set format y \"%.0f%%\";
+            set xtics nomirror;
+            set ytics nomirror;
+            set grid xtics ytics linestyle 101;
+            set style line 101 lc rgb \"0x50000000\" dashtype '-' lw 2;
+$data << EOD
+{points}
+EOD
+            set output \"{output}\";
+            set xlabel \"{xlabel}\" noenhanced;
+            set ylabel \"{ylabel}\" noenhanced;
+            set label \"{caption}\" at graph 0.05, graph 0.92 left front noenhanced;
+            {captions}
+            plot x lc rgb '#909090' dashtype '-' lw 2, \
+                 '$data' using ($1*100):($2*100) with filledcurves x1 fc rgb '#a6cee3', \
+                 '$data' using ($1*100):($2*100) with lines lc rgb '#1f78b4' lw 4;
+            ",
+            points = lorenz.to_csv(GNUPLOT_DELIMITER),
+            output = out_file.to_string_lossy().into_owned(),
+            xlabel = meta.label("Share of authors"),
+            ylabel = meta.label("Share of commits"),
+            caption = format!("Gini = {:.2} ({})", lorenz.gini, range).replace('"', "'"),
+            captions = self.format_captions());
+
+        self.run_gnuplot(&gnuplot_cmd)
+    }
+
+    pub fn plot_slope(&self,
+                      meta: &ProjectMeta,
+                      unit: &str,
+                      diff: &SnapshotDiff, out_file: &Path) -> Result<()>
+    {
+        // One line per cohort from its value in the first window to its
+        // value in the second; growth in blue and shrinkage in red. Only the
+        // biggest movers are shown, so the labels stay legible.
+
+        const N_SHOWN: usize = 15;
+        let points = diff.rows.iter().take(N_SHOWN)
+            .map(|(name, a_avg, b_avg)|
+            {
+                let color = if b_avg >= a_avg { 0x1f78b4 } else { 0xe31a1c };
+                format!("0|{}|{}|{}\n1|{}|{}|{}\n", a_avg, name, color, b_avg, name, color)
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let gnuplot_cmd = format!("
+            set terminal pngcairo size 1600,1600 enhanced background rgb 'white' font 'Verdana,20';
+            set datafile separator '|';
+            set border 2 lw 2;
+            set key off;
+            set xrange [-0.6:1.6];
+            set yrange [0:*];
+            set xtics (\"{a}\" 0, \"{b}\" 1) nomirror scale 0;
+            set ytics nomirror;
+            set grid ytics linestyle 101;
+            set style line 101 lc rgb \"0x50000000\" dashtype '-' lw 2;
+$data << EOD
+{points}
+EOD
+            set output \"{output}\";
+            set ylabel \"{ylabel}\" noenhanced;
+            {captions}
+            plot '$data' using 1:2:4 with linespoints lw 3 pt 7 ps 1.5 lc rgb variable, \
+                 '' using ($1 == 0 ? -0.03 : NaN):2:3 with labels right noenhanced, \
+                 '' using ($1 == 1 ? 1.03 : NaN):2:3 with labels left noenhanced;
+            ",
+            a = diff.a,
+            b = diff.b,
+            points = points,
+            output = out_file.to_string_lossy().into_owned(),
+            ylabel = format!("{} ({})", unit, meta.label("per year")).replace('"', "'"),
+            captions = self.format_captions());
+
+        self.run_gnuplot(&gnuplot_cmd)
+    }
+
+    pub fn plot_graph_stats(&self,
+                            meta: &ProjectMeta,
+                            csv: &str, out_file: &Path) -> Result<()>
+    {
+        // Merge rate and linearity as shares on the left axis, with branch
+        // lifetimes overlaid on the right.
+
+        let gnuplot_cmd = format!("
+            set terminal pngcairo size 2560,1200 enhanced background rgb 'white' font 'Verdana,25';
+            set datafile separator '|';
+            set datafile missing 'NaN';
+            set border 11 lw 2;
+            set key autotitle columnheader noenhanced top left horizontal nobox;
+            set yrange [0:100];
+            set format y \"%.0f%%\";
+            set y2range [0:*];
+            set xtics nomirror rotate by 45 right;
+            set ytics nomirror;
+            set y2tics;
+            set grid ytics linestyle 101;
+            set style line 101 lc rgb \"0x50000000\" dashtype '-' lw 2;
+$data << EOD
+{csv}
+EOD
+            set output \"{output}\";
+            set ylabel \"{ylabel}\" noenhanced;
+            set y2label \"{y2label}\" noenhanced;
+            {captions}
+            plot '$data' using ($2*100):xtic(1) with lines lc rgb '#e31a1c' lw 4 title columnheader(2), \
+                 '' using ($3*100) with lines lc rgb '#1f78b4' lw 4 title columnheader(3), \
+                 '' using 4 axes x1y2 with linespoints lc rgb '#33a02c' lw 2 pt 7 title columnheader(4);
+            ",
+            csv = csv,
+            output = out_file.to_string_lossy().into_owned(),
+            ylabel = meta.label("Share of commits"),
+            y2label = meta.label("Days"),
+            captions = self.format_captions());
+
+        self.run_gnuplot(&gnuplot_cmd)
+    }
+
+    pub fn plot_coupling(&self,
+                         meta: &ProjectMeta,
+                         csv: &str, n_pairs: usize, out_file: &Path) -> Result<()>
+    {
+        // Each directory pair's coupling per year, as a line.
+
+        let gnuplot_cmd = format!("
+            set terminal pngcairo size 2560,1200 enhanced background rgb 'white' font 'Verdana,25';
+            set datafile separator '|';
+            set border 3 lw 2;
+            set key autotitle columnheader noenhanced outside right top vertical nobox;
+            set yrange [0:*];
+            set xtics nomirror rotate by 45 right;
+            set ytics nomirror;
+            set grid ytics linestyle 101;
+            set style line 101 lc rgb \"0x50000000\" dashtype '-' lw 2;
+$data << EOD
+{csv}
+EOD
+            set output \"{output}\";
+            set ylabel \"{ylabel}\" noenhanced;
+            {captions}
+            plot for [i=2:{last_col}] '$data' using i:xtic(1) with linespoints lw 4 pt 7 title columnheader(i);
+            ",
+            csv = csv,
+            output = out_file.to_string_lossy().into_owned(),
+            ylabel = meta.label("Coupling (Jaccard index)"),
+            last_col = n_pairs + 1,
+            captions = self.format_captions());
+
+        self.run_gnuplot(&gnuplot_cmd)
+    }
+
+    pub fn plot_contributors_strip(&self,
+                                   meta: &ProjectMeta,
+                                   unit: &str,
+                                   hist: &CohortHist, out_file: &Path,
+                                   first_year: Option<i32>, last_year: Option<i32>) -> Result<()>
+    {
+        // One row of cells per author, shaded by activity on a log scale so
+        // occasional activity still shows up next to the busiest intervals.
+        // Cohorts are expected to be authors, numbered from the top row.
+
+        let bounds = hist.get_bounds().unwrap();
+        let first_year = first_year.or(meta.first_year).unwrap_or(bounds.0.year());
+        let last_year = last_year.or(meta.last_year).unwrap_or(bounds.1.year());
+        let bins_per_year = if let TimeBin::Year(_) = bounds.0 { 1 } else { 12 };
+        let n_authors = hist.get_n_cohorts();
+
+        let cells = hist.to_vecs().iter().enumerate()
+            .flat_map(|(x, (_, values))|
+                      values.iter().filter(|(g, _)| *g >= 0)
+                          .map(move |(g, v)| format!("{}|{}|{}", x, g, v)))
+            .collect::<Vec<String>>()
+            .join("\n");
+        let (xtick_every, xtick_rotate) = self.xtick_layout(first_year, last_year);
+        let xtics = (first_year..=last_year)
+            .filter(|year| (year - first_year) % xtick_every == 0)
+            .map(|year| format!("\"{}\" {}", year,
+                                (year - bounds.0.year()) * bins_per_year + (bins_per_year - 1) / 2))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let ytics = (0..n_authors)
+            .map(|g| format!("\"{}\" {}", hist.get_cohort_name(g).replace('"', "'"), g))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        let gnuplot_cmd = format!("
+            set terminal pngcairo size 2560,{height} enhanced background rgb 'white' font 'Verdana,20';
+            set datafile separator '|';
+            set border 0;
+            set key off;
+            unset colorbox;
+            set palette defined (0 'white', 1 '#a6cee3', 2 '#1f78b4', 3 '#08306b');
+            set xrange [{xrange_0}:{xrange_1}];
+            set yrange [{yrange_0}:-0.5];
+            set xtics ({xtics}) scale 0 nomirror;
+            set ytics ({ytics}) scale 0 nomirror noenhanced;
+            {xtick_rotation}
+$data << EOD
+{cells}
+EOD
+            set output \"{output}\";
+            set title \"{title}\" noenhanced;
+            {captions}
+            plot '$data' using 1:2:(log10($3 + 1)) with image;
+            ",
+            height = 200 + n_authors * 40,
+            xrange_0 = ((first_year - bounds.0.year()) * bins_per_year) as f32 - 0.5,
+            xrange_1 = ((last_year - bounds.0.year() + 1) * bins_per_year) as f32 - 0.5,
+            yrange_0 = n_authors as f32 - 0.5,
+            xtics = xtics,
+            ytics = ytics,
+            xtick_rotation = Plotter::format_xtick_rotation(xtick_rotate),
+            cells = cells,
+            output = out_file.to_string_lossy().into_owned(),
+            title = unit.replace('"', "'"),
+            captions = self.format_captions());
+
+        self.run_gnuplot(&gnuplot_cmd)
+    }
+
+    pub fn plot_author_overlap(&self,
+                               meta: &ProjectMeta,
+                               overlap: &AuthorOverlap, out_file: &Path) -> Result<()>
+    {
+        // A square heatmap shaded by the Jaccard index of each pair and
+        // labeled with the number of shared authors. The diagonal is each
+        // item's own author count.
+
+        let n_items = overlap.items.len();
+        let tics = overlap.items.iter().enumerate()
+            .map(|(i, item)| format!("\"{}\" {}", item.replace('"', "'"), i))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let cells = (0..n_items)
+            .flat_map(|i| (0..n_items).map(move |j| (i, j)))
+            .map(|(i, j)| format!("{}|{}|{}|{}", j, i, overlap.jaccard(i, j), overlap.shared[i][j]))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let gnuplot_cmd = format!("
+            set terminal pngcairo size {size},{size} enhanced background rgb 'white' font 'Verdana,20';
+            set datafile separator '|';
+            set border 0;
+            set key off;
+            set palette defined (0 'white', 1 '#a6cee3', 2 '#1f78b4', 3 '#08306b');
+            set cbrange [0:1];
+            set cblabel \"{cblabel}\" noenhanced;
+            set xrange [-0.5:{range_1}];
+            set yrange [{range_1}:-0.5];
+            set xtics ({tics}) scale 0 nomirror noenhanced rotate by 45 right;
+            set ytics ({tics}) scale 0 nomirror noenhanced;
+            set title \"{title}\" noenhanced;
+            set output \"{output}\";
+            {captions}
+$cells << EOD
+{cells}
+EOD
+            plot '$cells' using 1:2:3 with image, \
+                 '' using 1:2:($4 > 0 ? sprintf('%d', $4) : '') with labels font ',16';
+            ",
+            size = 800 + n_items * 80,
+            cblabel = meta.label("Jaccard index").replace('"', "'"),
+            range_1 = n_items as f32 - 0.5,
+            tics = tics,
+            title = meta.label("Authors shared between pairs").replace('"', "'"),
+            output = out_file.to_string_lossy().into_owned(),
+            captions = self.format_captions(),
+            cells = cells);
+
+        self.run_gnuplot(&gnuplot_cmd)
+    }
+
+    pub fn plot_domain_crosstab(&self,
+                                meta: &ProjectMeta,
+                                tab: &DomainCrossTab, out_file: &Path) -> Result<()>
+    {
+        // A heatmap per era with committer domains as rows and author
+        // domains as columns, shaded on a log scale and labeled with the
+        // commit counts. Off-diagonal cells are commits applied on behalf of
+        // another domain.
+
+        let n_rows = tab.committer_domains.len();
+        let n_cols = tab.author_domains.len();
+        let tics = |domains: &Vec<String>|
+        {
+            domains.iter().enumerate()
+                .map(|(i, d)| format!("\"{}\" {}", d.replace('"', "'"), i))
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+
+        let panels = tab.eras.iter().zip(&tab.counts).enumerate()
+            .map(|(i, (era, era_counts))|
+            {
+                let cells = era_counts.iter().enumerate()
+                    .flat_map(|(c, row)| row.iter().enumerate().map(move |(a, n)| format!("{}|{}|{}", a, c, n)))
+                    .collect::<Vec<String>>()
+                    .join("\n");
+
+                format!("
+$era{i} << EOD
+{cells}
+EOD
+                    set title \"{era}\" noenhanced;
+                    {ytics}
+                    plot '$era{i}' using 1:2:(log10($3 + 1)) with image, \
+                         '' using 1:2:($3 > 0 ? sprintf('%d', $3) : '') with labels font ',16';
+                    ",
+                    i = i,
+                    cells = cells,
+                    era = era.replace('"', "'"),
+                    ytics = if i == 0 { format!("set ytics ({}) scale 0 nomirror noenhanced;",
+                                                tics(&tab.committer_domains)) }
+                            else { "set ytics format '';".to_string() })
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let gnuplot_cmd = format!("
+            set terminal pngcairo size {width},{height} enhanced background rgb 'white' font 'Verdana,20';
+            set datafile separator '|';
+            set border 0;
+            set key off;
+            unset colorbox;
+            set palette defined (0 'white', 1 '#a6cee3', 2 '#1f78b4', 3 '#08306b');
+            set xrange [-0.5:{xrange_1}];
+            set yrange [{yrange_0}:-0.5];
+            set xtics ({xtics}) scale 0 nomirror noenhanced rotate by 45 right;
+            set output \"{output}\";
+            {captions}
+            set multiplot layout 1,{n_eras} title \"{title}\" noenhanced;
+            {panels}
+            unset multiplot;
+            ",
+            width = 600 + tab.eras.len() * n_cols * 90,
+            height = 600 + n_rows * 60,
+            xrange_1 = n_cols as f32 - 0.5,
+            yrange_0 = n_rows as f32 - 0.5,
+            xtics = tics(&tab.author_domains),
+            output = out_file.to_string_lossy().into_owned(),
+            captions = self.format_captions(),
+            n_eras = tab.eras.len(),
+            title = meta.label("Commits by committer domain (rows) and author domain (columns)").replace('"', "'"),
+            panels = panels);
+
+        self.run_gnuplot(&gnuplot_cmd)
+    }
+}
+
+fn lighten(color: &str, amount: f64) -> String
+{
+    // Mix a '#rrggbb' color with white.
+
+    let channel = |i: usize|
+    {
+        let c = u8::from_str_radix(&color[i..i + 2], 16).unwrap_or(0) as f64;
+        (c + (255.0 - c) * amount).round() as u8
+    };
+
+    format!("#{:02x}{:02x}{:02x}", channel(1), channel(3), channel(5))
 }
@@ -0,0 +1,136 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* ------------ *
+ * PNG metadata *
+ * ------------ */
+
+use std::fs;
+use std::path::Path;
+use crate::errors::*;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+fn crc32(bytes: &[u8]) -> u32
+{
+    // The same CRC as zlib's; PNG chunks are small enough that we don't
+    // need a lookup table.
+
+    let mut crc = 0xffff_ffffu32;
+
+    for b in bytes
+    {
+        crc ^= *b as u32;
+        for _ in 0..8
+        {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+fn format_chunk(chunk_type: &[u8], data: &[u8]) -> Vec<u8>
+{
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(chunk_type);
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(&crc32(&chunk[4..]).to_be_bytes());
+    chunk
+}
+
+fn format_itxt_chunk(keyword: &str, text: &str) -> Vec<u8>
+{
+    // iTXt rather than tEXt, since paths and names on the command line
+    // may not fit in Latin-1. Uncompressed, with no language tag.
+
+    let mut data = Vec::new();
+
+    data.extend_from_slice(keyword.as_bytes());
+    data.extend_from_slice(&[ 0, 0, 0, 0, 0 ]);
+    data.extend_from_slice(text.as_bytes());
+    format_chunk(b"iTXt", &data)
+}
+
+fn find_iend(png: &[u8]) -> Option<usize>
+{
+    let mut pos = PNG_SIGNATURE.len();
+
+    while pos + 8 <= png.len()
+    {
+        let len = u32::from_be_bytes([ png[pos], png[pos + 1], png[pos + 2], png[pos + 3] ]) as usize;
+        if &png[pos + 4..pos + 8] == b"IEND" { return Some(pos); }
+        pos += len + 12;
+    }
+
+    None
+}
+
+pub fn add_text(png: &[u8], entries: &[(&str, String)]) -> Result<Vec<u8>>
+{
+    // Text chunks may go anywhere between the header and the end, so we
+    // put them right before IEND and leave the image data alone.
+
+    let iend = find_iend(png).ok_or("Malformed PNG file")?;
+    let mut out = png[..iend].to_vec();
+
+    for (keyword, text) in entries
+    {
+        out.extend(format_itxt_chunk(keyword, text));
+    }
+
+    out.extend_from_slice(&png[iend..]);
+    Ok(out)
+}
+
+pub fn embed_text(path: &Path, entries: &[(&str, String)]) -> Result<()>
+{
+    // Anything that isn't a PNG (e.g. a CSV written instead of a plot) is
+    // left as is.
+
+    let png = fs::read(path).chain_err(|| format!("Could not read {}", path.display()))?;
+    if !png.starts_with(PNG_SIGNATURE) { return Ok(()); }
+
+    let png = add_text(&png, entries).chain_err(|| format!("Could not add metadata to {}", path.display()))?;
+    fs::write(path, png).chain_err(|| format!("Could not write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserts_text_before_iend() {
+        let iend = format_chunk(b"IEND", &[]);
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend(format_chunk(b"IHDR", &[ 0; 13 ]));
+        png.extend(&iend);
+
+        let out = add_text(&png, &[ ("Software", "fornalder".to_string()) ]).unwrap();
+
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert!(out.ends_with(&iend));
+        assert_eq!(find_iend(&out), Some(out.len() - 12));
+        assert!(out.windows(4).any(|w| w == b"iTXt"));
+        assert_eq!(out.len(), png.len() + 12 + "Software".len() + 5 + "fornalder".len());
+    }
+}
@@ -22,11 +22,43 @@
  * ProjectMeta *
  * ----------- */
 
+use chrono::{ NaiveDate, TimeZone, Utc };
+use std::collections::HashMap;
 use std::fs;
 use std::path::*;
 use serde::{Deserialize};
-use crate::cohorthist::*;
+use serde_json::Value;
+use crate::commitdb::CommitDb;
 use crate::errors::*;
+use crate::identities::Identities;
+use crate::timebin::TimeBin;
+
+// A point in time in the metadata file: a year, optionally narrowed down to
+// a month and a day. Markers, excluded intervals, epochs and domain active
+// ranges count months and days from 1; pattern spans count them from 0, like
+// time bins.
+
+#[derive(Deserialize, Debug, Copy, Clone)]
+pub struct YearMonth
+{
+    pub year: i32,
+    pub month: Option<i32>,
+    #[serde(default)]
+    pub day: Option<i32>,
+}
+
+impl YearMonth
+{
+    fn to_bin(self) -> TimeBin
+    {
+        match (self.month, self.day)
+        {
+            (Some(m), Some(d)) => TimeBin::day(self.year, m as u32, d as u32),
+            (Some(m), None) => TimeBin::Month(self.year, m as u32),
+            (None, _) => TimeBin::Year(self.year)
+        }
+    }
+}
 
 #[derive(Deserialize, Debug)]
 struct Marker
@@ -36,37 +68,202 @@ struct Marker
     text: String
 }
 
+// A span of time whose commits can't be trusted, e.g. a mass re-import of
+// an old CVS history. Like marker months, the months are 1-based. With a
+// repo glob, only the matching repos' commits are left out, and the rest of
+// the span is charted as usual.
+
+#[derive(Deserialize, Debug)]
+struct ExcludedInterval
+{
+    begin: YearMonth,
+    end: Option<YearMonth>,
+    text: Option<String>,
+    repo: Option<String>
+}
+
+fn first_day(time: YearMonth) -> Option<NaiveDate>
+{
+    NaiveDate::from_ymd_opt(time.year, time.month.unwrap_or(1) as u32, time.day.unwrap_or(1) as u32)
+}
+
+fn day_after(time: YearMonth) -> Option<NaiveDate>
+{
+    // The first day after the year, month or day given, with 1-based months.
+
+    match (time.month, time.day)
+    {
+        (Some(m), Some(d)) => NaiveDate::from_ymd_opt(time.year, m as u32, d as u32)?.succ_opt(),
+        (Some(12), None) | (None, _) => NaiveDate::from_ymd_opt(time.year + 1, 1, 1),
+        (Some(m), None) => NaiveDate::from_ymd_opt(time.year, m as u32 + 1, 1)
+    }
+}
+
+impl ExcludedInterval
+{
+    fn date_range(&self) -> Option<(NaiveDate, NaiveDate)>
+    {
+        // The first day of the interval and the day after its last. An
+        // interval without an end covers the year, month or day it begins in.
+
+        let begin = first_day(self.begin)?;
+        let end = day_after(self.end.unwrap_or(self.begin))?;
+
+        if end <= begin { None } else { Some((begin, end)) }
+    }
+
+    fn sql_repo_selector(&self) -> Option<String>
+    {
+        let repo = self.repo.as_ref()?;
+        let (begin, end) = self.date_range()?;
+
+        Some(format!("(raw_commits.repo_name glob {} and author_time >= {} and author_time < {})",
+                     sql_quote(repo),
+                     begin.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp(),
+                     end.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp()))
+    }
+}
+
+// A named era of the history, e.g. the years before a project moved from
+// CVS to git. Epochs are shaded in turn behind the charts and can be used
+// as a cohort. Months are 1-based, and an epoch without an end lasts until
+// the present.
+
+#[derive(Deserialize, Debug)]
+struct Epoch
+{
+    name: String,
+    begin: YearMonth,
+    end: Option<YearMonth>
+}
+
+impl Epoch
+{
+    fn date_range(&self) -> Option<(NaiveDate, Option<NaiveDate>)>
+    {
+        let begin = first_day(self.begin)?;
+        let end = match self.end
+        {
+            Some(end) => Some(day_after(end)?),
+            None => None
+        };
+
+        if end.is_some_and(|end| end <= begin) { None } else { Some((begin, end)) }
+    }
+
+    fn sql_selector(&self) -> Option<String>
+    {
+        let (begin, end) = self.date_range()?;
+        let timestamp = |date: NaiveDate| date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+
+        Some(match end
+        {
+            Some(end) => format!("(author_time >= {} and author_time < {})", timestamp(begin), timestamp(end)),
+            None => format!("(author_time >= {})", timestamp(begin))
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum PatternType
+{
+    Glob,
+    Regex
+}
+
 #[derive(Deserialize, Debug)]
 pub struct AggregatePattern
 {
     pattern: String,
+    pattern_type: Option<PatternType>,
     begin: Option<YearMonth>,
     end: Option<YearMonth>
 }
 
 impl AggregatePattern
 {
+    fn describe(&self, domain: &DomainMeta) -> String
+    {
+        format!("domain \"{}\": pattern \"{}\"", domain.name, self.pattern)
+    }
+
     fn sql_selector(&self, string_field: &str, timestamp_field: &str) -> String
     {
         let mut s: String;
 
-        s = format!("({} glob '{}'", string_field, self.pattern);
+        // Both sides are normalized so that e.g. " Jane@RedHat.COM" matches
+        // "*@redhat.com". Regexes are made case-insensitive instead of being
+        // lowercased, since that would change the meaning of escapes like \S.
 
-        if self.begin.is_some()
+        let pattern = self.pattern.trim();
+
+        s = match self.pattern_type.unwrap_or(PatternType::Glob)
+        {
+            PatternType::Glob => format!("(lower(trim({})) glob {}",
+                                         string_field, sql_quote(&pattern.to_lowercase())),
+            PatternType::Regex => format!("(lower(trim({})) regexp {}",
+                                          string_field, sql_quote(&format!("(?i){}", pattern)))
+        };
+
+        if let Some(begin) = self.begin
         {
             s += &format!(" and {} >= {}",
                           timestamp_field,
-                          self.begin.unwrap().begin_dt().timestamp());
+                          Utc.from_utc_datetime(&begin.to_bin().begin_dt()).timestamp());
         }
 
-        if self.end.is_some()
+        if let Some(end) = self.end
         {
             s += &format!(" and {} < {}",
                           timestamp_field,
-                          self.end.unwrap().end_dt().timestamp());
+                          Utc.from_utc_datetime(&end.to_bin().end_dt()).timestamp());
         }
 
-        s + &")".to_string()
+        s + ")"
+    }
+}
+
+fn sql_quote(s: &str) -> String
+{
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+// Corrections to the built-in bot detection. Patterns are matched against
+// both the author's name and e-mail address, and "exclude" wins.
+
+#[derive(Deserialize, Debug)]
+pub struct BotMeta
+{
+    include: Option<Vec<AggregatePattern>>,
+    exclude: Option<Vec<AggregatePattern>>
+}
+
+impl BotMeta
+{
+    fn sql_pattern_selector(ap: &AggregatePattern) -> String
+    {
+        format!("({} or {})",
+                ap.sql_selector("author_name", "author_time"),
+                ap.sql_selector("author_email", "author_time"))
+    }
+
+    fn sql_selector(patterns: &Option<Vec<AggregatePattern>>) -> Option<String>
+    {
+        let patterns = patterns.as_deref().unwrap_or(&[]);
+        if patterns.is_empty() { return None; }
+
+        Some(patterns.iter().map(BotMeta::sql_pattern_selector).collect::<Vec<String>>().join(" or "))
+    }
+
+    pub fn sql_include_selector(&self) -> Option<String>
+    {
+        BotMeta::sql_selector(&self.include)
+    }
+
+    pub fn sql_exclude_selector(&self) -> Option<String>
+    {
+        BotMeta::sql_selector(&self.exclude)
     }
 }
 
@@ -75,17 +272,65 @@ pub struct DomainMeta
 {
     pub name: String,
     pub show: Option<bool>,
+    pub active_from: Option<YearMonth>,
+    pub active_to: Option<YearMonth>,
     pub aggregate_emails: Option<Vec<AggregatePattern>>
 }
 
 impl DomainMeta
 {
     pub fn sql_emails_selector(&self) -> String
+    {
+        self.sql_emails_selector_for("author_email", "author_time")
+    }
+
+    pub fn sql_emails_selector_for(&self, email_column: &str, time_column: &str) -> String
     {
         if self.aggregate_emails.is_none() { return "".to_string(); }
 
-        self.aggregate_emails.as_ref().unwrap().iter()
-            .map(|ae| ae.sql_selector("author_email", "author_time")).collect::<Vec<String>>().join(" or ")
+        let patterns = self.aggregate_emails.as_ref().unwrap().iter()
+            .map(|ae| ae.sql_selector(email_column, time_column)).collect::<Vec<String>>().join(" or ");
+
+        match self.sql_active_selector(time_column)
+        {
+            Some(active) => format!("({}) and {}", patterns, active),
+            None => patterns
+        }
+    }
+
+    pub fn sql_active_selector(&self, time_column: &str) -> Option<String>
+    {
+        // A domain can be limited to a span of time, e.g. when a company
+        // took over another's domain. Patterns only apply within it. The
+        // end is inclusive, and months are 1-based like those of epochs.
+
+        let timestamp = |date: NaiveDate| date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+        let mut bounds = Vec::new();
+
+        if let Some(from) = self.active_from.and_then(first_day)
+        {
+            bounds.push(format!("{} >= {}", time_column, timestamp(from)));
+        }
+
+        if let Some(to) = self.active_to.and_then(day_after)
+        {
+            bounds.push(format!("{} < {}", time_column, timestamp(to)));
+        }
+
+        if bounds.is_empty() { None } else { Some(format!("({})", bounds.join(" and "))) }
+    }
+
+    fn check_active_range(&self) -> Result<()>
+    {
+        for (key, time) in &[ ("active_from", self.active_from), ("active_to", self.active_to) ]
+        {
+            if time.is_some_and(|t| first_day(t).is_none())
+            {
+                bail!("Domain \"{}\" has an invalid {} date", self.name, key);
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -96,7 +341,18 @@ pub struct ProjectMeta
     pub first_year: Option<i32>,
     pub last_year: Option<i32>,
     pub domains: Option<Vec<DomainMeta>>,
-    markers: Option<Vec<Marker>>
+    pub bots: Option<BotMeta>,
+    pub labels: Option<HashMap<String, String>>,
+    pub repo_aliases: Option<HashMap<String, String>>,
+    pub countries: Option<HashMap<String, String>>,
+    pub suffix_weights: Option<HashMap<String, f64>>,
+    markers: Option<Vec<Marker>>,
+    excluded: Option<Vec<ExcludedInterval>>,
+    epochs: Option<Vec<Epoch>>,
+    #[serde(rename = "identities")]
+    identities_file: Option<PathBuf>,
+    #[serde(skip)]
+    pub identities: Option<Identities>
 }
 
 impl ProjectMeta
@@ -104,17 +360,277 @@ impl ProjectMeta
     pub fn new() -> ProjectMeta
     {
         ProjectMeta { name: None, first_year: None, last_year: None, markers: None,
-                      domains: None }
+                      domains: None, bots: None, labels: None, repo_aliases: None,
+                      countries: None, suffix_weights: None, excluded: None, epochs: None,
+                      identities_file: None, identities: None }
     }
 
-    pub fn from_file(filename: &PathBuf) -> Result<ProjectMeta>
+    pub fn from_file(filename: &Path) -> Result<ProjectMeta>
     {
         let content = fs::read_to_string(filename).chain_err(|| "Could not read meta file")?;
-        let pm: ProjectMeta = serde_json::from_str(&content).chain_err(|| "Failed to parse project metadata")?;
+        let mut pm: ProjectMeta = serde_json::from_str(&content).chain_err(|| "Failed to parse project metadata")?;
+
+        for domain in pm.domains.as_deref().unwrap_or(&[])
+        {
+            domain.check_active_range()?;
+        }
+
+        // The identities file is kept apart, since resolve-identities
+        // rewrites it. Its path is relative to the metadata file.
+
+        if let Some(path) = &pm.identities_file
+        {
+            let path = filename.parent().unwrap_or_else(|| Path::new("")).join(path);
+            pm.identities = Some(Identities::from_file(&path)
+                                 .chain_err(|| format!("Could not load {}", path.display()))?);
+            pm.identities_file = Some(path);
+        }
 
         Ok(pm)
     }
 
+    pub fn identities_path(&self) -> Option<&PathBuf>
+    {
+        // Where the identities were loaded from, if anywhere.
+
+        self.identities_file.as_ref()
+    }
+
+    pub fn label(&self, text: &str) -> String
+    {
+        // Translate a built-in label (e.g. "Other", "Brief", "Authors")
+        // if the metadata provides a replacement.
+
+        self.labels.as_ref()
+            .and_then(|l| l.get(text))
+            .unwrap_or(&text.to_string())
+            .clone()
+    }
+
+    pub fn check(&self, cdb: &mut CommitDb) -> Result<Vec<String>>
+    {
+        // Look for things that are valid but probably not what was meant:
+        // patterns that match nothing or compete for the same commits, and
+        // markers that fall outside the ingested history.
+
+        let mut problems = Vec::new();
+        let domains = self.domains.as_deref().unwrap_or(&[]);
+        let patterns: Vec<(&DomainMeta, &AggregatePattern, String)> = domains.iter()
+            .flat_map(|d| d.aggregate_emails.as_deref().unwrap_or(&[]).iter()
+                          .map(move |ap| (d, ap, ap.sql_selector("author_email", "author_time"))))
+            .collect();
+
+        for domain in domains.iter().filter(|d| d.aggregate_emails.is_none())
+        {
+            let n = cdb.count_commits_where(&format!("author_domain = {}", sql_quote(&domain.name)))?;
+            if n == 0
+            {
+                problems.push(format!("domain \"{}\" has no commits", domain.name));
+            }
+        }
+
+        for (domain, ap, selector) in &patterns
+        {
+            if cdb.count_commits_where(selector)? == 0
+            {
+                problems.push(format!("{} matches no commits", ap.describe(domain)));
+            }
+        }
+
+        if let Some(bots) = &self.bots
+        {
+            for (kind, patterns) in &[ ("include", &bots.include), ("exclude", &bots.exclude) ]
+            {
+                for ap in patterns.as_deref().unwrap_or(&[])
+                {
+                    if cdb.count_commits_where(&BotMeta::sql_pattern_selector(ap))? == 0
+                    {
+                        problems.push(format!("bots {}: pattern \"{}\" matches no commits", kind, ap.pattern));
+                    }
+                }
+            }
+        }
+
+        for (i, (domain_a, ap_a, selector_a)) in patterns.iter().enumerate()
+        {
+            for (domain_b, ap_b, selector_b) in &patterns[i + 1..]
+            {
+                if domain_a.name == domain_b.name { continue; }
+
+                let n = cdb.count_commits_where(&format!("{} and {}", selector_a, selector_b))?;
+                if n > 0
+                {
+                    problems.push(format!("{} overlaps {} in {} commits",
+                                          ap_a.describe(domain_a), ap_b.describe(domain_b), n));
+                }
+            }
+        }
+
+        for x in self.excluded.as_deref().unwrap_or(&[])
+        {
+            if let Some(selector) = x.sql_repo_selector()
+            {
+                if cdb.count_commits_where(&selector)? == 0
+                {
+                    problems.push(format!("excluded interval for repo \"{}\" matches no commits",
+                                          x.repo.as_ref().unwrap()));
+                }
+            }
+        }
+
+        let epochs: Vec<(&Epoch, (NaiveDate, Option<NaiveDate>))> = self.epochs.as_deref().unwrap_or(&[]).iter()
+            .filter_map(|e| e.date_range().map(|range| (e, range)))
+            .collect();
+
+        for (i, (epoch_a, (begin_a, end_a))) in epochs.iter().enumerate()
+        {
+            if cdb.count_commits_where(&epoch_a.sql_selector().unwrap())? == 0
+            {
+                problems.push(format!("epoch \"{}\" has no commits", epoch_a.name));
+            }
+
+            for (epoch_b, (begin_b, end_b)) in &epochs[i + 1..]
+            {
+                if end_a.is_none_or(|end_a| end_a > *begin_b) && end_b.is_none_or(|end_b| end_b > *begin_a)
+                {
+                    problems.push(format!("epoch \"{}\" overlaps epoch \"{}\"", epoch_a.name, epoch_b.name));
+                }
+            }
+        }
+
+        if let Some((first, last)) = cdb.get_time_bounds()?
+        {
+            for marker in self.markers.as_deref().unwrap_or(&[])
+            {
+                // Marker months are 1-based. Markers without a month apply
+                // to the whole year.
+
+                let year = marker.time.year;
+                let (begin, end) = match marker.time.month
+                {
+                    Some(m) => (m - 1, m - 1),
+                    None => (0, 11)
+                };
+
+                if TimeBin::Month(year, end as u32) < first || TimeBin::Month(year, begin as u32) > last
+                {
+                    problems.push(format!("marker \"{}\" at {} is outside the data range",
+                                          marker.text,
+                                          match marker.time.month
+                                          {
+                                              Some(m) => format!("{}-{:02}", year, m),
+                                              None => year.to_string()
+                                          }));
+                }
+            }
+        }
+
+        Ok(problems)
+    }
+
+    pub fn canonical_repo_name(&self, repo_name: &str) -> String
+    {
+        // Follow aliases to the current name of a repo. Chains are allowed
+        // (e.g. after two renames), cycles are cut short.
+
+        let mut name = repo_name.to_string();
+
+        if let Some(aliases) = &self.repo_aliases
+        {
+            for _ in 0..aliases.len()
+            {
+                match aliases.get(&name)
+                {
+                    Some(next) => name = next.clone(),
+                    None => break
+                }
+            }
+        }
+
+        name
+    }
+
+    pub fn add_events_from_file(&mut self, filename: &Path) -> Result<()>
+    {
+        // Markers kept outside the metadata, so they can be shared between
+        // projects. One event per line as "date,label,row", where the date
+        // is YYYY, YYYY-MM or YYYY-MM-DD and the row may be left out.
+
+        let content = fs::read_to_string(filename).chain_err(|| "Could not read events file")?;
+        let markers = self.markers.get_or_insert_with(Vec::new);
+
+        for (i, line) in content.lines().enumerate()
+        {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+            if i == 0 && line.to_lowercase().starts_with("date,") { continue; }
+
+            markers.push(parse_event(line)
+                         .chain_err(|| format!("{}:{}: Could not parse event", filename.display(), i + 1))?);
+        }
+
+        Ok(())
+    }
+
+    pub fn marker_dates(&self) -> Vec<(NaiveDate, i32, String)>
+    {
+        // The first day each marker applies to, with its row and text, for
+        // charts that place markers by date. Marker months and days are
+        // 1-based.
+
+        self.markers.as_deref().unwrap_or(&[]).iter()
+            .filter_map(|m| NaiveDate::from_ymd_opt(m.time.year, m.time.month.unwrap_or(1) as u32,
+                                                    m.time.day.unwrap_or(1) as u32)
+                        .map(|date| (date, m.row, m.text.clone())))
+            .collect()
+    }
+
+    pub fn excluded_ranges(&self) -> Vec<(NaiveDate, NaiveDate, Option<String>)>
+    {
+        // The first day of each excluded interval and the day after its
+        // last, with its annotation. Intervals limited to some repos are
+        // left out of the query instead; see sql_repo_exclusion_selector().
+
+        self.excluded.as_deref().unwrap_or(&[]).iter()
+            .filter(|x| x.repo.is_none())
+            .filter_map(|x| x.date_range().map(|(begin, end)| (begin, end, x.text.clone())))
+            .collect()
+    }
+
+    pub fn epoch_ranges(&self) -> Vec<(NaiveDate, Option<NaiveDate>, String)>
+    {
+        // The first day of each epoch, the day after its last if it has
+        // ended, and its name.
+
+        self.epochs.as_deref().unwrap_or(&[]).iter()
+            .filter_map(|e| e.date_range().map(|(begin, end)| (begin, end, e.name.clone())))
+            .collect()
+    }
+
+    pub fn sql_epoch_selector(&self) -> Option<String>
+    {
+        // Names the epoch a commit falls in, or 'Unknown' if none. Where
+        // epochs overlap, the first one listed wins.
+
+        let cases: Vec<String> = self.epochs.as_deref().unwrap_or(&[]).iter()
+            .filter_map(|e| e.sql_selector().map(|sel| format!("when {} then {}", sel, sql_quote(&e.name))))
+            .collect();
+
+        if cases.is_empty() { None } else { Some(format!("case {} else 'Unknown' end", cases.join(" "))) }
+    }
+
+    pub fn sql_repo_exclusion_selector(&self) -> Option<String>
+    {
+        // Matches the commits in excluded intervals that are limited to
+        // some repos.
+
+        let selectors: Vec<String> = self.excluded.as_deref().unwrap_or(&[]).iter()
+            .filter_map(|x| x.sql_repo_selector())
+            .collect();
+
+        if selectors.is_empty() { None } else { Some(selectors.join(" or ")) }
+    }
+
     pub fn markers_to_gnuplot(&self) -> (String, i32)
     {
         if self.markers.is_none() || self.markers.as_ref().unwrap().is_empty()
@@ -128,9 +644,305 @@ impl ProjectMeta
             + &self.markers.as_ref().unwrap().iter()
                 .map(|m| { n_markers += 1;
                            format!("'{}', '{:02}', {}, '{}',",
-                                   m.time.year, m.time.month.unwrap_or(-1), m.row, m.text) })
+                                   m.time.year, m.time.month.unwrap_or(-1), m.row,
+                                   m.text.replace('\'', "''")) })
                 .collect::<Vec<String>>().join(" ")
-            + &" ];".to_string(),
+            + " ];",
          n_markers)
     }
 }
+
+fn parse_event(line: &str) -> Result<Marker>
+{
+    let (date, rest) = line.split_once(',').chain_err(|| "Expected date,label,row")?;
+
+    // The row is optional, and the label may itself contain commas.
+
+    let (text, row) = match rest.rsplit_once(',')
+    {
+        Some((text, row)) if row.trim().parse::<i32>().is_ok() => (text, row.trim().parse::<i32>().unwrap()),
+        _ => (rest, 0)
+    };
+
+    let mut text = text.trim();
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"')
+    {
+        text = &text[1..text.len() - 1];
+    }
+
+    let mut date_parts = date.trim().split('-');
+    let year = date_parts.next().unwrap().parse::<i32>()
+        .chain_err(|| format!("Invalid year in date \"{}\"", date.trim()))?;
+    let month = match date_parts.next()
+    {
+        Some(m) => match m.parse::<i32>()
+        {
+            Ok(m) if (1..=12).contains(&m) => Some(m),
+            _ => bail!("Invalid month in date \"{}\"", date.trim())
+        },
+        None => None
+    };
+    let day = match date_parts.next()
+    {
+        Some(d) => match d.parse::<i32>()
+        {
+            Ok(d) if NaiveDate::from_ymd_opt(year, month.unwrap() as u32, d as u32).is_some() => Some(d),
+            _ => bail!("Invalid day in date \"{}\"", date.trim())
+        },
+        None => None
+    };
+
+    Ok(Marker { time: YearMonth { year, month, day }, row, text: text.replace("\"\"", "\"") })
+}
+
+// Fields we know about, by JSON path with array indices removed. Anything
+// else is silently ignored by serde, which hides typos.
+
+const KNOWN_FIELDS: &[&str] =
+&[
+    "name", "first_year", "last_year", "labels", "labels.*", "repo_aliases", "repo_aliases.*",
+    "countries", "countries.*", "suffix_weights", "suffix_weights.*", "identities",
+    "markers", "markers.time", "markers.time.year", "markers.time.month", "markers.time.day",
+    "markers.row", "markers.text",
+    "excluded", "excluded.begin", "excluded.begin.year", "excluded.begin.month", "excluded.begin.day",
+    "excluded.end", "excluded.end.year", "excluded.end.month", "excluded.end.day", "excluded.text",
+    "excluded.repo",
+    "epochs", "epochs.name", "epochs.begin", "epochs.begin.year", "epochs.begin.month", "epochs.begin.day",
+    "epochs.end", "epochs.end.year", "epochs.end.month", "epochs.end.day",
+    "domains", "domains.name", "domains.show",
+    "domains.active_from", "domains.active_from.year", "domains.active_from.month", "domains.active_from.day",
+    "domains.active_to", "domains.active_to.year", "domains.active_to.month", "domains.active_to.day",
+    "domains.aggregate_emails",
+    "domains.aggregate_emails.pattern", "domains.aggregate_emails.pattern_type",
+    "domains.aggregate_emails.begin", "domains.aggregate_emails.begin.year",
+    "domains.aggregate_emails.begin.month",
+    "domains.aggregate_emails.end", "domains.aggregate_emails.end.year",
+    "domains.aggregate_emails.end.month",
+    "bots", "bots.include", "bots.exclude",
+    "bots.include.pattern", "bots.include.pattern_type",
+    "bots.include.begin", "bots.include.begin.year", "bots.include.begin.month",
+    "bots.include.end", "bots.include.end.year", "bots.include.end.month",
+    "bots.exclude.pattern", "bots.exclude.pattern_type",
+    "bots.exclude.begin", "bots.exclude.begin.year", "bots.exclude.begin.month",
+    "bots.exclude.end", "bots.exclude.end.year", "bots.exclude.end.month"
+];
+
+pub fn find_unknown_fields(content: &str) -> Result<Vec<String>>
+{
+    fn walk(value: &Value, path: &str, pattern: &str, unknown: &mut Vec<String>)
+    {
+        match value
+        {
+            Value::Object(map) =>
+            {
+                for (key, child) in map
+                {
+                    let child_path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                    let child_pattern = if pattern.is_empty() { key.clone() } else { format!("{}.{}", pattern, key) };
+
+                    if KNOWN_FIELDS.contains(&child_pattern.as_str())
+                    {
+                        walk(child, &child_path, &child_pattern, unknown);
+                    }
+                    else if !KNOWN_FIELDS.contains(&format!("{}.*", pattern).as_str())
+                    {
+                        unknown.push(child_path);
+                    }
+                }
+            },
+            Value::Array(items) =>
+            {
+                for (i, item) in items.iter().enumerate()
+                {
+                    walk(item, &format!("{}[{}]", path, i), pattern, unknown);
+                }
+            },
+            _ => { }
+        }
+    }
+
+    let value: Value = serde_json::from_str(content).chain_err(|| "Failed to parse project metadata")?;
+    let mut unknown = Vec::new();
+
+    walk(&value, "", "", &mut unknown);
+    Ok(unknown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::{ Connection, NO_PARAMS };
+    use crate::commitdb::add_regexp_function;
+
+    fn matches(pattern: &str, email: &str) -> bool {
+        let conn = Connection::open_in_memory().unwrap();
+        add_regexp_function(&conn).unwrap();
+
+        let ap: AggregatePattern = serde_json::from_str(pattern).unwrap();
+        conn.query_row(&format!("select {} from (select '{}' as author_email, 0 as author_time);",
+                                ap.sql_selector("author_email", "author_time"),
+                                email.replace('\'', "''")),
+                       NO_PARAMS, |r| r.get(0)).unwrap()
+    }
+
+    #[test]
+    fn glob_ignores_case_and_whitespace() {
+        assert!(matches(r#"{ "pattern": "*@redhat.com" }"#, "Jane@RedHat.COM"));
+        assert!(matches(r#"{ "pattern": " *@RedHat.com " }"#, " jane@redhat.com "));
+        assert!(!matches(r#"{ "pattern": "*@redhat.com" }"#, "jane@redhat.com.evil.org"));
+    }
+
+    #[test]
+    fn glob_subdomains() {
+        let pattern = r#"{ "pattern": "*@*.ibm.com" }"#;
+
+        assert!(matches(pattern, "joe@us.ibm.com"));
+        assert!(matches(pattern, "joe@linux.vnet.IBM.com"));
+        assert!(!matches(pattern, "joe@ibm.com"));
+        assert!(!matches(pattern, "joe@notibm.com"));
+    }
+
+    #[test]
+    fn regex_patterns() {
+        let pattern = r#"{ "pattern": "@(.+\\.)?(suse\\.(com|de|cz)|novell\\.com)$", "pattern_type": "regex" }"#;
+
+        assert!(matches(pattern, "hpj@suse.com"));
+        assert!(matches(pattern, "hpj@SUSE.CZ"));
+        assert!(matches(pattern, "hpj@provo.novell.com"));
+        assert!(!matches(pattern, "hpj@suse.community"));
+        assert!(!matches(pattern, "hpj@opensuse.org"));
+    }
+
+    #[test]
+    fn patterns_with_quotes() {
+        assert!(matches(r#"{ "pattern": "o'brien@*" }"#, "O'Brien@example.com"));
+    }
+
+    #[test]
+    fn time_bounds() {
+        let ap: AggregatePattern = serde_json::from_str(
+            r#"{ "pattern": "*@gnome.org", "begin": { "year": 2010 }, "end": { "year": 2012 } }"#).unwrap();
+
+        assert_eq!(ap.sql_selector("author_email", "author_time"),
+                   "(lower(trim(author_email)) glob '*@gnome.org' \
+                    and author_time >= 1262304000 and author_time < 1356998400)");
+    }
+
+    #[test]
+    fn domain_active_range() {
+        let domain: DomainMeta = serde_json::from_str(
+            r#"{ "name": "x.com", "active_from": { "year": 2010 }, "active_to": { "year": 2011, "month": 12 },
+                 "aggregate_emails": [ { "pattern": "*@x.com" }, { "pattern": "*@y.com" } ] }"#).unwrap();
+
+        assert_eq!(domain.sql_emails_selector(),
+                   "((lower(trim(author_email)) glob '*@x.com') or (lower(trim(author_email)) glob '*@y.com')) \
+                    and (author_time >= 1262304000 and author_time < 1325376000)");
+    }
+
+    #[test]
+    fn rejects_invalid_active_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("meta.json");
+
+        std::fs::write(&path, r#"{ "domains": [ { "name": "x.com", "active_to": { "year": 2011, "month": 12 } } ] }"#)
+            .unwrap();
+        assert!(ProjectMeta::from_file(&path).is_ok());
+
+        std::fs::write(&path, r#"{ "domains": [ { "name": "x.com", "active_to": { "year": 2011, "month": 13 } } ] }"#)
+            .unwrap();
+        assert!(ProjectMeta::from_file(&path).is_err());
+    }
+
+    #[test]
+    fn unknown_fields() {
+        let content = r#"{ "name": "X", "frist_year": 2000,
+                           "labels": { "Other": "Andre" },
+                           "domains": [ { "name": "a" },
+                                        { "name": "b", "aggregate_emails": [ { "patern": "*@b" } ] } ] }"#;
+
+        assert_eq!(find_unknown_fields(content).unwrap(),
+                   vec!["domains[1].aggregate_emails[0].patern", "frist_year"]);
+    }
+
+    #[test]
+    fn resolves_identities_path() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("identities.json"), r#"{ "merge": [], "split": [] }"#).unwrap();
+        std::fs::write(dir.path().join("meta.json"), r#"{ "identities": "identities.json" }"#).unwrap();
+
+        let meta = ProjectMeta::from_file(&dir.path().join("meta.json")).unwrap();
+        assert_eq!(meta.identities_path(), Some(&dir.path().join("identities.json")));
+        assert!(ProjectMeta::new().identities_path().is_none());
+    }
+
+    #[test]
+    fn known_bot_fields() {
+        let content = r#"{ "bots": { "include": [ { "pattern": "*[bot]*", "begin": { "year": 2015 } } ],
+                                     "exclude": [ { "pattern": "^robot", "pattern_type": "regex",
+                                                    "end": { "year": 2020, "month": 6 } } ] } }"#;
+
+        assert!(serde_json::from_str::<ProjectMeta>(content).is_ok());
+        assert!(find_unknown_fields(content).unwrap().is_empty());
+    }
+
+    #[test]
+    fn repo_alias_chains() {
+        let meta: ProjectMeta = serde_json::from_str(
+            r#"{ "repo_aliases": { "gitg-old": "gitg", "gitg": "GNOME/gitg", "a": "b", "b": "a" } }"#).unwrap();
+
+        assert_eq!(meta.canonical_repo_name("gitg-old"), "GNOME/gitg");
+        assert_eq!(meta.canonical_repo_name("gitg"), "GNOME/gitg");
+        assert_eq!(meta.canonical_repo_name("gtk"), "gtk");
+        meta.canonical_repo_name("a");
+    }
+
+    #[test]
+    fn excluded_ranges() {
+        let meta: ProjectMeta = serde_json::from_str(
+            r#"{ "excluded": [ { "begin": { "year": 2003 }, "text": "CVS import" },
+                               { "begin": { "year": 2005, "month": 11 }, "end": { "year": 2005, "month": 12 } },
+                               { "begin": { "year": 2007, "month": 3, "day": 31 } },
+                               { "begin": { "year": 2009 }, "end": { "year": 2008 } } ] }"#).unwrap();
+        let ymd = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+
+        assert_eq!(meta.excluded_ranges(),
+                   vec![ (ymd(2003, 1, 1), ymd(2004, 1, 1), Some("CVS import".to_string())),
+                         (ymd(2005, 11, 1), ymd(2006, 1, 1), None),
+                         (ymd(2007, 3, 31), ymd(2007, 4, 1), None) ]);
+    }
+
+    #[test]
+    fn epoch_ranges() {
+        let meta: ProjectMeta = serde_json::from_str(
+            r#"{ "epochs": [ { "name": "CVS era", "begin": { "year": 1997 }, "end": { "year": 2008, "month": 3 } },
+                             { "name": "Git era", "begin": { "year": 2008, "month": 4 } } ] }"#).unwrap();
+        let ymd = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+
+        assert_eq!(meta.epoch_ranges(),
+                   vec![ (ymd(1997, 1, 1), Some(ymd(2008, 4, 1)), "CVS era".to_string()),
+                         (ymd(2008, 4, 1), None, "Git era".to_string()) ]);
+        assert_eq!(meta.sql_epoch_selector().unwrap(),
+                   "case when (author_time >= 852076800 and author_time < 1207008000) then 'CVS era' \
+                    when (author_time >= 1207008000) then 'Git era' else 'Unknown' end");
+        assert!(ProjectMeta::new().sql_epoch_selector().is_none());
+    }
+
+    #[test]
+    fn parses_events() {
+        let m = parse_event("2011-04-06,\"GNOME 3.0, finally\",2").unwrap();
+        assert_eq!((m.time.year, m.time.month, m.row, m.text.as_str()), (2011, Some(4), 2, "GNOME 3.0, finally"));
+
+        let m = parse_event("2014-04-07,Heartbleed (CVE-2014-0160)").unwrap();
+        assert_eq!((m.time.year, m.time.month, m.row, m.text.as_str()), (2014, Some(4), 0, "Heartbleed (CVE-2014-0160)"));
+
+        let m = parse_event("1997,Founded,1").unwrap();
+        assert_eq!((m.time.year, m.time.month), (1997, None));
+
+        let m = parse_event("2020-02-29,Leap day").unwrap();
+        assert_eq!((m.time.year, m.time.month, m.time.day), (2020, Some(2), Some(29)));
+
+        assert!(parse_event("2021-02-29,Nope,0").is_err());
+        assert!(parse_event("1997-13,Nope,0").is_err());
+        assert!(parse_event("whenever").is_err());
+    }
+}
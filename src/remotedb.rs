@@ -0,0 +1,151 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* -------- *
+ * RemoteDb *
+ * -------- */
+
+use sha2::{ Digest, Sha256 };
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::process::Command;
+use crate::errors::*;
+
+// Published databases can be given as HTTP(S) URLs. They're downloaded to
+// a per-user cache, and revalidated with the server's ETag on later runs so
+// they're only fetched again when they change. We use curl for the
+// transfer, like we use git and gnuplot for everything else.
+
+pub fn is_http_url(db_path: &Path) -> bool
+{
+    let s = db_path.to_string_lossy();
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
+// Returns a local path for the database, fetching it first if it's remote.
+
+pub fn resolve_db_path(db_path: PathBuf) -> Result<PathBuf>
+{
+    if !is_http_url(&db_path) { return Ok(db_path); }
+
+    let url = db_path.to_string_lossy().into_owned();
+    let cache_dir = cache_dir()?;
+    fs::create_dir_all(&cache_dir).chain_err(|| format!("Could not create {}", cache_dir.display()))?;
+
+    let key = &format!("{:x}", Sha256::digest(url.as_bytes()))[..16];
+    let cached_path = cache_dir.join(format!("{}.sqlite", key));
+    let etag_path = cache_dir.join(format!("{}.etag", key));
+    let download_path = cache_dir.join(format!("{}.download", key));
+    let headers_path = cache_dir.join(format!("{}.headers", key));
+
+    let etag = if cached_path.exists() { fs::read_to_string(&etag_path).ok() } else { None };
+
+    let mut cmd = Command::new("curl");
+    cmd.arg("--silent")
+       .arg("--show-error")
+       .arg("--location")
+       .arg("--output").arg(&download_path)
+       .arg("--dump-header").arg(&headers_path)
+       .arg("--write-out").arg("%{http_code}");
+    if let Some(etag) = &etag
+    {
+        cmd.arg("--header").arg(format!("If-None-Match: {}", etag.trim()));
+    }
+    cmd.arg(&url);
+
+    let output = cmd.output().chain_err(|| "Could not run curl")?;
+    let status = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let headers = fs::read_to_string(&headers_path).unwrap_or_default();
+    fs::remove_file(&headers_path).ok();
+
+    match status.as_str()
+    {
+        "200" if output.status.success() =>
+        {
+            fs::rename(&download_path, &cached_path)
+                .chain_err(|| format!("Could not store {}", cached_path.display()))?;
+
+            match parse_etag(&headers)
+            {
+                Some(etag) => fs::write(&etag_path, etag).chain_err(|| "Could not store ETag")?,
+                None => { fs::remove_file(&etag_path).ok(); }
+            }
+        },
+        "304" => { fs::remove_file(&download_path).ok(); },
+        _ =>
+        {
+            // Keep going with what we have if the server can't be reached,
+            // so published databases can still be plotted offline.
+
+            fs::remove_file(&download_path).ok();
+
+            let reason = if status == "000" { String::from_utf8_lossy(&output.stderr).trim().to_string() }
+                         else { format!("HTTP status {}", status) };
+            if !cached_path.exists()
+            {
+                bail!("Could not download {}: {}", url, reason);
+            }
+
+            eprintln!("Could not revalidate {} ({}); using cached copy.", url, reason);
+        }
+    }
+
+    Ok(cached_path)
+}
+
+fn cache_dir() -> Result<PathBuf>
+{
+    if let Some(dir) = std::env::var_os("XDG_CACHE_HOME").filter(|d| !d.is_empty())
+    {
+        return Ok(PathBuf::from(dir).join("fornalder"));
+    }
+
+    let home = std::env::var_os("HOME").chain_err(|| "Could not find a cache directory; set XDG_CACHE_HOME")?;
+    Ok(PathBuf::from(home).join(".cache").join("fornalder"))
+}
+
+fn parse_etag(headers: &str) -> Option<String>
+{
+    // With redirects, curl dumps the headers of every response in turn; we
+    // want the last one's.
+
+    let last_response = headers.rsplit("HTTP/").next().unwrap_or("");
+
+    last_response.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("etag"))
+        .map(|(_, value)| value.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn etag_from_last_response() {
+        let headers = "HTTP/1.1 302 Found\r\nETag: \"old\"\r\nLocation: /db\r\n\r\n\
+                       HTTP/2 200\r\ncontent-type: application/octet-stream\r\netag: W/\"abc\"\r\n\r\n";
+
+        assert_eq!(parse_etag(headers), Some("W/\"abc\"".to_string()));
+        assert_eq!(parse_etag("HTTP/1.1 200 OK\r\n\r\n"), None);
+        assert!(is_http_url(&PathBuf::from("https://example.org/gnome.sqlite")));
+        assert!(!is_http_url(&PathBuf::from("gnome.sqlite")));
+    }
+}
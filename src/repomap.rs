@@ -0,0 +1,71 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* ------- *
+ * RepoMap *
+ * ------- */
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::*;
+use crate::errors::*;
+
+// Maps repository paths to the names they should be ingested under. The
+// file is a flat JSON object, e.g:
+//
+// { "gnome/gtk/checkout": "gtk", "/srv/git/glib.git": "glib" }
+//
+// Relative paths are resolved against the current working directory.
+
+pub struct RepoMap
+{
+    names: HashMap<PathBuf, String>
+}
+
+impl RepoMap
+{
+    pub fn new() -> RepoMap
+    {
+        RepoMap { names: HashMap::new() }
+    }
+
+    pub fn from_file(filename: &Path) -> Result<RepoMap>
+    {
+        let content = fs::read_to_string(filename).chain_err(|| "Could not read repo map file")?;
+        let raw: HashMap<String, String> = serde_json::from_str(&content)
+            .chain_err(|| "Failed to parse repo map")?;
+        let mut names = HashMap::new();
+
+        for (path, name) in raw
+        {
+            let path = PathBuf::from(&path);
+            let path = path.canonicalize().unwrap_or(path);
+            names.insert(path, name.trim().to_string());
+        }
+
+        Ok(RepoMap { names })
+    }
+
+    pub fn get_name(&self, repo_path: &Path) -> Option<&String>
+    {
+        let repo_path = repo_path.canonicalize().ok()?;
+        self.names.get(&repo_path)
+    }
+}
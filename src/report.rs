@@ -0,0 +1,396 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* ------ *
+ * Report *
+ * ------ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::*;
+use std::str::FromStr;
+use crate::common::ReportFormat;
+use crate::errors::*;
+use crate::timebin::TimeBin;
+
+// A span of whole months, given as "2020" or "2020-Q3".
+
+#[derive(Debug, Copy, Clone)]
+pub struct Period
+{
+    pub first: TimeBin,
+    pub last: TimeBin
+}
+
+impl Period
+{
+    pub fn n_months(&self) -> i32
+    {
+        month_index(self.last) - month_index(self.first) + 1
+    }
+
+    pub fn previous(&self) -> Period
+    {
+        let n = self.n_months();
+
+        Period { first: TimeBin::from_month_index(month_index(self.first) - n),
+                 last: TimeBin::from_month_index(month_index(self.last) - n) }
+    }
+
+    pub fn is_quarter(&self) -> bool
+    {
+        self.n_months() == 3
+    }
+
+    pub fn name(&self) -> String
+    {
+        match self.first
+        {
+            TimeBin::Month(year, month) if self.is_quarter() => format!("{}-Q{}", year, month / 3 + 1),
+            _ => self.first.year().to_string()
+        }
+    }
+}
+
+impl FromStr for Period
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Period, String>
+    {
+        let invalid = || format!("Expected a year or a quarter like 2020-Q3, got '{}'", s);
+        let mut parts = s.splitn(2, "-Q");
+        let year: i32 = parts.next().unwrap().parse().map_err(|_| invalid())?;
+
+        match parts.next()
+        {
+            None => Ok(Period { first: TimeBin::Month(year, 0), last: TimeBin::Month(year, 11) }),
+            Some(q) =>
+            {
+                let q: u32 = q.parse().map_err(|_| invalid())?;
+                if !(1..=4).contains(&q) { return Err(invalid()); }
+
+                Ok(Period { first: TimeBin::Month(year, (q - 1) * 3),
+                            last: TimeBin::Month(year, (q - 1) * 3 + 2) })
+            }
+        }
+    }
+}
+
+pub fn month_index(ym: TimeBin) -> i32
+{
+    match ym
+    {
+        TimeBin::Month(year, month) => year * 12 + month as i32,
+        _ => ym.year() * 12
+    }
+}
+
+pub struct PeriodStats
+{
+    pub n_commits: i64,
+    pub n_authors: i64,
+    pub new_authors: Vec<(String, i64)>,
+    pub top_authors: Vec<(String, i64, i64)>,
+    pub busiest_month: Option<(TimeBin, i64)>
+}
+
+// A table of rows under a caption. In HTML, it can be sorted by clicking on
+// a column header.
+
+struct Table
+{
+    caption: String,
+    header: Vec<String>,
+    rows: Vec<Vec<String>>
+}
+
+pub struct Report
+{
+    title: String,
+    period: Period,
+    stats: PeriodStats,
+    gainers: Vec<(String, i64)>,
+    losers: Vec<(String, i64)>,
+    domains: Vec<(String, i64, i64)>,
+    plots: Vec<(String, String)>,
+    parameters: Vec<(String, String)>
+}
+
+const N_LISTED: usize = 10;
+const N_TABLED: usize = 25;
+
+// Sorts a table by the clicked column, numerically if every cell in it is a
+// number. Clicking the same column again reverses the order.
+
+const SORT_SCRIPT: &str = "<script>
+document.querySelectorAll('table.sortable th').forEach(function (th) {
+  th.style.cursor = 'pointer';
+  th.addEventListener('click', function () {
+    var tbody = th.closest('table').tBodies[0];
+    var rows = Array.from(tbody.rows);
+    var cell = function (r) { return r.cells[th.cellIndex].textContent; };
+    var numeric = rows.every(function (r) { return cell(r) !== '' && !isNaN(cell(r)); });
+    var dir = th.dataset.dir === 'desc' ? 1 : -1;
+    rows.sort(function (a, b) {
+      return dir * (numeric ? cell(a) - cell(b) : cell(a).localeCompare(cell(b)));
+    });
+    th.closest('tr').querySelectorAll('th').forEach(function (h) { delete h.dataset.dir; });
+    th.dataset.dir = dir === 1 ? 'asc' : 'desc';
+    rows.forEach(function (r) { tbody.appendChild(r); });
+  });
+});
+</script>
+";
+
+impl Report
+{
+    pub fn new(title: &str, period: Period, stats: PeriodStats,
+               domain_commits: &HashMap<String, i64>, prev_domain_commits: &HashMap<String, i64>,
+               plots: Vec<(String, String)>, parameters: Vec<(String, String)>) -> Report
+    {
+        // Domains ranked by change in commits since the previous period of
+        // the same length.
+
+        let mut deltas: Vec<(String, i64)> = domain_commits.keys().chain(prev_domain_commits.keys())
+            .map(|d| (d.clone(),
+                      domain_commits.get(d).unwrap_or(&0) - prev_domain_commits.get(d).unwrap_or(&0)))
+            .collect();
+        deltas.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        deltas.dedup();
+
+        let gainers = deltas.iter().filter(|(_, d)| *d > 0).take(N_LISTED / 2).cloned().collect();
+        let losers = deltas.iter().rev().filter(|(_, d)| *d < 0).take(N_LISTED / 2).cloned().collect();
+
+        let mut domains: Vec<(String, i64, i64)> = domain_commits.iter()
+            .map(|(d, n)| (d.clone(), *n, *prev_domain_commits.get(d).unwrap_or(&0)))
+            .collect();
+        domains.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        domains.truncate(N_TABLED);
+
+        Report { title: title.to_string(), period, stats, gainers, losers, domains, plots, parameters }
+    }
+
+    pub fn embed_plots(&mut self, plot_dir: &Path) -> Result<()>
+    {
+        // Replace plot file names with data URIs, so the HTML report is a
+        // single file that can be passed around on its own.
+
+        for (_, src) in self.plots.iter_mut()
+        {
+            let png = fs::read(plot_dir.join(&src)).chain_err(|| format!("Could not read plot {}", src))?;
+            *src = format!("data:image/png;base64,{}", encode_base64(&png));
+        }
+
+        Ok(())
+    }
+
+    fn sections(&self) -> Vec<(String, Vec<String>)>
+    {
+        let summary = vec![
+            format!("{} commits by {} authors.", self.stats.n_commits, self.stats.n_authors),
+            format!("{} new contributors.", self.stats.new_authors.len()),
+            match self.stats.busiest_month
+            {
+                Some((ym, n)) => format!("Busiest month: {} with {} commits.", ym, n),
+                None => "No commits in this period.".to_string()
+            }
+        ];
+        let new_authors = self.stats.new_authors.iter().take(N_LISTED)
+            .map(|(name, n)| format!("{} ({} commits)", name, n))
+            .collect();
+        let gainers = self.gainers.iter().map(|(d, n)| format!("{} (+{} commits)", d, n)).collect();
+        let losers = self.losers.iter().map(|(d, n)| format!("{} ({} commits)", d, n)).collect();
+
+        vec![ ("Summary".to_string(), summary),
+              ("Most active new contributors".to_string(), new_authors),
+              (format!("Domains gaining commits since {}", self.period.previous().name()), gainers),
+              (format!("Domains losing commits since {}", self.period.previous().name()), losers) ]
+    }
+
+    fn tables(&self) -> Vec<Table>
+    {
+        let authors = Table
+        {
+            caption: "Top authors".to_string(),
+            header: vec![ "Author".to_string(), "Commits".to_string(), "Changes".to_string() ],
+            rows: self.stats.top_authors.iter().take(N_TABLED)
+                .map(|(name, n_commits, n_changes)| vec![ name.clone(), n_commits.to_string(), n_changes.to_string() ])
+                .collect()
+        };
+        let domains = Table
+        {
+            caption: "Top domains".to_string(),
+            header: vec![ "Domain".to_string(), "Commits".to_string(),
+                          format!("Commits in {}", self.period.previous().name()) ],
+            rows: self.domains.iter()
+                .map(|(domain, n, prev_n)| vec![ domain.clone(), n.to_string(), prev_n.to_string() ])
+                .collect()
+        };
+        let parameters = Table
+        {
+            caption: "Parameters".to_string(),
+            header: vec![ "Parameter".to_string(), "Value".to_string() ],
+            rows: self.parameters.iter().map(|(k, v)| vec![ k.clone(), v.clone() ]).collect()
+        };
+
+        vec![ authors, domains, parameters ].into_iter().filter(|t| !t.rows.is_empty()).collect()
+    }
+
+    pub fn to_markdown(&self) -> String
+    {
+        let mut md = format!("# {} {}\n", self.title, self.period.name());
+
+        for (heading, items) in self.sections()
+        {
+            if items.is_empty() { continue; }
+
+            md += &format!("\n## {}\n\n", heading);
+            md += &items.iter().map(|i| format!("- {}\n", i)).collect::<String>();
+        }
+
+        for (caption, file_name) in &self.plots
+        {
+            md += &format!("\n## {}\n\n![{}]({})\n", caption, caption, file_name);
+        }
+
+        for table in self.tables()
+        {
+            let row = |cells: &[String]| format!("| {} |\n", cells.iter().map(|c| c.replace('|', "\\|"))
+                                                  .collect::<Vec<_>>().join(" | "));
+
+            md += &format!("\n## {}\n\n", table.caption);
+            md += &row(&table.header);
+            md += &format!("|{}\n", "---|".repeat(table.header.len()));
+            md += &table.rows.iter().map(|r| row(r)).collect::<String>();
+        }
+
+        md
+    }
+
+    pub fn to_html(&self) -> String
+    {
+        let title = html_escape(&format!("{} {}", self.title, self.period.name()));
+        let mut html = format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+                                <title>{}</title>\n</head>\n<body>\n<h1>{}</h1>\n", title, title);
+
+        for (heading, items) in self.sections()
+        {
+            if items.is_empty() { continue; }
+
+            html += &format!("<h2>{}</h2>\n<ul>\n", html_escape(&heading));
+            html += &items.iter().map(|i| format!("<li>{}</li>\n", html_escape(i))).collect::<String>();
+            html += "</ul>\n";
+        }
+
+        for (caption, file_name) in &self.plots
+        {
+            html += &format!("<h2>{}</h2>\n<img src=\"{}\" alt=\"{}\" width=\"100%\">\n",
+                             html_escape(caption), html_escape(file_name), html_escape(caption));
+        }
+
+        for table in self.tables()
+        {
+            let row = |tag: &str, cells: &[String]| format!("<tr>{}</tr>\n", cells.iter()
+                                                            .map(|c| format!("<{}>{}</{}>", tag, html_escape(c), tag))
+                                                            .collect::<String>());
+
+            html += &format!("<h2>{}</h2>\n<table class=\"sortable\">\n<thead>\n{}</thead>\n<tbody>\n",
+                             html_escape(&table.caption), row("th", &table.header));
+            html += &table.rows.iter().map(|r| row("td", r)).collect::<String>();
+            html += "</tbody>\n</table>\n";
+        }
+
+        html + SORT_SCRIPT + "</body>\n</html>\n"
+    }
+
+    pub fn write(&self, out_path: &Path, format: ReportFormat) -> Result<()>
+    {
+        let content = match format
+        {
+            ReportFormat::Markdown => self.to_markdown(),
+            ReportFormat::Html => self.to_html()
+        };
+
+        fs::write(out_path, content).chain_err(|| "Could not write report")
+    }
+}
+
+fn html_escape(s: &str) -> String
+{
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn encode_base64(data: &[u8]) -> String
+{
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3)
+    {
+        let bits = chunk.iter().enumerate().fold(0u32, |acc, (i, b)| acc | (*b as u32) << (16 - 8 * i));
+
+        for i in 0..4
+        {
+            text.push(if i <= chunk.len() { ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char }
+                      else { '=' });
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_periods() {
+        let q: Period = "2020-Q1".parse().unwrap();
+
+        assert_eq!((q.first, q.last), (TimeBin::Month(2020, 0), TimeBin::Month(2020, 2)));
+        assert_eq!(q.previous().name(), "2019-Q4");
+        assert_eq!("2020".parse::<Period>().unwrap().previous().name(), "2019");
+        assert!("2020-Q5".parse::<Period>().is_err());
+        assert!("Q1".parse::<Period>().is_err());
+    }
+
+    #[test]
+    fn domain_gainers_and_losers() {
+        let current: HashMap<String, i64> = vec![("a".to_string(), 10), ("b".to_string(), 2)].into_iter().collect();
+        let previous: HashMap<String, i64> = vec![("b".to_string(), 5), ("c".to_string(), 1)].into_iter().collect();
+        let stats = PeriodStats { n_commits: 12, n_authors: 3, new_authors: vec![], top_authors: vec![],
+                                  busiest_month: None };
+        let report = Report::new("X", "2020".parse().unwrap(), stats, &current, &previous, vec![], vec![]);
+
+        assert_eq!(report.gainers, vec![("a".to_string(), 10)]);
+        assert_eq!(report.losers, vec![("b".to_string(), -3), ("c".to_string(), -1)]);
+        assert!(report.to_html().contains("<li>12 commits by 3 authors.</li>"));
+        assert!(report.to_html().contains("<tr><td>a</td><td>10</td><td>0</td></tr>\n<tr><td>b</td><td>2</td><td>5</td></tr>"));
+    }
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(encode_base64(b""), "");
+        assert_eq!(encode_base64("Jörg".as_bytes()), "SsO2cmc=");
+        assert_eq!(encode_base64(b"ab"), "YWI=");
+        assert_eq!(encode_base64(b"abc"), "YWJj");
+    }
+}
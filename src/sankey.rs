@@ -0,0 +1,152 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+
+/* ------ *
+ * Sankey *
+ * ------ */
+
+use serde::{Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::*;
+use crate::common::ExportFormat;
+use crate::csvformat::{ csv_header, format_csv };
+use crate::errors::*;
+
+const ABSENT_NODE: &str = "(absent)";
+
+#[derive(Serialize, Debug)]
+struct SankeyEdge
+{
+    source: String,
+    target: String,
+    value: i64
+}
+
+// Author flows between cohorts across a sequence of periods. Each author is
+// assigned their dominant cohort (most commits) in every period, and an edge
+// is counted for each author between consecutive periods. Authors who join
+// or leave are routed through an "(absent)" node.
+
+pub struct Sankey
+{
+    period_names: Vec<String>,
+    edges: BTreeMap<(usize, String, String), i64>
+}
+
+impl Sankey
+{
+    pub fn new(boundaries: &[i32], dominant: &[(String, usize, String)]) -> Sankey
+    {
+        let n_periods = boundaries.len();
+        let mut authors: HashMap<&str, Vec<Option<&str>>> = HashMap::new();
+
+        for (author, period, item) in dominant
+        {
+            authors.entry(author).or_insert_with(|| vec![None; n_periods])[*period] = Some(item);
+        }
+
+        let mut edges = BTreeMap::new();
+
+        for periods in authors.values()
+        {
+            for i in 1..n_periods
+            {
+                if periods[i - 1].is_none() && periods[i].is_none() { continue; }
+
+                let from = periods[i - 1].unwrap_or(ABSENT_NODE).to_string();
+                let to = periods[i].unwrap_or(ABSENT_NODE).to_string();
+                *edges.entry((i - 1, from, to)).or_insert(0) += 1;
+            }
+        }
+
+        Sankey { period_names: period_names(boundaries), edges }
+    }
+
+    fn to_edges(&self) -> Vec<SankeyEdge>
+    {
+        self.edges.iter()
+            .map(|((period, from, to), value)| SankeyEdge
+                 {
+                     source: format!("{}: {}", self.period_names[*period], from),
+                     target: format!("{}: {}", self.period_names[*period + 1], to),
+                     value: *value
+                 })
+            .collect()
+    }
+
+    pub fn to_csv(&self, delimiter: u8) -> String
+    {
+        let mut rows = vec![ csv_header(&[ "source", "target", "value" ]) ];
+
+        rows.extend(self.to_edges().into_iter().map(|e| vec![ e.source, e.target, e.value.to_string() ]));
+        format_csv(&rows, delimiter)
+    }
+
+    pub fn write(&self, out_path: &Path, format: ExportFormat, delimiter: u8) -> Result<()>
+    {
+        let content = match format
+        {
+            ExportFormat::Csv => self.to_csv(delimiter),
+            ExportFormat::Json => serde_json::to_string_pretty(&self.to_edges())
+                .chain_err(|| "Could not serialize Sankey edges")?,
+            ExportFormat::Parquet => bail!("Sankey edges can only be exported as CSV or JSON")
+        };
+
+        fs::write(out_path, content + "\n").chain_err(|| "Could not write Sankey edges")
+    }
+}
+
+fn period_names(boundaries: &[i32]) -> Vec<String>
+{
+    boundaries.iter().enumerate()
+        .map(|(i, b)| match boundaries.get(i + 1)
+             {
+                 Some(next) if *next - 1 > *b => format!("{}-{}", b, next - 1),
+                 Some(_) => b.to_string(),
+                 None => format!("{}-", b)
+             })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sankey_flows() {
+        let dominant = vec![
+            ("a".to_string(), 0, "gnome.org".to_string()),
+            ("a".to_string(), 1, "redhat.com".to_string()),
+            ("b".to_string(), 0, "gnome.org".to_string()),
+            ("c".to_string(), 1, "redhat.com".to_string()),
+        ];
+        let sankey = Sankey::new(&[2010, 2015], &dominant);
+
+        assert_eq!(
+            sankey.to_csv(b'|'),
+            "source|target|value\n\
+             2010-2014: (absent)|2015-: redhat.com|1\n\
+             2010-2014: gnome.org|2015-: (absent)|1\n\
+             2010-2014: gnome.org|2015-: redhat.com|1",
+        );
+    }
+}
@@ -0,0 +1,229 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* -------- *
+ * Seasonal *
+ * -------- */
+
+use crate::cohorthist::{ CohortHist, NO_COHORT };
+use crate::csvformat::{ csv_header, format_csv };
+use crate::errors::*;
+use crate::timebin::TimeBin;
+
+const MONTH_NAMES: [&str; 12] = [ "Jan", "Feb", "Mar", "Apr", "May", "Jun",
+                                  "Jul", "Aug", "Sep", "Oct", "Nov", "Dec" ];
+
+// The average month-of-year profile of each cohort in a monthly histogram,
+// e.g. showing summer peaks from student programs or lulls in December.
+
+pub struct SeasonalProfile
+{
+    pub names: Vec<String>,
+    pub n_cohorts: i32,
+    pub n_years: i32,
+    values: Vec<Vec<f64>>
+}
+
+impl SeasonalProfile
+{
+    pub fn new(hist: &CohortHist, first_year: Option<i32>, last_year: Option<i32>) -> Result<SeasonalProfile>
+    {
+        let (first_ym, last_ym, first_cohort, last_cohort) = match hist.get_bounds()
+        {
+            Some(bounds) if matches!(bounds.0, TimeBin::Month(..)) => bounds,
+            Some(_) => bail!("Seasonal profiles need a monthly histogram"),
+            None => bail!("No data")
+        };
+        let first_year = first_year.unwrap_or(first_ym.year()).max(first_ym.year());
+        let last_year = last_year.unwrap_or(last_ym.year()).min(last_ym.year());
+        if first_year > last_year
+        {
+            bail!("No data in the selected years");
+        }
+
+        let mut cohorts: Vec<i32> = (first_cohort..=last_cohort).collect();
+        if !hist.get_cohort_name(NO_COHORT).is_empty() { cohorts.push(NO_COHORT); }
+
+        // Average over the years each month was covered, so a partial first
+        // or last year doesn't drag its months down.
+
+        let mut values = vec![vec![0.0; cohorts.len()]; 12];
+        let mut n_samples = [0; 12];
+
+        for year in first_year..=last_year
+        {
+            for month in 0..12
+            {
+                let ym = TimeBin::Month(year, month);
+                if ym < first_ym || ym > last_ym { continue; }
+
+                for (i, cohort) in cohorts.iter().enumerate()
+                {
+                    values[month as usize][i] += hist.get_value(ym, *cohort).unwrap_or(0.0);
+                }
+                n_samples[month as usize] += 1;
+            }
+        }
+
+        for (month_values, n) in values.iter_mut().zip(&n_samples)
+        {
+            if *n > 0 { month_values.iter_mut().for_each(|v| *v /= *n as f64); }
+        }
+
+        Ok(SeasonalProfile
+        {
+            names: cohorts.iter().map(|g| hist.get_cohort_name(*g)).collect(),
+            n_cohorts: last_cohort - first_cohort + 1,
+            n_years: last_year - first_year + 1,
+            values
+        })
+    }
+
+    pub fn to_csv(&self, delimiter: u8) -> String
+    {
+        let mut rows = vec![ csv_header(&[ "Month", "Sum" ]) ];
+
+        rows[0].extend(self.names.iter()
+                       .map(|n| if n.is_empty() { "(blank)".to_string() } else { n.clone() }));
+        for (month_values, name) in self.values.iter().zip(MONTH_NAMES.iter())
+        {
+            let mut row = vec![ name.to_string(), format!("{:.2}", month_values.iter().fold(0.0, |sum, v| sum + v)) ];
+
+            row.extend(month_values.iter().map(|v| format!("{:.2}", v)));
+            rows.push(row);
+        }
+
+        format_csv(&rows, delimiter)
+    }
+}
+
+pub fn seasonally_adjust(hist: &CohortHist) -> Result<CohortHist>
+{
+    // Classical multiplicative decomposition: each value is divided by its
+    // month's seasonal index, the average ratio of that month's values to
+    // a centered 12-month moving average. Each cohort gets its own indexes.
+
+    let (first_ym, _, _, _) = match hist.get_bounds()
+    {
+        Some(bounds) if matches!(bounds.0, TimeBin::Month(..)) => bounds,
+        Some(_) => bail!("Seasonal adjustment needs a monthly histogram"),
+        None => bail!("No data")
+    };
+
+    let vecs = hist.to_vecs();
+    if vecs.len() < 24
+    {
+        bail!("Seasonal adjustment needs at least two years of monthly data");
+    }
+
+    // The first entry of each row is the sum, which we recompute. Rows
+    // start in January, as to_vecs() pads out the first year.
+
+    let n_columns = vecs[0].1.len();
+    let mut adjusted = CohortHist::new();
+
+    for column in 1..n_columns
+    {
+        let cohort = vecs[0].1[column].0;
+        let series: Vec<f64> = vecs.iter().map(|(_, values)| values[column].1).collect();
+
+        for (ym, value) in vecs.iter().map(|(ym, _)| *ym).zip(adjust_series(&series))
+        {
+            if ym >= first_ym { adjusted.set_value(ym, cohort, value); }
+        }
+        adjusted.set_cohort_name(cohort, &hist.get_cohort_name(cohort));
+    }
+
+    adjusted.set_decimals(Some(2));
+    Ok(adjusted)
+}
+
+fn adjust_series(series: &[f64]) -> Vec<f64>
+{
+    // The series is expected to start in January.
+
+    let n = series.len();
+    let mut ratio_sums = [0.0; 12];
+    let mut n_ratios = [0; 12];
+
+    for t in 6..n.saturating_sub(6)
+    {
+        let ma = (0.5 * series[t - 6] + series[t - 5..=t + 5].iter().sum::<f64>() + 0.5 * series[t + 6]) / 12.0;
+        if ma > 0.0
+        {
+            ratio_sums[t % 12] += series[t] / ma;
+            n_ratios[t % 12] += 1;
+        }
+    }
+
+    // Months without any activity to compare against are left as they are.
+
+    let mut indexes = [1.0; 12];
+    for m in 0..12
+    {
+        if n_ratios[m] > 0 { indexes[m] = ratio_sums[m] / n_ratios[m] as f64; }
+    }
+
+    let mean = indexes.iter().sum::<f64>() / 12.0;
+    if mean > 0.0 { indexes.iter_mut().for_each(|i| *i /= mean); }
+
+    series.iter().enumerate()
+        .map(|(t, v)| { let i = indexes[t % 12]; if i > 0.0 { v / i } else { *v } })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seasonal_hist() -> CohortHist {
+        // Three years with a summer peak on top of a steady level.
+
+        let mut hist = CohortHist::new();
+        for year in 2018..2021
+        {
+            for month in 0..12
+            {
+                let value = if month == 6 { 30.0 } else { 10.0 };
+                hist.set_value(TimeBin::Month(year, month), 0, value);
+            }
+        }
+        hist.set_cohort_name(0, "gsoc");
+        hist
+    }
+
+    #[test]
+    fn month_profile() {
+        let profile = SeasonalProfile::new(&seasonal_hist(), Some(2019), None).unwrap();
+
+        assert_eq!(profile.n_years, 2);
+        assert!(profile.to_csv(b'|').starts_with("Month|Sum|gsoc\nJan|10.00|10.00\n"));
+        assert!(profile.to_csv(b'|').contains("\nJul|30.00|30.00\n"));
+    }
+
+    #[test]
+    fn adjustment_flattens_seasonality() {
+        let adjusted = seasonally_adjust(&seasonal_hist()).unwrap();
+        let jun = adjusted.get_value(TimeBin::Month(2019, 5), 0).unwrap();
+        let jul = adjusted.get_value(TimeBin::Month(2019, 6), 0).unwrap();
+
+        assert!((jun - jul).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,127 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* ------------ *
+ * SnapshotDiff *
+ * ------------ */
+
+use crate::cohorthist::CohortHist;
+use crate::common::YearRange;
+use crate::csvformat::format_csv;
+use crate::timebin::TimeBin;
+
+// Per-cohort yearly averages in two windows of time. Averages rather than
+// totals let windows of different lengths be compared, and keep distinct
+// author counts from adding up across years.
+
+pub struct SnapshotDiff
+{
+    pub a: YearRange,
+    pub b: YearRange,
+    pub rows: Vec<(String, f64, f64)>
+}
+
+impl SnapshotDiff
+{
+    pub fn new(hist: &CohortHist, a: YearRange, b: YearRange) -> SnapshotDiff
+    {
+        let mut rows = Vec::new();
+
+        if let Some((_, _, first_cohort, last_cohort)) = hist.get_bounds()
+        {
+            let window_avg = |cohort: i32, range: YearRange|
+            {
+                (range.first..=range.last)
+                    .filter_map(|year| hist.get_value(TimeBin::Year(year), cohort))
+                    .fold(0.0, |sum, v| sum + v) / range.n_years() as f64
+            };
+
+            for cohort in first_cohort..=last_cohort
+            {
+                let (a_avg, b_avg) = (window_avg(cohort, a), window_avg(cohort, b));
+                if a_avg == 0.0 && b_avg == 0.0 { continue; }
+
+                rows.push((hist.get_cohort_name(cohort), a_avg, b_avg));
+            }
+        }
+
+        // Biggest movers first, growth before shrinkage on ties.
+
+        rows.sort_by(|x, y| (y.2 - y.1).abs().partial_cmp(&(x.2 - x.1).abs()).unwrap()
+                     .then((y.2 - y.1).partial_cmp(&(x.2 - x.1)).unwrap())
+                     .then(x.0.cmp(&y.0)));
+
+        SnapshotDiff { a, b, rows }
+    }
+
+    pub fn to_table(&self) -> String
+    {
+        let mut table = format!("{:<32} {:>12} {:>12} {:>12} {:>9}\n",
+                                "Cohort", self.a.to_string(), self.b.to_string(), "Change", "Relative");
+
+        for (name, a_avg, b_avg) in &self.rows
+        {
+            table += &format!("{:<32} {:>12.1} {:>12.1} {:>+12.1} {:>9}\n",
+                              name, a_avg, b_avg, b_avg - a_avg, format_relative(*a_avg, *b_avg));
+        }
+
+        table
+    }
+
+    pub fn to_csv(&self, delimiter: u8) -> String
+    {
+        let mut rows = vec![ vec![ "Cohort".to_string(), self.a.to_string(), self.b.to_string() ] ];
+
+        rows.extend(self.rows.iter()
+                    .map(|(name, a_avg, b_avg)| vec![ name.clone(), a_avg.to_string(), b_avg.to_string() ]));
+        format_csv(&rows, delimiter)
+    }
+}
+
+fn format_relative(a: f64, b: f64) -> String
+{
+    if a == 0.0 { "new".to_string() }
+    else { format!("{:+.0}%", (b - a) / a * 100.0) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_and_ordering() {
+        let mut hist = CohortHist::new();
+        let ym = |year| TimeBin::Year(year);
+
+        hist.set_value(ym(2010), 0, 10.0);
+        hist.set_value(ym(2011), 0, 20.0);
+        hist.set_value(ym(2012), 0, 4.0);
+        hist.set_value(ym(2012), 1, 6.0);
+        hist.set_cohort_name(0, "shrinking");
+        hist.set_cohort_name(1, "new");
+
+        let diff = SnapshotDiff::new(&hist, "2010..2011".parse().unwrap(), "2012".parse().unwrap());
+
+        assert_eq!(diff.rows, vec![("shrinking".to_string(), 15.0, 4.0),
+                                   ("new".to_string(), 0.0, 6.0)]);
+        assert_eq!(format_relative(15.0, 4.0), "-73%");
+        assert_eq!(format_relative(0.0, 6.0), "new");
+    }
+}
@@ -26,15 +26,19 @@ use chrono::Datelike;
 use chrono::prelude::Utc;
 use std::io;
 use std::io::Write;
+use std::time::{ Duration, Instant };
 use crate::gitcommitreader::RawCommit;
 
 pub struct StatusLogger
 {
     repo_name: String,
     n_commits: u32,
+    n_expected: Option<u64>,
+    started: Instant,
     last_timestamp: i64,
     last_year: i32,
-    last_month: i32
+    last_month: i32,
+    finished: Vec<(String, u32, Duration)>
 }
 
 impl StatusLogger
@@ -45,9 +49,12 @@ impl StatusLogger
         {
             repo_name: "".to_string(),
             n_commits: 0,
+            n_expected: None,
+            started: Instant::now(),
             last_timestamp: 0,
             last_year: 0,
             last_month: 0,
+            finished: Vec::new()
         }
     }
 
@@ -55,6 +62,8 @@ impl StatusLogger
     {
         self.repo_name = repo_name.to_string();
         self.n_commits = 0;
+        self.n_expected = None;
+        self.started = Instant::now();
         self.last_timestamp = 0;
         self.last_year = 0;
         self.last_month = 0;
@@ -63,12 +72,38 @@ impl StatusLogger
         io::stdout().flush().unwrap();
     }
 
+    // The number of commits we expect to read, if known. Enables the ETA.
+
+    pub fn set_expected_commits(&mut self, n_expected: u64)
+    {
+        self.n_expected = Some(n_expected);
+    }
+
     pub fn log_warning(&mut self, message: &str)
     {
         eprint!("\r\x1b[1;33m{}: {}\x1b[0m\x1b[K\n", self.repo_name, message);
         self.last_timestamp = 0;
     }
 
+    fn format_progress(&self) -> String
+    {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 { self.n_commits as f64 / elapsed } else { 0.0 };
+
+        match self.n_expected
+        {
+            Some(n_expected) if rate > 0.0 =>
+            {
+                let n_left = n_expected.saturating_sub(self.n_commits as u64);
+
+                format!("{}/{} commits, {:.0}/s, ETA {}",
+                        self.n_commits, n_expected, rate,
+                        format_duration(Duration::from_secs_f64(n_left as f64 / rate)))
+            },
+            _ => format!("{} commits, {:.0}/s", self.n_commits, rate)
+        }
+    }
+
     pub fn log_commit(&mut self, commit: &RawCommit)
     {
         self.n_commits += 1;
@@ -83,11 +118,11 @@ impl StatusLogger
             || author_year != self.last_year
             || author_month != self.last_month
         {
-            eprint!("\r{}: {}-{:02} ({} commits)\x1b[K",
+            eprint!("\r{}: {}-{:02} ({})\x1b[K",
                    self.repo_name,
                    author_year,
                    author_month + 1,
-                   self.n_commits);
+                   self.format_progress());
             io::stderr().flush().unwrap();
 
             self.last_timestamp = timestamp;
@@ -98,21 +133,59 @@ impl StatusLogger
 
     pub fn end_repo(&mut self)
     {
+        let elapsed = self.started.elapsed();
+
         if self.last_year != 0
         {
-            eprint!("\r{}: {}-{:02} ({} commits)\x1b[K\n",
+            eprint!("\r{}: {}-{:02} ({} commits in {})\x1b[K\n",
                    self.repo_name,
                    self.last_year,
                    self.last_month + 1,
-                   self.n_commits);
+                   self.n_commits,
+                   format_duration(elapsed));
         }
         else
         {
-            eprint!("\r{}: {} commits\x1b[K\n",
+            eprint!("\r{}: {} commits in {}\x1b[K\n",
                    self.repo_name,
-                   self.n_commits);
+                   self.n_commits,
+                   format_duration(elapsed));
         }
 
         io::stderr().flush().unwrap();
+        self.finished.push((self.repo_name.clone(), self.n_commits, elapsed));
     }
+
+    // Per-repo timings, slowest first. Only worth it for several repos.
+
+    pub fn print_summary(&self)
+    {
+        if self.finished.len() < 2 { return; }
+
+        let mut finished: Vec<&(String, u32, Duration)> = self.finished.iter().collect();
+        finished.sort_by_key(|f| std::cmp::Reverse(f.2));
+
+        eprintln!("\n{:<40} {:>10} {:>10} {:>10}", "Repository", "Commits", "Time", "Rate");
+        for (repo_name, n_commits, elapsed) in &finished
+        {
+            eprintln!("{:<40} {:>10} {:>10} {:>8.0}/s",
+                      repo_name, n_commits, format_duration(*elapsed),
+                      *n_commits as f64 / elapsed.as_secs_f64().max(0.001));
+        }
+
+        let n_commits: u32 = self.finished.iter().map(|f| f.1).sum();
+        let elapsed: Duration = self.finished.iter().map(|f| f.2).sum();
+        eprintln!("{:<40} {:>10} {:>10} {:>8.0}/s",
+                  "Total", n_commits, format_duration(elapsed),
+                  n_commits as f64 / elapsed.as_secs_f64().max(0.001));
+    }
+}
+
+fn format_duration(d: Duration) -> String
+{
+    let secs = d.as_secs();
+
+    if secs >= 3600 { format!("{}h{:02}m", secs / 3600, secs % 3600 / 60) }
+    else if secs >= 60 { format!("{}m{:02}s", secs / 60, secs % 60) }
+    else { format!("{:.1}s", d.as_secs_f64()) }
 }
@@ -0,0 +1,133 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* ---------- *
+ * Stickiness *
+ * ---------- */
+
+use serde::{Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::*;
+use crate::common::ExportFormat;
+use crate::csvformat::{ csv_header, format_csv };
+use crate::errors::*;
+use crate::parquetexport::{ ParquetColumn, write_parquet };
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Serialize, Debug)]
+struct StickinessRow
+{
+    first_year: i32,
+    n_authors: i64,
+    n_returned: i64,
+    share_returned: f64,
+    median_gap_days: Option<i64>
+}
+
+// How many of the authors in each first-year cohort ever came back for a
+// second commit, and the median number of days it took them. Authors who
+// made their second commit within a day of the first count as zero days.
+
+pub struct Stickiness
+{
+    rows: Vec<StickinessRow>
+}
+
+impl Stickiness
+{
+    pub fn new(authors: &[(i32, Option<i64>)]) -> Stickiness
+    {
+        let mut gaps: BTreeMap<i32, (i64, Vec<i64>)> = BTreeMap::new();
+
+        for (first_year, gap) in authors
+        {
+            let entry = gaps.entry(*first_year).or_default();
+            entry.0 += 1;
+            entry.1.extend(gap.map(|g| g.div_euclid(SECONDS_PER_DAY)));
+        }
+
+        let rows = gaps.into_iter()
+            .map(|(first_year, (n_authors, mut days))|
+            {
+                days.sort_unstable();
+                StickinessRow
+                {
+                    first_year,
+                    n_authors,
+                    n_returned: days.len() as i64,
+                    share_returned: days.len() as f64 / n_authors as f64,
+                    median_gap_days: days.get(days.len() / 2).copied()
+                }
+            })
+            .collect();
+
+        Stickiness { rows }
+    }
+
+    pub fn to_csv(&self, delimiter: u8) -> String
+    {
+        let mut rows = vec![ csv_header(&[ "first_year", "n_authors", "n_returned", "share_returned",
+                                           "median_gap_days" ]) ];
+
+        rows.extend(self.rows.iter()
+                    .map(|r| vec![ r.first_year.to_string(), r.n_authors.to_string(), r.n_returned.to_string(),
+                                   format!("{:.4}", r.share_returned),
+                                   r.median_gap_days.map(|d| d.to_string()).unwrap_or_default() ]));
+        format_csv(&rows, delimiter)
+    }
+
+    pub fn write(&self, out_path: &Path, format: ExportFormat, delimiter: u8) -> Result<()>
+    {
+        let r = &self.rows;
+        let content = match format
+        {
+            ExportFormat::Csv => self.to_csv(delimiter),
+            ExportFormat::Json => serde_json::to_string_pretty(&self.rows)
+                .chain_err(|| "Could not serialize stickiness table")?,
+            ExportFormat::Parquet => return write_parquet(out_path, "stickiness", &[
+                ("first_year", ParquetColumn::Int(r.iter().map(|r| r.first_year).collect())),
+                ("n_authors", ParquetColumn::Long(r.iter().map(|r| r.n_authors).collect())),
+                ("n_returned", ParquetColumn::Long(r.iter().map(|r| r.n_returned).collect())),
+                ("share_returned", ParquetColumn::Double(r.iter().map(|r| r.share_returned).collect())),
+                ("median_gap_days", ParquetColumn::OptInt(r.iter().map(|r| r.median_gap_days.map(|d| d as i32)).collect())) ])
+        };
+
+        fs::write(out_path, content + "\n").chain_err(|| "Could not write stickiness table")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_returning_authors() {
+        let day = SECONDS_PER_DAY;
+        let stickiness = Stickiness::new(&[ (2015, None), (2015, Some(3 * day)), (2015, Some(40 * day + 1)),
+                                            (2015, Some(100)), (2016, None) ]);
+
+        assert_eq!(stickiness.to_csv(b'|'),
+                   "first_year|n_authors|n_returned|share_returned|median_gap_days\n\
+                    2015|4|3|0.7500|3\n\
+                    2016|1|0|0.0000|");
+    }
+}
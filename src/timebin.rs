@@ -0,0 +1,230 @@
+/* -*- Mode: rust; tab-width: 4; indent-tabs-mode: nil; c-basic-offset: 4 -*- */
+
+/* Copyright (C) 2020 Hans Petter Jansson
+ *
+ * This file is part of Fornalder, a program that visualizes long-term trends
+ * in contributions to version control repositories.
+ *
+ * Fornalder is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Fornalder is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Fornalder.  If not, see <http://www.gnu.org/licenses/>. */
+
+/* ------- *
+ * TimeBin *
+ * ------- */
+
+use chrono::{ Datelike, Duration, NaiveDate, NaiveDateTime, Weekday };
+use std::fmt;
+use std::str::FromStr;
+
+// A time bin: a year, a month, an ISO week or a day. Months are zero-based,
+// like chrono's month0(), and weeks are numbered from 1 within their ISO
+// year. Bins of the same kind sort in time order; histograms don't mix kinds.
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Debug)]
+pub enum TimeBin
+{
+    Year(i32),
+    Month(i32, u32),
+    Week(i32, u32),
+    Day(NaiveDate)
+}
+
+impl TimeBin
+{
+    pub fn day(year: i32, month0: u32, day0: u32) -> TimeBin
+    {
+        TimeBin::Day(NaiveDate::from_ymd_opt(year, month0 + 1, day0 + 1).unwrap())
+    }
+
+    pub fn week_of(date: NaiveDate) -> TimeBin
+    {
+        let week = date.iso_week();
+        TimeBin::Week(week.year(), week.week())
+    }
+
+    pub fn from_month_index(i: i32) -> TimeBin
+    {
+        TimeBin::Month(i.div_euclid(12), i.rem_euclid(12) as u32)
+    }
+
+    pub fn year(&self) -> i32
+    {
+        match *self
+        {
+            TimeBin::Year(year) | TimeBin::Month(year, _) | TimeBin::Week(year, _) => year,
+            TimeBin::Day(date) => date.year()
+        }
+    }
+
+    pub fn is_same_kind(&self, other: &TimeBin) -> bool
+    {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    pub fn first_of_year(&self) -> TimeBin
+    {
+        // The first bin of the same kind in this bin's year.
+
+        match *self
+        {
+            TimeBin::Year(year) => TimeBin::Year(year),
+            TimeBin::Month(year, _) => TimeBin::Month(year, 0),
+            TimeBin::Week(year, _) => TimeBin::Week(year, 1),
+            TimeBin::Day(date) => TimeBin::day(date.year(), 0, 0)
+        }
+    }
+
+    pub fn next(&self) -> TimeBin
+    {
+        match *self
+        {
+            TimeBin::Year(year) => TimeBin::Year(year + 1),
+            TimeBin::Month(year, 11) => TimeBin::Month(year + 1, 0),
+            TimeBin::Month(year, m) => TimeBin::Month(year, m + 1),
+            TimeBin::Week(..) => TimeBin::week_of(self.begin_dt().date() + Duration::days(7)),
+            TimeBin::Day(date) => TimeBin::Day(date.succ_opt().unwrap())
+        }
+    }
+
+    pub fn begin_dt(&self) -> NaiveDateTime
+    {
+        let date = match *self
+        {
+            TimeBin::Year(year) => NaiveDate::from_ymd_opt(year, 1, 1),
+            TimeBin::Month(year, m) => NaiveDate::from_ymd_opt(year, m + 1, 1),
+            TimeBin::Week(year, w) => NaiveDate::from_isoywd_opt(year, w, Weekday::Mon),
+            TimeBin::Day(date) => Some(date)
+        };
+
+        date.unwrap().and_hms_opt(0, 0, 0).unwrap()
+    }
+
+    pub fn end_dt(&self) -> NaiveDateTime
+    {
+        self.next().begin_dt()
+    }
+}
+
+impl fmt::Display for TimeBin
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            TimeBin::Year(year) => write!(f, "{}", year),
+            TimeBin::Month(year, m) => write!(f, "{}-{:02}", year, m + 1),
+            TimeBin::Week(year, w) => write!(f, "{}-W{:02}", year, w),
+            TimeBin::Day(date) => write!(f, "{}", date.format("%Y-%m-%d"))
+        }
+    }
+}
+
+impl FromStr for TimeBin
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<TimeBin, String>
+    {
+        // The inverse of Display: 2020, 2020-03, 2020-W05 or 2020-03-15.
+
+        let invalid = || format!("Expected a year, month, week or day like 2020, 2020-03, 2020-W05 \
+                                  or 2020-03-15, got '{}'", s);
+        let parts: Vec<&str> = s.split('-').collect();
+        let year: i32 = parts[0].parse().map_err(|_| invalid())?;
+
+        let bin = match parts[1..]
+        {
+            [] => TimeBin::Year(year),
+            [w] if w.starts_with('W') =>
+            {
+                let w: u32 = w[1..].parse().map_err(|_| invalid())?;
+                NaiveDate::from_isoywd_opt(year, w, Weekday::Mon).ok_or_else(invalid)?;
+                TimeBin::Week(year, w)
+            },
+            [m] =>
+            {
+                let m: u32 = m.parse().map_err(|_| invalid())?;
+                if !(1..=12).contains(&m) { return Err(invalid()); }
+                TimeBin::Month(year, m - 1)
+            },
+            [m, d] =>
+            {
+                let (m, d): (u32, u32) = (m.parse().map_err(|_| invalid())?, d.parse().map_err(|_| invalid())?);
+                TimeBin::Day(NaiveDate::from_ymd_opt(year, m, d).ok_or_else(invalid)?)
+            },
+            _ => return Err(invalid())
+        };
+
+        Ok(bin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dt(y: i32, m: u32, d: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap().and_hms_opt(0, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn without_month_next() {
+        assert_eq!(TimeBin::Year(2020).next(), TimeBin::Year(2021));
+    }
+
+    #[test]
+    fn with_month_next() {
+        assert_eq!(TimeBin::Month(2020, 0).next(), TimeBin::Month(2020, 1));
+        assert_eq!(TimeBin::Month(2020, 11).next(), TimeBin::Month(2021, 0));
+    }
+
+    #[test]
+    fn with_week_next() {
+        assert_eq!(TimeBin::Week(2020, 53).next(), TimeBin::Week(2021, 1));
+        assert_eq!(TimeBin::Week(2021, 1).begin_dt(), dt(2021, 1, 4));
+    }
+
+    #[test]
+    fn with_day_next() {
+        let last = TimeBin::day(2020, 1, 28);
+        assert_eq!(last.next(), TimeBin::day(2020, 2, 0));
+        assert_eq!(last.end_dt(), dt(2020, 3, 1));
+    }
+
+    #[test]
+    fn ym_begin() {
+        assert_eq!(TimeBin::Year(2020).begin_dt(), dt(2020, 1, 1));
+        assert_eq!(TimeBin::Month(2020, 11).begin_dt(), dt(2020, 12, 1));
+    }
+
+    #[test]
+    fn ym_end() {
+        assert_eq!(TimeBin::Year(2020).end_dt(), dt(2021, 1, 1));
+        assert_eq!(TimeBin::Month(2020, 0).end_dt(), dt(2020, 2, 1));
+        assert_eq!(TimeBin::Month(2020, 11).end_dt(), dt(2021, 1, 1));
+    }
+
+    #[test]
+    fn parses_what_it_formats() {
+        for s in &[ "2020", "2020-03", "2020-W05", "2020-02-29" ]
+        {
+            assert_eq!(s.parse::<TimeBin>().unwrap().to_string(), *s);
+        }
+
+        assert_eq!("2020-03".parse::<TimeBin>().unwrap(), TimeBin::Month(2020, 2));
+        assert!("2020-13".parse::<TimeBin>().is_err());
+        assert!("2021-W53".parse::<TimeBin>().is_err());
+        assert!("2021-02-29".parse::<TimeBin>().is_err());
+        assert!("Q1".parse::<TimeBin>().is_err());
+    }
+}